@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// the messages of other nodes are text, anything else is dropped before
+// the parsing on the connection
+fuzz_target!(|data: &[u8]| {
+    if let Ok(raw_msg) = std::str::from_utf8(data) {
+        fsy::check_namespaced_msg(raw_msg);
+    }
+});
@@ -0,0 +1,281 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use tokio::time::Instant;
+
+use crate::action::CommAction;
+use crate::ids::{GroupName, TicketId};
+use crate::space;
+
+// bytes of downloads let on the queue at once when not set
+pub const DEFAULT_MAX_IN_FLIGHT_BYTES: u64 = 1024 * 1024 * 1024;
+
+// how often what is left to pull of each group goes to the status
+pub const PROGRESS_CHECK_SECS: u64 = 5;
+
+// SyncProgress: what is left to pull of a group, from the downloads the
+// pushers announced, and how long it takes at the pace seen so far
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SyncProgress {
+    pub files: u64,            // downloads left, waiting or on the queue
+    pub bytes: u64,            // bytes of those downloads
+    pub eta_secs: Option<u64>, // none until something was pulled
+}
+
+// Pace: the pulls of a group since it had some left
+#[derive(Debug)]
+struct Pace {
+    started: Instant,
+    pulled_bytes: u64,
+}
+
+// Admission: downloads wait here until there is room for their bytes, both
+// under the in flight limit and on the disk, so big files don't take the
+// slots of the queue from the rest of the messages
+#[derive(Debug, Default)]
+pub struct Admission {
+    max_in_flight_bytes: u64,
+    in_flight: HashMap<TicketId, (GroupName, u64)>, // group and bytes of the admitted downloads
+    waiting: VecDeque<CommAction>,
+    paces: HashMap<GroupName, Pace>,
+}
+
+impl Admission {
+    pub fn new(max_in_flight_bytes: u64) -> Self {
+        Self {
+            max_in_flight_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_in_flight_bytes(&self) -> u64 {
+        self.in_flight.values().map(|(_, size)| size).sum()
+    }
+
+    pub fn get_waiting_count(&self) -> usize {
+        self.waiting.len()
+    }
+
+    // fits checks if a download of the size can go now. with nothing in
+    // flight it always goes, running out of disk is handled on the pull
+    fn fits(&self, size: u64, free_space: Option<u64>) -> bool {
+        let in_flight = self.get_in_flight_bytes();
+        if in_flight == 0 {
+            return true;
+        }
+
+        let needed = in_flight
+            .saturating_add(size)
+            .saturating_add(space::RESUME_FREE_BYTES);
+        in_flight.saturating_add(size) <= self.max_in_flight_bytes
+            && free_space.is_none_or(|free| free >= needed)
+    }
+
+    // admit retrieves the action if it can go on the queue now, downloads
+    // without room wait (behind the ones waiting already, to keep the order)
+    pub fn admit(&mut self, action: CommAction, free_space: Option<u64>) -> Option<CommAction> {
+        let CommAction::DownloadTarget(_, target_name, _, ticket_id, _, size, _, _) = &action
+        else {
+            return Some(action);
+        };
+
+        self.paces
+            .entry(target_name.clone())
+            .or_insert_with(|| Pace {
+                started: Instant::now(),
+                pulled_bytes: 0,
+            });
+        if !self.waiting.is_empty() || !self.fits(*size, free_space) {
+            self.waiting.push_back(action);
+            return None;
+        }
+
+        self.in_flight
+            .insert(ticket_id.clone(), (target_name.clone(), *size));
+        Some(action)
+    }
+
+    // take_admitted retrieves the waiting downloads that fit now, in order,
+    // with the free space of the group of each
+    pub fn take_admitted(
+        &mut self,
+        get_free_space: impl Fn(&str) -> Option<u64>,
+    ) -> Vec<CommAction> {
+        let mut admitted = vec![];
+        while let Some(CommAction::DownloadTarget(_, target_name, _, ticket_id, _, size, _, _)) =
+            self.waiting.front()
+        {
+            if !self.fits(*size, get_free_space(target_name)) {
+                break;
+            }
+
+            self.in_flight
+                .insert(ticket_id.clone(), (target_name.clone(), *size));
+            admitted.extend(self.waiting.pop_front());
+        }
+
+        admitted
+    }
+
+    // done lets go of the bytes of a download, pulled or not. the pulled
+    // ones set the pace of their group
+    pub fn done(&mut self, ticket_id: &str, is_pulled: bool) {
+        let Some((target_name, size)) = self.in_flight.remove(ticket_id) else {
+            return;
+        };
+
+        if is_pulled && let Some(pace) = self.paces.get_mut(&target_name) {
+            pace.pulled_bytes += size;
+        }
+    }
+
+    // get_progress retrieves what is left to pull of each group with pulls
+    // pending, the groups that have nothing left start over on the next one
+    pub fn get_progress(&mut self, now: Instant) -> HashMap<GroupName, SyncProgress> {
+        let mut progress: HashMap<GroupName, SyncProgress> = HashMap::new();
+        let in_flight = self
+            .in_flight
+            .values()
+            .map(|(target_name, size)| (target_name, size));
+        let waiting = self.waiting.iter().filter_map(|action| match action {
+            CommAction::DownloadTarget(_, target_name, _, _, _, size, _, _) => {
+                Some((target_name, size))
+            }
+            _ => None,
+        });
+        for (target_name, size) in in_flight.chain(waiting) {
+            let group = progress.entry(target_name.clone()).or_default();
+            group.files += 1;
+            group.bytes += size;
+        }
+
+        self.paces
+            .retain(|target_name, _| progress.contains_key(target_name));
+        for (target_name, group) in progress.iter_mut() {
+            let Some(pace) = self.paces.get(target_name) else {
+                continue;
+            };
+            group.eta_secs = get_eta_secs(pace, group.bytes, now);
+        }
+
+        progress
+    }
+}
+
+// get_eta_secs retrieves how long the bytes take at the pace of the group,
+// rounded to the closest second
+fn get_eta_secs(pace: &Pace, bytes: u64, now: Instant) -> Option<u64> {
+    if pace.pulled_bytes == 0 {
+        return None;
+    }
+
+    let elapsed_millis = now.saturating_duration_since(pace.started).as_millis();
+    let pulled_bytes = pace.pulled_bytes as u128;
+    let eta_millis = bytes as u128 * elapsed_millis / pulled_bytes;
+    Some(((eta_millis + 500) / 1000) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    fn get_download(ticket_id: &str, size: u64) -> CommAction {
+        CommAction::DownloadTarget(
+            "node".into(),
+            "group".into(),
+            format!("{ticket_id}.txt").into(),
+            ticket_id.into(),
+            "".into(),
+            size,
+            0,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_admit() -> Result<()> {
+        let mut admission = Admission::new(100);
+
+        // the first always goes, even over the limit
+        assert!(admission.admit(get_download("a", 150), Some(0)).is_some());
+        assert!(admission.admit(get_download("b", 10), None).is_none());
+        assert_eq!(admission.get_in_flight_bytes(), 150);
+
+        // the rest of the messages never wait
+        let msg = CommAction::SendMessage("node".into(), "foo".into());
+        assert_eq!(admission.admit(msg.clone(), Some(0)), Some(msg));
+
+        // the waiting one goes once there is room
+        assert!(admission.take_admitted(|_| None).is_empty());
+        admission.done("a", true);
+        assert_eq!(
+            admission.take_admitted(|_| None),
+            vec![get_download("b", 10)]
+        );
+        assert_eq!(admission.get_waiting_count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fits() -> Result<()> {
+        let free = space::RESUME_FREE_BYTES;
+        let test_values = [
+            // (in flight, size, free space, fits)
+            (0, 500, Some(0), true),
+            (10, 50, None, true),
+            (10, 90, None, true),
+            (10, 91, None, false),
+            (10, 50, Some(free + 60), true),
+            (10, 50, Some(free + 59), false),
+        ];
+
+        for spec in test_values {
+            let mut admission = Admission::new(100);
+            if spec.0 > 0 {
+                admission.admit(get_download("a", spec.0), None);
+            }
+            assert_eq!(admission.fits(spec.1, spec.2), spec.3, "{:?}", spec);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_progress() -> Result<()> {
+        let mut admission = Admission::new(1000);
+        admission.admit(get_download("a", 100), None);
+        admission.admit(get_download("b", 200), None);
+        admission.admit(get_download("c", 300), None);
+
+        // nothing pulled yet, no pace to go by
+        let now = Instant::now();
+        let progress = admission.get_progress(now);
+        let expected = SyncProgress {
+            files: 3,
+            bytes: 600,
+            eta_secs: None,
+        };
+        assert_eq!(progress.get("group"), Some(&expected));
+
+        // 100 bytes in 10 seconds, the 300 left take 30. the failed ones
+        // don't count for the pace
+        admission.done("a", true);
+        admission.done("b", false);
+        let progress = admission.get_progress(now + Duration::from_secs(10));
+        let expected = SyncProgress {
+            files: 1,
+            bytes: 300,
+            eta_secs: Some(30),
+        };
+        assert_eq!(progress.get("group"), Some(&expected));
+
+        // once nothing is left the group isn't there
+        admission.done("c", true);
+        assert!(admission.get_progress(now).is_empty());
+        assert!(admission.paces.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{Result, bail};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// nodes with it take the part appended to the files of the append only
+// groups, see `RequestAppend`
+pub const APPEND_FEATURE: &str = "append";
+
+// the parts appended are copied there before being ticketed
+pub const APPENDS_DIR_NAME: &str = "appends";
+
+// bytes before the offset compared by both sides, a file rotated or
+// rewritten since isn't appended to
+const TAIL_BYTES: u64 = 4096;
+
+// hash_tail retrieves the hash of the bytes of the file right before the
+// offset, what the puller has there should be what the pusher has
+pub fn hash_tail(file_path: &Path, offset: u64) -> Result<String> {
+    let mut file = File::open(file_path)?;
+    if file.metadata()?.len() < offset {
+        bail!("{} is shorter than {offset} bytes", file_path.display());
+    }
+
+    let start = offset.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(file.take(offset - start))?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// write_suffix copies what the file has past the offset to the storage, one
+// per node and file as each node can be at its own offset
+pub fn write_suffix(
+    storage_path: &Path,
+    group_name: &str,
+    node_id: &str,
+    relative_path: &str,
+    file_path: &Path,
+    offset: u64,
+) -> Result<PathBuf> {
+    let name = match relative_path.is_empty() {
+        true => file_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        false => relative_path.to_owned(),
+    };
+    let suffix_path = storage_path
+        .join(APPENDS_DIR_NAME)
+        .join(group_name)
+        .join(node_id)
+        .join(name);
+    if let Some(parent) = suffix_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = File::open(file_path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    io::copy(&mut file, &mut File::create(&suffix_path)?)?;
+    Ok(suffix_path)
+}
+
+// append_suffix writes the part pulled at the offset of the file, removing
+// it after. a part the file has already (sent again) leaves it as it is
+pub fn append_suffix(suffix_path: &Path, file_path: &Path, offset: u64, fsync: bool) -> Result<()> {
+    let suffix_len = fs::metadata(suffix_path)?.len();
+    let mut file = fs::OpenOptions::new().write(true).open(file_path)?;
+    let len = file.metadata()?.len();
+    if len < offset {
+        bail!("{} is shorter than {offset} bytes", file_path.display());
+    }
+
+    if len < offset + suffix_len {
+        file.seek(SeekFrom::Start(offset))?;
+        io::copy(&mut File::open(suffix_path)?, &mut file)?;
+        if fsync {
+            file.sync_all()?;
+        }
+    }
+
+    fs::remove_file(suffix_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_append_suffix() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_append");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let pushed_path = dir.join("pushed.log");
+        let pulled_path = dir.join("pulled.log");
+        fs::write(&pushed_path, "one\ntwo\nthree\n")?;
+
+        let test_values = [
+            // (pulled content, offset, appended content)
+            ("one\n", 4, Some("one\ntwo\nthree\n")),
+            ("one\ntwo\n", 8, Some("one\ntwo\nthree\n")),
+            // sent again, the file has it already
+            ("one\ntwo\nthree\n", 4, Some("one\ntwo\nthree\n")),
+            // shorter than what the pusher thinks
+            ("on", 4, None),
+        ];
+        for spec in test_values {
+            fs::write(&pulled_path, spec.0)?;
+            let suffix_path =
+                write_suffix(&dir, "logs", "foo", "app/pushed.log", &pushed_path, spec.1)?;
+            let res = append_suffix(&suffix_path, &pulled_path, spec.1, false);
+            assert_eq!(res.is_ok(), spec.2.is_some(), "{spec:?}");
+            if let Some(content) = spec.2 {
+                assert_eq!(fs::read_to_string(&pulled_path)?, content, "{spec:?}");
+                assert!(!suffix_path.exists());
+            }
+        }
+
+        // both sides tell the same part apart from a file rewritten since
+        fs::write(&pulled_path, "one\ntwo\n")?;
+        assert_eq!(hash_tail(&pulled_path, 8)?, hash_tail(&pushed_path, 8)?);
+        fs::write(&pulled_path, "uno\ntwo\n")?;
+        assert_ne!(hash_tail(&pulled_path, 8)?, hash_tail(&pushed_path, 8)?);
+        assert!(hash_tail(&pulled_path, 9).is_err());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::action::CommAction;
+use crate::ids::PeerId;
+use crate::{storage, target};
+
+pub const APPROVALS_FILE_NAME: &str = "approvals.toml";
+
+// PendingChange: a change a pusher notified on a group of manual approval,
+// not pulled until approved (`fsy approve`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingChange {
+    pub node_id: String, // last node that notified it, the one it is pulled from
+    pub received: DateTime<Utc>,
+}
+
+// PendingChanges: the changes waiting for approval of each group, by path
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PendingChanges {
+    #[serde(default)]
+    pub groups: BTreeMap<String, BTreeMap<String, PendingChange>>,
+}
+
+impl fmt::Display for PendingChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.groups.values().all(|changes| changes.is_empty()) {
+            return writeln!(f, "no pending changes");
+        }
+
+        for (group_name, changes) in &self.groups {
+            for (relative_path, change) in changes {
+                let relative_path = match relative_path.is_empty() {
+                    true => "(all)",
+                    false => relative_path,
+                };
+                writeln!(
+                    f,
+                    "{group_name}: {relative_path} from {} since {}",
+                    change.node_id,
+                    change.received.format("%Y-%m-%d %H:%M:%S UTC"),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PendingChanges {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: PendingChanges = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = toml::to_string(self)?;
+        storage::write_atomic(path, content.as_bytes())
+    }
+
+    // update loads, changes and saves the pending changes, the other lanes
+    // wait so none of their changes are lost
+    pub fn update<T>(path: &Path, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let _lock = storage::lock_state();
+        let mut pending = Self::load(path)?;
+        let res = change(&mut pending)?;
+        pending.save(path)?;
+        Ok(res)
+    }
+
+    // add notes a change of the node, returning if it wasn't pending already
+    pub fn add(
+        &mut self,
+        group_name: &str,
+        relative_path: &str,
+        node_id: &str,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let change = PendingChange {
+            node_id: node_id.to_owned(),
+            received: now,
+        };
+        self.groups
+            .entry(group_name.to_owned())
+            .or_default()
+            .insert(relative_path.to_owned(), change)
+            .is_none()
+    }
+
+    // take removes the changes of the group, only the one of the path if set
+    pub fn take(
+        &mut self,
+        group_name: &str,
+        relative_path: Option<&str>,
+    ) -> Vec<(String, PendingChange)> {
+        let Some(changes) = self.groups.get_mut(group_name) else {
+            return vec![];
+        };
+
+        let taken = match relative_path {
+            Some(relative_path) => changes.remove_entry(relative_path).into_iter().collect(),
+            None => std::mem::take(changes).into_iter().collect(),
+        };
+        if changes.is_empty() {
+            self.groups.remove(group_name);
+        }
+
+        taken
+    }
+}
+
+// approve takes the pending changes of the group (of the path if set) off
+// the storage, retrieving the actions that pull them. mirrors ask for the
+// manifest too, for what the pusher removed
+pub fn approve(
+    storage_path: &Path,
+    target_groups: &[target::TargetGroup],
+    group_name: &str,
+    relative_path: Option<&str>,
+) -> Result<Vec<CommAction>> {
+    let Some(group) = target::get_pull_group_with_name(target_groups, group_name) else {
+        bail!("no group \"{group_name}\" pulling");
+    };
+
+    let approvals_path = storage_path.join(APPROVALS_FILE_NAME);
+    let taken = PendingChanges::update(&approvals_path, |pending| {
+        let taken = pending.take(group_name, relative_path);
+        if taken.is_empty() {
+            match relative_path {
+                Some(relative_path) => {
+                    bail!("no change of \"{relative_path}\" pending on \"{group_name}\"")
+                }
+                None => bail!("no changes pending on \"{group_name}\""),
+            }
+        }
+        Ok(taken)
+    })?;
+
+    let mut actions: Vec<CommAction> = taken
+        .iter()
+        .map(|(relative_path, change)| {
+            CommAction::RequestTarget(
+                PeerId::from(change.node_id.as_str()),
+                group.name.clone(),
+                relative_path.as_str().into(),
+            )
+            .to_send_message()
+        })
+        .collect();
+    if group.is_mirror() {
+        let node_ids: BTreeSet<&str> = taken.iter().map(|(_, c)| c.node_id.as_str()).collect();
+        actions.extend(node_ids.into_iter().map(|node_id| {
+            CommAction::RequestManifest(node_id.into(), group.name.clone()).to_send_message()
+        }));
+    }
+
+    Ok(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_pending_changes() -> Result<()> {
+        let now = Utc::now();
+        let mut pending = PendingChanges::default();
+        let test_values = [
+            // (group, path, node, is_new)
+            ("docs", "a.txt", "foo", true),
+            ("docs", "a.txt", "bar", false),
+            ("docs", "b.txt", "foo", true),
+            ("photos", "", "foo", true),
+        ];
+
+        for spec in test_values {
+            assert_eq!(pending.add(spec.0, spec.1, spec.2, now), spec.3, "{spec:?}");
+        }
+
+        // the last node notifying it is the one it is pulled from
+        let taken = pending.take("docs", Some("a.txt"));
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].1.node_id, "bar");
+        assert!(pending.take("docs", Some("a.txt")).is_empty());
+
+        assert_eq!(pending.take("docs", None).len(), 1);
+        assert!(!pending.groups.contains_key("docs"));
+        assert_eq!(pending.take("photos", None).len(), 1);
+        assert!(pending.take("foo", None).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_approve() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_approve");
+        let _ = fs::remove_dir_all(&dir);
+        let approvals_path = dir.join(APPROVALS_FILE_NAME);
+        let now = Utc::now();
+        let mut pending = PendingChanges::default();
+        pending.add("docs", "a.txt", "foo", now);
+        pending.add("docs", "b.txt", "foo", now);
+        pending.add("backup", "c.txt", "foo", now);
+        pending.save(&approvals_path)?;
+
+        let target_groups: Vec<target::TargetGroup> = ["docs", "backup"]
+            .iter()
+            .map(|name| target::TargetGroup {
+                name: (*name).into(),
+                targets: vec![target::Target {
+                    mode: target::TargetMode::Pull,
+                    node_name: "foo".to_string(),
+                    include: vec![],
+                }],
+                mirror: *name == "backup",
+                ..Default::default()
+            })
+            .collect();
+
+        let test_values = [
+            // (group, path, actions, pending left)
+            ("docs", Some("a.txt"), Some(1), 2),
+            ("docs", Some("a.txt"), None, 2),
+            ("docs", None, Some(1), 1),
+            ("backup", None, Some(2), 0),
+            ("photos", None, None, 0),
+        ];
+        for spec in test_values {
+            let actions = approve(&dir, &target_groups, spec.0, spec.1);
+            assert_eq!(actions.ok().map(|a| a.len()), spec.2, "{spec:?}");
+            let pending = PendingChanges::load(&approvals_path)?;
+            let left: usize = pending.groups.values().map(|changes| changes.len()).sum();
+            assert_eq!(left, spec.3, "{spec:?}");
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{export, reserved};
+
+// folder on the fsy folder of the target where the old versions are kept
+pub const VERSIONS_DIR_NAME: &str = "versions";
+
+// get_version_path retrieves where a file of the target is kept when replaced
+// or removed, under a folder dated with the time it happened
+fn get_version_path(base_path: &Path, file_path: &Path, stamp: &str) -> Option<PathBuf> {
+    let dir = Path::new(VERSIONS_DIR_NAME).join(stamp);
+    reserved::get_reserved_path(base_path, &dir, file_path)
+}
+
+// archive_files moves the current version of the files to the versions
+// folder of the target, files that don't exist are ignored. past the max
+// versions of a file, the oldest go
+pub fn archive_files(
+    base_path: &Path,
+    file_paths: &[PathBuf],
+    max_versions: Option<usize>,
+) -> Result<usize> {
+    let stamp = Utc::now().format("%Y-%m-%d_%H%M%S").to_string();
+    let mut archived = 0;
+    for file_path in file_paths {
+        if !file_path.is_file() {
+            continue;
+        }
+
+        let Some(version_path) = get_version_path(base_path, file_path, &stamp) else {
+            continue;
+        };
+        if let Some(parent) = version_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        export::move_into_place(file_path, &version_path, false)?;
+        archived += 1;
+    }
+
+    if let Some(max_versions) = max_versions {
+        prune_versions(base_path, file_paths, max_versions)?;
+    }
+    Ok(archived)
+}
+
+// prune_versions removes the versions of the files past the most recent
+// max versions, along with the folders left empty
+pub fn prune_versions(
+    base_path: &Path,
+    file_paths: &[PathBuf],
+    max_versions: usize,
+) -> Result<usize> {
+    let versions_path = reserved::get_root(base_path)
+        .join(reserved::FSY_DIR_NAME)
+        .join(VERSIONS_DIR_NAME);
+    let Ok(entries) = fs::read_dir(&versions_path) else {
+        return Ok(0);
+    };
+
+    // NOTE: the stamps sort as the time they were made
+    let mut stamps: Vec<String> = entries
+        .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().to_string()))
+        .collect();
+    stamps.sort_by(|a, b| b.cmp(a));
+
+    let mut pruned = 0;
+    for file_path in file_paths {
+        let versions = stamps
+            .iter()
+            .filter_map(|stamp| get_version_path(base_path, file_path, stamp))
+            .filter(|p| p.is_file());
+        for version_path in versions.skip(max_versions) {
+            fs::remove_file(&version_path)?;
+            pruned += 1;
+
+            let mut dir = version_path.parent();
+            while let Some(d) = dir
+                && d != versions_path
+                && fs::remove_dir(d).is_ok()
+            {
+                dir = d.parent();
+            }
+        }
+    }
+
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_version_path() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_archive_version_path");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("group"))?;
+        fs::write(dir.join("file.txt"), b"")?;
+
+        let test_values = [
+            // (base_path, file_path, expected)
+            ("group", "group/a.txt", Some("group/.fsy/versions/stamp/a.txt")),
+            ("group", "group/foo/a.txt", Some("group/.fsy/versions/stamp/foo/a.txt")),
+            ("file.txt", "file.txt", Some(".fsy/versions/stamp/file.txt")),
+            ("group", "group", None),
+            ("group", "other/a.txt", None),
+        ];
+
+        for spec in test_values {
+            let res = get_version_path(&dir.join(spec.0), &dir.join(spec.1), "stamp");
+            assert_eq!(res, spec.2.map(|p| dir.join(p)), "{}", spec.1);
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_files() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_archive_files");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("foo"))?;
+        fs::write(dir.join("a.txt"), b"a")?;
+        fs::write(dir.join("foo/b.txt"), b"b")?;
+
+        let file_paths = vec![dir.join("a.txt"), dir.join("foo/b.txt"), dir.join("c.txt")];
+        assert_eq!(archive_files(&dir, &file_paths, None)?, 2);
+        assert!(!dir.join("a.txt").exists());
+
+        let versions_path = dir.join(reserved::FSY_DIR_NAME).join(VERSIONS_DIR_NAME);
+        let stamps: Vec<_> = fs::read_dir(versions_path)?.collect();
+        assert_eq!(stamps.len(), 1);
+        let stamp_path = stamps[0].as_ref().unwrap().path();
+        assert_eq!(fs::read_to_string(stamp_path.join("foo/b.txt"))?, "b");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_versions() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_archive_prune");
+        let _ = fs::remove_dir_all(&dir);
+        let versions_path = dir.join(reserved::FSY_DIR_NAME).join(VERSIONS_DIR_NAME);
+        for stamp in [
+            "2024-01-01_000000",
+            "2024-01-02_000000",
+            "2024-01-03_000000",
+        ] {
+            fs::create_dir_all(versions_path.join(stamp).join("foo"))?;
+            fs::write(versions_path.join(stamp).join("foo/a.txt"), stamp)?;
+        }
+        fs::write(versions_path.join("2024-01-01_000000/b.txt"), "b")?;
+
+        let file_paths = vec![dir.join("foo/a.txt"), dir.join("b.txt")];
+        assert_eq!(prune_versions(&dir, &file_paths, 3)?, 0);
+        assert_eq!(prune_versions(&dir, &file_paths, 1)?, 2);
+
+        // the most recent is kept, the emptied folders go
+        let version_path = versions_path.join("2024-01-03_000000/foo/a.txt");
+        assert_eq!(fs::read_to_string(version_path)?, "2024-01-03_000000");
+        assert!(!versions_path.join("2024-01-02_000000").exists());
+        assert!(versions_path.join("2024-01-01_000000/b.txt").exists());
+        assert!(!versions_path.join("2024-01-01_000000/foo").exists());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::target::NodeData;
+
+pub const BANDWIDTH_FILE_NAME: &str = "bandwidth.toml";
+
+// how many days of usage we keep around, enough for the weekly totals
+const RETENTION_DAYS: u64 = 7;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DayUsage {
+    pub date: NaiveDate,
+    pub sent: u64,     // bytes sent to the peer
+    pub received: u64, // bytes received from the peer
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerUsage {
+    pub node_id: String,
+    pub days: Vec<DayUsage>,
+}
+
+// Usage: totals of a peer on a time window
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl Usage {
+    pub fn total(&self) -> u64 {
+        self.sent + self.received
+    }
+}
+
+// Bandwidth: bytes transferred per peer per day, persisted on the storage
+// so the totals survive restarts
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Bandwidth {
+    pub peers: Vec<PeerUsage>,
+}
+
+impl Bandwidth {
+    pub fn add(&mut self, node_id: &str, sent: u64, received: u64, date: NaiveDate) {
+        let peer = match self.peers.iter_mut().position(|p| p.node_id == node_id) {
+            Some(i) => &mut self.peers[i],
+            None => {
+                self.peers.push(PeerUsage {
+                    node_id: node_id.to_owned(),
+                    days: vec![],
+                });
+                self.peers.last_mut().unwrap()
+            }
+        };
+
+        match peer.days.iter_mut().find(|d| d.date == date) {
+            Some(day) => {
+                day.sent += sent;
+                day.received += received;
+            }
+            None => peer.days.push(DayUsage {
+                date,
+                sent,
+                received,
+            }),
+        }
+
+        self.prune(date);
+    }
+
+    // get_usage sums the usage of a peer on the last `days` up to the date
+    pub fn get_usage(&self, node_id: &str, date: NaiveDate, days: u64) -> Usage {
+        let Some(peer) = self.peers.iter().find(|p| p.node_id == node_id) else {
+            return Usage::default();
+        };
+
+        let since = date - Days::new(days.saturating_sub(1));
+        peer.days
+            .iter()
+            .filter(|d| d.date >= since && d.date <= date)
+            .fold(Usage::default(), |acc, d| Usage {
+                sent: acc.sent + d.sent,
+                received: acc.received + d.received,
+            })
+    }
+
+    // is_over_quota checks the soft quotas of a node, being over them
+    // means we should stop pushing to it for now
+    pub fn is_over_quota(&self, node: &NodeData, date: NaiveDate) -> bool {
+        if let Some(quota) = node.daily_quota_bytes
+            && self.get_usage(&node.id, date, 1).total() >= quota
+        {
+            return true;
+        }
+
+        if let Some(quota) = node.weekly_quota_bytes
+            && self.get_usage(&node.id, date, 7).total() >= quota
+        {
+            return true;
+        }
+
+        false
+    }
+
+    fn prune(&mut self, date: NaiveDate) {
+        let since = date - Days::new(RETENTION_DAYS);
+        for peer in self.peers.iter_mut() {
+            peer.days.retain(|d| d.date > since);
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let parsed: Bandwidth = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 1, day).unwrap()
+    }
+
+    #[test]
+    fn test_get_usage() -> Result<()> {
+        let mut bandwidth = Bandwidth::default();
+        bandwidth.add("foo", 10, 0, get_date(1));
+        bandwidth.add("foo", 5, 1, get_date(1));
+        bandwidth.add("foo", 0, 100, get_date(5));
+        bandwidth.add("bar", 1000, 1000, get_date(5));
+
+        let test_values = [
+            // (node_id, date, days, sent, received)
+            ("foo", 1, 1, 15, 1),
+            ("foo", 5, 1, 0, 100),
+            ("foo", 5, 7, 15, 101),
+            ("foo", 8, 7, 0, 100),
+            ("bar", 5, 7, 1000, 1000),
+            ("zed", 5, 7, 0, 0),
+        ];
+
+        for spec in test_values {
+            let usage = bandwidth.get_usage(spec.0, get_date(spec.1), spec.2);
+            assert_eq!(usage.sent, spec.3);
+            assert_eq!(usage.received, spec.4);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune() -> Result<()> {
+        let mut bandwidth = Bandwidth::default();
+        bandwidth.add("foo", 10, 0, get_date(1));
+        bandwidth.add("foo", 10, 0, get_date(7));
+        assert_eq!(bandwidth.peers[0].days.len(), 2);
+
+        bandwidth.add("foo", 10, 0, get_date(8));
+        assert_eq!(bandwidth.peers[0].days.len(), 2);
+        assert_eq!(bandwidth.peers[0].days[0].date, get_date(7));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_over_quota() -> Result<()> {
+        let mut bandwidth = Bandwidth::default();
+        bandwidth.add("foo", 60, 0, get_date(1));
+        bandwidth.add("foo", 60, 0, get_date(2));
+
+        let test_values = [
+            // (daily_quota, weekly_quota, over)
+            (None, None, false),
+            (Some(100), None, false),
+            (Some(50), None, true),
+            (None, Some(200), false),
+            (None, Some(100), true),
+            (Some(100), Some(100), true),
+        ];
+
+        for spec in test_values {
+            let node = NodeData {
+                name: "foo".to_string(),
+                id: "foo".into(),
+                daily_quota_bytes: spec.0,
+                weekly_quota_bytes: spec.1,
+                ..Default::default()
+            };
+            assert_eq!(bandwidth.is_over_quota(&node, get_date(2)), spec.2);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::{Result, bail};
+use iroh::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::target::NodeData;
+
+pub const BLOCKLIST_FILE_NAME: &str = "blocklist.toml";
+
+// offenses (oversized or garbage messages) a node gets blocked at, no fsy
+// node sends those
+pub const OFFENSES_TO_BLOCK: u64 = 3;
+
+// Blocklist: nodes whose messages are dropped and that never get tickets,
+// for decommissioned or lost devices
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Blocklist {
+    pub node_ids: Vec<String>,
+    #[serde(skip)]
+    contacts: HashMap<String, u64>, // messages received from each blocked node
+    #[serde(skip)]
+    offenses: HashMap<String, u64>, // offenses of each node since the start
+    #[serde(skip)]
+    pinned: Vec<String>, // blocked on the config, never unblocked from elsewhere
+}
+
+impl Blocklist {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: Blocklist = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn is_blocked(&self, node_id: &str) -> bool {
+        self.node_ids.iter().any(|id| id == node_id)
+    }
+
+    // block adds the node, false if it was already there
+    pub fn block(&mut self, node_id: &str) -> bool {
+        if self.is_blocked(node_id) {
+            return false;
+        }
+
+        self.node_ids.push(node_id.to_owned());
+        true
+    }
+
+    // unblock removes the node, false if it wasn't there
+    pub fn unblock(&mut self, node_id: &str) -> bool {
+        let len = self.node_ids.len();
+        self.node_ids.retain(|id| id != node_id);
+        self.node_ids.len() != len
+    }
+
+    // pin blocks the node for as long as the daemon runs, as the ones of
+    // `blocked_nodes` on the config
+    pub fn pin(&mut self, node_id: &str) {
+        self.block(node_id);
+        if !self.pinned.iter().any(|id| id == node_id) {
+            self.pinned.push(node_id.to_owned());
+        }
+    }
+
+    // set_node_ids replaces the blocked nodes, keeping the contacts count
+    // and the pinned ones
+    pub fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        self.node_ids = node_ids;
+        for node_id in self.pinned.clone() {
+            self.block(&node_id);
+        }
+    }
+
+    // record_contact counts a message from a blocked node, returning how
+    // many came from it so far
+    pub fn record_contact(&mut self, node_id: &str) -> u64 {
+        let count = self.contacts.entry(node_id.to_owned()).or_default();
+        *count += 1;
+        *count
+    }
+
+    // record_offense counts an offense of the node, blocking it once it
+    // gets to OFFENSES_TO_BLOCK. returns if it got blocked now
+    pub fn record_offense(&mut self, node_id: &str) -> bool {
+        let count = self.offenses.entry(node_id.to_owned()).or_default();
+        *count += 1;
+        *count >= OFFENSES_TO_BLOCK && self.block(node_id)
+    }
+}
+
+// should_log_contact keeps a node insisting from flooding the logs, only
+// the 1st, 2nd, 4th, 8th... contacts are logged
+pub fn should_log_contact(count: u64) -> bool {
+    count.is_power_of_two()
+}
+
+// get_node_id retrieves the id of the node with the name, or the id itself
+// if it is a valid one, blocking a node not on the config is allowed
+pub fn get_node_id(nodes: &[NodeData], node: &str) -> Result<String> {
+    if let Some(data) = nodes.iter().find(|n| n.name == node) {
+        return Ok(data.id.to_string());
+    }
+
+    if NodeId::from_str(node).is_err() {
+        bail!("unknown node \"{node}\", use its name on the config or its id");
+    }
+
+    Ok(node.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_should_log_contact() -> Result<()> {
+        let test_values = [
+            // (count, should_log)
+            (1, true),
+            (2, true),
+            (3, false),
+            (4, true),
+            (7, false),
+            (64, true),
+            (100, false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(should_log_contact(spec.0), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocklist() -> Result<()> {
+        let mut blocklist = Blocklist::default();
+        assert!(blocklist.block("foo"));
+        assert!(blocklist.is_blocked("foo"));
+        assert!(!blocklist.is_blocked("bar"));
+
+        assert!(blocklist.block("bar"));
+        assert!(!blocklist.block("bar"));
+        assert!(blocklist.is_blocked("bar"));
+
+        assert_eq!(blocklist.record_contact("bar"), 1);
+        assert_eq!(blocklist.record_contact("bar"), 2);
+        blocklist.set_node_ids(vec!["bar".to_string()]);
+        assert!(!blocklist.is_blocked("foo"));
+        assert_eq!(blocklist.record_contact("bar"), 3);
+
+        assert!(blocklist.unblock("bar"));
+        assert!(!blocklist.unblock("bar"));
+        assert!(!blocklist.is_blocked("bar"));
+
+        // the ones of the config can't be wiped
+        blocklist.pin("baz");
+        blocklist.set_node_ids(vec![]);
+        assert!(blocklist.is_blocked("baz"));
+        assert_eq!(blocklist.node_ids, vec!["baz".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_offense() -> Result<()> {
+        let mut blocklist = Blocklist::default();
+        for _ in 1..OFFENSES_TO_BLOCK {
+            assert!(!blocklist.record_offense("foo"));
+        }
+        assert!(!blocklist.is_blocked("foo"));
+
+        assert!(blocklist.record_offense("foo"));
+        assert!(blocklist.is_blocked("foo"));
+        assert!(!blocklist.record_offense("foo"));
+        assert!(!blocklist.is_blocked("bar"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_node_id() -> Result<()> {
+        let node_id = "ae58ff8833241ac82d6ff7611046ed67b5072d142c588d0063e942d9a75502b6";
+        let nodes = vec![NodeData {
+            name: "foo".to_string(),
+            id: "foo_id".into(),
+            ..Default::default()
+        }];
+        let test_values = [
+            // (node, node_id)
+            ("foo", Some("foo_id")),
+            (node_id, Some(node_id)),
+            ("bar", None),
+        ];
+
+        for spec in test_values {
+            let res = get_node_id(&nodes, spec.0);
+            match spec.1 {
+                Some(expected) => assert_eq!(res?, expected),
+                None => assert!(res.is_err()),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::action::CommAction;
+
+pub const CAPTURE_FILE_NAME: &str = "capture.log";
+
+// the capture is on while this file is on the storage, so it can be turned
+// on and off without restarting the daemon
+const CAPTURE_ON_FILE_NAME: &str = "capture.on";
+
+// once the capture gets this big it moves to `capture.log.1` (replacing
+// the previous one) and starts over
+const MAX_CAPTURE_BYTES: u64 = 10 * 1024 * 1024;
+
+// storage of the daemon, set on init
+static STORAGE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// CaptureToggle: the outcome of `fsy debug capture`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CaptureToggle {
+    pub on: bool,
+    pub capture_path: String, // where the messages go
+}
+
+impl fmt::Display for CaptureToggle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.on {
+            true => writeln!(f, "capturing messages to {}", self.capture_path),
+            false => writeln!(f, "capture off"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inbound => write!(f, "in"),
+            Self::Outbound => write!(f, "out"),
+        }
+    }
+}
+
+// init captures the messages to the storage from now on, if turned on
+pub fn init(storage_path: &Path) {
+    if let Ok(mut path) = STORAGE_PATH.lock() {
+        *path = Some(storage_path.to_path_buf());
+    }
+}
+
+pub fn set_capture(storage_path: &Path, on: bool) -> Result<()> {
+    let on_path = storage_path.join(CAPTURE_ON_FILE_NAME);
+    if on {
+        fs::create_dir_all(storage_path)?;
+        fs::write(on_path, "")?;
+    } else if on_path.exists() {
+        fs::remove_file(on_path)?;
+    }
+
+    Ok(())
+}
+
+pub fn is_capture_on(storage_path: &Path) -> bool {
+    storage_path.join(CAPTURE_ON_FILE_NAME).exists()
+}
+
+// get_capture_line decodes the message of the node into a line of the
+// capture, the raw message is kept when it can't be decoded
+fn get_capture_line(direction: &Direction, node_id: &str, raw_msg: &str) -> String {
+    let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S%.3f");
+    let action = match CommAction::from_namespaced_msg(node_id, raw_msg) {
+        CommAction::Unknown => format!("Unknown({raw_msg:?})"),
+        action => format!("{action:?}"),
+    };
+
+    format!("{timestamp} {direction} {node_id} {action}")
+}
+
+// write_line appends the line to the capture, moving it aside when too big
+fn write_line(capture_path: &Path, line: &str) -> Result<()> {
+    let is_full = fs::metadata(capture_path).is_ok_and(|m| m.len() >= MAX_CAPTURE_BYTES);
+    if is_full {
+        let mut rotated_path = capture_path.as_os_str().to_owned();
+        rotated_path.push(".1");
+        fs::rename(capture_path, rotated_path)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(capture_path)?;
+    writeln!(file, "{line}")?;
+    Ok(())
+}
+
+// record keeps the message sent to / received from the node on the
+// capture, when it is turned on
+pub fn record(direction: Direction, node_id: &str, raw_msg: &str) {
+    let Some(storage_path) = STORAGE_PATH.lock().ok().and_then(|p| p.clone()) else {
+        return;
+    };
+    if !is_capture_on(&storage_path) {
+        return;
+    }
+
+    let line = get_capture_line(&direction, node_id, raw_msg);
+    if let Err(e) = write_line(&storage_path.join(CAPTURE_FILE_NAME), &line) {
+        // NOTE: the capture is for debugging, it never gets in the way
+        eprintln!("unable to capture message: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_capture_line() -> Result<()> {
+        let test_values = [
+            // (direction, raw_msg, expected end)
+            (
+                Direction::Outbound,
+                "9]]::foo",
+                "out 1234 RequestManifest(\"1234\", \"foo\")",
+            ),
+            (
+                Direction::Inbound,
+                "2]]::foo;bar.txt",
+                "in 1234 TargetHasChanged(\"1234\", \"foo\", \"bar.txt\")",
+            ),
+            (Direction::Inbound, "foo", "in 1234 Unknown(\"foo\")"),
+        ];
+
+        for spec in test_values {
+            let line = get_capture_line(&spec.0, "1234", spec.1);
+            assert!(line.ends_with(spec.2), "{line}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_line() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_capture");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let capture_path = dir.join(CAPTURE_FILE_NAME);
+
+        write_line(&capture_path, "foo")?;
+        write_line(&capture_path, "bar")?;
+        assert_eq!(fs::read_to_string(&capture_path)?, "foo\nbar\n");
+
+        // a full capture moves aside
+        let file = OpenOptions::new().append(true).open(&capture_path)?;
+        file.set_len(MAX_CAPTURE_BYTES)?;
+        write_line(&capture_path, "zed")?;
+        assert_eq!(fs::read_to_string(&capture_path)?, "zed\n");
+        assert_eq!(
+            fs::metadata(dir.join(format!("{CAPTURE_FILE_NAME}.1")))?.len(),
+            MAX_CAPTURE_BYTES
+        );
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_capture() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_capture_on");
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(!is_capture_on(&dir));
+        set_capture(&dir, true)?;
+        assert!(is_capture_on(&dir));
+        set_capture(&dir, false)?;
+        assert!(!is_capture_on(&dir));
+        set_capture(&dir, false)?;
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
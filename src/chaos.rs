@@ -0,0 +1,108 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Chaos: failures made up on purpose, so the retries and the reconciles
+// can be tried out before trusting fsy with real data. only a build with
+// the `chaos` feature acts on them, the others ignore it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Chaos {
+    #[serde(default)]
+    pub drop_message: f64, // probability a message sent is lost on the way, unnoticed
+    #[serde(default)]
+    pub kill_connection: f64, // probability the connection of a message is closed before it goes
+    #[serde(default)]
+    pub delay_transfer: f64, // probability a download waits before starting
+    #[serde(default)]
+    pub delay_transfer_millisecs: u64, // how long at most a delayed download waits
+}
+
+impl Chaos {
+    pub fn drops_message(&self) -> bool {
+        happens(self.drop_message)
+    }
+
+    pub fn kills_connection(&self) -> bool {
+        happens(self.kill_connection)
+    }
+
+    // get_transfer_delay retrieves how long the download waits, none if it
+    // goes right away
+    pub fn get_transfer_delay(&self) -> Option<Duration> {
+        if self.delay_transfer_millisecs == 0 || !happens(self.delay_transfer) {
+            return None;
+        }
+
+        let millisecs = rand::random::<u64>() % self.delay_transfer_millisecs + 1;
+        Some(Duration::from_millis(millisecs))
+    }
+
+    // validate checks the probabilities go from 0 to 1
+    pub fn validate(&self) -> Result<()> {
+        let probabilities = [
+            ("drop_message", self.drop_message),
+            ("kill_connection", self.kill_connection),
+            ("delay_transfer", self.delay_transfer),
+        ];
+        for (key, probability) in probabilities {
+            if !(0.0..=1.0).contains(&probability) {
+                bail!("chaos {key} of {probability} needs to be between 0 and 1");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn is_enabled() -> bool {
+    cfg!(feature = "chaos")
+}
+
+#[cfg(feature = "chaos")]
+fn happens(probability: f64) -> bool {
+    probability > 0.0 && rand::random::<f64>() < probability
+}
+
+#[cfg(not(feature = "chaos"))]
+fn happens(_probability: f64) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chaos() -> Result<()> {
+        let test_values = [
+            // (drop message, valid)
+            (0.0, true),
+            (0.5, true),
+            (1.0, true),
+            (1.5, false),
+            (-0.1, false),
+            (f64::NAN, false),
+        ];
+        for spec in test_values {
+            let chaos = Chaos {
+                drop_message: spec.0,
+                ..Default::default()
+            };
+            assert_eq!(chaos.validate().is_ok(), spec.1, "{spec:?}");
+        }
+
+        let chaos = Chaos {
+            drop_message: 1.0,
+            kill_connection: 0.0,
+            delay_transfer: 1.0,
+            delay_transfer_millisecs: 100,
+        };
+        assert!(!chaos.kills_connection());
+        assert_eq!(chaos.drops_message(), is_enabled());
+        let delay = chaos.get_transfer_delay();
+        assert_eq!(delay.is_some(), is_enabled());
+        assert!(delay.is_none_or(|d| d <= Duration::from_millis(100)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::{manifest, target};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Checksum {
+    pub sha256: String,
+    pub relative_path: String,
+}
+
+// Checksums: the sha256 of every file of a group as it is now, shown as
+// `sha256sum` does so copies can be verified without fsy (`sha256sum -c`
+// from the folder of the group)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Checksums {
+    pub group_name: String,
+    pub files: Vec<Checksum>,
+}
+
+impl fmt::Display for Checksums {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for checksum in &self.files {
+            writeln!(f, "{}", get_line(&checksum.sha256, &checksum.relative_path))?;
+        }
+
+        Ok(())
+    }
+}
+
+// get_line retrieves the line of the file as `sha256sum` writes it, a path
+// with a backslash or a line break goes escaped with the line starting by
+// a backslash
+fn get_line(sha256: &str, relative_path: &str) -> String {
+    if !relative_path.contains(['\\', '\n', '\r']) {
+        return format!("{sha256}  {relative_path}");
+    }
+
+    let escaped = relative_path
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\\{sha256}  {escaped}")
+}
+
+pub fn get_file_sha256(file_path: &Path) -> Result<String> {
+    let mut file = File::open(file_path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// get_checksums goes through the files of the group hashing them. a group
+// of a single file has it by its name
+pub fn get_checksums(group: &target::TargetGroup) -> Result<Checksums> {
+    let mut files = vec![];
+    for root in group.get_roots() {
+        let root_path = Path::new(&root.path);
+        if root_path.is_file() {
+            let file_name = root_path.file_name().unwrap_or_default();
+            files.push(Checksum {
+                sha256: get_file_sha256(root_path)?,
+                relative_path: file_name.to_string_lossy().to_string(),
+            });
+            continue;
+        }
+
+        for relative_path in manifest::list_files(root_path)? {
+            files.push(Checksum {
+                sha256: get_file_sha256(&root_path.join(&relative_path))?,
+                relative_path: root.get_group_relative_path(&relative_path),
+            });
+        }
+    }
+
+    files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+    Ok(Checksums {
+        group_name: group.name.to_string(),
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_get_checksums() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_checksum");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a/.fsy"))?;
+        fs::create_dir_all(dir.join("a/sub"))?;
+        fs::write(dir.join("a/abc.txt"), "abc")?;
+        fs::write(dir.join("a/sub/empty.txt"), "")?;
+        fs::write(dir.join("a/.fsy/lock"), "")?;
+
+        let group = target::TargetGroup {
+            name: "docs".into(),
+            path: dir.join("a").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let checksums = get_checksums(&group)?;
+        assert_eq!(
+            checksums.to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  abc.txt\n\
+             e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  sub/empty.txt\n"
+        );
+
+        let group = target::TargetGroup {
+            path: dir.join("a/abc.txt").to_string_lossy().to_string(),
+            ..group
+        };
+        let files = get_checksums(&group)?.files;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].relative_path, "abc.txt");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_line() -> Result<()> {
+        let test_values = [
+            // (path, line)
+            ("a.txt", "abc  a.txt"),
+            ("a b/c.txt", "abc  a b/c.txt"),
+            ("a\\b.txt", "\\abc  a\\\\b.txt"),
+            ("a\nb.txt", "\\abc  a\\nb.txt"),
+        ];
+        for spec in test_values {
+            assert_eq!(get_line("abc", spec.0), spec.1, "{spec:?}");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1063 @@
+use anyhow::{Result, bail};
+use serde::Serialize;
+use std::fmt;
+
+use crate::history;
+use crate::logs::LogLevel;
+
+pub const USAGE: &str = "usage: fsy [command] [flags]
+
+commands:
+  run       starts the daemon (default)
+              --force   takes over a stale lock of the same config,
+                        left by a daemon no longer running
+              --verbose   logs debug lines too, over log_level
+              --quiet     only logs warnings and errors
+  status    shows the status of the running daemon
+              --tag <tag>   only the groups with the tag
+  health    checks the running daemon is not wedged, exiting with an
+            error if it is, for service managers and orchestrators
+              --ready   also checks the nodes can find it
+  id        shows the node id of this environment
+              --qr   renders it as a qr code
+  confirm <group>
+            syncs the changes of a group paused by its safety limits
+  approve [group] [path]
+            pulls the changes of a group of manual approval (or only
+            the one of the path) through the running daemon, shows the
+            ones pending without a group
+  rollback <group>
+            shows the snapshots taken before the pulls of the group,
+            with fs_snapshot_command
+  rollback <group> --to <snapshot>
+            rolls the group back to the snapshot with its
+            fs_rollback_command. run it with the daemon stopped
+  freeze [group] [path]
+            leaves the path (a file or a folder) of the group out of
+            the sync both ways until unfrozen, shows the ones frozen
+            without a group
+  unfreeze <group> <path>
+            syncs the path again from its next change
+  notify <group> [path]
+            lets the pullers of the group know the path (or the whole
+            group) changed, through the running daemon
+  announce [group]
+            lets the pullers of the push groups (or the group) know
+            where they are at, the ones that missed changes catch up
+  diff <group> <node>
+            shows the files of the group ahead, behind or conflicting
+            with the last list of files the node (name or id) sent,
+            without reaching it
+  manifest export <group>
+            outputs the sha256 of every file of the group as a
+            checksum file, to verify copies with `sha256sum -c`
+  logs      shows the logs of the daemon
+              --follow   keeps showing new logs as they come
+  events    follows what the running daemon does as it happens
+            (transfers, peers, errors), for tools built on top of it
+  recent    shows the last paths synced, pulled from and pulled by
+            the nodes, the most recent first
+              --group <group>   only the ones of the group
+              --count <n>       how many, 20 by default
+  pause     pauses the sync of the running daemon until resumed (or
+            restarted)
+  resume    resumes the sync paused with `fsy pause`
+  tray      shows a tray icon with the state of the running daemon,
+            the last paths synced and a toggle to pause it. needs fsy
+            built with the tray feature, on linux
+  config check
+            checks the config for mistakes
+  config example <topology>
+            outputs a commented config of a common setup to adapt:
+            backup (a laptop pushing to a server), backup-server,
+            mesh (three nodes editing the same files) or publish
+            (a node pushing to others that only pull)
+  import syncthing <config.xml>
+            outputs the syncthing folders and devices as fsy config
+  conflicts list
+            shows the conflict copies kept on pulls
+  conflicts resolve <n>
+            resolves the conflict <n> of the list keeping the pulled
+            version, the conflict copy is removed
+              --use-copy   keeps the conflict copy instead
+  key rotate
+            replaces the key of this node, announcing the new node id
+            to the nodes. it is used once all of them confirm
+              --force   uses it right away, nodes that didn't confirm
+                        need the new node id set by hand
+  key show <group>
+            shows the key the nodes of the group share, with the
+            pullers that didn't confirm it yet
+  key rotate <group>
+            makes a new key for the group pushed from here, sent
+            signed to its pullers through the running daemon
+  debug capture <on|off>
+            records every message sent to and received from the nodes
+            on the storage (capture.log), for protocol issues
+  node block <node>
+            drops the messages of the node (name or id) and never sends
+            anything to it, for lost or decommissioned devices
+  node unblock <node>
+            talks to the node again
+  peers pending
+            shows the unknown nodes that contacted this one, with
+            trust_on_first_use
+  peers approve <node id> [name]
+            adds the pending node to the config, named as given
+  storage migrate <path>
+            moves the data of the storage at the path (blobs, partial
+            downloads, state) into the one of the config, after
+            changing storage_path. run it with the daemon stopped
+  seed import <group> <path>
+            records a copy of the group made out of band (a disk
+            carried over) as synced, copying it into the group if it
+            is somewhere else. the sync only transfers what differs
+  tags enable <tag>
+  tags disable <tag>
+            turns the groups with the tag on or off at once, as the
+            work ones on a home machine. applies on the daemon restart
+  promote   turns a standby node into the one pushing its groups,
+            once the node it was the standby of is gone. applies on
+            the daemon restart
+  share <group>
+            outputs a token another node can accept to pull the group
+  accept <token> [path]
+            adds the group of the token to the config, pulling it into
+            the path (~/fsy/<group> by default) from the node sharing it
+
+flags:
+  --json    outputs machine readable json instead of text
+  --profile <name>
+            uses a separate config, identity and storage, so one
+            machine can be part of more than one mesh
+  --config <path>
+            uses the config at the path (FSY_CONFIG also sets it)
+            instead of the one on the user config dir";
+
+// flags that take a value, as `--tag work` or `--tag=work`
+const VALUE_FLAGS: [&str; 4] = ["--tag", "--to", "--group", "--count"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run { force: bool, log: Option<LogLevel> },
+    Status { tag: Option<String> },
+    Health { ready: bool },
+    Logs { follow: bool },
+    Events,
+    Recent { group: Option<String>, count: usize },
+    Pause { paused: bool },
+    Tray,
+    Id { qr: bool },
+    Confirm { group_name: String },
+    ChangesPending,
+    Approve { group: String, path: Option<String> },
+    Rollback { group: String, to: Option<String> },
+    FrozenList,
+    Freeze { group: String, path: String },
+    Unfreeze { group: String, path: String },
+    Notify { group_name: String, path: String },
+    Announce { group_name: Option<String> },
+    Diff { group_name: String, node: String },
+    ManifestExport { group_name: String },
+    ConfigCheck,
+    ConfigExample { topology: String },
+    ImportSyncthing { path: String },
+    ConflictsList,
+    ConflictsResolve { index: usize, use_copy: bool },
+    KeyRotate { force: bool },
+    KeyShow { group_name: String },
+    KeyRotateGroup { group_name: String },
+    DebugCapture { on: bool },
+    NodeBlock { node: String },
+    NodeUnblock { node: String },
+    PeersPending,
+    PeersApprove { id: String, name: Option<String> },
+    SeedImport { group_name: String, path: String },
+    StorageMigrate { from: String },
+    TagsToggle { tag: String, enabled: bool },
+    Promote,
+    Share { group_name: String },
+    Accept { token: String, path: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub json: bool,              // output as json for scripting
+    pub profile: Option<String>, // separate config, identity and storage
+    pub config: Option<String>,  // path of the config, the default one if unset
+}
+
+impl Cli {
+    pub fn from_env() -> Result<Self> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        parse_args(&args)
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Cli> {
+    let mut json = false;
+    let mut profile: Option<String> = None;
+    let mut config: Option<String> = None;
+    let mut flags: Vec<&str> = vec![];
+    let mut flag_values: Vec<(&str, String)> = vec![];
+    let mut positionals: Vec<&str> = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--profile" => match args.next() {
+                Some(name) => profile = Some(get_profile_name(name)?),
+                None => bail!("missing <name> of --profile\n\n{USAGE}"),
+            },
+            flag if flag.starts_with("--profile=") => {
+                profile = Some(get_profile_name(&flag["--profile=".len()..])?);
+            }
+            "--config" => match args.next() {
+                Some(path) => config = Some(path.to_owned()),
+                None => bail!("missing <path> of --config\n\n{USAGE}"),
+            },
+            flag if flag.starts_with("--config=") => match &flag["--config=".len()..] {
+                "" => bail!("missing <path> of --config\n\n{USAGE}"),
+                path => config = Some(path.to_owned()),
+            },
+            flag if VALUE_FLAGS.contains(&flag) => match args.next() {
+                Some(value) => flag_values.push((flag, value.to_owned())),
+                None => bail!("missing value of {flag}\n\n{USAGE}"),
+            },
+            flag if flag.starts_with("--") => match flag.split_once('=') {
+                Some((flag, value)) if VALUE_FLAGS.contains(&flag) => {
+                    flag_values.push((flag, value.to_owned()))
+                }
+                _ => flags.push(flag),
+            },
+            positional => positionals.push(positional),
+        }
+    }
+
+    let command = match positionals.first() {
+        None | Some(&"run") => Command::Run {
+            force: take_flag(&mut flags, "--force"),
+            log: get_log_level(&mut flags)?,
+        },
+        Some(&"status") => Command::Status {
+            tag: take_flag_value(&mut flag_values, "--tag"),
+        },
+        Some(&"health") => Command::Health {
+            ready: take_flag(&mut flags, "--ready"),
+        },
+        Some(&"logs") => Command::Logs {
+            follow: take_flag(&mut flags, "--follow"),
+        },
+        Some(&"events") => Command::Events,
+        Some(&"recent") => Command::Recent {
+            group: take_flag_value(&mut flag_values, "--group"),
+            count: match take_flag_value(&mut flag_values, "--count") {
+                Some(count) => match count.parse::<usize>() {
+                    Ok(count) if count > 0 => count,
+                    _ => bail!("invalid count \"{count}\", use a number over 0"),
+                },
+                None => history::DEFAULT_RECENT_COUNT,
+            },
+        },
+        Some(&"id") => Command::Id {
+            qr: take_flag(&mut flags, "--qr"),
+        },
+        Some(&"confirm") => Command::Confirm {
+            group_name: get_positional(&positionals, 1, "group")?,
+        },
+        Some(&"approve") => match positionals.get(1) {
+            None => Command::ChangesPending,
+            Some(group) => Command::Approve {
+                group: group.to_string(),
+                path: positionals.get(2).map(|p| p.to_string()),
+            },
+        },
+        Some(&"rollback") => Command::Rollback {
+            group: get_positional(&positionals, 1, "group")?,
+            to: take_flag_value(&mut flag_values, "--to"),
+        },
+        Some(&"freeze") => match positionals.get(1) {
+            None => Command::FrozenList,
+            Some(group) => Command::Freeze {
+                group: group.to_string(),
+                path: get_positional(&positionals, 2, "path")?,
+            },
+        },
+        Some(&"unfreeze") => Command::Unfreeze {
+            group: get_positional(&positionals, 1, "group")?,
+            path: get_positional(&positionals, 2, "path")?,
+        },
+        Some(&"notify") => Command::Notify {
+            group_name: get_positional(&positionals, 1, "group")?,
+            path: positionals
+                .get(2)
+                .map(|p| p.to_string())
+                .unwrap_or_default(),
+        },
+        Some(&"pause") => Command::Pause { paused: true },
+        Some(&"resume") => Command::Pause { paused: false },
+        Some(&"tray") => Command::Tray,
+        Some(&"announce") => Command::Announce {
+            group_name: positionals.get(1).map(|p| p.to_string()),
+        },
+        Some(&"diff") => Command::Diff {
+            group_name: get_positional(&positionals, 1, "group")?,
+            node: get_positional(&positionals, 2, "node")?,
+        },
+        Some(&"manifest") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "export" => Command::ManifestExport {
+                group_name: get_positional(&positionals, 2, "group")?,
+            },
+            other => bail!("unknown manifest subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"config") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "check" => Command::ConfigCheck,
+            "example" => Command::ConfigExample {
+                topology: get_positional(&positionals, 2, "topology")?,
+            },
+            other => bail!("unknown config subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"import") => match get_positional(&positionals, 1, "source")?.as_str() {
+            "syncthing" => Command::ImportSyncthing {
+                path: get_positional(&positionals, 2, "config.xml")?,
+            },
+            other => bail!("unknown import source \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"conflicts") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "list" => Command::ConflictsList,
+            "resolve" => {
+                let index = get_positional(&positionals, 2, "n")?;
+                let Ok(index) = index.parse::<usize>() else {
+                    bail!("invalid conflict \"{index}\", use its number on the list");
+                };
+
+                Command::ConflictsResolve {
+                    index,
+                    use_copy: take_flag(&mut flags, "--use-copy"),
+                }
+            }
+            other => bail!("unknown conflicts subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"key") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "show" => Command::KeyShow {
+                group_name: get_positional(&positionals, 2, "group")?,
+            },
+            "rotate" => match positionals.get(2) {
+                None => Command::KeyRotate {
+                    force: take_flag(&mut flags, "--force"),
+                },
+                Some(group) => Command::KeyRotateGroup {
+                    group_name: group.to_string(),
+                },
+            },
+            other => bail!("unknown key subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"debug") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "capture" => match get_positional(&positionals, 2, "on|off")?.as_str() {
+                "on" => Command::DebugCapture { on: true },
+                "off" => Command::DebugCapture { on: false },
+                other => bail!("invalid capture \"{other}\", use on or off"),
+            },
+            other => bail!("unknown debug subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"node") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "block" => Command::NodeBlock {
+                node: get_positional(&positionals, 2, "node")?,
+            },
+            "unblock" => Command::NodeUnblock {
+                node: get_positional(&positionals, 2, "node")?,
+            },
+            other => bail!("unknown node subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"peers") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "pending" => Command::PeersPending,
+            "approve" => Command::PeersApprove {
+                id: get_positional(&positionals, 2, "node id")?,
+                name: positionals.get(3).map(|n| n.to_string()),
+            },
+            other => bail!("unknown peers subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"seed") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "import" => Command::SeedImport {
+                group_name: get_positional(&positionals, 2, "group")?,
+                path: get_positional(&positionals, 3, "path")?,
+            },
+            other => bail!("unknown seed subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"storage") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "migrate" => Command::StorageMigrate {
+                from: get_positional(&positionals, 2, "path")?,
+            },
+            other => bail!("unknown storage subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"tags") => match get_positional(&positionals, 1, "subcommand")?.as_str() {
+            "enable" => Command::TagsToggle {
+                tag: get_positional(&positionals, 2, "tag")?,
+                enabled: true,
+            },
+            "disable" => Command::TagsToggle {
+                tag: get_positional(&positionals, 2, "tag")?,
+                enabled: false,
+            },
+            other => bail!("unknown tags subcommand \"{other}\"\n\n{USAGE}"),
+        },
+        Some(&"promote") => Command::Promote,
+        Some(&"share") => Command::Share {
+            group_name: get_positional(&positionals, 1, "group")?,
+        },
+        Some(&"accept") => Command::Accept {
+            token: get_positional(&positionals, 1, "token")?,
+            path: positionals.get(2).map(|p| p.to_string()),
+        },
+        Some(other) => bail!("unknown command \"{other}\"\n\n{USAGE}"),
+    };
+
+    // every flag should have been taken by the command
+    if let Some(flag) = flags.first().or(flag_values.first().map(|(f, _)| f)) {
+        bail!("unknown flag \"{flag}\"\n\n{USAGE}");
+    }
+
+    Ok(Cli {
+        command,
+        json,
+        profile,
+        config,
+    })
+}
+
+// get_profile_name validates the name, it ends up on paths
+fn get_profile_name(name: &str) -> Result<String> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        bail!("invalid profile \"{name}\", use letters, numbers, `-` and `_`");
+    }
+
+    Ok(name.to_owned())
+}
+
+// get_positional retrieves a required positional argument of a command
+fn get_positional(positionals: &[&str], index: usize, name: &str) -> Result<String> {
+    match positionals.get(index) {
+        Some(value) => Ok(value.to_string()),
+        None => bail!("missing <{name}>\n\n{USAGE}"),
+    }
+}
+
+// get_log_level retrieves the level set by --verbose or --quiet, if any
+fn get_log_level(flags: &mut Vec<&str>) -> Result<Option<LogLevel>> {
+    match (take_flag(flags, "--verbose"), take_flag(flags, "--quiet")) {
+        (true, true) => bail!("--verbose and --quiet can't be used together"),
+        (true, false) => Ok(Some(LogLevel::Debug)),
+        (false, true) => Ok(Some(LogLevel::Warning)),
+        (false, false) => Ok(None),
+    }
+}
+
+// take_flag removes the flag from the list, returning if it was there
+fn take_flag(flags: &mut Vec<&str>, flag: &str) -> bool {
+    let len = flags.len();
+    flags.retain(|f| *f != flag);
+    flags.len() != len
+}
+
+// take_flag_value takes the value of a flag from the ones given, the last
+// one wins if repeated
+fn take_flag_value(flag_values: &mut Vec<(&str, String)>, flag: &str) -> Option<String> {
+    let mut value = None;
+    flag_values.retain(|(f, v)| match *f == flag {
+        true => {
+            value = Some(v.clone());
+            false
+        }
+        false => true,
+    });
+    value
+}
+
+// print_output prints the value either as json or as human text so
+// every command outputs the same way
+pub fn print_output<T: Serialize + fmt::Display>(value: &T, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(value)?);
+    } else {
+        print!("{value}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parse_args() -> Result<()> {
+        let test_values = [
+            // (args, cli)
+            (
+                vec![],
+                Some((
+                    Command::Run {
+                        force: false,
+                        log: None,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["run"],
+                Some((
+                    Command::Run {
+                        force: false,
+                        log: None,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["run", "--force"],
+                Some((
+                    Command::Run {
+                        force: true,
+                        log: None,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["--force"],
+                Some((
+                    Command::Run {
+                        force: true,
+                        log: None,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["run", "--verbose"],
+                Some((
+                    Command::Run {
+                        force: false,
+                        log: Some(LogLevel::Debug),
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["--quiet", "--force"],
+                Some((
+                    Command::Run {
+                        force: true,
+                        log: Some(LogLevel::Warning),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["run", "--verbose", "--quiet"], None),
+            (vec!["status"], Some((Command::Status { tag: None }, false))),
+            (
+                vec!["status", "--json"],
+                Some((Command::Status { tag: None }, true)),
+            ),
+            (
+                vec!["--json", "status"],
+                Some((Command::Status { tag: None }, true)),
+            ),
+            (
+                vec!["status", "--tag", "work"],
+                Some((
+                    Command::Status {
+                        tag: Some("work".to_string()),
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["status", "--tag=work", "--json"],
+                Some((
+                    Command::Status {
+                        tag: Some("work".to_string()),
+                    },
+                    true,
+                )),
+            ),
+            (vec!["status", "--tag"], None),
+            (vec!["logs", "--tag", "work"], None),
+            (
+                vec!["health"],
+                Some((Command::Health { ready: false }, false)),
+            ),
+            (
+                vec!["health", "--ready"],
+                Some((Command::Health { ready: true }, false)),
+            ),
+            (vec!["logs"], Some((Command::Logs { follow: false }, false))),
+            (
+                vec!["logs", "--follow"],
+                Some((Command::Logs { follow: true }, false)),
+            ),
+            (vec!["events"], Some((Command::Events, false))),
+            (
+                vec!["recent"],
+                Some((
+                    Command::Recent {
+                        group: None,
+                        count: history::DEFAULT_RECENT_COUNT,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["recent", "--group", "docs", "--count=5", "--json"],
+                Some((
+                    Command::Recent {
+                        group: Some("docs".to_string()),
+                        count: 5,
+                    },
+                    true,
+                )),
+            ),
+            (vec!["recent", "--count", "none"], None),
+            (vec!["recent", "--count", "0"], None),
+            (vec!["id"], Some((Command::Id { qr: false }, false))),
+            (vec!["id", "--qr"], Some((Command::Id { qr: true }, false))),
+            (
+                vec!["confirm", "foo"],
+                Some((
+                    Command::Confirm {
+                        group_name: "foo".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["confirm"], None),
+            (
+                vec!["rollback", "foo"],
+                Some((
+                    Command::Rollback {
+                        group: "foo".to_string(),
+                        to: None,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["rollback", "foo", "--to", "fsy-foo-20240501102030"],
+                Some((
+                    Command::Rollback {
+                        group: "foo".to_string(),
+                        to: Some("fsy-foo-20240501102030".to_string()),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["rollback", "--to"], None),
+            (vec!["freeze"], Some((Command::FrozenList, false))),
+            (
+                vec!["freeze", "foo", "a/b.txt"],
+                Some((
+                    Command::Freeze {
+                        group: "foo".to_string(),
+                        path: "a/b.txt".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["freeze", "foo"], None),
+            (
+                vec!["unfreeze", "foo", "a"],
+                Some((
+                    Command::Unfreeze {
+                        group: "foo".to_string(),
+                        path: "a".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["unfreeze", "foo"], None),
+            (vec!["approve"], Some((Command::ChangesPending, false))),
+            (
+                vec!["approve", "foo", "a/b.txt"],
+                Some((
+                    Command::Approve {
+                        group: "foo".to_string(),
+                        path: Some("a/b.txt".to_string()),
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["notify", "foo"],
+                Some((
+                    Command::Notify {
+                        group_name: "foo".to_string(),
+                        path: "".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["notify", "foo", "a/b.txt"],
+                Some((
+                    Command::Notify {
+                        group_name: "foo".to_string(),
+                        path: "a/b.txt".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["notify"], None),
+            (
+                vec!["announce"],
+                Some((Command::Announce { group_name: None }, false)),
+            ),
+            (
+                vec!["announce", "foo"],
+                Some((
+                    Command::Announce {
+                        group_name: Some("foo".to_string()),
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["diff", "docs", "laptop"],
+                Some((
+                    Command::Diff {
+                        group_name: "docs".to_string(),
+                        node: "laptop".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["diff", "docs"], None),
+            (
+                vec!["manifest", "export", "docs"],
+                Some((
+                    Command::ManifestExport {
+                        group_name: "docs".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["manifest", "export"], None),
+            (vec!["manifest", "import", "docs"], None),
+            (vec!["config", "check"], Some((Command::ConfigCheck, false))),
+            (
+                vec!["config", "check", "--json"],
+                Some((Command::ConfigCheck, true)),
+            ),
+            (
+                vec!["config", "example", "mesh"],
+                Some((
+                    Command::ConfigExample {
+                        topology: "mesh".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["config", "example"], None),
+            (vec!["config"], None),
+            (vec!["config", "foo"], None),
+            (
+                vec!["import", "syncthing", "config.xml"],
+                Some((
+                    Command::ImportSyncthing {
+                        path: "config.xml".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["import", "syncthing"], None),
+            (vec!["import", "foo", "config.xml"], None),
+            (
+                vec!["conflicts", "list"],
+                Some((Command::ConflictsList, false)),
+            ),
+            (
+                vec!["conflicts", "resolve", "2"],
+                Some((
+                    Command::ConflictsResolve {
+                        index: 2,
+                        use_copy: false,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["conflicts", "resolve", "2", "--use-copy"],
+                Some((
+                    Command::ConflictsResolve {
+                        index: 2,
+                        use_copy: true,
+                    },
+                    false,
+                )),
+            ),
+            (vec!["conflicts", "resolve"], None),
+            (vec!["conflicts", "resolve", "foo"], None),
+            (vec!["conflicts", "list", "--use-copy"], None),
+            (vec!["conflicts"], None),
+            (
+                vec!["key", "rotate"],
+                Some((Command::KeyRotate { force: false }, false)),
+            ),
+            (
+                vec!["key", "rotate", "--force"],
+                Some((Command::KeyRotate { force: true }, false)),
+            ),
+            (vec!["key"], None),
+            (
+                vec!["key", "show", "docs"],
+                Some((
+                    Command::KeyShow {
+                        group_name: "docs".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["key", "show"], None),
+            (
+                vec!["key", "rotate", "docs"],
+                Some((
+                    Command::KeyRotateGroup {
+                        group_name: "docs".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["key", "rotate", "docs", "--force"], None),
+            (
+                vec!["debug", "capture", "on"],
+                Some((Command::DebugCapture { on: true }, false)),
+            ),
+            (
+                vec!["debug", "capture", "off"],
+                Some((Command::DebugCapture { on: false }, false)),
+            ),
+            (vec!["debug", "capture"], None),
+            (vec!["debug", "capture", "foo"], None),
+            (
+                vec!["node", "block", "laptop"],
+                Some((
+                    Command::NodeBlock {
+                        node: "laptop".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["node", "unblock", "laptop"],
+                Some((
+                    Command::NodeUnblock {
+                        node: "laptop".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["node", "block"], None),
+            (vec!["node", "foo", "laptop"], None),
+            (
+                vec!["peers", "pending"],
+                Some((Command::PeersPending, false)),
+            ),
+            (
+                vec!["peers", "approve", "abcd"],
+                Some((
+                    Command::PeersApprove {
+                        id: "abcd".to_string(),
+                        name: None,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["peers", "approve", "abcd", "laptop"],
+                Some((
+                    Command::PeersApprove {
+                        id: "abcd".to_string(),
+                        name: Some("laptop".to_string()),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["peers", "approve"], None),
+            (vec!["peers"], None),
+            (
+                vec!["seed", "import", "docs", "/mnt/disk/docs"],
+                Some((
+                    Command::SeedImport {
+                        group_name: "docs".to_string(),
+                        path: "/mnt/disk/docs".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["seed", "import", "docs"], None),
+            (vec!["seed", "foo", "docs", "/mnt"], None),
+            (
+                vec!["storage", "migrate", "/tmp/fsy_storage"],
+                Some((
+                    Command::StorageMigrate {
+                        from: "/tmp/fsy_storage".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["storage", "migrate"], None),
+            (
+                vec!["tags", "enable", "work"],
+                Some((
+                    Command::TagsToggle {
+                        tag: "work".to_string(),
+                        enabled: true,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["tags", "disable", "work"],
+                Some((
+                    Command::TagsToggle {
+                        tag: "work".to_string(),
+                        enabled: false,
+                    },
+                    false,
+                )),
+            ),
+            (vec!["tags", "disable"], None),
+            (vec!["tags", "foo", "work"], None),
+            (
+                vec!["share", "docs"],
+                Some((
+                    Command::Share {
+                        group_name: "docs".to_string(),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["promote"], Some((Command::Promote, false))),
+            (
+                vec!["pause"],
+                Some((Command::Pause { paused: true }, false)),
+            ),
+            (
+                vec!["resume"],
+                Some((Command::Pause { paused: false }, false)),
+            ),
+            (vec!["tray"], Some((Command::Tray, false))),
+            (vec!["share"], None),
+            (
+                vec!["accept", "abc"],
+                Some((
+                    Command::Accept {
+                        token: "abc".to_string(),
+                        path: None,
+                    },
+                    false,
+                )),
+            ),
+            (
+                vec!["accept", "abc", "/mnt/docs"],
+                Some((
+                    Command::Accept {
+                        token: "abc".to_string(),
+                        path: Some("/mnt/docs".to_string()),
+                    },
+                    false,
+                )),
+            ),
+            (vec!["accept"], None),
+            (vec!["foo"], None),
+            (vec!["status", "--foo"], None),
+            (vec!["status", "--qr"], None),
+        ];
+
+        for spec in test_values {
+            let args: Vec<String> = spec.0.iter().map(|a| a.to_string()).collect();
+            let res = parse_args(&args);
+            match spec.1 {
+                Some((command, json)) => assert_eq!(
+                    res?,
+                    Cli {
+                        command,
+                        json,
+                        profile: None,
+                        config: None,
+                    }
+                ),
+                None => assert!(res.is_err()),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_args_profile() -> Result<()> {
+        let test_values = [
+            // (args, profile)
+            (vec!["status"], Some(None)),
+            (vec!["--profile", "work", "status"], Some(Some("work"))),
+            (vec!["status", "--profile", "work"], Some(Some("work"))),
+            (
+                vec!["status", "--profile=my_work-2"],
+                Some(Some("my_work-2")),
+            ),
+            (vec!["status", "--profile"], None),
+            (vec!["status", "--profile="], None),
+            (vec!["status", "--profile", "../work"], None),
+        ];
+
+        for spec in test_values {
+            let args: Vec<String> = spec.0.iter().map(|a| a.to_string()).collect();
+            let res = parse_args(&args);
+            match spec.1 {
+                Some(profile) => {
+                    let cli = res?;
+                    assert_eq!(cli.command, Command::Status { tag: None });
+                    assert_eq!(cli.profile.as_deref(), profile);
+                }
+                None => assert!(res.is_err()),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_args_config() -> Result<()> {
+        let test_values = [
+            // (args, config)
+            (vec!["status"], Some(None)),
+            (
+                vec!["--config", "/etc/fsy.toml", "status"],
+                Some(Some("/etc/fsy.toml")),
+            ),
+            (vec!["status", "--config=fsy.toml"], Some(Some("fsy.toml"))),
+            (vec!["status", "--config"], None),
+            (vec!["status", "--config="], None),
+        ];
+
+        for spec in test_values {
+            let args: Vec<String> = spec.0.iter().map(|a| a.to_string()).collect();
+            let res = parse_args(&args);
+            match spec.1 {
+                Some(config) => {
+                    let cli = res?;
+                    assert_eq!(cli.command, Command::Status { tag: None });
+                    assert_eq!(cli.config.as_deref(), config);
+                }
+                None => assert!(res.is_err()),
+            }
+        }
+
+        Ok(())
+    }
+}
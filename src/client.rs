@@ -0,0 +1,186 @@
+use anyhow::{Result, bail};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::config;
+use crate::control;
+
+// send_request sends a request to the control api of the daemon through the
+// stream, returning the http code and json body of the response. the token
+// goes along when set, the control api port asks for it
+async fn send_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    token: Option<&str>,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> Result<(u16, serde_json::Value)> {
+    let mut reader = BufReader::new(stream);
+    let authorization = token
+        .map(|token| format!("Authorization: Bearer {token}\r\n"))
+        .unwrap_or_default();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\n{authorization}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    reader.get_mut().write_all(request.as_bytes()).await?;
+    reader.get_mut().flush().await?;
+
+    let (status_line, body) = control::read_message(&mut reader).await?;
+    let code = status_line.split_whitespace().nth(1).map(|c| c.parse::<u16>());
+    let Some(Ok(code)) = code else {
+        bail!("invalid response from the daemon");
+    };
+
+    Ok((code, serde_json::from_str(&body)?))
+}
+
+// read_stream sends a request whose response goes on for as long as the
+// daemon runs, handing each line of its body over as it comes
+async fn read_stream<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    path: &str,
+    mut on_line: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    reader.get_mut().write_all(request.as_bytes()).await?;
+    reader.get_mut().flush().await?;
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    if line.split_whitespace().nth(1) != Some("200") {
+        bail!("invalid response from the daemon");
+    }
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        on_line(line.trim())?;
+    }
+}
+
+// connect reaches the running daemon of the config, through its unix
+// socket or, elsewhere, through the control api port if set
+#[cfg(unix)]
+async fn connect(config: &config::Config) -> Result<tokio::net::UnixStream> {
+    let socket_path = config
+        .get_storage_path()
+        .join(control::CONTROL_SOCKET_FILE_NAME);
+    let Ok(stream) = tokio::net::UnixStream::connect(&socket_path).await else {
+        bail!("unable to reach the daemon, is it running?");
+    };
+    Ok(stream)
+}
+
+#[cfg(not(unix))]
+async fn connect(config: &config::Config) -> Result<tokio::net::TcpStream> {
+    let Some(port) = config.local.api_port else {
+        bail!("unable to reach the daemon, set `api_port` on the config");
+    };
+    let Ok(stream) = tokio::net::TcpStream::connect(("127.0.0.1", port)).await else {
+        bail!("unable to reach the daemon, is it running?");
+    };
+    Ok(stream)
+}
+
+// get_token retrieves the token the control api port asks for, the unix
+// socket needs none
+#[cfg(unix)]
+fn get_token(_config: &config::Config) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn get_token(config: &config::Config) -> Option<String> {
+    let token_path = config
+        .get_storage_path()
+        .join(control::CONTROL_TOKEN_FILE_NAME);
+    let token = std::fs::read_to_string(token_path).ok()?;
+    Some(token.trim().to_owned())
+}
+
+// request talks to the running daemon of the config
+pub async fn request(
+    config: &config::Config,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> Result<(u16, serde_json::Value)> {
+    let token = get_token(config);
+    send_request(connect(config).await?, token.as_deref(), method, path, body).await
+}
+
+// follow hands over each line the daemon streams on the path (`/events`)
+// until it stops
+pub async fn follow(
+    config: &config::Config,
+    path: &str,
+    on_line: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    read_stream(connect(config).await?, path, on_line).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_send_request() -> Result<()> {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let response = "HTTP/1.1 400 Bad Request\r\nContent-Length: 15\r\n\r\n{\"error\":\"foo\"}";
+        server.write_all(response.as_bytes()).await?;
+
+        let (code, body) = send_request(client, Some("abc"), "POST", "/actions", "{}").await?;
+        assert_eq!(code, 400);
+        assert_eq!(body, serde_json::json!({ "error": "foo" }));
+
+        let mut request = vec![0u8; 256];
+        let read = server.read(&mut request).await?;
+        let request = String::from_utf8_lossy(&request[..read]);
+        assert!(request.starts_with("POST /actions HTTP/1.1\r\n"));
+        assert!(request.contains("\r\nAuthorization: Bearer abc\r\n"));
+        assert!(request.ends_with("Content-Length: 2\r\nConnection: close\r\n\r\n{}"));
+
+        Ok(())
+    }
+
+    // serve_once answers the request with the response and closes
+    fn serve_once(response: &'static str) -> tokio::io::DuplexStream {
+        let (client, mut server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let mut request = vec![0u8; 256];
+            let _ = server.read(&mut request).await;
+            let _ = server.write_all(response.as_bytes()).await;
+        });
+        client
+    }
+
+    #[tokio::test]
+    async fn test_read_stream() -> Result<()> {
+        let client =
+            serve_once("HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n{\"a\":1}\n{\"b\":2}\n");
+        let mut lines = vec![];
+        read_stream(client, "/events", |line| {
+            lines.push(line.to_string());
+            Ok(())
+        })
+        .await?;
+        assert_eq!(lines, vec![r#"{"a":1}"#, r#"{"b":2}"#]);
+
+        // the daemon refusing it isn't a stream
+        let client = serve_once("HTTP/1.1 404 Not Found\r\n\r\n");
+        assert!(read_stream(client, "/foo", |_| Ok(())).await.is_err());
+
+        Ok(())
+    }
+}
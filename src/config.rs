@@ -1,12 +1,47 @@
 use crate::{
-    key,
+    chaos::{self, Chaos},
+    connection, key,
+    logs::{self, LogLevel, log_warning},
     target::{NodeData, TargetGroup},
 };
 use anyhow::{Result, bail};
 use serde::{Deserialize, Serialize};
-use std::{env, ffi::OsString, fs, path::Path};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Component, Path, PathBuf},
+};
 
 const CONFIG_FILE_NAME: &str = "fsy/config.toml";
+const PROFILES_DIR_NAME: &str = "fsy/profiles";
+const STORAGE_DIR_NAME: &str = "fsy_storage";
+
+// environment variable with the path of the config, as `--config`
+pub const CONFIG_ENV_NAME: &str = "FSY_CONFIG";
+
+// bounds of the timings, out of them the daemon spins a core or takes too
+// long to notice anything
+const MAX_LOOP_DEBOUNCE_MILLISECS: u64 = 60_000;
+const MAX_PUSH_DEBOUNCE_MILLISECS: u64 = 3_600_000;
+
+// the timings still work past these, but harm the cpu or the latency
+const LOW_LOOP_DEBOUNCE_MILLISECS: u64 = 50;
+const HIGH_LOOP_DEBOUNCE_MILLISECS: u64 = 5_000;
+const HIGH_PUSH_DEBOUNCE_MILLISECS: u64 = 60_000;
+const LOW_MANIFEST_VERIFY_SECS: u64 = 60;
+
+// comments on the timings of a generated config, with their defaults
+const TIMING_COMMENTS: [(&str, &str); 2] = [
+    (
+        "push_debounce_millisecs",
+        "changes are pushed once settled for x ms, 500 by default (0 to 3600000)",
+    ),
+    (
+        "loop_debounce_millisecs",
+        "queue and events are checked every x ms, 250 by default (1 to 60000)",
+    ),
+];
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LocalNodeData {
@@ -14,15 +49,61 @@ pub struct LocalNodeData {
     pub secret_key: [u8; 32],
     pub push_debounce_millisecs: u64,
     pub loop_debounce_millisecs: u64,
+    #[serde(default)]
+    pub scan_files_per_sec: Option<u64>, // throttle of the startup scan, unlimited if unset
+    #[serde(default)]
+    pub api_port: Option<u16>, // local port of the control api, disabled if unset
+    #[serde(default)]
+    pub hook: Option<String>, // command run on daemon events (disk full, ...)
+    #[serde(default)]
+    pub pause_on_battery_below: Option<u8>, // percent of battery the sync waits under, unplugged
+    #[serde(default)]
+    pub pause_on_metered: bool, // the sync waits while on a metered connection
+    #[serde(default)]
+    pub manifest_verify_secs: Option<u64>, // how often the manifests are checked against the disk, hourly if unset
+    #[serde(default)]
+    pub announce_secs: Option<u64>, // how often the push groups are announced to their pullers, hourly if unset
+    #[serde(default)]
+    pub local_only: bool, // no relays and no public discovery, nodes are reached on their addrs
+    #[serde(default)]
+    pub local_port: Option<u16>, // port listened on when local only, any free one if unset
+    #[serde(default)]
+    pub log_level: Option<LogLevel>, // how much the daemon logs, info if unset
+    #[serde(default)]
+    pub log_filters: Vec<String>, // level of single modules, as "connection=debug"
+    #[serde(default)]
+    pub trust_on_first_use: bool, // unknown nodes contacting wait on `fsy peers pending` to be approved
+    #[serde(default)]
+    pub max_message_bytes: Option<usize>, // biggest message taken from a node, 1MB if unset
+    #[serde(default)]
+    pub max_in_flight_bytes: Option<u64>, // bytes of downloads queued at once, 1GB if unset
+    #[serde(default)]
+    pub max_open_files: Option<u64>, // open files the daemon keeps under, the limit of the process if unset
+    #[serde(default)]
+    pub max_pending_changes: Option<usize>, // changes of the watcher kept in memory, 100000 if unset
+    #[serde(default)]
+    pub purge_blobs: bool, // the blob store keeps copies only as long as needed, wiped on start
+    #[serde(default)]
+    pub storage_path: Option<String>, // where the daemon keeps its data, on the state dir if unset
+    #[serde(default)]
+    pub standby: bool, // every group is only pulled, mirrored and archived, until `fsy promote`
+    #[serde(default)]
+    pub standby_versions: Option<usize>, // old versions of each file a standby keeps, 10 if unset
+    #[serde(default)]
+    pub chaos: Option<Chaos>, // failures made up on purpose, only with the `chaos` feature
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(skip)]
     pub config_path: OsString,
+    #[serde(skip)]
+    pub profile: Option<String>, // each profile has its own config, identity and storage
     pub local: LocalNodeData,
     pub nodes: Vec<NodeData>,
     pub target_groups: Vec<TargetGroup>,
+    #[serde(default)]
+    pub blocked_nodes: Vec<String>, // node ids never talked to, see `fsy node block`
 }
 
 impl Default for Config {
@@ -31,37 +112,93 @@ impl Default for Config {
 
         Self {
             config_path: ".config".into(),
+            profile: None,
             local: LocalNodeData {
                 public_key: raw_secret_key.public().to_string(),
                 secret_key: raw_secret_key.secret().to_bytes(),
                 push_debounce_millisecs: 500,
                 loop_debounce_millisecs: 250,
+                scan_files_per_sec: None,
+                api_port: None,
+                hook: None,
+                pause_on_battery_below: None,
+                pause_on_metered: false,
+                manifest_verify_secs: None,
+                announce_secs: None,
+                local_only: false,
+                local_port: None,
+                log_level: None,
+                log_filters: vec![],
+                trust_on_first_use: false,
+                max_message_bytes: None,
+                max_in_flight_bytes: None,
+                max_open_files: None,
+                max_pending_changes: None,
+                purge_blobs: false,
+                storage_path: None,
+                standby: false,
+                standby_versions: None,
+                chaos: None,
             },
             nodes: vec![],
             target_groups: vec![],
+            blocked_nodes: vec![],
         }
     }
 }
 
 impl Config {
-    pub fn new(user_relative_path: &str) -> Result<Self> {
-        let config_path = get_config_path(user_relative_path).unwrap();
+    pub fn new(
+        user_relative_path: &str,
+        profile: Option<&str>,
+        explicit_path: Option<&str>,
+    ) -> Result<Self> {
+        let config_path = find_config_path(user_relative_path, profile, explicit_path)?;
+        let is_explicit = get_explicit_config_path(explicit_path).is_some();
+        let profile_name = profile;
+        let profile = profile.map(|p| p.to_owned());
 
         // create the file if not there
-        if !fs::exists(&config_path).unwrap() {
-            let s = Self {
+        if !Path::new(&config_path).exists() {
+            let mut conf = Self {
                 config_path,
+                profile,
                 ..Default::default()
             };
 
-            return save_config(s);
+            // NOTE: the user config dir can be read only (corporate images,
+            //       live cds), the config goes on the runtime dir then. the
+            //       reports go on stderr so the output of the command stays
+            //       as it is
+            if let Err(e) = save_config(&conf) {
+                let fallback = get_fallback_config_path(profile_name).filter(|_| !is_explicit);
+                let Some(fallback) = fallback else {
+                    bail!("{e}, set {CONFIG_ENV_NAME} or --config to a path that can be written");
+                };
+
+                eprintln!("config: {e}, writing it on {} instead", fallback.display());
+                conf.config_path = fallback.into_os_string();
+                save_config(&conf)?;
+            }
+            eprintln!(
+                "config: created on {}",
+                Path::new(&conf.config_path).display()
+            );
+
+            return Ok(conf);
         }
 
         // read the file now
-        let content = fs::read_to_string(&config_path).unwrap();
-        let mut parsed: Config = toml::from_str(&content).unwrap();
+        let Ok(content) = fs::read_to_string(&config_path) else {
+            bail!(
+                "unable to read the config at {}",
+                Path::new(&config_path).display()
+            );
+        };
+        let mut parsed: Config = toml::from_str(&content)?;
         // update with the path since we are not serializing it into the file
         parsed.config_path = config_path;
+        parsed.profile = profile;
 
         // NOTE: we regenerate then so we can use for testing for example
         //       only check if config exists because we are already generating
@@ -78,14 +215,85 @@ impl Config {
             parsed.local.secret_key = raw_secret_key.secret().to_bytes();
         }
 
+        // resolve the target paths so the watcher and the transfers always
+        // deal with absolute paths, no matter where the daemon started
+        let config_dir = Path::new(&parsed.config_path)
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
+        for group in parsed.target_groups.iter_mut() {
+            if group.paths.is_empty() {
+                group.path = resolve_path(&group.path, &config_dir)?;
+            }
+            for path in group.paths.iter_mut() {
+                *path = resolve_path(path, &config_dir)?;
+            }
+        }
+        if let Some(storage_path) = parsed.local.storage_path.as_mut() {
+            *storage_path = resolve_path(storage_path, &config_dir)?;
+        }
+
         // make sure the configuration is valid
-        validate_config(&parsed)?;
+        for warning in validate_config(&parsed)? {
+            log_warning!("config: {warning}");
+        }
 
         Ok(parsed)
     }
 }
 
-fn validate_config(conf: &Config) -> Result<()> {
+impl Config {
+    // get_storage_path is where the daemon keeps its data (blobs, status, ...)
+    pub fn get_storage_path(&self) -> PathBuf {
+        match &self.local.storage_path {
+            Some(storage_path) => PathBuf::from(storage_path),
+            None => self.get_default_storage_path(),
+        }
+    }
+
+    // get_default_storage_path is where the data is kept without a
+    // `storage_path`, on the state dir of the user
+    pub fn get_default_storage_path(&self) -> PathBuf {
+        get_state_dir().join(self.get_storage_dir_name())
+    }
+
+    // get_legacy_storage_path is where older versions kept the data without
+    // a `storage_path`, on the temp dir other users can write to as well
+    pub fn get_legacy_storage_path(&self) -> PathBuf {
+        env::temp_dir().join(self.get_storage_dir_name())
+    }
+
+    fn get_storage_dir_name(&self) -> String {
+        match &self.profile {
+            Some(profile) => format!("{STORAGE_DIR_NAME}_{profile}"),
+            None => STORAGE_DIR_NAME.to_owned(),
+        }
+    }
+}
+
+// get_state_dir retrieves where the data of the user goes: XDG_STATE_HOME,
+// `~/.local/state` or, on windows, LOCALAPPDATA. the temp dir only without
+// any of them, the storage on it is checked to be the user's on start
+fn get_state_dir() -> PathBuf {
+    let is_absolute = |p: &OsString| Path::new(p).is_absolute();
+    if let Some(dir) = env::var_os("XDG_STATE_HOME").filter(is_absolute) {
+        return PathBuf::from(dir);
+    }
+    if cfg!(windows)
+        && let Some(dir) = env::var_os("LOCALAPPDATA").filter(is_absolute)
+    {
+        return PathBuf::from(dir);
+    }
+
+    match env::var_os("HOME").filter(is_absolute) {
+        Some(home) => Path::new(&home).join(".local").join("state"),
+        None => env::temp_dir(),
+    }
+}
+
+// validate_config fails on what the daemon can't run with, retrieving the
+// warnings of what it runs badly with
+pub fn validate_config(conf: &Config) -> Result<Vec<String>> {
     // node names need to be unique
     for node_a in &conf.nodes {
         for node_b in &conf.nodes {
@@ -100,7 +308,9 @@ fn validate_config(conf: &Config) -> Result<()> {
     // target names need to be unique
     for target_a in &conf.target_groups {
         for target_b in &conf.target_groups {
-            if target_a.path == target_b.path || target_a.name != target_b.name {
+            if (target_a.path == target_b.path && target_a.paths == target_b.paths)
+                || target_a.name != target_b.name
+            {
                 continue;
             }
 
@@ -108,11 +318,121 @@ fn validate_config(conf: &Config) -> Result<()> {
         }
     }
 
-    Ok(())
+    // a group is on a path or spans many, never both
+    for group in &conf.target_groups {
+        if group.path.is_empty() == group.paths.is_empty() {
+            bail!("group \"{}\" needs either a path or paths", group.name);
+        }
+        if !group.paths.is_empty() && group.serve_http {
+            bail!(
+                "group \"{}\" spans many paths, it can't be served over http",
+                group.name
+            );
+        }
+    }
+
+    logs::parse_filters(&conf.local.log_filters)?;
+
+    let max_message_bytes = conf.local.max_message_bytes.unwrap_or(1);
+    if max_message_bytes == 0 || max_message_bytes > connection::MAX_MESSAGE_BYTES {
+        bail!(
+            "max_message_bytes needs to be between 1 and {}",
+            connection::MAX_MESSAGE_BYTES
+        );
+    }
+
+    let max_versions = conf.target_groups.iter().map(|g| g.max_versions);
+    if std::iter::once(conf.local.standby_versions)
+        .chain(max_versions)
+        .any(|v| v == Some(0))
+    {
+        bail!("standby_versions and max_versions need to be 1 or more");
+    }
+
+    let mut warnings = validate_timings(conf)?;
+    if let Some(chaos) = &conf.local.chaos {
+        chaos.validate()?;
+        warnings.push(match chaos::is_enabled() {
+            true => "chaos is on, messages and transfers fail on purpose".to_owned(),
+            false => "chaos is ignored, fsy was built without the chaos feature".to_owned(),
+        });
+    }
+
+    Ok(warnings)
+}
+
+// validate_timings checks the timings of the loops are within bounds,
+// retrieving warnings for the ones that spin the cpu or make the sync slow
+fn validate_timings(conf: &Config) -> Result<Vec<String>> {
+    let mut warnings = vec![];
+
+    let loop_millis = conf.local.loop_debounce_millisecs;
+    if loop_millis == 0 || loop_millis > MAX_LOOP_DEBOUNCE_MILLISECS {
+        bail!("loop_debounce_millisecs needs to be between 1 and {MAX_LOOP_DEBOUNCE_MILLISECS}");
+    }
+    if loop_millis < LOW_LOOP_DEBOUNCE_MILLISECS {
+        warnings.push(format!(
+            "loop_debounce_millisecs of {loop_millis} keeps the cpu busy, {LOW_LOOP_DEBOUNCE_MILLISECS} or more is advised"
+        ));
+    }
+    if loop_millis > HIGH_LOOP_DEBOUNCE_MILLISECS {
+        warnings.push(format!(
+            "loop_debounce_millisecs of {loop_millis} delays every transfer, {HIGH_LOOP_DEBOUNCE_MILLISECS} or less is advised"
+        ));
+    }
+
+    // the groups can have their own push debounce
+    let push_debounces = std::iter::once(("local", conf.local.push_debounce_millisecs)).chain(
+        conf.target_groups
+            .iter()
+            .filter_map(|g| Some((g.name.as_str(), g.push_debounce_millisecs?))),
+    );
+    for (name, push_millis) in push_debounces {
+        if push_millis > MAX_PUSH_DEBOUNCE_MILLISECS {
+            bail!(
+                "push_debounce_millisecs of {name} needs to be {MAX_PUSH_DEBOUNCE_MILLISECS} or less"
+            );
+        }
+        if push_millis > HIGH_PUSH_DEBOUNCE_MILLISECS {
+            warnings.push(format!(
+                "push_debounce_millisecs of {name} is {push_millis}, changes wait that long to be pushed"
+            ));
+        }
+    }
+
+    // NOTE: 0 only verifies on startup
+    if let Some(verify_secs) = conf.local.manifest_verify_secs
+        && verify_secs > 0
+        && verify_secs < LOW_MANIFEST_VERIFY_SECS
+    {
+        warnings.push(format!(
+            "manifest_verify_secs of {verify_secs} goes through the groups constantly, {LOW_MANIFEST_VERIFY_SECS} or more is advised"
+        ));
+    }
+
+    Ok(warnings)
+}
+
+// comment_timings adds what the timings do and their defaults to a
+// generated config
+fn comment_timings(content: &str) -> String {
+    content
+        .lines()
+        .map(|line| {
+            let comment = TIMING_COMMENTS
+                .iter()
+                .find(|(key, _)| line.starts_with(&format!("{key} = ")));
+            match comment {
+                Some((_, comment)) => format!("{line} # {comment}\n"),
+                None => format!("{line}\n"),
+            }
+        })
+        .collect()
 }
 
-fn save_config(conf: Config) -> Result<Config> {
-    let dir_name = match std::path::Path::new(&conf.config_path).parent() {
+fn save_config(conf: &Config) -> Result<()> {
+    let config_path = Path::new(&conf.config_path);
+    let dir_name = match config_path.parent() {
         Some(p) => p,
         None => {
             bail!("unable to get parent")
@@ -120,26 +440,131 @@ fn save_config(conf: Config) -> Result<Config> {
     };
 
     // make sure all directories are created
-    if let Err(_e) = std::fs::create_dir_all(dir_name) {
-        bail!("unable to create all dirs")
+    if let Err(e) = std::fs::create_dir_all(dir_name) {
+        bail!("unable to create {}: {e}", dir_name.display())
     }
 
-    let config_content = match toml::to_string(&conf) {
-        Ok(c) => c,
+    let config_content = match toml::to_string(conf) {
+        Ok(c) => comment_timings(&c),
         Err(_e) => {
             bail!("unable to change config to toml string")
         }
     };
 
     // write the config now
-    if let Err(_e) = std::fs::write(&conf.config_path, config_content) {
-        bail!("unable to write config file")
+    if let Err(e) = std::fs::write(config_path, config_content) {
+        bail!(
+            "unable to write the config at {}: {e}",
+            config_path.display()
+        )
     }
 
-    Ok(conf)
+    Ok(())
+}
+
+// resolve_path expands the home (`~`) and makes relative paths relative to
+// the base dir (the config dir), canonicalizing if the path already exists
+pub fn resolve_path(raw_path: &str, base_dir: &Path) -> Result<String> {
+    let home = env::var_os("HOME");
+    let expanded = match (raw_path.strip_prefix("~"), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            Path::new(&home).join(rest.trim_start_matches('/'))
+        }
+        (Some(_), None) => bail!("unable to expand \"{raw_path}\", there is no home"),
+        _ => PathBuf::from(raw_path),
+    };
+
+    let absolute = if expanded.is_absolute() {
+        expanded
+    } else {
+        base_dir.join(expanded)
+    };
+
+    // NOTE: the path might not exist yet, a pull might create it later
+    //       in that case, we only clean up the `.` and `..`
+    let resolved = match fs::canonicalize(&absolute) {
+        Ok(p) => p,
+        Err(_e) => absolute
+            .components()
+            .fold(PathBuf::new(), |mut acc, component| {
+                match component {
+                    Component::CurDir => {}
+                    Component::ParentDir => {
+                        acc.pop();
+                    }
+                    _ => acc.push(component),
+                }
+                acc
+            }),
+    };
+
+    Ok(resolved.to_string_lossy().to_string())
+}
+
+// find_config_path retrieves the config to use, the one of
+// `get_config_path` or, when it isn't there, the one written on the runtime
+// dir by a run that found the user config dir read only
+pub fn find_config_path(
+    user_relative_path: &str,
+    profile: Option<&str>,
+    explicit_path: Option<&str>,
+) -> Result<OsString> {
+    let config_path = get_config_path(user_relative_path, profile, explicit_path)?;
+    if get_explicit_config_path(explicit_path).is_some() || Path::new(&config_path).exists() {
+        return Ok(config_path);
+    }
+
+    match get_fallback_config_path(profile) {
+        Some(fallback) if fallback.exists() => Ok(fallback.into_os_string()),
+        _ => Ok(config_path),
+    }
 }
 
-fn get_config_path(user_relative_path: &str) -> Result<OsString> {
+// get_fallback_config_path retrieves where the config goes when the user
+// config dir can't be written, on the runtime dir. it has the key of the
+// node, so a dir other users can read (the temp dir) is never one
+pub fn get_fallback_config_path(profile: Option<&str>) -> Option<PathBuf> {
+    let dir = env::var_os("XDG_RUNTIME_DIR").filter(|p| Path::new(p).is_absolute())?;
+    Some(Path::new(&dir).join(get_config_file(profile)))
+}
+
+fn get_explicit_config_path(explicit_path: Option<&str>) -> Option<OsString> {
+    explicit_path
+        .map(OsString::from)
+        .or_else(|| env::var_os(CONFIG_ENV_NAME))
+        .filter(|p| !p.is_empty())
+}
+
+fn get_config_file(profile: Option<&str>) -> PathBuf {
+    match profile {
+        Some(profile) => Path::new(PROFILES_DIR_NAME)
+            .join(profile)
+            .join("config.toml"),
+        None => PathBuf::from(CONFIG_FILE_NAME),
+    }
+}
+
+// get_config_path retrieves where the config is. an explicit one (`--config`
+// or FSY_CONFIG) is used as it is so containers and nix can place it where
+// they want, otherwise it goes on the user config dir
+pub fn get_config_path(
+    user_relative_path: &str,
+    profile: Option<&str>,
+    explicit_path: Option<&str>,
+) -> Result<OsString> {
+    if let Some(explicit_path) = get_explicit_config_path(explicit_path) {
+        return Ok(std::path::absolute(explicit_path)?.into_os_string());
+    }
+
+    let config_file = get_config_file(profile);
+
+    // XDG_CONFIG_HOME takes the place of `~/.config`, a relative one is
+    // ignored as the spec says
+    let xdg_config_home = env::var_os("XDG_CONFIG_HOME").filter(|p| Path::new(p).is_absolute());
+    if let (true, Some(dir)) = (user_relative_path.is_empty(), xdg_config_home) {
+        return Ok(Path::new(&dir).join(&config_file).into_os_string());
+    }
+
     // being empty we want to create our own config
     let mut user_path = user_relative_path;
     if user_path.is_empty() {
@@ -150,7 +575,7 @@ fn get_config_path(user_relative_path: &str) -> Result<OsString> {
         // handle home case
         Some(p) => Ok(Path::new(&p)
             .join(user_path)
-            .join(CONFIG_FILE_NAME)
+            .join(&config_file)
             .into_os_string()),
 
         // handle case where there isn't an home
@@ -160,7 +585,7 @@ fn get_config_path(user_relative_path: &str) -> Result<OsString> {
                 .parent()
                 .unwrap()
                 .join(user_path)
-                .join(CONFIG_FILE_NAME)
+                .join(&config_file)
                 .into_os_string();
 
             Ok(res)
@@ -172,13 +597,123 @@ fn get_config_path(user_relative_path: &str) -> Result<OsString> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_comment_timings() -> Result<()> {
+        let content = toml::to_string(&Config::default())?;
+        let commented = comment_timings(&content);
+        assert!(commented.contains("loop_debounce_millisecs = 250 # queue and events"));
+        assert!(commented.contains("push_debounce_millisecs = 500 # changes are pushed"));
+
+        // still the same config
+        let parsed: Config = toml::from_str(&commented)?;
+        assert_eq!(toml::to_string(&parsed)?, content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_config() -> Result<()> {
+        let dir = env::temp_dir().join("fsy_test_save_config");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        // a dir that can't be created fails as a read only one does
+        fs::write(dir.join("fsy"), "")?;
+        let mut conf = Config {
+            config_path: dir.join("fsy/config.toml").into_os_string(),
+            ..Default::default()
+        };
+        let e = save_config(&conf).unwrap_err();
+        assert!(e.to_string().starts_with("unable to create"), "{e}");
+
+        conf.config_path = dir.join("config.toml").into_os_string();
+        save_config(&conf)?;
+        assert!(dir.join("config.toml").exists());
+
+        // an explicit config is the one, there or not
+        let explicit = dir.join("other.toml");
+        let found = find_config_path("", None, explicit.to_str())?;
+        assert_eq!(found, explicit.into_os_string());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
     #[test]
     fn test_get_config_path() -> Result<()> {
         let user_relative_path = "test_user_relative_path";
-        let res = get_config_path(user_relative_path)?;
+        let res = get_config_path(user_relative_path, None, None)?;
         let res_str = res.into_string().unwrap();
 
         assert!(&res_str.contains(user_relative_path));
         Ok(())
     }
+
+    #[test]
+    fn test_get_config_path_profile() -> Result<()> {
+        let default = get_config_path("", None, None)?;
+        let work = get_config_path("", Some("work"), None)?;
+        let home = get_config_path("", Some("home"), None)?;
+
+        assert_ne!(default, work);
+        assert_ne!(work, home);
+        assert!(Path::new(&work).ends_with("fsy/profiles/work/config.toml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_config_path_explicit() -> Result<()> {
+        let test_values = [
+            // (explicit path, profile, expected)
+            (
+                "/etc/fsy/config.toml",
+                None,
+                "/etc/fsy/config.toml".to_string(),
+            ),
+            (
+                "/etc/fsy/config.toml",
+                Some("work"),
+                "/etc/fsy/config.toml".to_string(),
+            ),
+            (
+                "fsy.toml",
+                None,
+                env::current_dir()?.join("fsy.toml").to_string_lossy().to_string(),
+            ),
+        ];
+
+        for spec in test_values {
+            let res = get_config_path("", spec.1, Some(spec.0))?;
+            assert_eq!(res, OsString::from(&spec.2), "{}", spec.0);
+        }
+
+        // empty is the same as not set
+        assert_eq!(
+            get_config_path("", None, Some(""))?,
+            get_config_path("", None, None)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_path() -> Result<()> {
+        let home = env::var("HOME")?;
+        let test_values = [
+            // (raw_path, base_dir, expected)
+            ("/foo/bar", "/base", "/foo/bar".to_string()),
+            ("~", "/base", home.clone()),
+            ("~/foo/bar", "/base", format!("{home}/foo/bar")),
+            ("foo/bar", "/base", "/base/foo/bar".to_string()),
+            ("./foo", "/base", "/base/foo".to_string()),
+            ("../foo", "/base/zed", "/base/foo".to_string()),
+            ("~foo", "/base", "/base/~foo".to_string()),
+        ];
+
+        for spec in test_values {
+            let res = resolve_path(spec.0, Path::new(spec.1))?;
+            assert_eq!(res, spec.2, "{}", spec.0);
+        }
+
+        Ok(())
+    }
 }
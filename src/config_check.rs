@@ -0,0 +1,685 @@
+use iroh::SecretKey;
+use serde::Serialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config};
+use crate::permissions;
+use crate::target::TargetMode;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum Severity {
+    #[serde(rename = "error")]
+    Error, // the daemon won't run or won't do what is expected
+    #[serde(rename = "warning")]
+    Warning, // most likely a mistake
+    #[serde(rename = "info")]
+    Info, // valid but worth knowing
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+// ConfigReport: findings of checking a configuration
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ConfigReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ConfigReport {
+    pub fn add(&mut self, severity: Severity, message: String) {
+        self.findings.push(Finding { severity, message });
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}: {}", finding.severity, finding.message)?;
+        }
+
+        writeln!(
+            f,
+            "{} errors, {} warnings, {} info",
+            self.count(Severity::Error),
+            self.count(Severity::Warning),
+            self.count(Severity::Info)
+        )
+    }
+}
+
+// check_config goes through the raw configuration reporting everything
+// that looks wrong instead of stopping at the first problem
+pub fn check_config(content: &str, config_dir: &Path) -> ConfigReport {
+    let mut report = ConfigReport::default();
+
+    let mut unknown_keys = vec![];
+    let deserializer = toml::Deserializer::new(content);
+    let parsed: Result<Config, _> =
+        serde_ignored::deserialize(deserializer, |path| unknown_keys.push(path.to_string()));
+    let conf = match parsed {
+        Ok(conf) => conf,
+        Err(e) => {
+            report.add(Severity::Error, format!("unable to parse the config: {e}"));
+            return report;
+        }
+    };
+
+    for key in unknown_keys {
+        report.add(
+            Severity::Warning,
+            format!("unknown key \"{key}\" is ignored"),
+        );
+    }
+
+    match config::validate_config(&conf) {
+        Ok(warnings) => warnings
+            .into_iter()
+            .for_each(|warning| report.add(Severity::Warning, warning)),
+        Err(e) => report.add(Severity::Error, e.to_string()),
+    }
+
+    let public_key = SecretKey::from_bytes(&conf.local.secret_key).public();
+    if public_key.to_string() != conf.local.public_key {
+        report.add(
+            Severity::Error,
+            "local public_key doesn't match the secret_key".to_string(),
+        );
+    }
+
+    check_nodes(&conf, &mut report);
+    check_groups(&conf, &mut report);
+    check_group_paths(&conf, config_dir, &mut report);
+
+    report
+}
+
+fn check_nodes(conf: &Config, report: &mut ConfigReport) {
+    for node in &conf.nodes {
+        let is_referenced = conf
+            .target_groups
+            .iter()
+            .any(|g| g.targets.iter().any(|t| t.node_name == node.name));
+        if !is_referenced {
+            report.add(
+                Severity::Warning,
+                format!("node \"{}\" isn't used by any target group", node.name),
+            );
+        }
+
+        let invalid_addrs = node
+            .addrs
+            .iter()
+            .filter(|addr| addr.parse::<SocketAddr>().is_err());
+        for addr in invalid_addrs {
+            report.add(
+                Severity::Error,
+                format!(
+                    "node \"{}\" addr \"{addr}\" is invalid, use an address as \"192.168.1.10:7070\"",
+                    node.name
+                ),
+            );
+        }
+    }
+
+    // without discovery, nodes are only found on their addresses
+    if conf.local.local_only {
+        for node in conf.nodes.iter().filter(|n| n.addrs.is_empty()) {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "node \"{}\" has no addrs, it can't be reached while local only",
+                    node.name
+                ),
+            );
+        }
+    }
+}
+
+fn check_groups(conf: &Config, report: &mut ConfigReport) {
+    for group in &conf.target_groups {
+        if group.targets.is_empty() {
+            report.add(
+                Severity::Warning,
+                format!("group \"{}\" has no targets, nothing syncs", group.name),
+            );
+            continue;
+        }
+
+        for target in &group.targets {
+            if !conf.nodes.iter().any(|n| n.name == target.node_name) {
+                report.add(
+                    Severity::Error,
+                    format!(
+                        "group \"{}\" targets unknown node \"{}\"",
+                        group.name, target.node_name
+                    ),
+                );
+            }
+        }
+
+        // a node listed more than once gets the changes more than once
+        let mut node_names: Vec<&str> = vec![];
+        for target in &group.targets {
+            if node_names.contains(&target.node_name.as_str()) {
+                report.add(
+                    Severity::Warning,
+                    format!(
+                        "group \"{}\" lists node \"{}\" more than once, use a single push-pull target",
+                        group.name, target.node_name
+                    ),
+                );
+            }
+            node_names.push(&target.node_name);
+        }
+
+        // what a node pulls from us is up to its config
+        for target in &group.targets {
+            if !target.include.is_empty() && target.mode == TargetMode::Pull {
+                report.add(
+                    Severity::Warning,
+                    format!(
+                        "group \"{}\" includes paths for node \"{}\" but only pulls from it, include is ignored",
+                        group.name, target.node_name
+                    ),
+                );
+            }
+        }
+
+        if group.mirror && !group.is_mirror() {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "group \"{}\" is a mirror but also pushes, mirror is ignored",
+                    group.name
+                ),
+            );
+        }
+
+        if group.archive && !group.is_archive() {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "group \"{}\" is an archive but also pushes, archive is ignored",
+                    group.name
+                ),
+            );
+        }
+
+        let is_pull_only = group.targets.iter().all(|t| t.mode == TargetMode::Pull);
+        if group.snapshot && is_pull_only {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "group \"{}\" takes snapshots but only pulls, snapshot is ignored",
+                    group.name
+                ),
+            );
+        }
+
+        if group.max_parallel_actions == Some(0) {
+            report.add(
+                Severity::Warning,
+                format!("group \"{}\" runs 0 actions at once, 1 is used", group.name),
+            );
+        }
+
+        // tags go on `fsy status --tag` and the control api query
+        let invalid_tags = group.tags.iter().filter(|tag| {
+            tag.is_empty()
+                || !tag
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        });
+        for tag in invalid_tags {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "group \"{}\" has tag \"{tag}\", use letters, numbers, `-` and `_` to filter by it",
+                    group.name
+                ),
+            );
+        }
+
+        // the mapping goes on the files as they are pulled
+        for (key, value) in [("chmod", &group.chmod), ("umask", &group.umask)] {
+            if let Some(Err(e)) = value.as_deref().map(permissions::parse_mode) {
+                report.add(
+                    Severity::Error,
+                    format!("group \"{}\" {key} is invalid: {e}", group.name),
+                );
+            }
+        }
+        if let Some(Err(e)) = group.chown.as_deref().map(permissions::parse_owner) {
+            report.add(
+                Severity::Error,
+                format!("group \"{}\" chown is invalid: {e}", group.name),
+            );
+        }
+
+        let has_permissions =
+            group.chown.is_some() || group.chmod.is_some() || group.umask.is_some();
+        let is_push_only = group.targets.iter().all(|t| t.mode == TargetMode::Push);
+        if has_permissions && is_push_only {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "group \"{}\" maps permissions but only pushes, chown, chmod and umask are ignored",
+                    group.name
+                ),
+            );
+        }
+
+        if group.push_debounce_millisecs.is_some() && is_pull_only {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "group \"{}\" has a push debounce but only pulls, push_debounce_millisecs is ignored",
+                    group.name
+                ),
+            );
+        }
+
+        if group.delete_grace_secs.is_some() && is_pull_only {
+            report.add(
+                Severity::Warning,
+                format!(
+                    "group \"{}\" has a delete grace but only pulls, delete_grace_secs is ignored",
+                    group.name
+                ),
+            );
+        }
+
+        // NOTE: the watcher can't follow a path changing with the date
+        if group.is_dated() && !is_pull_only {
+            report.add(
+                Severity::Error,
+                format!(
+                    "group \"{}\" path has a date but the group pushes, only pull groups can have dated paths",
+                    group.name
+                ),
+            );
+        }
+
+        if group.serve_http {
+            match &group.http_bind {
+                None => report.add(
+                    Severity::Error,
+                    format!(
+                        "group \"{}\" is served over http but has no http_bind",
+                        group.name
+                    ),
+                ),
+                Some(bind) if bind.parse::<SocketAddr>().is_err() => report.add(
+                    Severity::Error,
+                    format!(
+                        "group \"{}\" http_bind \"{bind}\" is invalid, use an address as \"0.0.0.0:8080\"",
+                        group.name
+                    ),
+                ),
+                Some(_) => {}
+            }
+        }
+
+        if is_pull_only {
+            report.add(
+                Severity::Info,
+                format!(
+                    "group \"{}\" only pulls, local changes to it are never pushed",
+                    group.name
+                ),
+            );
+        }
+    }
+
+    // a single group can be served on an address
+    let mut binds: Vec<(&str, &str)> = vec![];
+    for group in conf.target_groups.iter().filter(|g| g.serve_http) {
+        let Some(bind) = group.http_bind.as_deref() else {
+            continue;
+        };
+        if let Some((name, _)) = binds.iter().find(|(_, b)| *b == bind) {
+            report.add(
+                Severity::Error,
+                format!(
+                    "groups \"{name}\" and \"{}\" are served on the same http_bind",
+                    group.name
+                ),
+            );
+        }
+        binds.push((&group.name, bind));
+    }
+}
+
+fn check_group_paths(conf: &Config, config_dir: &Path, report: &mut ConfigReport) {
+    let mut paths: Vec<(&str, PathBuf)> = vec![];
+    for group in &conf.target_groups {
+        for root in group.get_roots() {
+            match config::resolve_path(&root.path, config_dir) {
+                Ok(p) => paths.push((&group.name, PathBuf::from(p))),
+                Err(e) => report.add(
+                    Severity::Error,
+                    format!("group \"{}\" path is invalid: {e}", group.name),
+                ),
+            }
+        }
+    }
+
+    for (i, (name_a, path_a)) in paths.iter().enumerate() {
+        for (name_b, path_b) in paths.iter().skip(i + 1) {
+            let is_nested = path_a.starts_with(path_b) || path_b.starts_with(path_a);
+            let message = if name_a == name_b && is_nested {
+                format!("group \"{name_a}\" has paths inside of each other")
+            } else if path_a == path_b {
+                format!("groups \"{name_a}\" and \"{name_b}\" share the same path")
+            } else if path_b.starts_with(path_a) {
+                format!("group \"{name_b}\" path is inside group \"{name_a}\"")
+            } else if path_a.starts_with(path_b) {
+                format!("group \"{name_a}\" path is inside group \"{name_b}\"")
+            } else {
+                continue;
+            };
+
+            report.add(Severity::Warning, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key;
+    use anyhow::Result;
+
+    fn get_config_content(rest: &str) -> String {
+        let secret_key = key::generate_node_secret_key();
+        format!(
+            "{rest}\n[local]\npublic_key = \"{}\"\nsecret_key = {:?}\npush_debounce_millisecs = 500\nloop_debounce_millisecs = 250\n",
+            secret_key.public(),
+            secret_key.to_bytes()
+        )
+    }
+
+    #[test]
+    fn test_check_config() -> Result<()> {
+        let nodes = "[[nodes]]\nname = \"foo\"\nid = \"foo_id\"\n";
+        let test_values = [
+            // (content, expected findings)
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![],
+            ),
+            ("nodes = [\n".to_string(), vec![Severity::Error]),
+            (
+                format!("foo = 1\ntarget_groups = []\n{nodes}"),
+                vec![Severity::Warning, Severity::Warning],
+            ),
+            (
+                format!("{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\ntargets = []\n"),
+                vec![Severity::Warning, Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"pull\"\nnode_name = \"bar\"\n"
+                ),
+                vec![Severity::Warning, Severity::Error, Severity::Info],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n[[target_groups.targets]]\nmode = \"pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n[[target_groups]]\nname = \"b\"\npath = \"/a/b\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\nmirror = true\n[[target_groups.targets]]\nmode = \"push-pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\narchive = true\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\nsnapshot = true\n[[target_groups.targets]]\nmode = \"pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning, Severity::Info],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"pull\"\nnode_name = \"foo\"\ninclude = [\"2024/**\"]\n"
+                ),
+                vec![Severity::Warning, Severity::Info],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\nmax_parallel_actions = 0\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\ntags = [\"work\", \"my stuff\"]\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\nchown = \"www:web\"\nchmod = \"680\"\numask = \"027\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Error, Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\nchown = \":\"\nchmod = \"640\"\n[[target_groups.targets]]\nmode = \"push-pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Error],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\nserve_http = true\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n[[target_groups]]\nname = \"b\"\npath = \"/b\"\nserve_http = true\nhttp_bind = \"tv\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Error, Severity::Error],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\nserve_http = true\nhttp_bind = \"0.0.0.0:8080\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n[[target_groups]]\nname = \"b\"\npath = \"/b\"\nserve_http = true\nhttp_bind = \"0.0.0.0:8080\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Error],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\npush_debounce_millisecs = 5000\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\npush_debounce_millisecs = 5000\n[[target_groups.targets]]\nmode = \"pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning, Severity::Info],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\ndelete_grace_secs = 60\n[[target_groups.targets]]\nmode = \"pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning, Severity::Info],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a/{{YYYY-MM-DD}}\"\n[[target_groups.targets]]\nmode = \"pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Info],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a/{{YYYY-MM-DD}}\"\n[[target_groups.targets]]\nmode = \"push-pull\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Error],
+            ),
+            (
+                "[[nodes]]\nname = \"foo\"\nid = \"foo_id\"\naddrs = [\"192.168.1.10:7070\", \"[fe80::1]:7070\"]\n[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                    .to_string(),
+                vec![],
+            ),
+            (
+                "[[nodes]]\nname = \"foo\"\nid = \"foo_id\"\naddrs = [\"192.168.1.10\"]\n[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                    .to_string(),
+                vec![Severity::Error],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npaths = [\"/a\", \"/b\"]\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npaths = [\"/a\", \"/a/b\"]\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Warning],
+            ),
+            (
+                format!(
+                    "{nodes}[[target_groups]]\nname = \"a\"\npath = \"/a\"\npaths = [\"/b\"]\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n"
+                ),
+                vec![Severity::Error],
+            ),
+        ];
+
+        for spec in test_values {
+            let content = get_config_content(&spec.0);
+            let report = check_config(&content, Path::new("/base"));
+            let severities: Vec<Severity> =
+                report.findings.iter().map(|f| f.severity.clone()).collect();
+            assert_eq!(severities, spec.1, "{report}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_config_local_only() -> Result<()> {
+        let test_values = [
+            // (addrs, expected findings)
+            ("", vec![Severity::Warning]),
+            ("addrs = [\"192.168.1.10:7070\"]\n", vec![]),
+        ];
+
+        for spec in test_values {
+            let content = get_config_content(&format!(
+                "[[nodes]]\nname = \"foo\"\nid = \"foo_id\"\n{}[[target_groups]]\nname = \"a\"\npath = \"/a\"\n[[target_groups.targets]]\nmode = \"push\"\nnode_name = \"foo\"\n",
+                spec.0
+            )) + "local_only = true\n";
+            let report = check_config(&content, Path::new("/base"));
+            let severities: Vec<Severity> =
+                report.findings.iter().map(|f| f.severity.clone()).collect();
+            assert_eq!(severities, spec.1, "{report}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_config_max_message_bytes() -> Result<()> {
+        let test_values = [
+            // (max_message_bytes, expected findings)
+            ("1048576", vec![]),
+            ("1", vec![]),
+            ("0", vec![Severity::Error]),
+            ("1000000000", vec![Severity::Error]),
+        ];
+
+        for spec in test_values {
+            let content = get_config_content("nodes = []\ntarget_groups = []\n")
+                + &format!("max_message_bytes = {}\n", spec.0);
+            let report = check_config(&content, Path::new("/base"));
+            let severities: Vec<Severity> =
+                report.findings.iter().map(|f| f.severity.clone()).collect();
+            assert_eq!(severities, spec.1, "{report}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_config_timings() -> Result<()> {
+        let test_values = [
+            // (loop millis, push millis, verify secs, expected findings)
+            ("250", "500", "3600", vec![]),
+            ("0", "500", "3600", vec![Severity::Error]),
+            ("1", "500", "3600", vec![Severity::Warning]),
+            ("10000", "500", "3600", vec![Severity::Warning]),
+            ("100000", "500", "3600", vec![Severity::Error]),
+            ("250", "0", "3600", vec![]),
+            ("250", "120000", "3600", vec![Severity::Warning]),
+            ("250", "5000000", "3600", vec![Severity::Error]),
+            ("250", "500", "0", vec![]),
+            ("250", "500", "5", vec![Severity::Warning]),
+        ];
+
+        for spec in test_values {
+            let content = get_config_content("nodes = []\ntarget_groups = []\n")
+                .replace(
+                    "loop_debounce_millisecs = 250",
+                    &format!("loop_debounce_millisecs = {}", spec.0),
+                )
+                .replace(
+                    "push_debounce_millisecs = 500",
+                    &format!("push_debounce_millisecs = {}", spec.1),
+                )
+                + &format!("manifest_verify_secs = {}\n", spec.2);
+            let report = check_config(&content, Path::new("/base"));
+            let severities: Vec<Severity> =
+                report.findings.iter().map(|f| f.severity.clone()).collect();
+            assert_eq!(severities, spec.3, "{report}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_config_key_mismatch() -> Result<()> {
+        let content = get_config_content("").replace("public_key = \"", "public_key = \"a");
+        let report = check_config(&content, Path::new("/base"));
+        assert!(report.has_errors());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::{export, reserved, target};
+
+pub const CONFLICTS_FILE_NAME: &str = "conflicts.toml";
+pub const DEFAULT_CONFLICT_NAME: &str = "{name}.conflict-{node}-{date}{ext}";
+
+// folders on the fsy folder of the target
+const CONFLICTS_DIR_NAME: &str = "conflicts";
+const SYNCED_DIR_NAME: &str = "synced";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum ConflictPolicy {
+    #[default]
+    #[serde(rename = "overwrite")]
+    Overwrite, // the pulled version replaces the local one
+    #[serde(rename = "keep-both")]
+    KeepBoth, // local changes not synced yet are kept as a conflict copy
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum ConflictLocation {
+    #[default]
+    #[serde(rename = "next-to-file")]
+    NextToFile,
+    #[serde(rename = "conflicts-dir")]
+    ConflictsDir, // on the `.fsy/conflicts` folder of the target
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub group_name: String,
+    pub relative_path: String,
+    pub copy_path: String, // where the local version was kept
+    pub node_name: String, // node the pulled version came from
+    pub timestamp: DateTime<Utc>,
+}
+
+// Conflicts: the conflict copies made, kept on the storage so
+// `fsy conflicts` can list and resolve them
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Conflicts {
+    #[serde(default)]
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Conflicts {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: Conflicts = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    // prune forgets the conflicts whose copy was removed by hand
+    pub fn prune(&mut self) {
+        self.conflicts.retain(|c| Path::new(&c.copy_path).exists());
+    }
+}
+
+impl fmt::Display for Conflicts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.conflicts.is_empty() {
+            return writeln!(f, "no conflicts");
+        }
+
+        for (i, conflict) in self.conflicts.iter().enumerate() {
+            writeln!(
+                f,
+                "{}. {}: {} with {} at {}, local version on {}",
+                i + 1,
+                conflict.group_name,
+                conflict.relative_path,
+                conflict.node_name,
+                conflict.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                conflict.copy_path
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Resolution: the outcome of `fsy conflicts resolve`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Resolution {
+    pub group_name: String,
+    pub relative_path: String,
+    pub use_copy: bool, // the conflict copy took the place of the pulled version
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "conflict on {} resolved", self.relative_path)
+    }
+}
+
+// get_conflict_name renders the template with the file and the node the
+// pulled version came from. separators are replaced so it stays a name
+pub fn get_conflict_name(
+    template: &str,
+    file_name: &str,
+    node_name: &str,
+    date: DateTime<Utc>,
+) -> String {
+    let path = Path::new(file_name);
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = match path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => "".to_owned(),
+    };
+
+    template
+        .replace("{name}", &name)
+        .replace("{ext}", &ext)
+        .replace("{node}", node_name)
+        .replace("{date}", &date.format("%Y%m%d-%H%M%S").to_string())
+        .replace(['/', '\\'], "_")
+}
+
+// get_conflict_path retrieves where the local version of a file is kept
+// when it conflicts with the pulled one
+pub fn get_conflict_path(
+    group: &target::TargetGroup,
+    file_path: &Path,
+    node_name: &str,
+    date: DateTime<Utc>,
+) -> Option<PathBuf> {
+    let file_name = file_path.file_name()?.to_string_lossy();
+    let template = group
+        .conflict_name
+        .as_deref()
+        .unwrap_or(DEFAULT_CONFLICT_NAME);
+    let conflict_name = get_conflict_name(template, &file_name, node_name, date);
+
+    match group.conflict_location {
+        ConflictLocation::NextToFile => Some(file_path.with_file_name(conflict_name)),
+        ConflictLocation::ConflictsDir => {
+            let root = group.get_root_of_file(file_path)?;
+            let dir = Path::new(CONFLICTS_DIR_NAME);
+            let path = reserved::get_reserved_path(Path::new(&root.path), dir, file_path)?;
+            Some(path.with_file_name(conflict_name))
+        }
+    }
+}
+
+fn get_modified_nanos(file_path: &Path) -> Result<u128> {
+    let modified = fs::metadata(file_path)?.modified()?;
+    Ok(modified.duration_since(UNIX_EPOCH)?.as_nanos())
+}
+
+// mark_synced keeps the modified time of the file as it was synced, later
+// changes are local ones
+pub fn mark_synced(base_path: &Path, file_path: &Path) -> Result<()> {
+    let Some(synced_path) =
+        reserved::get_reserved_path(base_path, Path::new(SYNCED_DIR_NAME), file_path)
+    else {
+        return Ok(());
+    };
+
+    if let Some(parent) = synced_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(synced_path, get_modified_nanos(file_path)?.to_string())?;
+    Ok(())
+}
+
+// has_local_changes checks if the file changed since it was last synced, a
+// file that was never synced has changes as long as it exists
+pub fn has_local_changes(base_path: &Path, file_path: &Path) -> Result<bool> {
+    if !file_path.is_file() {
+        return Ok(false);
+    }
+
+    let synced_path = reserved::get_reserved_path(base_path, Path::new(SYNCED_DIR_NAME), file_path);
+    let synced = synced_path.and_then(|p| fs::read_to_string(p).ok());
+    let Some(synced) = synced else {
+        return Ok(true);
+    };
+
+    Ok(synced.trim() != get_modified_nanos(file_path)?.to_string())
+}
+
+// files_differ compares the content of both files, without loading them
+pub fn files_differ(a: &Path, b: &Path) -> Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(true);
+    }
+
+    let mut reader_a = BufReader::new(File::open(a)?);
+    let mut reader_b = BufReader::new(File::open(b)?);
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+    loop {
+        let read = reader_a.read(&mut buf_a)?;
+        if read == 0 {
+            return Ok(false);
+        }
+
+        reader_b.read_exact(&mut buf_b[..read])?;
+        if buf_a[..read] != buf_b[..read] {
+            return Ok(true);
+        }
+    }
+}
+
+// keep_conflict moves the local version of the file to its conflict copy
+// and records it on the storage
+pub fn keep_conflict(
+    storage_path: &Path,
+    group: &target::TargetGroup,
+    file_path: &Path,
+    relative_path: &str,
+    node_name: &str,
+) -> Result<PathBuf> {
+    let now = Utc::now();
+    let Some(copy_path) = get_conflict_path(group, file_path, node_name, now) else {
+        bail!("unable to find where to keep the conflict copy of {relative_path}");
+    };
+
+    if let Some(parent) = copy_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    export::move_into_place(file_path, &copy_path, false)?;
+
+    let conflicts_path = storage_path.join(CONFLICTS_FILE_NAME);
+    let mut conflicts = Conflicts::load(&conflicts_path)?;
+    conflicts.conflicts.push(Conflict {
+        group_name: group.name.to_string(),
+        relative_path: relative_path.to_owned(),
+        copy_path: copy_path.to_string_lossy().to_string(),
+        node_name: node_name.to_owned(),
+        timestamp: now,
+    });
+    conflicts.save(&conflicts_path)?;
+
+    Ok(copy_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_get_conflict_name() -> Result<()> {
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let test_values = [
+            // (template, file_name, node_name, expected)
+            (
+                DEFAULT_CONFLICT_NAME,
+                "a.txt",
+                "laptop",
+                "a.conflict-laptop-20240102-030405.txt",
+            ),
+            (
+                DEFAULT_CONFLICT_NAME,
+                "a",
+                "laptop",
+                "a.conflict-laptop-20240102-030405",
+            ),
+            (
+                DEFAULT_CONFLICT_NAME,
+                "a.tar.gz",
+                "lap/top",
+                "a.tar.conflict-lap_top-20240102-030405.gz",
+            ),
+            ("{node}-{name}{ext}", ".bashrc", "laptop", "laptop-.bashrc"),
+        ];
+
+        for spec in test_values {
+            let res = get_conflict_name(spec.0, spec.1, spec.2, date);
+            assert_eq!(res, spec.3, "{}", spec.1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_conflict_path() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_conflict_path");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("foo"))?;
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+
+        let mut group = target::TargetGroup {
+            path: dir.to_string_lossy().to_string(),
+            conflict_name: Some("{name}-{node}{ext}".to_string()),
+            ..Default::default()
+        };
+        let res = get_conflict_path(&group, &dir.join("foo/a.txt"), "laptop", date);
+        assert_eq!(res, Some(dir.join("foo/a-laptop.txt")));
+
+        group.conflict_location = ConflictLocation::ConflictsDir;
+        let res = get_conflict_path(&group, &dir.join("foo/a.txt"), "laptop", date);
+        assert_eq!(res, Some(dir.join(".fsy/conflicts/foo/a-laptop.txt")));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_local_changes() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_conflict_local_changes");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let file_path = dir.join("a.txt");
+
+        assert!(!has_local_changes(&dir, &file_path)?);
+        fs::write(&file_path, b"a")?;
+        assert!(has_local_changes(&dir, &file_path)?);
+
+        mark_synced(&dir, &file_path)?;
+        assert!(!has_local_changes(&dir, &file_path)?);
+
+        // make sure the modified time moves on
+        let later = fs::metadata(&file_path)?.modified()? + std::time::Duration::from_secs(1);
+        File::options()
+            .write(true)
+            .open(&file_path)?
+            .set_modified(later)?;
+        assert!(has_local_changes(&dir, &file_path)?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_files_differ() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_conflict_files_differ");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("a"), b"foo")?;
+        fs::write(dir.join("b"), b"foo")?;
+        fs::write(dir.join("c"), b"bar")?;
+        fs::write(dir.join("d"), b"foobar")?;
+
+        assert!(!files_differ(&dir.join("a"), &dir.join("b"))?);
+        assert!(files_differ(&dir.join("a"), &dir.join("c"))?);
+        assert!(files_differ(&dir.join("a"), &dir.join("d"))?);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_conflict() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_conflict_keep");
+        let _ = fs::remove_dir_all(&dir);
+        let base_path = dir.join("group");
+        let storage_path = dir.join("storage");
+        fs::create_dir_all(&base_path)?;
+        fs::create_dir_all(&storage_path)?;
+        fs::write(base_path.join("a.txt"), b"local")?;
+
+        let group = target::TargetGroup {
+            name: "foo".into(),
+            path: base_path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let copy_path = keep_conflict(
+            &storage_path,
+            &group,
+            &base_path.join("a.txt"),
+            "a.txt",
+            "laptop",
+        )?;
+        assert!(!base_path.join("a.txt").exists());
+        assert_eq!(fs::read_to_string(&copy_path)?, "local");
+
+        let mut conflicts = Conflicts::load(&storage_path.join(CONFLICTS_FILE_NAME))?;
+        assert_eq!(conflicts.conflicts.len(), 1);
+        assert_eq!(conflicts.conflicts[0].relative_path, "a.txt");
+
+        // removed by hand
+        fs::remove_file(&copy_path)?;
+        conflicts.prune();
+        assert!(conflicts.conflicts.is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,53 +1,302 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
 use iroh::{
-    Endpoint, NodeAddr, NodeId, SecretKey, Watcher,
+    Endpoint, NodeAddr, NodeId, RelayMode, SecretKey, Watcher,
+    endpoint::{ConnectionType, ReadToEndError},
     protocol::{self, AcceptError, ProtocolHandler},
 };
-use iroh_blobs::{store::{fs::FsStore, mem::MemStore}, ticket::BlobTicket, BlobsProtocol};
-use std::{ path::{Path, PathBuf}, str::FromStr };
-use tokio::sync::watch;
+use iroh_blobs::{api::{proto::ExportRangesItem, Store}, provider, store::{fs::{options::{GcConfig, Options}, FsStore}, mem::MemStore}, ticket::BlobTicket, BlobsProtocol};
+use n0_future::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::chaos::Chaos;
+use crate::ids::{PeerId, TicketId};
+use crate::logs::{log_debug, log_warning};
+use crate::providers::Providers;
+use crate::purge;
+use crate::ticketed::{Ticketed, TicketedFile};
 
 const MESSAGE_PROTOCOL_ALPN: &[u8] = b"iroh/ping/0";
 
+// biggest message taken from a node unless the config sets another, the
+// ones fsy sends are way smaller, files and manifests go through tickets
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+// biggest message the config can allow, nothing bigger is ever taken
+pub const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+// how many times a download is tried before giving up, the first retry
+// waits DOWNLOAD_RETRY_MILLISECS and each one after doubles it
+const DOWNLOAD_ATTEMPTS: u32 = 5;
+const DOWNLOAD_RETRY_MILLISECS: u64 = 1000;
+
+// how often blobs nothing needs are dropped from the store, when purging
+const BLOB_GC_SECS: u64 = 60;
+
 #[derive(Debug, Clone)]
 pub enum ConnEvent {
     // node_id, raw_msg
     ReceivedMessage(String, String),
 }
 
+// StoreMode: where the blobs are kept while transferring
+#[derive(Debug, Clone)]
+pub enum StoreMode {
+    // file system store on the given path, the one used by the app
+    Fs(PathBuf),
+    // memory store, everything goes away once closed. useful for tests
+    Memory,
+}
+
+// DiscoveryMode: how the endpoint finds the other nodes
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscoveryMode {
+    // n0 discovery and relays, reaching nodes over the internet
+    N0,
+    // no relays and no discovery, only bound to localhost. nodes need
+    // to be made known through `add_node_addr`. useful for tests
+    LocalOnly,
+    // no relays and no public discovery, bound on every interface on the
+    // port (any free one if unset). nodes are reached on the addresses
+    // they are given so nothing leaves the network
+    Lan(Option<u16>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    pub store: StoreMode,
+    pub discovery: DiscoveryMode,
+    pub max_message_bytes: usize, // longer streams are aborted as an offense
+    pub purge_blobs: bool,        // the store is wiped on start and drops blobs nothing needs
+    pub chaos: Chaos,             // failures made up on purpose, with the `chaos` feature
+}
+
+impl ConnectionOptions {
+    // local_memory sets up a connection that never touches the disk
+    // or the network outside of the machine, meant for CI tests
+    #[allow(dead_code)]
+    pub fn local_memory() -> Self {
+        Self {
+            store: StoreMode::Memory,
+            discovery: DiscoveryMode::LocalOnly,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            purge_blobs: false,
+            chaos: Chaos::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum BlobStore {
+    Fs(FsStore),
+    Memory(MemStore),
+}
+
+impl Deref for BlobStore {
+    type Target = Store;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Fs(store) => store,
+            Self::Memory(store) => store,
+        }
+    }
+}
+
+// how often the path to each peer is looked at for the status
+pub const PATH_CHECK_SECS: u64 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PathKind {
+    #[serde(rename = "direct")]
+    Direct, // straight to the node, nat traversal worked
+    #[serde(rename = "relay")]
+    Relay, // through a relay server, slower
+    #[serde(rename = "mixed")]
+    Mixed, // a direct address is being tried, the relay is used meanwhile
+    #[serde(rename = "none")]
+    None, // no path to the node
+}
+
+impl fmt::Display for PathKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Direct => "direct",
+            Self::Relay => "relayed",
+            Self::Mixed => "mixed",
+            Self::None => "unreachable",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+// PeerPath: how the data to a node goes, direct or relayed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerPath {
+    pub kind: PathKind,
+    pub addr: Option<String>, // address or relay the data goes through
+    pub latency_ms: Option<u64>,
+}
+
+// NetworkHealth: whether the endpoint can take part in the sync
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct NetworkHealth {
+    pub bound: bool,     // listening on a socket
+    pub discovery: bool, // the nodes can find us, through a relay or on our addresses
+}
+
+impl From<&ConnectionType> for PeerPath {
+    fn from(conn_type: &ConnectionType) -> Self {
+        let (kind, addr) = match conn_type {
+            ConnectionType::Direct(addr) => (PathKind::Direct, Some(addr.to_string())),
+            ConnectionType::Relay(url) => (PathKind::Relay, Some(url.to_string())),
+            ConnectionType::Mixed(addr, _) => (PathKind::Mixed, Some(addr.to_string())),
+            ConnectionType::None => (PathKind::None, None),
+        };
+
+        Self {
+            kind,
+            addr,
+            latency_ms: None,
+        }
+    }
+}
+
+// TransferBytes: bytes (sent, received) per node id since last taken
+type TransferBytes = Arc<Mutex<HashMap<String, (u64, u64)>>>;
+
+// ReachablePeers: node ids that became reachable since last taken
+type ReachablePeers = Arc<Mutex<Vec<String>>>;
+
+// Offenses: nodes that sent something no fsy node sends (an oversized or
+// non utf-8 message), once per offense
+type Offenses = Arc<Mutex<Vec<String>>>;
+
+// Downloads: (node id, hash) of the blobs nodes asked for (or got all of)
+// since last taken
+type Downloads = Arc<Mutex<Vec<(String, String)>>>;
+
+// SendResult: how a message handed to the sender of a node went
+#[derive(Debug)]
+pub struct SendResult {
+    pub node_id: String,
+    pub msg: String,
+    pub res: Result<()>,
+}
+
+type SendResults = Arc<Mutex<Vec<SendResult>>>;
+
+// PeerSenders: the channel to the task sending the messages of each node
+type PeerSenders = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<String>>>>;
+
 #[derive(Clone)]
 pub struct Connection {
     router: protocol::Router,
     message_watcher_rx: watch::Receiver<Option<ConnEvent>>,
-    // store: MemStore,
-    store: FsStore,
+    store: BlobStore,
+    transfer_bytes: TransferBytes,
+    reachable_peers: ReachablePeers,
+    offenses: Offenses,
+    downloads: Downloads,
+    completed: Downloads, // the downloads nodes finished
+    peer_senders: PeerSenders,
+    send_results: SendResults,
+    snapshot_tickets: HashMap<PathBuf, BlobTicket>, // tickets of the last snapshot of each file
+    providers: Providers, // other nodes known to have each hash
+    ticketed: Ticketed, // files the tickets were made of, to catch stale ones
+    discovery: DiscoveryMode,
+    chaos: Arc<Chaos>,
 }
 
 impl Connection {
-    pub async fn new(raw_secret_key: &[u8; 32], store_path: &Path) -> Result<Self> {
+    pub async fn new(
+        raw_secret_key: &[u8; 32],
+        store_path: &Path,
+        discovery: DiscoveryMode,
+        max_message_bytes: usize,
+        purge_blobs: bool,
+        chaos: Chaos,
+    ) -> Result<Self> {
+        let options = ConnectionOptions {
+            store: StoreMode::Fs(store_path.to_path_buf()),
+            discovery,
+            max_message_bytes,
+            purge_blobs,
+            chaos,
+        };
+
+        Self::new_with_options(raw_secret_key, options).await
+    }
+
+    pub async fn new_with_options(
+        raw_secret_key: &[u8; 32],
+        options: ConnectionOptions,
+    ) -> Result<Self> {
         let secret_key = SecretKey::from_bytes(raw_secret_key);
 
-        let endpoint = Endpoint::builder()
-            .secret_key(secret_key)
+        let builder = Endpoint::builder().secret_key(secret_key);
+        let builder = match options.discovery.clone() {
             // TODO: what about discovery over custom relay and local?
-            .discovery_n0()
             // TODO: local is not working
             // .add_discovery(discovery::mdns::MdnsDiscovery::builder())
-            .bind()
-            .await
-            .unwrap();
+            DiscoveryMode::N0 => builder.discovery_n0(),
+            DiscoveryMode::LocalOnly => builder
+                .clear_discovery()
+                .relay_mode(RelayMode::Disabled)
+                .bind_addr_v4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)),
+            DiscoveryMode::Lan(port) => builder
+                .clear_discovery()
+                .relay_mode(RelayMode::Disabled)
+                .bind_addr_v4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port.unwrap_or(0))),
+        };
+        let endpoint = builder.bind().await?;
 
         // setup the protocol for the blobs back and forth
         // should use a file system on temporary dir
         // sending a file with gbs will fill up the ram and crash
-        // let store = MemStore::new();
-        let store = FsStore::load(store_path).await.unwrap();
-        let blobs = BlobsProtocol::new(&store, endpoint.clone(), None);
+        let store = match options.store {
+            StoreMode::Fs(store_path) if options.purge_blobs => {
+                BlobStore::Fs(load_purged_store(&store_path).await?)
+            }
+            StoreMode::Fs(store_path) => BlobStore::Fs(FsStore::load(store_path).await?),
+            StoreMode::Memory => BlobStore::Memory(MemStore::new()),
+        };
+        // listen to the provider events so we know how much we serve to each node
+        let transfer_bytes: TransferBytes = Arc::new(Mutex::new(HashMap::new()));
+        let downloads: Downloads = Arc::new(Mutex::new(vec![]));
+        let completed: Downloads = Arc::new(Mutex::new(vec![]));
+        let (provider_events_tx, provider_events_rx) = mpsc::channel(32);
+        tokio::spawn(handle_provider_events(
+            provider_events_rx,
+            transfer_bytes.clone(),
+            downloads.clone(),
+            completed.clone(),
+        ));
+        let blobs = BlobsProtocol::new(&store, endpoint.clone(), Some(provider_events_tx));
 
         // TODO: how can i check for the allowed list?
         //       how do i know that the user can actually connect?
         let (message_watcher_tx, message_watcher_rx) = watch::channel(None);
-        let message_protocol = MessageProtocol::new(message_watcher_tx);
+        let reachable_peers: ReachablePeers = Arc::new(Mutex::new(vec![]));
+        let offenses: Offenses = Arc::new(Mutex::new(vec![]));
+        let message_protocol = MessageProtocol {
+            message_watcher_tx,
+            reachable_peers: reachable_peers.clone(),
+            offenses: offenses.clone(),
+            max_message_bytes: options.max_message_bytes.min(MAX_MESSAGE_BYTES),
+        };
         let router = protocol::Router::builder(endpoint.clone())
             .accept(iroh_blobs::ALPN, blobs.clone()) // TODO: will this work?!
             .accept(MESSAGE_PROTOCOL_ALPN, message_protocol)
@@ -63,11 +312,121 @@ impl Connection {
             router,
             message_watcher_rx,
             store,
+            transfer_bytes,
+            reachable_peers,
+            offenses,
+            downloads,
+            completed,
+            peer_senders: Arc::new(Mutex::new(HashMap::new())),
+            send_results: Arc::new(Mutex::new(vec![])),
+            snapshot_tickets: HashMap::new(),
+            providers: Providers::default(),
+            ticketed: Ticketed::default(),
+            discovery: options.discovery,
+            chaos: Arc::new(options.chaos),
         })
     }
 
-    pub fn get_node_id(&self) -> String {
-        self.router.endpoint().node_id().to_string()
+    pub fn get_node_id(&self) -> PeerId {
+        self.router.endpoint().node_id().to_string().into()
+    }
+
+    #[allow(dead_code)]
+    pub async fn get_node_addr(&self) -> NodeAddr {
+        self.router.endpoint().node_addr().initialized().await
+    }
+
+    // add_node_addr makes a node reachable without discovery, for example,
+    // on a local only connection
+    pub fn add_node_addr(&self, node_addr: NodeAddr) -> Result<()> {
+        self.router.endpoint().add_node_addr(node_addr)?;
+        Ok(())
+    }
+
+    // add_direct_addrs makes a node reachable on the `ip:port` addresses it
+    // has on the config
+    pub fn add_direct_addrs(&self, node_id: &str, addrs: &[String]) -> Result<()> {
+        let node_id = NodeId::from_str(node_id)?;
+        let addrs = addrs
+            .iter()
+            .map(|addr| addr.parse::<SocketAddr>())
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_node_addr(NodeAddr::new(node_id).with_direct_addresses(addrs))
+    }
+
+    // take_transfer_bytes retrieves the (sent, received) bytes per node id
+    // since the last time it was called
+    pub fn take_transfer_bytes(&self) -> HashMap<String, (u64, u64)> {
+        let mut transfer_bytes = self.transfer_bytes.lock().unwrap();
+        std::mem::take(&mut *transfer_bytes)
+    }
+
+    // take_reachable_peers retrieves the nodes that became reachable since the
+    // last time it was called, the ones that connected to us or that we got
+    // a path to
+    pub fn take_reachable_peers(&self) -> Vec<String> {
+        let mut reachable_peers = self.reachable_peers.lock().unwrap();
+        std::mem::take(&mut *reachable_peers)
+    }
+
+    // take_offenses retrieves the node of each offense since the last time
+    pub fn take_offenses(&self) -> Vec<String> {
+        let mut offenses = self.offenses.lock().unwrap();
+        std::mem::take(&mut *offenses)
+    }
+
+    // watch_peer waits on the connection to the node in the background, it
+    // is taken as reachable once there is a path to it
+    pub fn watch_peer(&self, node_id: &str) {
+        let Ok(node) = NodeId::from_str(node_id) else {
+            return;
+        };
+        // NOTE: without any address of the node there is nothing to watch,
+        //       it shows up once it connects to us
+        let Some(mut conn_type) = self.router.endpoint().conn_type(node) else {
+            return;
+        };
+
+        let reachable_peers = self.reachable_peers.clone();
+        let node_id = node_id.to_owned();
+        tokio::spawn(async move {
+            while let Ok(conn_type) = conn_type.updated().await {
+                if conn_type != ConnectionType::None {
+                    reachable_peers.lock().unwrap().push(node_id);
+                    return;
+                }
+            }
+        });
+    }
+
+    // get_peer_path retrieves how the data to the node goes, none if there
+    // was never a path to it
+    pub fn get_peer_path(&self, node_id: &str) -> Option<PeerPath> {
+        let node = NodeId::from_str(node_id).ok()?;
+        let info = self.router.endpoint().remote_info(node)?;
+        Some(PeerPath {
+            latency_ms: info.latency.map(|latency| latency.as_millis() as u64),
+            ..PeerPath::from(&info.conn_type)
+        })
+    }
+
+    // get_network_health checks if the endpoint listens and can be found,
+    // with relays once it has a home relay and without once it knows its
+    // addresses
+    pub fn get_network_health(&self) -> NetworkHealth {
+        let endpoint = self.router.endpoint();
+        let discovery = match self.discovery {
+            DiscoveryMode::N0 => !endpoint.home_relay().get().is_empty(),
+            DiscoveryMode::LocalOnly | DiscoveryMode::Lan(_) => endpoint
+                .direct_addresses()
+                .get()
+                .is_some_and(|addrs| !addrs.is_empty()),
+        };
+
+        NetworkHealth {
+            bound: !endpoint.is_closed() && !endpoint.bound_sockets().is_empty(),
+            discovery,
+        }
     }
 
     pub fn get_events(&mut self) -> Result<Option<ConnEvent>> {
@@ -81,52 +440,468 @@ impl Connection {
         Ok(watch_msg)
     }
 
-    pub async fn send_msg_to_node(&self, node_id: String, msg: String) -> Result<()> {
-        let node = NodeId::from_str(&node_id);
-        let node_addr = NodeAddr::new(node.unwrap());
+    // send_msg_to_node sends the message right away, waiting on the node
+    #[allow(dead_code)]
+    pub async fn send_msg_to_node(&self, node_id: PeerId, msg: String) -> Result<()> {
+        send_msg(self.router.endpoint(), &node_id, &msg).await
+    }
 
-        // open a connection to the accepting node
-        let conn = self
-            .router
-            .endpoint()
-            .connect(node_addr, MESSAGE_PROTOCOL_ALPN)
-            .await?;
+    // queue_msg_to_node hands the message to the sender of the node. the
+    // messages to a node go out one after the other, the ones to different
+    // nodes at the same time. how it went is on `take_send_results`
+    pub fn queue_msg_to_node(&self, node_id: PeerId, msg: String) {
+        let node_id = String::from(node_id);
+        let endpoint = self.router.endpoint();
+        let mut peer_senders = self.peer_senders.lock().unwrap();
+        let spawn = |node_id: String| {
+            let send_results = self.send_results.clone();
+            spawn_peer_sender(endpoint.clone(), node_id, send_results, self.chaos.clone())
+        };
+        let sender = peer_senders
+            .entry(node_id.clone())
+            .or_insert_with(|| spawn(node_id.clone()));
+        if sender.is_closed() {
+            *sender = spawn(node_id);
+        }
+
+        let _ = sender.send(msg);
+    }
+
+    // take_send_results retrieves how the messages handed to the senders went
+    // since the last time it was called
+    pub fn take_send_results(&self) -> Vec<SendResult> {
+        let mut send_results = self.send_results.lock().unwrap();
+        std::mem::take(&mut *send_results)
+    }
+
+    // get_file_ticket adds the file to the store and makes the ticket to it.
+    // NOTE: the future doesn't borrow the connection, it can run on its own
+    pub fn get_file_ticket(
+        &self,
+        file_path: String,
+    ) -> impl Future<Output = Result<BlobTicket>> + use<> {
+        let transfer = self.get_transfer();
+        async move { transfer.get_file_ticket(file_path).await }
+    }
+
+    pub fn set_snapshot_tickets(&mut self, tickets: Vec<(PathBuf, BlobTicket)>) {
+        self.snapshot_tickets.extend(tickets);
+    }
+
+    // get_snapshot_ticket retrieves the ticket of the file as it was on the
+    // last snapshot taken of it
+    pub fn get_snapshot_ticket(&self, file_path: &Path) -> Option<BlobTicket> {
+        self.snapshot_tickets.get(file_path).cloned()
+    }
+
+    // add_ticketed notes the file the ticket of the hash was made of for the
+    // node, as it is now
+    pub fn add_ticketed(&mut self, hash: &str, file: TicketedFile) {
+        self.ticketed.add(hash, file);
+    }
+
+    // take_stale_tickets retrieves the files nodes started downloading since
+    // the last time that changed after their ticket was made
+    pub fn take_stale_tickets(&mut self) -> Vec<TicketedFile> {
+        let downloads = std::mem::take(&mut *self.downloads.lock().unwrap());
+        downloads
+            .into_iter()
+            .filter_map(|(node_id, hash)| self.ticketed.take_stale(&hash, &node_id))
+            .collect()
+    }
 
-        let (mut send, mut recv) = conn.open_bi().await?; // Open a bidirectional QUIC stream
+    // take_completed_tickets retrieves the files nodes finished downloading
+    // from us since the last time, the ones we made the ticket of
+    pub fn take_completed_tickets(&mut self) -> Vec<TicketedFile> {
+        let completed = std::mem::take(&mut *self.completed.lock().unwrap());
+        completed
+            .into_iter()
+            .filter_map(|(node_id, hash)| self.ticketed.get(&hash, &node_id).cloned())
+            .collect()
+    }
 
-        send.write_all(msg.as_bytes()).await?; // send message
-        send.finish()?; // signal the end of data for this particular stream
+    // add_provider notes the node has the content of the hash, downloads of
+    // it can fetch from there too
+    pub fn add_provider(&mut self, hash: &str, node_id: &str) {
+        self.providers.add(hash, node_id);
+    }
 
-        // wait for the ok
-        let response = recv.read_to_end(2).await?;
-        assert_eq!(&response, b"ok");
+    // get_providers retrieves where the content of the ticket can be fetched
+    // from, the node of the ticket first
+    fn get_providers(&self, ticket: &BlobTicket) -> Vec<NodeId> {
+        let ticket_node_id = ticket.node_addr().node_id;
+        let own_node_id = self.router.endpoint().node_id();
+        let node_ids = self
+            .providers
+            .get(&ticket.hash().to_string())
+            .into_iter()
+            .filter_map(|node_id| NodeId::from_str(&node_id).ok())
+            .filter(|node_id| *node_id != ticket_node_id && *node_id != own_node_id);
+        std::iter::once(ticket_node_id).chain(node_ids).collect()
+    }
+
+    // download_ticket_to_path gets the content of the ticket into the path.
+    // NOTE: the future doesn't borrow the connection, it can run on its own
+    pub fn download_ticket_to_path(
+        &self,
+        ticket_id: TicketId,
+        file_path: String,
+    ) -> impl Future<Output = Result<()>> + use<> {
+        let transfer = self.get_transfer();
+        let providers = self.get_ticket_providers(&ticket_id);
+        async move {
+            transfer
+                .download_ticket_to_path(ticket_id, file_path, providers)
+                .await
+        }
+    }
 
-        // nothing else more to do in the connection.
-        let close_msg = "bye";
-        conn.close(0u32.into(), close_msg.as_bytes());
+    // download_ticket_stream retrieves the content of the ticket in order,
+    // chunk by chunk, without exporting it to a file first
+    #[allow(dead_code)]
+    pub async fn download_ticket_stream(
+        &self,
+        ticket_id: TicketId,
+    ) -> Result<impl Stream<Item = Result<Bytes>> + use<>> {
+        let providers = self.get_ticket_providers(&ticket_id);
+        self.get_transfer()
+            .download_ticket_stream(ticket_id, providers)
+            .await
+    }
+
+    // get_ticket_providers retrieves where the content of the ticket can be
+    // fetched from, nothing if it isn't a ticket
+    fn get_ticket_providers(&self, ticket_id: &TicketId) -> Vec<NodeId> {
+        ticket_id
+            .parse::<BlobTicket>()
+            .map(|ticket| self.get_providers(&ticket))
+            .unwrap_or_default()
+    }
+
+    fn get_transfer(&self) -> Transfer {
+        Transfer {
+            store: self.store.clone(),
+            endpoint: self.router.endpoint().clone(),
+            transfer_bytes: self.transfer_bytes.clone(),
+            chaos: self.chaos.clone(),
+        }
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.router.endpoint().close().await;
+        self.router.shutdown().await?;
 
         Ok(())
     }
 
+    // spawn moves the connection to a task of its own, everything else goes
+    // through the handle. transfers run on tasks of their own too so a long
+    // download doesn't hold back the messages or other transfers
+    pub fn spawn(self) -> ConnectionHandle {
+        let node_id = self.get_node_id();
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_connection(self, commands_rx));
+
+        ConnectionHandle {
+            node_id,
+            commands_tx,
+        }
+    }
+}
+
+// Command: what the task of the connection is asked to do, the ones with an
+// answer carry where it goes back to
+enum Command {
+    GetEvents(oneshot::Sender<Option<ConnEvent>>),
+    QueueMsg(PeerId, String),
+    TakeSendResults(oneshot::Sender<Vec<SendResult>>),
+    TakeTransferBytes(oneshot::Sender<HashMap<String, (u64, u64)>>),
+    TakeReachablePeers(oneshot::Sender<Vec<String>>),
+    TakeOffenses(oneshot::Sender<Vec<String>>),
+    WatchPeer(String),
+    GetPeerPath(String, oneshot::Sender<Option<PeerPath>>),
+    GetNetworkHealth(oneshot::Sender<NetworkHealth>),
+    GetFileTicket(String, oneshot::Sender<Result<BlobTicket>>),
+    SetSnapshotTickets(Vec<(PathBuf, BlobTicket)>),
+    GetSnapshotTicket(PathBuf, oneshot::Sender<Option<BlobTicket>>),
+    AddTicketed(String, TicketedFile),
+    TakeStaleTickets(oneshot::Sender<Vec<TicketedFile>>),
+    TakeCompletedTickets(oneshot::Sender<Vec<TicketedFile>>),
+    AddProvider(String, String),
+    DownloadTicketToPath(TicketId, String, oneshot::Sender<Result<()>>),
+    Close(oneshot::Sender<Result<()>>),
+}
+
+// run_connection answers the commands of the handles until closed. the ones
+// waiting on the network are spawned, the answer is sent once done
+async fn run_connection(mut conn: Connection, mut commands_rx: mpsc::UnboundedReceiver<Command>) {
+    while let Some(command) = commands_rx.recv().await {
+        match command {
+            Command::GetEvents(reply_tx) => {
+                let _ = reply_tx.send(conn.get_events().unwrap_or_default());
+            }
+            Command::QueueMsg(node_id, msg) => conn.queue_msg_to_node(node_id, msg),
+            Command::TakeSendResults(reply_tx) => {
+                let _ = reply_tx.send(conn.take_send_results());
+            }
+            Command::TakeTransferBytes(reply_tx) => {
+                let _ = reply_tx.send(conn.take_transfer_bytes());
+            }
+            Command::TakeReachablePeers(reply_tx) => {
+                let _ = reply_tx.send(conn.take_reachable_peers());
+            }
+            Command::TakeOffenses(reply_tx) => {
+                let _ = reply_tx.send(conn.take_offenses());
+            }
+            Command::WatchPeer(node_id) => conn.watch_peer(&node_id),
+            Command::GetPeerPath(node_id, reply_tx) => {
+                let _ = reply_tx.send(conn.get_peer_path(&node_id));
+            }
+            Command::GetNetworkHealth(reply_tx) => {
+                let _ = reply_tx.send(conn.get_network_health());
+            }
+            Command::GetFileTicket(file_path, reply_tx) => {
+                let ticket = conn.get_file_ticket(file_path);
+                tokio::spawn(async move {
+                    let _ = reply_tx.send(ticket.await);
+                });
+            }
+            Command::SetSnapshotTickets(tickets) => conn.set_snapshot_tickets(tickets),
+            Command::GetSnapshotTicket(file_path, reply_tx) => {
+                let _ = reply_tx.send(conn.get_snapshot_ticket(&file_path));
+            }
+            Command::AddTicketed(hash, file) => conn.add_ticketed(&hash, file),
+            Command::TakeStaleTickets(reply_tx) => {
+                let _ = reply_tx.send(conn.take_stale_tickets());
+            }
+            Command::TakeCompletedTickets(reply_tx) => {
+                let _ = reply_tx.send(conn.take_completed_tickets());
+            }
+            Command::AddProvider(hash, node_id) => conn.add_provider(&hash, &node_id),
+            Command::DownloadTicketToPath(ticket_id, file_path, reply_tx) => {
+                let download = conn.download_ticket_to_path(ticket_id, file_path);
+                tokio::spawn(async move {
+                    let _ = reply_tx.send(download.await);
+                });
+            }
+            Command::Close(reply_tx) => {
+                let _ = reply_tx.send(conn.close().await);
+                return;
+            }
+        }
+    }
+}
+
+// ConnectionHandle: how the rest of fsy talks to the connection, cloned to
+// every task that needs it. once closed, the questions get nothing back
+#[derive(Clone)]
+pub struct ConnectionHandle {
+    node_id: PeerId,
+    commands_tx: mpsc::UnboundedSender<Command>,
+}
+
+impl ConnectionHandle {
+    pub fn get_node_id(&self) -> PeerId {
+        self.node_id.clone()
+    }
+
+    pub async fn get_events(&self) -> Option<ConnEvent> {
+        self.ask(Command::GetEvents).await.unwrap_or_default()
+    }
+
+    pub fn queue_msg_to_node(&self, node_id: PeerId, msg: String) {
+        let _ = self.commands_tx.send(Command::QueueMsg(node_id, msg));
+    }
+
+    pub async fn take_send_results(&self) -> Vec<SendResult> {
+        self.ask(Command::TakeSendResults).await.unwrap_or_default()
+    }
+
+    pub async fn take_transfer_bytes(&self) -> HashMap<String, (u64, u64)> {
+        self.ask(Command::TakeTransferBytes)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn take_reachable_peers(&self) -> Vec<String> {
+        self.ask(Command::TakeReachablePeers)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn take_offenses(&self) -> Vec<String> {
+        self.ask(Command::TakeOffenses).await.unwrap_or_default()
+    }
+
+    pub fn watch_peer(&self, node_id: &str) {
+        let _ = self
+            .commands_tx
+            .send(Command::WatchPeer(node_id.to_owned()));
+    }
+
+    pub async fn get_peer_path(&self, node_id: &str) -> Option<PeerPath> {
+        let node_id = node_id.to_owned();
+        self.ask(|reply_tx| Command::GetPeerPath(node_id, reply_tx))
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn get_network_health(&self) -> NetworkHealth {
+        self.ask(Command::GetNetworkHealth)
+            .await
+            .unwrap_or_default()
+    }
+
     pub async fn get_file_ticket(&self, file_path: String) -> Result<BlobTicket> {
+        self.ask(|reply_tx| Command::GetFileTicket(file_path, reply_tx))
+            .await?
+    }
+
+    pub fn set_snapshot_tickets(&self, tickets: Vec<(PathBuf, BlobTicket)>) {
+        let _ = self.commands_tx.send(Command::SetSnapshotTickets(tickets));
+    }
+
+    pub async fn get_snapshot_ticket(&self, file_path: &Path) -> Option<BlobTicket> {
+        let file_path = file_path.to_path_buf();
+        self.ask(|reply_tx| Command::GetSnapshotTicket(file_path, reply_tx))
+            .await
+            .unwrap_or_default()
+    }
+
+    pub fn add_ticketed(&self, hash: &str, file: TicketedFile) {
+        let _ = self
+            .commands_tx
+            .send(Command::AddTicketed(hash.to_owned(), file));
+    }
+
+    pub async fn take_stale_tickets(&self) -> Vec<TicketedFile> {
+        self.ask(Command::TakeStaleTickets)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn take_completed_tickets(&self) -> Vec<TicketedFile> {
+        self.ask(Command::TakeCompletedTickets)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub fn add_provider(&self, hash: &str, node_id: &str) {
+        let _ = self
+            .commands_tx
+            .send(Command::AddProvider(hash.to_owned(), node_id.to_owned()));
+    }
+
+    pub async fn download_ticket_to_path(
+        &self,
+        ticket_id: TicketId,
+        file_path: String,
+    ) -> Result<()> {
+        self.ask(|reply_tx| Command::DownloadTicketToPath(ticket_id, file_path, reply_tx))
+            .await?
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.ask(Command::Close).await?
+    }
+
+    // ask sends the command and waits on its answer
+    async fn ask<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Result<T> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.commands_tx
+            .send(command(reply_tx))
+            .map_err(|_| anyhow!("connection is closed"))?;
+        Ok(reply_rx.await?)
+    }
+}
+
+// Transfer: what adding and downloading blobs needs of the connection, cheap
+// to clone so they run on their own without holding the connection back
+#[derive(Clone)]
+struct Transfer {
+    store: BlobStore,
+    endpoint: Endpoint,
+    transfer_bytes: TransferBytes,
+    chaos: Arc<Chaos>,
+}
+
+impl Transfer {
+    async fn get_file_ticket(&self, file_path: String) -> Result<BlobTicket> {
         let filename: PathBuf = file_path.parse()?;
         let abs_path = std::path::absolute(&filename)?;
         let tag = self.store.blobs().add_path(abs_path).await?;
-        let addr = self.router.endpoint().node_addr().initialized().await;
+        let addr = self.endpoint.node_addr().initialized().await;
         let ticket = BlobTicket::new(addr, tag.hash, tag.format);
 
         Ok(ticket)
     }
 
-    pub async fn download_ticket_to_path(&self, ticket_id: String, file_path: String) -> Result<()> {
+    // fetch_ticket gets the content of the ticket into the store
+    async fn fetch_ticket(&self, ticket: &BlobTicket, providers: Vec<NodeId>) -> Result<()> {
+        // NOTE: a transfer dies when the path to the provider changes (switching
+        //       networks for example). the store keeps what was already verified
+        //       and a new download only asks for what is missing, so we retry,
+        //       redialing through whatever address discovery knows by then
+        if let Some(delay) = self.chaos.get_transfer_delay() {
+            log_debug!("- chaos delays the download of {} {delay:?}", ticket.hash());
+            tokio::time::sleep(delay).await;
+        }
+
+        let downloader = self.store.downloader(&self.endpoint);
+        let mut attempt = 0;
+        loop {
+            // the ticket knows where the provider is, let the endpoint know too
+            // so we don't rely only on discovery, which is still tried if not
+            if ticket.node_addr().node_id != self.endpoint.node_id()
+                && let Err(e) = self.endpoint.add_node_addr(ticket.node_addr().clone())
+            {
+                log_warning!("- unable to add the address of {}: {e}", ticket.node_addr().node_id);
+            }
+
+            // NOTE: nodes that pulled the content already serve it too, the
+            //       downloader moves on to them when one can't
+            log_debug!(
+                "- downloading {} from {} nodes",
+                ticket.hash(),
+                providers.len()
+            );
+            let res = downloader.download(ticket.hash(), providers.clone()).await;
+            match res {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt + 1 < DOWNLOAD_ATTEMPTS => {
+                    let delay = get_download_retry_delay(attempt);
+                    log_warning!("- download of {} failed, resuming in {delay:?}: {e}", ticket.hash());
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn download_ticket_to_path(
+        &self,
+        ticket_id: TicketId,
+        file_path: String,
+        providers: Vec<NodeId>,
+    ) -> Result<()> {
         let filename: PathBuf = file_path.parse()?;
         let abs_path = std::path::absolute(filename)?;
         let ticket: BlobTicket = ticket_id.parse()?;
 
-        let downloader = self.store.downloader(self.router.endpoint());
-        downloader.download(ticket.hash(), Some(ticket.node_addr().node_id)).await?;
-        // TODO: should return bytes instead
-        self.store.blobs().export(ticket.hash(), abs_path).await?;
+        // NOTE: the tag keeps the blob from being dropped until it is on the
+        //       path, once let go nothing needs it anymore
+        let _tag = self.store.tags().temp_tag(ticket.hash_and_format()).await?;
+        self.fetch_ticket(&ticket, providers).await?;
+        self.store.blobs().export(ticket.hash(), &abs_path).await?;
+
+        let size = std::fs::metadata(&abs_path)?.len();
+        add_transfer_bytes(
+            &self.transfer_bytes,
+            &ticket.node_addr().node_id.to_string(),
+            0,
+            size,
+        );
 
         // let connection = self
         //     .router
@@ -171,58 +946,516 @@ impl Connection {
         // Ok(bytes)
     }
 
-    pub async fn close(&self) -> Result<()> {
-        self.router.endpoint().close().await;
-        self.router.shutdown().await?;
+    async fn download_ticket_stream(
+        &self,
+        ticket_id: TicketId,
+        providers: Vec<NodeId>,
+    ) -> Result<impl Stream<Item = Result<Bytes>> + use<>> {
+        let ticket: BlobTicket = ticket_id.parse()?;
+        self.fetch_ticket(&ticket, providers).await?;
 
-        Ok(())
+        let transfer_bytes = self.transfer_bytes.clone();
+        let node_id = ticket.node_addr().node_id.to_string();
+        let stream = self
+            .store
+            .blobs()
+            .export_ranges(ticket.hash(), 0u64..)
+            .stream()
+            .filter_map(move |item| match item {
+                ExportRangesItem::Size(_) => None,
+                ExportRangesItem::Data(leaf) => {
+                    add_transfer_bytes(&transfer_bytes, &node_id, 0, leaf.data.len() as u64);
+                    Some(Ok(leaf.data))
+                }
+                ExportRangesItem::Error(e) => Some(Err(e.into())),
+            });
+
+        Ok(stream)
     }
 }
 
-#[derive(Debug, Clone)]
-struct MessageProtocol {
-    message_watcher_tx: watch::Sender<Option<ConnEvent>>,
+// load_purged_store wipes what the store had before loading it, and has it
+// drop every so often the blobs nothing needs (pulled ones once written)
+async fn load_purged_store(store_path: &Path) -> Result<FsStore> {
+    let wiped = purge::purge_store(store_path)?;
+    log_debug!("- purged {wiped} files of the store");
+
+    let options = Options {
+        gc: Some(GcConfig {
+            interval: Duration::from_secs(BLOB_GC_SECS),
+            add_protected: None,
+        }),
+        ..Options::new(store_path)
+    };
+    FsStore::load_with_opts(store_path.join("blobs.db"), options).await
 }
 
-impl MessageProtocol {
-    pub fn new(watcher_tx: watch::Sender<Option<ConnEvent>>) -> Self {
-        Self {
-            message_watcher_tx: watcher_tx,
+// get_ticket_hash retrieves the hash of the content of the ticket
+pub fn get_ticket_hash(ticket_id: &str) -> Result<String> {
+    let ticket: BlobTicket = ticket_id.parse()?;
+    Ok(ticket.hash().to_string())
+}
+
+fn get_download_retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis(DOWNLOAD_RETRY_MILLISECS * 2u64.pow(attempt))
+}
+
+async fn send_msg(endpoint: &Endpoint, node_id: &str, msg: &str) -> Result<()> {
+    if msg.len() > MAX_MESSAGE_BYTES {
+        bail!("message to {node_id} is over {MAX_MESSAGE_BYTES} bytes");
+    }
+
+    let node_addr = NodeAddr::new(NodeId::from_str(node_id)?);
+
+    // open a connection to the accepting node
+    log_debug!("- connecting to {node_id}");
+    let conn = endpoint.connect(node_addr, MESSAGE_PROTOCOL_ALPN).await?;
+    log_debug!("- sending {} bytes to {node_id}", msg.len());
+
+    let (mut send, mut recv) = conn.open_bi().await?; // Open a bidirectional QUIC stream
+
+    send.write_all(msg.as_bytes()).await?; // send message
+    send.finish()?; // signal the end of data for this particular stream
+
+    // wait for the ok
+    let response = recv.read_to_end(2).await?;
+    if response != b"ok" {
+        bail!("unexpected response from {node_id}");
+    }
+
+    // nothing else more to do in the connection.
+    let close_msg = "bye";
+    conn.close(0u32.into(), close_msg.as_bytes());
+
+    Ok(())
+}
+
+// send_msg_with_chaos sends the message unless the chaos drops it, as if it
+// was lost on the way, or kills its connection, failing the send
+async fn send_msg_with_chaos(
+    endpoint: &Endpoint,
+    node_id: &str,
+    msg: &str,
+    chaos: &Chaos,
+) -> Result<()> {
+    if chaos.drops_message() {
+        log_debug!("- chaos drops a message to {node_id}");
+        return Ok(());
+    }
+
+    if chaos.kills_connection() {
+        log_debug!("- chaos kills the connection to {node_id}");
+        let node_addr = NodeAddr::new(NodeId::from_str(node_id)?);
+        let conn = endpoint.connect(node_addr, MESSAGE_PROTOCOL_ALPN).await?;
+        conn.close(1u32.into(), b"chaos");
+        bail!("connection to {node_id} killed by chaos");
+    }
+
+    send_msg(endpoint, node_id, msg).await
+}
+
+// spawn_peer_sender starts the task sending the messages of the node, one
+// at a time
+fn spawn_peer_sender(
+    endpoint: Endpoint,
+    node_id: String,
+    send_results: SendResults,
+    chaos: Arc<Chaos>,
+) -> mpsc::UnboundedSender<String> {
+    let (sender_tx, mut sender_rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        while let Some(msg) = sender_rx.recv().await {
+            let res = send_msg_with_chaos(&endpoint, &node_id, &msg, &chaos).await;
+            let node_id = node_id.clone();
+            send_results.lock().unwrap().push(SendResult { node_id, msg, res });
+        }
+    });
+
+    sender_tx
+}
+
+fn add_transfer_bytes(transfer_bytes: &TransferBytes, node_id: &str, sent: u64, received: u64) {
+    let mut transfer_bytes = transfer_bytes.lock().unwrap();
+    let entry = transfer_bytes.entry(node_id.to_owned()).or_default();
+    entry.0 += sent;
+    entry.1 += received;
+}
+
+async fn handle_provider_events(
+    mut events_rx: mpsc::Receiver<provider::Event>,
+    transfer_bytes: TransferBytes,
+    downloads: Downloads,
+    completed: Downloads,
+) {
+    // NOTE: transfers only know the connection, keep track of the node of each
+    let mut connection_nodes: HashMap<u64, String> = HashMap::new();
+    // and of the hash of each request, until it completes
+    let mut request_hashes: HashMap<(u64, u64), String> = HashMap::new();
+
+    while let Some(evt) = events_rx.recv().await {
+        match evt {
+            provider::Event::ClientConnected {
+                connection_id,
+                node_id,
+                permitted,
+            } => {
+                connection_nodes.insert(connection_id, node_id.to_string());
+                let _ = permitted.send(true).await;
+            }
+            provider::Event::ConnectionClosed { connection_id } => {
+                connection_nodes.remove(&connection_id);
+                request_hashes.retain(|(id, _), _| *id != connection_id);
+            }
+            provider::Event::GetRequestReceived {
+                connection_id,
+                request_id,
+                hash,
+                ..
+            } => {
+                if let Some(node_id) = connection_nodes.get(&connection_id) {
+                    let mut downloads = downloads.lock().unwrap();
+                    downloads.push((node_id.clone(), hash.to_string()));
+                    request_hashes.insert((connection_id, request_id), hash.to_string());
+                }
+            }
+            provider::Event::PushRequestReceived { permitted, .. } => {
+                // nodes don't push blobs to us, we download them
+                let _ = permitted.send(false).await;
+            }
+            provider::Event::TransferCompleted {
+                connection_id,
+                request_id,
+                stats,
+            } => {
+                let hash = request_hashes.remove(&(connection_id, request_id));
+                if let Some(node_id) = connection_nodes.get(&connection_id) {
+                    let sent = stats.payload_bytes_sent + stats.other_bytes_sent;
+                    add_transfer_bytes(&transfer_bytes, node_id, sent, 0);
+                    if let Some(hash) = hash {
+                        completed.lock().unwrap().push((node_id.clone(), hash));
+                    }
+                }
+            }
+            provider::Event::TransferAborted {
+                connection_id,
+                request_id,
+                ..
+            } => {
+                request_hashes.remove(&(connection_id, request_id));
+            }
+            _ => {}
         }
     }
 }
 
+#[derive(Debug, Clone)]
+struct MessageProtocol {
+    message_watcher_tx: watch::Sender<Option<ConnEvent>>,
+    reachable_peers: ReachablePeers,
+    offenses: Offenses,
+    max_message_bytes: usize,
+}
+
 impl ProtocolHandler for MessageProtocol {
     async fn accept(
         &self,
         connection: iroh::endpoint::Connection,
     ) -> std::result::Result<(), AcceptError> {
         let node_id = connection.remote_node_id()?;
+        log_debug!("- connection from {node_id}");
+        self.reachable_peers
+            .lock()
+            .unwrap()
+            .push(node_id.to_string());
 
         let (mut send, mut recv) = connection
             .accept_bi()
             .await
             .map_err(AcceptError::from_err)?;
 
-        // read until the peer finishes the stream
-        let res = recv
-            .read_to_end(usize::MAX)
-            .await
-            .map_err(AcceptError::from_err)?;
+        // read until the peer finishes the stream, a bigger one is aborted
+        // right away instead of being kept on memory
+        let res = match recv.read_to_end(self.max_message_bytes).await {
+            Ok(res) => res,
+            Err(ReadToEndError::TooLong) => {
+                log_warning!(
+                    "- warning: dropped a message of {node_id} over {} bytes",
+                    self.max_message_bytes
+                );
+                self.offenses.lock().unwrap().push(node_id.to_string());
+                connection.close(1u32.into(), b"too long");
+                return Ok(());
+            }
+            Err(e) => return Err(AcceptError::from_err(e)),
+        };
 
         // send an ok message that arrived
         send.write_all(b"ok").await.map_err(AcceptError::from_err)?;
         send.finish()?;
 
-        let res = String::from_utf8_lossy(&res);
-
         // wait until the remote closes the connection, which it does once it
         // received the response.
         connection.closed().await;
 
-        let evt = ConnEvent::ReceivedMessage(node_id.to_string(), res.to_string());
+        // messages are text, anything else isn't from fsy
+        let Ok(res) = String::from_utf8(res) else {
+            log_warning!("- warning: dropped a message of {node_id} that isn't utf-8");
+            self.offenses.lock().unwrap().push(node_id.to_string());
+            return Ok(());
+        };
+
+        let evt = ConnEvent::ReceivedMessage(node_id.to_string(), res);
         let _ = self.message_watcher_tx.send(Some(evt));
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    async fn get_local_pair() -> Result<(Connection, Connection)> {
+        let key_a = crate::key::generate_node_secret_key().secret().to_bytes();
+        let key_b = crate::key::generate_node_secret_key().secret().to_bytes();
+        let conn_a = Connection::new_with_options(&key_a, ConnectionOptions::local_memory()).await?;
+        let conn_b = Connection::new_with_options(&key_b, ConnectionOptions::local_memory()).await?;
+
+        conn_a.add_node_addr(conn_b.get_node_addr().await)?;
+        conn_b.add_node_addr(conn_a.get_node_addr().await)?;
+
+        Ok((conn_a, conn_b))
+    }
+
+    #[test]
+    fn test_get_download_retry_delay() -> Result<()> {
+        let test_values = [
+            // (attempt, expected_millisecs)
+            (0, 1000),
+            (1, 2000),
+            (3, 8000),
+        ];
+
+        for spec in test_values {
+            assert_eq!(get_download_retry_delay(spec.0), Duration::from_millis(spec.1));
+        }
+
+        Ok(())
+    }
+
+    async fn wait_for_event(conn: &mut Connection) -> Option<ConnEvent> {
+        for _ in 0..100 {
+            if let Ok(Some(evt)) = conn.get_events() {
+                return Some(evt);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        None
+    }
+
+    #[tokio::test]
+    async fn test_local_memory_send_msg() -> Result<()> {
+        let (conn_a, mut conn_b) = get_local_pair().await?;
+
+        conn_a
+            .send_msg_to_node(conn_b.get_node_id(), "foo".to_string())
+            .await?;
+
+        let evt = wait_for_event(&mut conn_b).await;
+        match evt {
+            Some(ConnEvent::ReceivedMessage(node_id, msg)) => {
+                assert_eq!(node_id, conn_a.get_node_id());
+                assert_eq!(msg, "foo");
+            }
+            None => panic!("message never arrived"),
+        }
+
+        // the node that connected is reachable
+        assert_eq!(conn_b.take_reachable_peers(), vec![conn_a.get_node_id()]);
+        assert!(conn_b.take_reachable_peers().is_empty());
+
+        conn_a.close().await?;
+        conn_b.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_memory_queue_msg() -> Result<()> {
+        let (conn_a, conn_b) = get_local_pair().await?;
+
+        // messages to a node go out in order, an invalid node doesn't hold
+        // the others back
+        conn_a.queue_msg_to_node("zed".into(), "foo".to_string());
+        conn_a.queue_msg_to_node(conn_b.get_node_id(), "foo".to_string());
+        conn_a.queue_msg_to_node(conn_b.get_node_id(), "bar".to_string());
+
+        let mut send_results = vec![];
+        for _ in 0..100 {
+            send_results.extend(conn_a.take_send_results());
+            if send_results.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let test_values = [
+            // (node_id, msg, is_ok)
+            ("zed".to_string(), "foo", false),
+            (conn_b.get_node_id().to_string(), "foo", true),
+            (conn_b.get_node_id().to_string(), "bar", true),
+        ];
+
+        for spec in test_values {
+            let send_result = send_results
+                .iter()
+                .find(|r| r.node_id == spec.0 && r.msg == spec.1)
+                .unwrap();
+            assert_eq!(send_result.res.is_ok(), spec.2, "{}", spec.1);
+        }
+        let sent_to_b: Vec<&str> = send_results
+            .iter()
+            .filter(|r| r.node_id == conn_b.get_node_id())
+            .map(|r| r.msg.as_str())
+            .collect();
+        assert_eq!(sent_to_b, vec!["foo", "bar"]);
+
+        conn_a.close().await?;
+        conn_b.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_memory_blob_transfer() -> Result<()> {
+        let (conn_a, conn_b) = get_local_pair().await?;
+
+        let dir = std::env::temp_dir().join(format!("fsy_test_conn_{}", conn_a.get_node_id()));
+        std::fs::create_dir_all(&dir)?;
+        let src = dir.join("src.txt");
+        let dst = dir.join("dst.txt");
+        std::fs::write(&src, b"foo bar")?;
+
+        let ticket = conn_a
+            .get_file_ticket(src.to_string_lossy().to_string())
+            .await?;
+        conn_b
+            .download_ticket_to_path(ticket.to_string().into(), dst.to_string_lossy().to_string())
+            .await?;
+
+        assert_eq!(std::fs::read(&dst)?, b"foo bar");
+
+        // both sides know how much went through
+        let received = conn_b.take_transfer_bytes();
+        assert_eq!(received.get(conn_a.get_node_id().as_str()), Some(&(0, 7)));
+        assert!(conn_b.take_transfer_bytes().is_empty());
+
+        let mut sent = conn_a.take_transfer_bytes();
+        for _ in 0..20 {
+            if !sent.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sent = conn_a.take_transfer_bytes();
+        }
+        let sent = sent.get(conn_b.get_node_id().as_str()).unwrap();
+        assert!(sent.0 >= 7);
+
+        std::fs::remove_dir_all(&dir)?;
+        conn_a.close().await?;
+        conn_b.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_memory_blob_stream() -> Result<()> {
+        let (conn_a, conn_b) = get_local_pair().await?;
+
+        let dir = std::env::temp_dir().join(format!("fsy_test_conn_{}", conn_a.get_node_id()));
+        std::fs::create_dir_all(&dir)?;
+        let src = dir.join("src.txt");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&src, &content)?;
+
+        let ticket = conn_a
+            .get_file_ticket(src.to_string_lossy().to_string())
+            .await?;
+        let mut stream = conn_b
+            .download_ticket_stream(ticket.to_string().into())
+            .await?;
+        let mut received = vec![];
+        while let Some(chunk) = stream.next().await {
+            received.extend_from_slice(&chunk?);
+        }
+
+        assert_eq!(received, content);
+        let transfer_bytes = conn_b.take_transfer_bytes();
+        assert_eq!(
+            transfer_bytes.get(conn_a.get_node_id().as_str()),
+            Some(&(0, 5000))
+        );
+
+        std::fs::remove_dir_all(&dir)?;
+        conn_a.close().await?;
+        conn_b.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connection_handle() -> Result<()> {
+        let (conn_a, conn_b) = get_local_pair().await?;
+
+        // content a doesn't have, b keeps retrying its download
+        let missing = BlobTicket::new(
+            conn_a.get_node_addr().await,
+            iroh_blobs::Hash::new(b"missing"),
+            iroh_blobs::BlobFormat::Raw,
+        );
+        let conn_a = conn_a.spawn();
+        let conn_b = conn_b.spawn();
+        let download_conn = conn_b.clone();
+        let download = tokio::spawn(async move {
+            let dst = std::env::temp_dir().join("fsy_test_conn_missing");
+            download_conn
+                .download_ticket_to_path(
+                    missing.to_string().into(),
+                    dst.to_string_lossy().to_string(),
+                )
+                .await
+        });
+
+        // messages go through while the download is still on
+        conn_b.queue_msg_to_node(conn_a.get_node_id(), "foo".to_string());
+        let mut evt = None;
+        for _ in 0..40 {
+            evt = conn_a.get_events().await;
+            if evt.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        match evt {
+            Some(ConnEvent::ReceivedMessage(node_id, msg)) => {
+                assert_eq!(node_id, conn_b.get_node_id());
+                assert_eq!(msg, "foo");
+            }
+            None => panic!("message never arrived"),
+        }
+        assert!(!download.is_finished());
+        let send_results = conn_b.take_send_results().await;
+        assert_eq!(send_results.len(), 1);
+        assert!(send_results[0].res.is_ok());
+        let health = conn_a.get_network_health().await;
+        assert!(health.bound && health.discovery, "{health:?}");
+
+        // once closed, the handle gets nothing back
+        download.abort();
+        conn_a.close().await?;
+        conn_b.close().await?;
+        assert!(conn_b.take_send_results().await.is_empty());
+        assert_eq!(conn_b.get_network_health().await, NetworkHealth::default());
+        assert!(conn_b.get_file_ticket("foo".to_string()).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1210 @@
+use anyhow::{Result, bail};
+use chrono::Utc;
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Mutex, broadcast};
+
+use crate::action::CommAction;
+use crate::events::Event;
+use crate::ids::{GroupName, PeerId};
+use crate::logs::{log, log_error};
+use crate::state::SharedState;
+use crate::{approval, blocklist, health, key, queue, reserved, target, websocket};
+
+pub const CONTROL_SOCKET_FILE_NAME: &str = "control.sock";
+
+// the token the control api port asks for, readable only by the user
+pub const CONTROL_TOKEN_FILE_NAME: &str = "control.token";
+
+// routes the control api port answers without the token, they only tell
+// how the daemon is doing
+const PUBLIC_ROUTES: [&str; 4] = ["/status", "/healthz", "/readyz", "/events"];
+
+// messages bigger than this are refused, the status is the biggest one
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+// the request line and each header can't be longer, nor the headers more
+const MAX_LINE_BYTES: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+// ControlRequest: what external tooling (build systems, scripts...) can ask
+// the daemon to do. only actions a change on the file system could trigger
+// anyway are allowed
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    // let the pullers of the group know the path changed
+    TargetChanged {
+        group: String,
+        #[serde(default)]
+        path: String,
+        #[serde(default)]
+        node: Option<String>, // node name, all of the group if unset
+    },
+    // ask the pushers of a mirror group for what it should have
+    RequestManifest {
+        group: String,
+        #[serde(default)]
+        node: Option<String>,
+    },
+}
+
+// RotationRequest: the new key of the node to announce to the nodes, see
+// `fsy key rotate`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RotationRequest {
+    pub public_key: String,
+    pub signature: String,
+    pub node_ids: Vec<String>,
+}
+
+// GroupKeyRequest: the new key of a group to send to its pullers, see
+// `fsy key rotate <group>`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupKeyRequest {
+    pub group: String,
+    pub version: u64,
+    pub key: String,
+    pub signature: String,
+    pub node_ids: Vec<String>,
+}
+
+// AnnounceRequest: the push group to announce to its pullers, all of them
+// if unset. see `fsy announce`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AnnounceRequest {
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+// ApproveRequest: the changes pending on a group of manual approval to pull,
+// all of them if no path. see `fsy approve`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ApproveRequest {
+    pub group: String,
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+// PauseRequest: the user pausing or resuming the whole sync, see `fsy pause`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PauseRequest {
+    pub paused: bool,
+}
+
+// ControlState: what the control api needs from the daemon
+pub struct ControlState {
+    pub target_groups: Vec<target::TargetGroup>,
+    pub nodes: Vec<target::NodeData>,
+    pub actions_queue: Arc<Mutex<queue::Queue<CommAction>>>,
+    pub status: SharedState,
+    pub blocklist: Arc<Mutex<blocklist::Blocklist>>,
+    pub announce_tx: UnboundedSender<GroupName>, // groups the daemon announces once there is room
+    pub pause_tx: UnboundedSender<bool>,         // the user pausing (or resuming) the sync
+    pub storage_path: PathBuf,                   // where the pending changes are
+}
+
+// get_node_ids retrieves the nodes of the group with the modes, only the
+// one with the name if set
+fn get_node_ids(
+    group: &target::TargetGroup,
+    nodes: &[target::NodeData],
+    modes: &[target::TargetMode],
+    node_name: Option<&str>,
+) -> Result<Vec<PeerId>> {
+    let node_ids = group.get_node_ids(nodes, modes);
+    let Some(node_name) = node_name else {
+        return Ok(node_ids);
+    };
+
+    let node = nodes
+        .iter()
+        .find(|n| n.name == node_name && node_ids.contains(&n.id));
+    match node {
+        Some(node) => Ok(vec![node.id.clone()]),
+        None => bail!(
+            "node \"{node_name}\" is not a target of group \"{}\"",
+            group.name
+        ),
+    }
+}
+
+// is_valid_relative_path checks the path stays inside of the target
+fn is_valid_relative_path(relative_path: &str) -> bool {
+    let path = Path::new(relative_path);
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+        && !reserved::is_reserved_path(path)
+}
+
+// get_actions maps the request to the actions to queue
+pub fn get_actions(
+    request: &ControlRequest,
+    target_groups: &[target::TargetGroup],
+    nodes: &[target::NodeData],
+) -> Result<Vec<CommAction>> {
+    match request {
+        ControlRequest::TargetChanged { group, path, node } => {
+            let Some(group) = target::get_push_group_with_name(target_groups, group) else {
+                bail!("no group \"{group}\" pushing");
+            };
+            if !path.is_empty() && !is_valid_relative_path(path) {
+                bail!("invalid path \"{path}\", it needs to be relative to the group");
+            }
+
+            let modes = [target::TargetMode::Push, target::TargetMode::PushPull];
+            let node_ids = get_node_ids(&group, nodes, &modes, node.as_deref())?;
+            Ok(node_ids
+                .into_iter()
+                .map(|node_id| {
+                    CommAction::TargetHasChanged(node_id, group.name.clone(), path.into())
+                        .to_send_message()
+                })
+                .collect())
+        }
+        ControlRequest::RequestManifest { group, node } => {
+            let mirror = target_groups
+                .iter()
+                .find(|g| g.name == *group && g.is_mirror());
+            let Some(group) = mirror else {
+                bail!("no mirror group \"{group}\"");
+            };
+
+            let node_ids =
+                get_node_ids(group, nodes, &[target::TargetMode::Pull], node.as_deref())?;
+            Ok(node_ids
+                .into_iter()
+                .map(|node_id| {
+                    CommAction::RequestManifest(node_id, group.name.clone()).to_send_message()
+                })
+                .collect())
+        }
+    }
+}
+
+// get_announced_groups retrieves the push groups of the request
+pub fn get_announced_groups(
+    request: &AnnounceRequest,
+    target_groups: &[target::TargetGroup],
+) -> Result<Vec<GroupName>> {
+    let group_names = target::get_push_group_names(target_groups);
+    let Some(group) = &request.group else {
+        return Ok(group_names);
+    };
+
+    if !group_names.iter().any(|name| name == group) {
+        bail!("no group \"{group}\" pushing");
+    }
+    Ok(vec![group.into()])
+}
+
+#[derive(Debug, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+// read_message reads the first line of a request / response and the body,
+// the minimal http needed by the control api
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<(String, String)> {
+    let (first_line, body, _) = read_message_with_headers(reader).await?;
+    Ok((first_line, body))
+}
+
+// read_line reads a line of the message, refusing one too long instead of
+// keeping all of it on memory
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut String) -> Result<usize> {
+    let read = reader
+        .take(MAX_LINE_BYTES as u64 + 1)
+        .read_line(line)
+        .await?;
+    if read > MAX_LINE_BYTES {
+        bail!("line too long");
+    }
+    Ok(read)
+}
+
+// read_message_with_headers reads a message as `read_message` does, along
+// with its headers (names lowercased)
+async fn read_message_with_headers<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<(String, String, Vec<(String, String)>)> {
+    let mut first_line = String::new();
+    read_line(reader, &mut first_line).await?;
+
+    let mut line = String::new();
+    let mut headers = vec![];
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if read_line(reader, &mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if headers.len() >= MAX_HEADERS {
+            bail!("too many headers");
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "content-length" {
+                content_length = value.trim().parse()?;
+            }
+            headers.push((name, value.trim().to_owned()));
+        }
+    }
+
+    if content_length > MAX_BODY_BYTES {
+        bail!("message too big");
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+
+    Ok((
+        first_line.trim().to_owned(),
+        String::from_utf8(body)?,
+        headers,
+    ))
+}
+
+// read_request reads the request along with the key of the client when it
+// asks to upgrade to websocket, and the token it sent
+async fn read_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<(HttpRequest, Option<String>, Option<String>)> {
+    let (request_line, body, headers) = read_message_with_headers(reader).await?;
+    let mut spl = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (spl.next(), spl.next()) else {
+        bail!("invalid request line");
+    };
+
+    let get_header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v);
+    let is_websocket = get_header("upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let websocket_key = get_header("sec-websocket-key").filter(|_| is_websocket);
+    let token = get_header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_owned());
+
+    // NOTE: any page open on a browser of the machine can reach the api on
+    //       the port, only the local ones are let in. the rest need a json
+    //       body to change anything, which a page can't send without the
+    //       browser asking first (and being refused)
+    if let Some(origin) = get_header("origin")
+        && !websocket::is_local_origin(origin)
+    {
+        bail!("request of origin \"{origin}\" refused");
+    }
+    let is_json = get_header("content-type")
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("application/json"));
+    if method != "GET" && !is_json {
+        bail!("{method} needs a content-type of application/json");
+    }
+
+    let request = HttpRequest {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        body,
+    };
+    Ok((request, websocket_key.cloned(), token))
+}
+
+// is_public_request checks if the request only asks how the daemon is doing,
+// what the control api port answers to anyone
+fn is_public_request(request: &HttpRequest) -> bool {
+    let path = request
+        .path
+        .split_once('?')
+        .map_or(request.path.as_str(), |(p, _)| p);
+    request.method == "GET" && PUBLIC_ROUTES.contains(&path)
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    code: u16,
+    body: &serde_json::Value,
+) -> Result<()> {
+    let reason = match code {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        503 => "Service Unavailable",
+        500 => "Internal Server Error",
+        _ => "Error",
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {code} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+// get_query_value retrieves the value of the key on the query of the path,
+// the values are simple names so they aren't decoded
+fn get_query_value<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+// handle_request runs the request, returning the http code and json body
+pub async fn handle_request(
+    request: &HttpRequest,
+    state: &ControlState,
+) -> (u16, serde_json::Value) {
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    match (request.method.as_str(), path) {
+        ("POST", "/actions") => {
+            let actions = serde_json::from_str::<ControlRequest>(&request.body)
+                .map_err(anyhow::Error::from)
+                .and_then(|req| get_actions(&req, &state.target_groups, &state.nodes));
+            match actions {
+                Ok(actions) => {
+                    let queued = actions.len();
+                    state.actions_queue.lock().await.push_multiple(actions);
+                    (200, serde_json::json!({ "queued": queued }))
+                }
+                Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+            }
+        }
+        // the cli updated the blocked nodes, they apply right away
+        ("POST", "/blocklist") => {
+            match serde_json::from_str::<blocklist::Blocklist>(&request.body) {
+                Ok(updated) => {
+                    let blocked = updated.node_ids.len();
+                    state.blocklist.lock().await.set_node_ids(updated.node_ids);
+                    (200, serde_json::json!({ "blocked": blocked }))
+                }
+                Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+            }
+        }
+        // the generations go out through the event loop, as the dirty groups
+        ("POST", "/announce") => {
+            let group_names = serde_json::from_str::<AnnounceRequest>(&request.body)
+                .map_err(anyhow::Error::from)
+                .and_then(|req| get_announced_groups(&req, &state.target_groups));
+            match group_names {
+                Ok(group_names) => {
+                    let announced = group_names.len();
+                    for group_name in group_names {
+                        let _ = state.announce_tx.send(group_name);
+                    }
+                    (200, serde_json::json!({ "announced": announced }))
+                }
+                Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+            }
+        }
+        // the event loop pauses the sync as it does on low battery
+        ("POST", "/pause") => match serde_json::from_str::<PauseRequest>(&request.body) {
+            Ok(pause) => {
+                let _ = state.pause_tx.send(pause.paused);
+                (200, serde_json::json!({ "paused": pause.paused }))
+            }
+            Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+        },
+        // the changes approved are pulled as if they were just notified
+        ("POST", "/approve") => {
+            let actions = serde_json::from_str::<ApproveRequest>(&request.body)
+                .map_err(anyhow::Error::from)
+                .and_then(|req| {
+                    approval::approve(
+                        &state.storage_path,
+                        &state.target_groups,
+                        &req.group,
+                        req.path.as_deref(),
+                    )
+                });
+            match actions {
+                Ok(actions) => {
+                    let queued = actions.len();
+                    state.actions_queue.lock().await.push_multiple(actions);
+                    (200, serde_json::json!({ "queued": queued }))
+                }
+                Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+            }
+        }
+        // the new key is signed by the cli, the daemon only announces it
+        ("POST", "/rotation") => match serde_json::from_str::<RotationRequest>(&request.body) {
+            Ok(rotation) => {
+                let actions: Vec<CommAction> = rotation
+                    .node_ids
+                    .iter()
+                    .map(|node_id| {
+                        CommAction::RotateKey(
+                            node_id.into(),
+                            rotation.public_key.as_str().into(),
+                            rotation.signature.clone(),
+                        )
+                        .to_send_message()
+                    })
+                    .collect();
+                let queued = actions.len();
+                state.actions_queue.lock().await.push_multiple(actions);
+                (200, serde_json::json!({ "queued": queued }))
+            }
+            Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+        },
+        // the key of the group is signed by the cli as well
+        ("POST", "/group-key") => match serde_json::from_str::<GroupKeyRequest>(&request.body) {
+            Ok(group_key) => {
+                let actions: Vec<CommAction> = group_key
+                    .node_ids
+                    .iter()
+                    .map(|node_id| {
+                        CommAction::RotateGroupKey(
+                            node_id.into(),
+                            group_key.group.as_str().into(),
+                            group_key.version,
+                            group_key.key.clone(),
+                            group_key.signature.clone(),
+                        )
+                        .to_send_message()
+                    })
+                    .collect();
+                let queued = actions.len();
+                state.actions_queue.lock().await.push_multiple(actions);
+                (200, serde_json::json!({ "queued": queued }))
+            }
+            Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+        },
+        ("GET", "/status") => {
+            let mut status = state.status.get().await;
+            if let Some(tag) = get_query_value(query, "tag") {
+                status.filter_tag(tag);
+            }
+            match serde_json::to_value(status) {
+                Ok(status) => (200, status),
+                Err(e) => (500, serde_json::json!({ "error": e.to_string() })),
+            }
+        }
+        ("GET", "/state") => match serde_json::to_value(state.status.snapshot().await) {
+            Ok(snapshot) => (200, snapshot),
+            Err(e) => (500, serde_json::json!({ "error": e.to_string() })),
+        },
+        // for orchestrators to restart a wedged daemon, and know when it syncs
+        ("GET", "/healthz") | ("GET", "/readyz") => {
+            let status = state.status.get().await;
+            let problems = health::get_problems(&status, Utc::now().timestamp(), path == "/readyz");
+            let code = if problems.is_empty() { 200 } else { 503 };
+            (
+                code,
+                serde_json::json!({ "ok": problems.is_empty(), "problems": problems }),
+            )
+        }
+        _ => (404, serde_json::json!({ "error": "not found" })),
+    }
+}
+
+// stream_events writes the events of the daemon as they come, a json per
+// line, until the client goes away
+async fn stream_events<W: AsyncWrite + Unpin>(writer: &mut W, status: &SharedState) -> Result<()> {
+    let mut events = status.subscribe();
+    let headers =
+        "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n";
+    writer.write_all(headers.as_bytes()).await?;
+    writer.flush().await?;
+
+    while let Some(event) = recv_event(&mut events).await {
+        // NOTE: a client gone is only noticed on the next event
+        let line = format!("{}\n", serde_json::to_string(&event)?);
+        if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+// stream_events_ws writes the events of the daemon as they come, a json per
+// websocket message, so dashboards on a browser can follow them. ends once
+// the client closes
+async fn stream_events_ws<S>(stream: S, key: &str, status: &SharedState) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut events = status.subscribe();
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    writer
+        .write_all(websocket::get_upgrade_response(key).as_bytes())
+        .await?;
+    writer.flush().await?;
+
+    // NOTE: the messages of the client are only read to know when it goes
+    let read_client = async {
+        loop {
+            match websocket::read_frame(&mut reader).await {
+                Ok((websocket::OPCODE_CLOSE, _)) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+    };
+    let write_events = async {
+        while let Some(event) = recv_event(&mut events).await {
+            let frame = websocket::encode_frame(
+                websocket::OPCODE_TEXT,
+                serde_json::to_string(&event)?.as_bytes(),
+            );
+            if writer.write_all(&frame).await.is_err() || writer.flush().await.is_err() {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    };
+
+    tokio::select! {
+        _ = read_client => {
+            let close = websocket::encode_frame(websocket::OPCODE_CLOSE, &[]);
+            let _ = writer.write_all(&close).await;
+            let _ = writer.flush().await;
+            Ok(())
+        }
+        res = write_events => res,
+    }
+}
+
+// recv_event retrieves the next event of the daemon, none once it stops
+async fn recv_event(events: &mut broadcast::Receiver<Event>) -> Option<Event> {
+    match events.recv().await {
+        Ok(event) => Some(event),
+        Err(broadcast::error::RecvError::Lagged(missed)) => Some(Event::Lagged { missed }),
+        Err(broadcast::error::RecvError::Closed) => None,
+    }
+}
+
+// handle_connection answers the request of the connection. with a token,
+// only the public routes are answered to a client not sending it
+async fn handle_connection<S>(stream: S, state: &ControlState, token: Option<&str>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(stream);
+    let (code, body) = match read_request(&mut reader).await {
+        Ok((request, _, sent_token))
+            if token.is_some()
+                && sent_token.as_deref() != token
+                && !is_public_request(&request) =>
+        {
+            let error = format!(
+                "{} needs the token of {CONTROL_TOKEN_FILE_NAME}",
+                request.path
+            );
+            (401, serde_json::json!({ "error": error }))
+        }
+        Ok((request, key, _)) if request.method == "GET" && request.path == "/events" => {
+            // NOTE: nothing is sent before the response, the buffer is empty
+            return match key {
+                Some(key) => stream_events_ws(reader.into_inner(), &key, &state.status).await,
+                None => stream_events(reader.get_mut(), &state.status).await,
+            };
+        }
+        Ok((request, _, _)) => handle_request(&request, state).await,
+        Err(e) => (400, serde_json::json!({ "error": e.to_string() })),
+    };
+
+    write_response(reader.get_mut(), code, &body).await
+}
+
+// write_token saves a new token for the control api port on the storage,
+// retrieving it. the one of the last start stops working
+fn write_token(storage_path: &Path) -> Result<String> {
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    key::write_private(&storage_path.join(CONTROL_TOKEN_FILE_NAME), &token)?;
+    Ok(token)
+}
+
+// serve_tcp listens to the control api on the port, local only. any user of
+// the machine can reach it, so changing something takes the token only the
+// user running the daemon can read
+pub async fn serve_tcp(port: u16, state: Arc<ControlState>) -> Result<()> {
+    let token: Arc<str> = write_token(&state.storage_path)?.into();
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    log!("- control api on 127.0.0.1:{port}");
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &state, Some(&token)).await {
+                log_error!("- control api error: {e}");
+            }
+        });
+    }
+}
+
+// serve_unix listens to the control api on a unix socket, only the user
+// running the daemon can use it
+#[cfg(unix)]
+pub async fn serve_unix(socket_path: &std::path::Path, state: Arc<ControlState>) -> Result<()> {
+    use std::fs;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tokio::net::UnixListener;
+
+    // NOTE: a daemon that crashed leaves the socket behind, the instance
+    //       lock keeps the storage to this daemon. one answering on it is
+    //       never removed anyway
+    if tokio::net::UnixStream::connect(socket_path).await.is_ok() {
+        bail!("another daemon answers on {}", socket_path.display());
+    }
+    let _ = fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path)?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+    let uid = fs::metadata(socket_path)?.uid();
+    log!("- control api on {}", socket_path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+
+        // NOTE: the permissions are set right after binding, the peer is
+        //       checked anyway in case someone got in between
+        let peer_uid = stream.peer_cred().map(|cred| cred.uid());
+        if peer_uid.ok() != Some(uid) {
+            log!("- control api refused a connection of another user");
+            continue;
+        }
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &state, None).await {
+                log_error!("- control api error: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_state() -> ControlState {
+        let nodes = ["foo", "bar"]
+            .iter()
+            .map(|name| target::NodeData {
+                name: name.to_string(),
+                id: format!("{name}_id").into(),
+                ..Default::default()
+            })
+            .collect();
+        let targets = vec![
+            target::Target {
+                mode: target::TargetMode::Push,
+                node_name: "foo".to_string(),
+                include: vec![],
+            },
+            target::Target {
+                mode: target::TargetMode::PushPull,
+                node_name: "bar".to_string(),
+                include: vec![],
+            },
+        ];
+        let target_groups = vec![
+            target::TargetGroup {
+                name: "docs".into(),
+                path: "/tmp/docs".to_string(),
+                targets,
+                ..Default::default()
+            },
+            target::TargetGroup {
+                name: "backup".into(),
+                path: "/tmp/backup".to_string(),
+                targets: vec![target::Target {
+                    mode: target::TargetMode::Pull,
+                    node_name: "foo".to_string(),
+                    include: vec![],
+                }],
+                mirror: true,
+                ..Default::default()
+            },
+        ];
+
+        let status = SharedState::new(
+            crate::status::Status::new("1234", &target_groups),
+            std::env::temp_dir().join("fsy_test_control_status.toml"),
+        );
+        ControlState {
+            target_groups,
+            nodes,
+            actions_queue: Arc::new(Mutex::new(queue::Queue::new(10))),
+            status,
+            blocklist: Arc::new(Mutex::new(blocklist::Blocklist::default())),
+            announce_tx: tokio::sync::mpsc::unbounded_channel().0,
+            pause_tx: tokio::sync::mpsc::unbounded_channel().0,
+            storage_path: std::env::temp_dir().join("fsy_test_control"),
+        }
+    }
+
+    #[test]
+    fn test_get_actions() -> Result<()> {
+        let state = get_state();
+        let test_values = [
+            // (body, expected node ids, none if refused)
+            (
+                r#"{"action":"target-changed","group":"docs","path":"a/b.txt"}"#,
+                Some(vec!["foo_id", "bar_id"]),
+            ),
+            (
+                r#"{"action":"target-changed","group":"docs","node":"bar"}"#,
+                Some(vec!["bar_id"]),
+            ),
+            (
+                r#"{"action":"target-changed","group":"docs","node":"zed"}"#,
+                None,
+            ),
+            (r#"{"action":"target-changed","group":"backup"}"#, None),
+            (
+                r#"{"action":"target-changed","group":"docs","path":"../a.txt"}"#,
+                None,
+            ),
+            (
+                r#"{"action":"target-changed","group":"docs","path":"/a.txt"}"#,
+                None,
+            ),
+            (
+                r#"{"action":"target-changed","group":"docs","path":".fsy/a.txt"}"#,
+                None,
+            ),
+            (
+                r#"{"action":"request-manifest","group":"backup"}"#,
+                Some(vec!["foo_id"]),
+            ),
+            (r#"{"action":"request-manifest","group":"docs"}"#, None),
+        ];
+
+        for spec in test_values {
+            let request: ControlRequest = serde_json::from_str(spec.0)?;
+            let res = get_actions(&request, &state.target_groups, &state.nodes);
+            match spec.1 {
+                Some(node_ids) => {
+                    let res: Vec<PeerId> = res?
+                        .into_iter()
+                        .filter_map(|a| match a {
+                            CommAction::SendMessage(node_id, _) => Some(node_id),
+                            _ => None,
+                        })
+                        .collect();
+                    assert_eq!(res, node_ids, "{}", spec.0);
+                }
+                None => assert!(res.is_err(), "{}", spec.0),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_request() -> Result<()> {
+        let raw = "POST /actions HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let (request, key, token) = read_request(&mut reader).await?;
+        assert_eq!(
+            request,
+            HttpRequest {
+                method: "POST".to_string(),
+                path: "/actions".to_string(),
+                body: "{\"a\":1}".to_string(),
+            }
+        );
+        assert_eq!(key, None);
+        assert_eq!(token, None);
+
+        let raw = "GET /status HTTP/1.1\r\nAuthorization: Bearer abc\r\n\r\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let (_, _, token) = read_request(&mut reader).await?;
+        assert_eq!(token.as_deref(), Some("abc"));
+
+        let test_values = [
+            // (headers, websocket key)
+            (
+                "Upgrade: websocket\r\nSec-WebSocket-Key: abc\r\n",
+                Some("abc"),
+            ),
+            (
+                "upgrade: WebSocket\r\nsec-websocket-key:  abc \r\n",
+                Some("abc"),
+            ),
+            ("Sec-WebSocket-Key: abc\r\n", None),
+            ("Upgrade: h2c\r\nSec-WebSocket-Key: abc\r\n", None),
+        ];
+        for spec in test_values {
+            let raw = format!("GET /events HTTP/1.1\r\n{}\r\n", spec.0);
+            let mut reader = BufReader::new(raw.as_bytes());
+            let (_, key, _) = read_request(&mut reader).await?;
+            assert_eq!(key.as_deref(), spec.1, "{spec:?}");
+        }
+
+        let test_values = [
+            // (method, headers, allowed)
+            (
+                "GET",
+                "Origin: https://example.com\r\nUpgrade: websocket\r\nSec-WebSocket-Key: abc\r\n",
+                false,
+            ),
+            ("GET", "Origin: http://localhost:8080\r\n", true),
+            ("GET", "Origin: https://example.com\r\n", false),
+            (
+                "POST",
+                "Content-Type: application/json; charset=utf-8\r\n",
+                true,
+            ),
+            ("POST", "Content-Type: text/plain\r\n", false),
+            ("POST", "", false),
+            ("OPTIONS", "Origin: http://127.0.0.1\r\n", false),
+            (
+                "POST",
+                "Origin: https://example.com\r\nContent-Type: application/json\r\n",
+                false,
+            ),
+        ];
+        for spec in test_values {
+            let raw = format!("{} /blocklist HTTP/1.1\r\n{}\r\n", spec.0, spec.1);
+            let mut reader = BufReader::new(raw.as_bytes());
+            assert_eq!(read_request(&mut reader).await.is_ok(), spec.2, "{spec:?}");
+        }
+
+        let raw = "POST /actions HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 999999\r\n\r\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+        assert!(read_request(&mut reader).await.is_err());
+
+        // lines going on and endless headers aren't kept on memory
+        let test_values = [
+            // (raw, is read)
+            (format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100)), true),
+            (
+                format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_BYTES)),
+                false,
+            ),
+            (
+                format!(
+                    "GET / HTTP/1.1\r\nX-Foo: {}\r\n\r\n",
+                    "a".repeat(MAX_LINE_BYTES)
+                ),
+                false,
+            ),
+            (
+                format!(
+                    "GET / HTTP/1.1\r\n{}\r\n",
+                    "X-Foo: a\r\n".repeat(MAX_HEADERS)
+                ),
+                true,
+            ),
+            (
+                format!(
+                    "GET / HTTP/1.1\r\n{}\r\n",
+                    "X-Foo: a\r\n".repeat(MAX_HEADERS + 1)
+                ),
+                false,
+            ),
+        ];
+        for spec in test_values {
+            let mut reader = BufReader::new(spec.0.as_bytes());
+            assert_eq!(
+                read_request(&mut reader).await.is_ok(),
+                spec.1,
+                "{}",
+                spec.0.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_request() -> Result<()> {
+        let state = get_state();
+        let request = HttpRequest {
+            method: "POST".to_string(),
+            path: "/actions".to_string(),
+            body: r#"{"action":"target-changed","group":"docs"}"#.to_string(),
+        };
+        let (code, body) = handle_request(&request, &state).await;
+        assert_eq!(code, 200);
+        assert_eq!(body, serde_json::json!({ "queued": 2 }));
+        assert_eq!(state.actions_queue.lock().await.len(), 2);
+
+        let request = HttpRequest {
+            body: r#"{"action":"delete-everything"}"#.to_string(),
+            ..request
+        };
+        assert_eq!(handle_request(&request, &state).await.0, 400);
+
+        let request = HttpRequest {
+            path: "/foo".to_string(),
+            ..request
+        };
+        assert_eq!(handle_request(&request, &state).await.0, 404);
+
+        let request = HttpRequest {
+            path: "/blocklist".to_string(),
+            body: r#"{"node_ids":["foo_id"]}"#.to_string(),
+            ..request
+        };
+        assert_eq!(handle_request(&request, &state).await.0, 200);
+        assert!(state.blocklist.lock().await.is_blocked("foo_id"));
+
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/status?tag=work".to_string(),
+            body: "".to_string(),
+        };
+        let (code, body) = handle_request(&request, &state).await;
+        assert_eq!(code, 200);
+        assert_eq!(body["groups"], serde_json::json!([]));
+
+        let request = HttpRequest {
+            path: "/state".to_string(),
+            ..request
+        };
+        let (code, body) = handle_request(&request, &state).await;
+        assert_eq!(code, 200);
+        assert_eq!(body["status"]["node_id"], "1234");
+        assert_eq!(body["transfers"], serde_json::json!([]));
+
+        // alive while starting, but not ready yet
+        let request = HttpRequest {
+            path: "/healthz".to_string(),
+            ..request
+        };
+        assert_eq!(handle_request(&request, &state).await.0, 200);
+        let request = HttpRequest {
+            path: "/readyz".to_string(),
+            ..request
+        };
+        let (code, body) = handle_request(&request, &state).await;
+        assert_eq!(code, 503);
+        assert_eq!(body["problems"], serde_json::json!(["starting"]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_announce() -> Result<()> {
+        let (announce_tx, mut announce_rx) = tokio::sync::mpsc::unbounded_channel();
+        let state = ControlState {
+            announce_tx,
+            ..get_state()
+        };
+
+        let test_values = [
+            // (body, code, announced groups)
+            (r#"{}"#, 200, vec!["docs"]),
+            (r#"{"group":"docs"}"#, 200, vec!["docs"]),
+            (r#"{"group":"backup"}"#, 400, vec![]),
+            (r#"{"group":"foo"}"#, 400, vec![]),
+        ];
+
+        for spec in test_values {
+            let request = HttpRequest {
+                method: "POST".to_string(),
+                path: "/announce".to_string(),
+                body: spec.0.to_string(),
+            };
+            let (code, _) = handle_request(&request, &state).await;
+            assert_eq!(code, spec.1, "{}", spec.0);
+
+            let mut announced = vec![];
+            while let Ok(group_name) = announce_rx.try_recv() {
+                announced.push(group_name);
+            }
+            assert_eq!(announced, spec.2, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_pause() -> Result<()> {
+        let (pause_tx, mut pause_rx) = tokio::sync::mpsc::unbounded_channel();
+        let state = ControlState {
+            pause_tx,
+            ..get_state()
+        };
+
+        let test_values = [
+            // (body, code, paused)
+            (r#"{"paused":true}"#, 200, Some(true)),
+            (r#"{"paused":false}"#, 200, Some(false)),
+            (r#"{}"#, 400, None),
+        ];
+
+        for spec in test_values {
+            let request = HttpRequest {
+                method: "POST".to_string(),
+                path: "/pause".to_string(),
+                body: spec.0.to_string(),
+            };
+            let (code, _) = handle_request(&request, &state).await;
+            assert_eq!(code, spec.1, "{}", spec.0);
+            assert_eq!(pause_rx.try_recv().ok(), spec.2, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_approve() -> Result<()> {
+        let state = get_state();
+        let _ = std::fs::remove_dir_all(&state.storage_path);
+        let mut pending = approval::PendingChanges::default();
+        pending.add("backup", "a.txt", "foo_id", Utc::now());
+        pending.save(&state.storage_path.join(approval::APPROVALS_FILE_NAME))?;
+
+        let test_values = [
+            // (body, code, actions on the queue)
+            (r#"{"group":"backup","path":"b.txt"}"#, 400, 0),
+            // the mirror asks for the manifest too
+            (r#"{"group":"backup","path":"a.txt"}"#, 200, 2),
+            (r#"{"group":"backup"}"#, 400, 2),
+            (r#"{"group":"foo"}"#, 400, 2),
+        ];
+
+        for spec in test_values {
+            let request = HttpRequest {
+                method: "POST".to_string(),
+                path: "/approve".to_string(),
+                body: spec.0.to_string(),
+            };
+            let (code, _) = handle_request(&request, &state).await;
+            assert_eq!(code, spec.1, "{}", spec.0);
+            assert_eq!(state.actions_queue.lock().await.len(), spec.2, "{}", spec.0);
+        }
+
+        std::fs::remove_dir_all(&state.storage_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_connection_token() -> Result<()> {
+        let state = Arc::new(get_state());
+        let test_values = [
+            // (method, path, headers, code)
+            ("POST", "/blocklist", "", 401),
+            ("POST", "/blocklist", "Authorization: Bearer foo\r\n", 401),
+            ("GET", "/state", "", 401),
+            ("POST", "/pause", "", 401),
+            ("GET", "/status?tag=work", "", 200),
+            ("GET", "/healthz", "", 200),
+            ("POST", "/blocklist", "Authorization: Bearer abc\r\n", 200),
+        ];
+
+        for spec in test_values {
+            let (client, server) = tokio::io::duplex(4096);
+            let server_state = state.clone();
+            tokio::spawn(
+                async move { handle_connection(server, &server_state, Some("abc")).await },
+            );
+
+            let body = r#"{"node_ids":["foo_id"]}"#;
+            let request = format!(
+                "{} {} HTTP/1.1\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\n\r\n{body}",
+                spec.0,
+                spec.1,
+                spec.2,
+                body.len()
+            );
+            let mut reader = BufReader::new(client);
+            reader.get_mut().write_all(request.as_bytes()).await?;
+            let (status_line, _) = read_message(&mut reader).await?;
+            let code = format!(" {} ", spec.3);
+            assert!(status_line.contains(&code), "{spec:?} {status_line}");
+            let is_blocked = state.blocklist.lock().await.is_blocked("foo_id");
+            assert_eq!(is_blocked, spec.3 == 200 && spec.0 == "POST", "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_events() -> Result<()> {
+        let state = Arc::new(get_state());
+        let (client, server) = tokio::io::duplex(4096);
+        let server_state = state.clone();
+        tokio::spawn(async move { handle_connection(server, &server_state, None).await });
+
+        let mut reader = BufReader::new(client);
+        let request = "GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        reader.get_mut().write_all(request.as_bytes()).await?;
+
+        // the events from the headers on come, a line each
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+        while line.trim() != "" {
+            line.clear();
+            reader.read_line(&mut line).await?;
+        }
+
+        state
+            .status
+            .update_state(|s| s.add_error(Some("docs"), "unable to pull"))
+            .await?;
+        line.clear();
+        reader.read_line(&mut line).await?;
+        let event: Event = serde_json::from_str(&line)?;
+        assert_eq!(
+            event,
+            Event::Error {
+                group_name: Some("docs".to_string()),
+                message: "unable to pull".to_string(),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_events_ws() -> Result<()> {
+        let state = Arc::new(get_state());
+        let (client, server) = tokio::io::duplex(4096);
+        let server_state = state.clone();
+        let handle =
+            tokio::spawn(async move { handle_connection(server, &server_state, None).await });
+
+        let mut reader = BufReader::new(client);
+        let request = "GET /events HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        reader.get_mut().write_all(request.as_bytes()).await?;
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        assert_eq!(line, "HTTP/1.1 101 Switching Protocols\r\n");
+        let mut accept = None;
+        while line.trim() != "" {
+            line.clear();
+            reader.read_line(&mut line).await?;
+            if let Some(value) = line.strip_prefix("Sec-WebSocket-Accept: ") {
+                accept = Some(value.trim().to_owned());
+            }
+        }
+        assert_eq!(accept.as_deref(), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        // an event per message
+        state
+            .status
+            .update_state(|s| s.add_error(Some("docs"), "unable to pull"))
+            .await?;
+        let (opcode, payload) = websocket::read_frame(&mut reader).await?;
+        assert_eq!(opcode, websocket::OPCODE_TEXT);
+        let event: Event = serde_json::from_slice(&payload)?;
+        assert!(matches!(event, Event::Error { .. }));
+
+        // the client closing ends it
+        let mut close = vec![0x88, 0x80];
+        close.extend_from_slice(&[0, 0, 0, 0]);
+        reader.get_mut().write_all(&close).await?;
+        handle.await??;
+        let (opcode, _) = websocket::read_frame(&mut reader).await?;
+        assert_eq!(opcode, websocket::OPCODE_CLOSE);
+
+        Ok(())
+    }
+}
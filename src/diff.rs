@@ -0,0 +1,180 @@
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::{manifest, pulled, target};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum DiffKind {
+    #[serde(rename = "ahead")]
+    Ahead, // this node has it, the other doesn't
+    #[serde(rename = "behind")]
+    Behind, // the other node has it, this one doesn't
+    #[serde(rename = "conflicting")]
+    Conflicting, // both have it, changed here since it was last pulled
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiffEntry {
+    pub relative_path: String,
+    pub kind: DiffKind,
+}
+
+// GroupDiff: how the files of a group here differ from the last manifest of
+// the group a node sent, worked out without reaching the node
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupDiff {
+    pub group_name: String,
+    pub node_name: String,
+    pub received: DateTime<Utc>, // when the manifest of the node was received
+    pub files: Vec<DiffEntry>,
+}
+
+impl GroupDiff {
+    pub fn get_count(&self, kind: DiffKind) -> usize {
+        self.files.iter().filter(|f| f.kind == kind).count()
+    }
+}
+
+impl fmt::Display for GroupDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} against {} (as of {}): {} ahead, {} behind, {} conflicting",
+            self.group_name,
+            self.node_name,
+            self.received.format("%Y-%m-%d %H:%M:%S"),
+            self.get_count(DiffKind::Ahead),
+            self.get_count(DiffKind::Behind),
+            self.get_count(DiffKind::Conflicting)
+        )?;
+        for entry in &self.files {
+            let sign = match entry.kind {
+                DiffKind::Ahead => '+',
+                DiffKind::Behind => '-',
+                DiffKind::Conflicting => '!',
+            };
+            writeln!(f, "{sign} {}", entry.relative_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+// get_diff retrieves the files that differ between the local and remote
+// lists, in order. files on both differ when they changed here since they
+// were pulled
+pub fn get_diff(
+    local_files: &[String],
+    remote_files: &[String],
+    has_changed: impl Fn(&str) -> bool,
+) -> Vec<DiffEntry> {
+    let local_files: BTreeSet<&String> = local_files.iter().collect();
+    let remote_files: BTreeSet<&String> = remote_files.iter().collect();
+
+    local_files
+        .union(&remote_files)
+        .filter_map(|relative_path| {
+            let kind = match (
+                local_files.contains(relative_path),
+                remote_files.contains(relative_path),
+            ) {
+                (true, false) => DiffKind::Ahead,
+                (false, true) => DiffKind::Behind,
+                _ if has_changed(relative_path) => DiffKind::Conflicting,
+                _ => return None,
+            };
+
+            Some(DiffEntry {
+                relative_path: relative_path.to_string(),
+                kind,
+            })
+        })
+        .collect()
+}
+
+// diff_group compares the files of the group against the last manifest of
+// it the node sent, it needs none of the nodes to be reachable
+pub fn diff_group(
+    storage_path: &Path,
+    group: &target::TargetGroup,
+    node_id: &str,
+    node_name: &str,
+) -> Result<GroupDiff> {
+    let remote_path = manifest::get_remote_path(storage_path, &group.name, node_id);
+    let Ok(content) = fs::read_to_string(&remote_path) else {
+        bail!(
+            "no manifest of \"{}\" received from {node_name} yet, mirrors and reconciles get it",
+            group.name
+        );
+    };
+    let received: DateTime<Utc> = fs::metadata(&remote_path)?.modified()?.into();
+    let remote_files = manifest::decode_manifest(&content);
+    let local_files = manifest::list_group_files(&group.get_roots())?;
+
+    let pulled = pulled::Pulled::load(&storage_path.join(pulled::PULLED_FILE_NAME))?;
+    let files = get_diff(&local_files, &remote_files, |relative_path| {
+        group
+            .get_file_path(relative_path)
+            .is_some_and(|file_path| pulled.has_changed(&group.name, relative_path, &file_path))
+    });
+
+    Ok(GroupDiff {
+        group_name: group.name.to_string(),
+        node_name: node_name.to_owned(),
+        received,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_diff() -> Result<()> {
+        let to_files =
+            |files: &[&str]| -> Vec<String> { files.iter().map(|f| f.to_string()).collect() };
+        let test_values = [
+            // (local, remote, changed, expected)
+            (vec!["a", "b"], vec!["a", "b"], vec![], vec![]),
+            (
+                vec!["a", "b"],
+                vec!["b", "c"],
+                vec![],
+                vec![("a", DiffKind::Ahead), ("c", DiffKind::Behind)],
+            ),
+            (
+                vec!["a", "b"],
+                vec!["a", "b"],
+                vec!["b"],
+                vec![("b", DiffKind::Conflicting)],
+            ),
+            // only files on both can conflict
+            (vec![], vec!["a"], vec!["a"], vec![("a", DiffKind::Behind)]),
+            (vec![], vec![], vec![], vec![]),
+        ];
+
+        for spec in test_values {
+            let diff = get_diff(&to_files(&spec.0), &to_files(&spec.1), |f| {
+                spec.2.contains(&f)
+            });
+            let expected: Vec<DiffEntry> = spec
+                .3
+                .iter()
+                .map(|(relative_path, kind)| DiffEntry {
+                    relative_path: relative_path.to_string(),
+                    kind: *kind,
+                })
+                .collect();
+            assert_eq!(diff, expected, "{:?}", spec);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// events kept for a subscriber reading slower than they come, past it the
+// subscriber misses the oldest and gets a `Lagged` instead
+pub const EVENTS_CAPACITY: usize = 256;
+
+// Event: what happens on the daemon as it happens, for whoever follows it
+// (`fsy events`, a tui...) instead of going through the logs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    TransferStarted {
+        node_id: String,
+        group_name: String,
+        relative_path: String,
+    },
+    TransferEnded {
+        node_id: String,
+        group_name: String,
+        relative_path: String,
+    },
+    // a change on a group of manual approval, pulled once `fsy approve`
+    ChangePending {
+        node_id: String,
+        group_name: String,
+        relative_path: String,
+    },
+    PeerSeen {
+        node_id: String,
+        version: String,
+    },
+    PeerPath {
+        node_id: String,
+        path: Option<String>, // how the data goes ("direct", "relay"...), none once unreachable
+    },
+    Error {
+        group_name: Option<String>,
+        message: String,
+    },
+    // the subscriber read too slow and missed some, the state is on `/state`
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TransferStarted {
+                node_id,
+                group_name,
+                relative_path,
+            } => writeln!(f, "{group_name}: pulling {relative_path} from {node_id}"),
+            Self::TransferEnded {
+                node_id,
+                group_name,
+                relative_path,
+            } => writeln!(f, "{group_name}: done with {relative_path} from {node_id}"),
+            Self::ChangePending {
+                node_id,
+                group_name,
+                relative_path,
+            } => writeln!(
+                f,
+                "{group_name}: {relative_path} changed on {node_id}, waiting for approval"
+            ),
+            Self::PeerSeen { node_id, version } => writeln!(f, "{node_id}: seen on {version}"),
+            Self::PeerPath {
+                node_id,
+                path: Some(path),
+            } => writeln!(f, "{node_id}: reachable ({path})"),
+            Self::PeerPath {
+                node_id,
+                path: None,
+            } => writeln!(f, "{node_id}: unreachable"),
+            Self::Error {
+                group_name: Some(group_name),
+                message,
+            } => writeln!(f, "{group_name}: error, {message}"),
+            Self::Error {
+                group_name: None,
+                message,
+            } => writeln!(f, "error, {message}"),
+            Self::Lagged { missed } => writeln!(f, "missed {missed} events"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_event_json() -> Result<()> {
+        let test_values = [
+            // (event, json)
+            (
+                Event::TransferStarted {
+                    node_id: "foo".into(),
+                    group_name: "docs".into(),
+                    relative_path: "a.txt".into(),
+                },
+                r#"{"event":"transfer-started","node_id":"foo","group_name":"docs","relative_path":"a.txt"}"#,
+            ),
+            (
+                Event::ChangePending {
+                    node_id: "foo".into(),
+                    group_name: "docs".into(),
+                    relative_path: "a.txt".into(),
+                },
+                r#"{"event":"change-pending","node_id":"foo","group_name":"docs","relative_path":"a.txt"}"#,
+            ),
+            (
+                Event::PeerPath {
+                    node_id: "foo".into(),
+                    path: None,
+                },
+                r#"{"event":"peer-path","node_id":"foo","path":null}"#,
+            ),
+            (
+                Event::Lagged { missed: 3 },
+                r#"{"event":"lagged","missed":3}"#,
+            ),
+        ];
+
+        for (event, json) in test_values {
+            assert_eq!(serde_json::to_string(&event)?, json);
+            assert_eq!(serde_json::from_str::<Event>(json)?, event);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,496 @@
+use anyhow::{Result, bail};
+use serde::Serialize;
+use std::fmt;
+
+use crate::config::{Config, LocalNodeData};
+use crate::conflict::ConflictPolicy;
+use crate::target::{Durability, NodeData, Target, TargetGroup, TargetMode};
+
+// comments of the keys of an example, by the table they are on. the examples
+// are the config structs as they are, a key missing here fails the tests so
+// the comments follow the config as it changes
+const TOP_COMMENTS: [(&str, &str); 1] = [(
+    "blocked_nodes",
+    "node ids never talked to, see `fsy node block`",
+)];
+
+const LOCAL_COMMENTS: [(&str, &str); 24] = [
+    (
+        "public_key",
+        "id of this node, a new one. keep the one of your config when adapting it",
+    ),
+    (
+        "secret_key",
+        "key of this node, a new one. keep the one of your config when adapting it",
+    ),
+    (
+        "push_debounce_millisecs",
+        "changes are pushed once settled for x ms",
+    ),
+    (
+        "loop_debounce_millisecs",
+        "queue and events are checked every x ms",
+    ),
+    (
+        "scan_files_per_sec",
+        "throttle of the startup scan, unlimited if unset",
+    ),
+    (
+        "api_port",
+        "local port of the control api, the unix socket only if unset",
+    ),
+    (
+        "hook",
+        "command run on daemon events (synced, disk-full, ...)",
+    ),
+    (
+        "pause_on_battery_below",
+        "percent of battery the sync waits under, unplugged",
+    ),
+    (
+        "pause_on_metered",
+        "the sync waits while on a metered connection",
+    ),
+    (
+        "manifest_verify_secs",
+        "how often the groups are checked against the disk, hourly if unset",
+    ),
+    (
+        "announce_secs",
+        "how often the push groups are announced to their pullers, hourly if unset",
+    ),
+    (
+        "local_only",
+        "no relays and no public discovery, nodes are reached on their addrs",
+    ),
+    (
+        "local_port",
+        "port listened on when local only, any free one if unset",
+    ),
+    (
+        "log_level",
+        "\"error\", \"warning\", \"info\" (default) or \"debug\"",
+    ),
+    (
+        "log_filters",
+        "level of single modules, as \"connection=debug\"",
+    ),
+    (
+        "trust_on_first_use",
+        "unknown nodes contacting wait on `fsy peers pending` to be approved",
+    ),
+    (
+        "max_message_bytes",
+        "biggest message taken from a node, 1MB if unset",
+    ),
+    (
+        "max_in_flight_bytes",
+        "bytes of downloads queued at once, 1GB if unset",
+    ),
+    (
+        "max_open_files",
+        "open files the daemon keeps under, the limit of the process if unset",
+    ),
+    (
+        "max_pending_changes",
+        "changes of the watcher kept in memory, 100000 if unset",
+    ),
+    (
+        "purge_blobs",
+        "the blob store keeps copies only as long as needed, wiped on start",
+    ),
+    (
+        "storage_path",
+        "where the daemon keeps its data, on the state dir if unset",
+    ),
+    (
+        "standby",
+        "every group is only pulled, mirrored and archived, until `fsy promote`",
+    ),
+    (
+        "standby_versions",
+        "old versions of each file a standby keeps, 10 if unset",
+    ),
+];
+
+const NODE_COMMENTS: [(&str, &str); 5] = [
+    ("name", "name of the node on this config, the groups use it"),
+    ("id", "id of the node, `fsy id` on it"),
+    (
+        "daily_quota_bytes",
+        "soft limit of bytes a day, pushes to the node pause over it",
+    ),
+    (
+        "weekly_quota_bytes",
+        "soft limit of bytes a week, pushes to the node pause over it",
+    ),
+    (
+        "addrs",
+        "`ip:port` the node is reached on without discovery",
+    ),
+];
+
+const GROUP_COMMENTS: [(&str, &str); 32] = [
+    ("name", "name of the group, the same on every node of it"),
+    ("path", "file or folder synced, `~` is the home"),
+    ("paths", "folders of a group spanning many, instead of path"),
+    (
+        "durability",
+        "\"none\" or \"fsync\", pulled files synced to disk right away",
+    ),
+    ("priority", "higher priority groups sync first"),
+    (
+        "max_file_size",
+        "bigger files pause the group until `fsy confirm`",
+    ),
+    (
+        "max_files_per_batch",
+        "more files changed at once pause the group until `fsy confirm`",
+    ),
+    (
+        "sync_xattrs",
+        "extended attributes (tags, labels) go along with the files",
+    ),
+    (
+        "windows_path_policy",
+        "\"rename\", \"skip\" or \"fail\" names windows can't handle",
+    ),
+    (
+        "mirror",
+        "pulled files the pusher doesn't have anymore go to the trash",
+    ),
+    (
+        "archive",
+        "replaced files are kept under `.fsy/versions`, a backup",
+    ),
+    (
+        "conflict",
+        "\"overwrite\" or \"keep-both\" when both sides changed a file",
+    ),
+    ("conflict_name", "name of the conflict copies"),
+    ("conflict_location", "\"next-to-file\" or \"conflicts-dir\""),
+    (
+        "snapshot",
+        "changed files are sent as they were all at once",
+    ),
+    ("description", "what the group is about, on `fsy status`"),
+    (
+        "tags",
+        "labels to filter and toggle groups, `fsy status --tag`",
+    ),
+    ("chown", "owner of the pulled files, \"user:group\""),
+    ("chmod", "octal mode of the pulled files, \"640\""),
+    (
+        "umask",
+        "octal umask of the pulled files when no chmod, \"027\"",
+    ),
+    ("serve_http", "the files are served read only over http"),
+    (
+        "http_bind",
+        "address the files are served on, \"127.0.0.1:8080\"",
+    ),
+    (
+        "push_debounce_millisecs",
+        "overrides the one of local for the group",
+    ),
+    ("transforms", "what the files go through before being sent"),
+    (
+        "max_parallel_actions",
+        "actions of the group run at once, 1 if unset",
+    ),
+    (
+        "tombstone_generations",
+        "generations removed files are remembered for, 1000 if unset",
+    ),
+    (
+        "append_only",
+        "the files only grow (logs, journals), only what was appended is sent",
+    ),
+    (
+        "approval",
+        "\"auto\" or \"manual\", changes wait for `fsy approve` before pulling",
+    ),
+    (
+        "fs_snapshot_command",
+        "snapshots the file system before a batch of pulls lands",
+    ),
+    (
+        "fs_rollback_command",
+        "rolls the file system back to a snapshot, `fsy rollback`",
+    ),
+    (
+        "delete_grace_secs",
+        "removed files are only removed on the pullers if still gone after it",
+    ),
+    (
+        "max_versions",
+        "old versions of each file an archive keeps, all if unset",
+    ),
+];
+
+const TARGET_COMMENTS: [(&str, &str); 3] = [
+    ("mode", "\"push\", \"pull\" or \"push-pull\" with the node"),
+    ("node_name", "name of the node on nodes"),
+    (
+        "include",
+        "patterns of the paths pushed to the node, all if empty",
+    ),
+];
+
+const TRANSFORM_COMMENTS: [(&str, &str); 3] = [
+    ("kind", "\"strip-exif\", \"line-endings\" or \"command\""),
+    ("to", "line endings of line-endings, \"lf\" or \"crlf\""),
+    ("command", "shell command of command, the file on stdin"),
+];
+
+// get_comment retrieves what the key of the table does
+fn get_comment(table: &str, key: &str) -> Option<&'static str> {
+    let comments: &[(&str, &str)] = match table {
+        "" => &TOP_COMMENTS,
+        "local" => &LOCAL_COMMENTS,
+        "nodes" => &NODE_COMMENTS,
+        "target_groups" => &GROUP_COMMENTS,
+        "target_groups.targets" => &TARGET_COMMENTS,
+        "target_groups.transforms" => &TRANSFORM_COMMENTS,
+        _ => return None,
+    };
+    comments
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, comment)| *comment)
+}
+
+// Topology: a common way of setting the nodes up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topology {
+    Backup,       // a laptop pushing to a server
+    BackupServer, // the server of the backup
+    Mesh,         // three nodes editing the same files
+    Publish,      // a node pushing to the ones that only pull
+}
+
+pub const TOPOLOGIES: [Topology; 4] = [
+    Topology::Backup,
+    Topology::BackupServer,
+    Topology::Mesh,
+    Topology::Publish,
+];
+
+impl Topology {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match TOPOLOGIES.iter().find(|t| t.name() == name) {
+            Some(topology) => Ok(*topology),
+            None => {
+                let names: Vec<&str> = TOPOLOGIES.iter().map(|t| t.name()).collect();
+                bail!("unknown example \"{name}\", one of {}", names.join(", "))
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Backup => "backup",
+            Self::BackupServer => "backup-server",
+            Self::Mesh => "mesh",
+            Self::Publish => "publish",
+        }
+    }
+
+    fn get_description(&self) -> &'static str {
+        match self {
+            Self::Backup => {
+                "laptop <-> server backup, the config of the laptop.
+the documents are pushed to the server as they change, the server keeps the
+versions they replace (see `fsy config example backup-server`). the sync
+waits while the laptop is on battery or on a metered connection"
+            }
+            Self::BackupServer => {
+                "laptop <-> server backup, the config of the server.
+the documents of the laptop are pulled into a folder of the server, the
+versions replaced are kept under `.fsy/versions` and the files are synced
+to disk right away (see `fsy config example backup`)"
+            }
+            Self::Mesh => {
+                "three node mesh, the config of the desktop.
+the notes are edited on any of the desktop, the laptop and the workstation,
+each pushes and pulls them with the other two. a file edited on two of them
+at once is kept as a conflict copy, the other nodes have the same config
+with the names of the other two"
+            }
+            Self::Publish => {
+                "publish only, the config of the node publishing.
+the site is pushed to the mirrors as it was once built, they only pull it.
+on the mirrors the group has a pull target of this node and `mirror = true`
+so files removed here are removed there too"
+            }
+        }
+    }
+
+    fn get_config(&self) -> Config {
+        // NOTE: a new key, the example is a config of a node of its own
+        let local = Config::default().local;
+
+        match self {
+            Self::Backup => Config {
+                local: LocalNodeData {
+                    pause_on_battery_below: Some(20),
+                    pause_on_metered: true,
+                    ..local
+                },
+                nodes: vec![get_node("server")],
+                target_groups: vec![TargetGroup {
+                    name: "documents".into(),
+                    path: "~/Documents".to_string(),
+                    targets: vec![get_target(TargetMode::Push, "server")],
+                    description: Some("documents backed up to the server".to_string()),
+                    tags: vec!["backup".to_string()],
+                    ..Default::default()
+                }],
+                ..Config::default()
+            },
+            Self::BackupServer => Config {
+                local: LocalNodeData {
+                    storage_path: Some("/var/lib/fsy".to_string()),
+                    ..local
+                },
+                nodes: vec![get_node("laptop")],
+                target_groups: vec![TargetGroup {
+                    name: "documents".into(),
+                    path: "/srv/backup/laptop/documents".to_string(),
+                    targets: vec![get_target(TargetMode::Pull, "laptop")],
+                    durability: Durability::Fsync,
+                    archive: true,
+                    description: Some("documents of the laptop".to_string()),
+                    tags: vec!["backup".to_string()],
+                    ..Default::default()
+                }],
+                ..Config::default()
+            },
+            Self::Mesh => Config {
+                local,
+                nodes: vec![get_node("laptop"), get_node("workstation")],
+                target_groups: vec![TargetGroup {
+                    name: "notes".into(),
+                    path: "~/notes".to_string(),
+                    targets: vec![
+                        get_target(TargetMode::PushPull, "laptop"),
+                        get_target(TargetMode::PushPull, "workstation"),
+                    ],
+                    conflict: ConflictPolicy::KeepBoth,
+                    description: Some("notes edited on every node".to_string()),
+                    ..Default::default()
+                }],
+                ..Config::default()
+            },
+            Self::Publish => Config {
+                local,
+                nodes: vec![get_node("mirror-1"), get_node("mirror-2")],
+                target_groups: vec![TargetGroup {
+                    name: "site".into(),
+                    path: "~/site/public".to_string(),
+                    targets: vec![
+                        get_target(TargetMode::Push, "mirror-1"),
+                        get_target(TargetMode::Push, "mirror-2"),
+                    ],
+                    snapshot: true,
+                    push_debounce_millisecs: Some(5000),
+                    description: Some("the built site, published to the mirrors".to_string()),
+                    ..Default::default()
+                }],
+                ..Config::default()
+            },
+        }
+    }
+}
+
+fn get_node(name: &str) -> NodeData {
+    NodeData {
+        name: name.to_string(),
+        id: format!("<id of the {name}, `fsy id` on it>").into(),
+        ..Default::default()
+    }
+}
+
+fn get_target(mode: TargetMode, node_name: &str) -> Target {
+    Target {
+        mode,
+        node_name: node_name.to_string(),
+        include: vec![],
+    }
+}
+
+// Example: a commented config of a topology, see `fsy config example`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Example {
+    pub name: String,
+    pub config: String,
+}
+
+impl fmt::Display for Example {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.config)
+    }
+}
+
+// get_example renders the config of the topology with what each key does,
+// the keys not set have their defaults
+pub fn get_example(topology: Topology) -> Result<Example> {
+    let content = toml::to_string(&topology.get_config())?;
+
+    let mut config = String::new();
+    for line in topology.get_description().lines() {
+        config.push_str(&format!("# {line}\n"));
+    }
+    config.push_str("#\n# adapt the names, ids and paths, `fsy config check` finds mistakes\n\n");
+    config.push_str(&comment_keys(&content)?);
+
+    Ok(Example {
+        name: topology.name().to_string(),
+        config,
+    })
+}
+
+// comment_keys puts the comment of each key of the config above it
+fn comment_keys(content: &str) -> Result<String> {
+    let mut table = "";
+    let mut commented = String::new();
+    for line in content.lines() {
+        if let Some(name) = line.strip_prefix('[') {
+            table = name.trim_matches(['[', ']']);
+        } else if let Some((key, _)) = line.split_once(" = ") {
+            let Some(comment) = get_comment(table, key) else {
+                bail!("no comment for {key} of [{table}]");
+            };
+            commented.push_str(&format!("# {comment}\n"));
+        }
+        commented.push_str(&format!("{line}\n"));
+    }
+
+    Ok(commented)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_check;
+    use anyhow::Result;
+    use std::path::Path;
+
+    #[test]
+    fn test_get_example() -> Result<()> {
+        for topology in TOPOLOGIES {
+            // every key has a comment and it is a config fsy reads
+            let example = get_example(topology)?;
+            let parsed: Config = toml::from_str(&example.config)?;
+            assert!(!parsed.target_groups.is_empty(), "{}", example.name);
+            let report = config_check::check_config(&example.config, Path::new(""));
+            assert!(!report.has_errors(), "{}: {report}", example.name);
+            assert_eq!(Topology::from_name(&example.name)?, topology);
+        }
+
+        assert!(Topology::from_name("foo").is_err());
+        assert!(comment_keys("[local]\nfoo = 1\n").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::{Result, bail};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// is_same_device checks if both paths live on the same file system. paths
+// that don't exist yet use the closest existing parent instead.
+// when it is not possible to know, we assume they are
+pub fn is_same_device(path_a: &Path, path_b: &Path) -> bool {
+    match (get_device(path_a), get_device(path_b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+#[cfg(unix)]
+fn get_device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    path.ancestors()
+        .find_map(|p| fs::metadata(p).ok())
+        .map(|meta| meta.dev())
+}
+
+#[cfg(not(unix))]
+fn get_device(_path: &Path) -> Option<u64> {
+    None
+}
+
+// move_into_place moves the src file into the dst atomically, meaning
+// that the dst is either the old file or the new one, never half of it.
+// being on different file systems, a rename isn't possible so we
+// stream the content to a staging file next to the dst and rename it then.
+// with fsync, both the content and the directory entry are on disk once done
+pub fn move_into_place(src: &Path, dst: &Path, fsync: bool) -> Result<()> {
+    if is_same_device(src, dst) {
+        if fsync {
+            File::open(src)?.sync_all()?;
+        }
+
+        match fs::rename(src, dst) {
+            Ok(()) if fsync => return sync_parent_dir(dst),
+            Ok(()) => return Ok(()),
+            // NOTE: we might have been wrong about the device, copy it then
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    copy_into_place(src, dst)?;
+    fs::remove_file(src)?;
+
+    Ok(())
+}
+
+// copy_into_place streams the src into a staging file on the dst file
+// system, syncs it to disk and then renames it over the dst
+fn copy_into_place(src: &Path, dst: &Path) -> Result<()> {
+    let staging_path = get_staging_path(dst)?;
+
+    let res = (|| -> Result<()> {
+        let mut reader = File::open(src)?;
+        let mut writer = File::create(&staging_path)?;
+        io::copy(&mut reader, &mut writer)?;
+        writer.sync_all()?;
+
+        fs::rename(&staging_path, dst)?;
+        sync_parent_dir(dst)
+    })();
+
+    // don't leave the staging behind if something went wrong
+    if res.is_err() {
+        let _ = fs::remove_file(&staging_path);
+    }
+
+    res
+}
+
+fn get_staging_path(dst: &Path) -> Result<PathBuf> {
+    let Some(file_name) = dst.file_name() else {
+        bail!("unable to get file name of {}", dst.display());
+    };
+
+    let mut staging_name = file_name.to_os_string();
+    staging_name.push(".staging");
+    Ok(dst.with_file_name(staging_name))
+}
+
+// sync_parent_dir makes sure the directory entry of the path is on disk
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(parent)?.sync_all()?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_test_dir(name: &str) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("fsy_test_export_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_is_same_device() -> Result<()> {
+        let dir = get_test_dir("same_device")?;
+
+        assert!(is_same_device(&dir, &dir.join("foo")));
+        assert!(is_same_device(&dir.join("foo/bar"), &dir.join("zed")));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_into_place() -> Result<()> {
+        let dir = get_test_dir("move")?;
+        let src = dir.join("src");
+        let dst = dir.join("dst");
+        fs::write(&src, b"new")?;
+        fs::write(&dst, b"old")?;
+
+        move_into_place(&src, &dst, false)?;
+
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst)?, b"new");
+
+        fs::write(&src, b"newer")?;
+        move_into_place(&src, &dst, true)?;
+
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst)?, b"newer");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_into_place() -> Result<()> {
+        let dir = get_test_dir("copy")?;
+        let src = dir.join("src");
+        let dst = dir.join("dst");
+        fs::write(&src, b"new")?;
+        fs::write(&dst, b"old")?;
+
+        copy_into_place(&src, &dst)?;
+
+        assert_eq!(fs::read(&src)?, b"new");
+        assert_eq!(fs::read(&dst)?, b"new");
+        assert!(!get_staging_path(&dst)?.exists());
+
+        // a missing source keeps the old content in place
+        fs::remove_file(&src)?;
+        assert!(copy_into_place(&src, &dst).is_err());
+        assert_eq!(fs::read(&dst)?, b"new");
+        assert!(!get_staging_path(&dst)?.exists());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path};
+
+pub const FROZEN_FILE_NAME: &str = "frozen.toml";
+
+// Frozen: the paths of each group left out of the sync both ways until
+// unfrozen (`fsy freeze`), a file or everything under a folder
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Frozen {
+    #[serde(default)]
+    pub groups: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl fmt::Display for Frozen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.groups.values().all(|paths| paths.is_empty()) {
+            return writeln!(f, "nothing frozen");
+        }
+
+        for (group_name, paths) in &self.groups {
+            for path in paths {
+                writeln!(f, "{group_name}: {path}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Frozen {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: Frozen = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    // freeze leaves the path of the group out, returning if it wasn't already
+    pub fn freeze(&mut self, group_name: &str, relative_path: &str) -> Result<bool> {
+        let path = Path::new(relative_path);
+        let is_valid = !relative_path.is_empty()
+            && path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !is_valid {
+            bail!("invalid path \"{relative_path}\", it needs to be relative to the group");
+        }
+
+        let relative_path = relative_path.trim_end_matches('/');
+        Ok(self
+            .groups
+            .entry(group_name.to_owned())
+            .or_default()
+            .insert(relative_path.to_owned()))
+    }
+
+    // unfreeze takes the path of the group back in, returning if it was out
+    pub fn unfreeze(&mut self, group_name: &str, relative_path: &str) -> bool {
+        let Some(paths) = self.groups.get_mut(group_name) else {
+            return false;
+        };
+
+        let unfrozen = paths.remove(relative_path.trim_end_matches('/'));
+        if paths.is_empty() {
+            self.groups.remove(group_name);
+        }
+        unfrozen
+    }
+
+    // is_frozen checks if the path of the group is frozen, itself or a
+    // folder it is under
+    pub fn is_frozen(&self, group_name: &str, relative_path: &str) -> bool {
+        let Some(paths) = self.groups.get(group_name) else {
+            return false;
+        };
+
+        paths.iter().any(|path| {
+            relative_path == path
+                || relative_path
+                    .strip_prefix(path.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+// FreezeToggle: the outcome of freezing or unfreezing a path
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FreezeToggle {
+    pub group_name: String,
+    pub path: String,
+    pub frozen: bool,
+    pub changed: bool,
+}
+
+impl fmt::Display for FreezeToggle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.frozen { "frozen" } else { "unfrozen" };
+        match self.changed {
+            true => writeln!(f, "{}: {} {state}", self.group_name, self.path),
+            false => writeln!(f, "{}: {} was {state} already", self.group_name, self.path),
+        }
+    }
+}
+
+// load_frozen retrieves the paths frozen, none when they can't be read as
+// the sync goes on regardless
+pub fn load_frozen(storage_path: &Path) -> Frozen {
+    Frozen::load(&storage_path.join(FROZEN_FILE_NAME)).unwrap_or_default()
+}
+
+// is_path_frozen checks if the path of the group is frozen on the storage
+pub fn is_path_frozen(storage_path: &Path, group_name: &str, relative_path: &str) -> bool {
+    load_frozen(storage_path).is_frozen(group_name, relative_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_frozen() -> Result<()> {
+        let mut frozen = Frozen::default();
+        assert!(frozen.freeze("docs", "drafts/")?);
+        assert!(frozen.freeze("docs", "a.txt")?);
+        assert!(!frozen.freeze("docs", "a.txt")?);
+        assert!(frozen.freeze("docs", "").is_err());
+        assert!(frozen.freeze("docs", "../a.txt").is_err());
+        assert!(frozen.freeze("docs", "/etc/passwd").is_err());
+
+        let test_values = [
+            // (group, path, is frozen)
+            ("docs", "a.txt", true),
+            ("docs", "a.txt.bak", false),
+            ("docs", "drafts", true),
+            ("docs", "drafts/b.txt", true),
+            ("docs", "drafts/c/d.txt", true),
+            ("docs", "drafts2/b.txt", false),
+            ("docs", "", false),
+            ("photos", "a.txt", false),
+        ];
+        for spec in test_values {
+            assert_eq!(frozen.is_frozen(spec.0, spec.1), spec.2, "{spec:?}");
+        }
+
+        assert!(frozen.unfreeze("docs", "drafts"));
+        assert!(!frozen.unfreeze("docs", "drafts"));
+        assert!(!frozen.is_frozen("docs", "drafts/b.txt"));
+        assert!(frozen.unfreeze("docs", "a.txt"));
+        assert!(frozen.groups.is_empty());
+
+        Ok(())
+    }
+}
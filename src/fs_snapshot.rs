@@ -0,0 +1,270 @@
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::hook;
+use crate::logs::log;
+use crate::target;
+
+pub const FS_SNAPSHOTS_FILE_NAME: &str = "fs_snapshots.toml";
+
+// snapshots of each group kept on the record, older ones are still on the
+// file system until its own retention removes them
+const MAX_FS_SNAPSHOTS: usize = 100;
+
+// pulls of each group of the current batch
+static BATCHES: Mutex<Option<Batches>> = Mutex::new(None);
+
+// FsSnapshot: a snapshot of the file system taken before a batch of pulls
+// of the group landed, to roll back to (`fsy rollback`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FsSnapshot {
+    pub name: String,
+    pub taken: DateTime<Utc>,
+}
+
+// FsSnapshots: the snapshots taken of each group, the oldest first
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FsSnapshots {
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<FsSnapshot>>,
+}
+
+impl fmt::Display for FsSnapshots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.groups.values().all(|snapshots| snapshots.is_empty()) {
+            return writeln!(f, "no snapshots taken");
+        }
+
+        for (group_name, snapshots) in &self.groups {
+            for snapshot in snapshots {
+                writeln!(
+                    f,
+                    "{group_name}: {} taken {}",
+                    snapshot.name,
+                    snapshot.taken.format("%Y-%m-%d %H:%M:%S UTC"),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FsSnapshots {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: FsSnapshots = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn add(&mut self, group_name: &str, snapshot: FsSnapshot) {
+        let snapshots = self.groups.entry(group_name.to_owned()).or_default();
+        snapshots.push(snapshot);
+        if snapshots.len() > MAX_FS_SNAPSHOTS {
+            snapshots.drain(..snapshots.len() - MAX_FS_SNAPSHOTS);
+        }
+    }
+
+    pub fn get(&self, group_name: &str, name: &str) -> Option<&FsSnapshot> {
+        self.groups.get(group_name)?.iter().find(|s| s.name == name)
+    }
+
+    // of_group retrieves the snapshots of the group alone, to list them
+    pub fn of_group(&self, group_name: &str) -> Self {
+        let groups = self
+            .groups
+            .get_key_value(group_name)
+            .map(|(name, snapshots)| (name.clone(), snapshots.clone()))
+            .into_iter()
+            .collect();
+        Self { groups }
+    }
+}
+
+// Batches: when the last pull of each group started, a pull after a while
+// without one (as the synced hook) starts a new batch
+#[derive(Debug, Default)]
+pub struct Batches {
+    last: HashMap<String, Instant>,
+}
+
+impl Batches {
+    // start notes a pull of the group, returning if it starts a batch
+    pub fn start(&mut self, group_name: &str, now: Instant) -> bool {
+        let settle = Duration::from_secs(hook::SYNCED_SETTLE_SECS);
+        let last = self.last.insert(group_name.to_owned(), now);
+        last.is_none_or(|last| now.duration_since(last) >= settle)
+    }
+
+    // forget makes the next pull of the group start a batch again
+    pub fn forget(&mut self, group_name: &str) {
+        self.last.remove(group_name);
+    }
+}
+
+// get_snapshot_name retrieves the name of a snapshot of the group taken at
+// the time, valid for btrfs, zfs and apfs alike
+pub fn get_snapshot_name(group_name: &str, taken: DateTime<Utc>) -> String {
+    let group_name: String = group_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("fsy-{group_name}-{}", taken.format("%Y%m%d%H%M%S"))
+}
+
+// run_command runs the command of the group through the shell, with the
+// snapshot and the folders of the group on the env vars, waiting for it
+pub async fn run_command(
+    command: &str,
+    target: &target::TargetGroup,
+    snapshot_name: &str,
+) -> Result<()> {
+    let paths: Vec<String> = target.get_roots().into_iter().map(|r| r.path).collect();
+    let output = hook::get_shell_command(command)
+        .env("FSY_GROUP", target.name.as_str())
+        .env("FSY_SNAPSHOT", snapshot_name)
+        .env("FSY_PATH", paths.join("\n"))
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "\"{command}\" failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+// snapshot_before_pull takes a snapshot of the group before the first pull
+// of a batch lands, when the group has a command for it. failing to take
+// it fails the pull, nothing lands without one
+pub async fn snapshot_before_pull(storage_path: &Path, target: &target::TargetGroup) -> Result<()> {
+    let Some(command) = &target.fs_snapshot_command else {
+        return Ok(());
+    };
+
+    let starts_batch = match BATCHES.lock() {
+        Ok(mut batches) => batches
+            .get_or_insert_default()
+            .start(&target.name, Instant::now()),
+        Err(_) => true,
+    };
+    if !starts_batch {
+        return Ok(());
+    }
+
+    let taken = Utc::now();
+    let name = get_snapshot_name(&target.name, taken);
+    if let Err(e) = run_command(command, target, &name).await {
+        if let Ok(mut batches) = BATCHES.lock() {
+            batches.get_or_insert_default().forget(&target.name);
+        }
+        bail!("unable to snapshot {} before pulling: {e}", target.name);
+    }
+
+    let snapshots_path = storage_path.join(FS_SNAPSHOTS_FILE_NAME);
+    let mut snapshots = FsSnapshots::load(&snapshots_path)?;
+    snapshots.add(
+        &target.name,
+        FsSnapshot {
+            name: name.clone(),
+            taken,
+        },
+    );
+    snapshots.save(&snapshots_path)?;
+    log!("- {}: snapshot {name} taken before pulling", target.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_batches_start() -> Result<()> {
+        let start = Instant::now();
+        let settle = Duration::from_secs(hook::SYNCED_SETTLE_SECS);
+        let mut batches = Batches::default();
+
+        let test_values = [
+            // (group, since start, starts batch)
+            ("docs", Duration::ZERO, true),
+            ("docs", Duration::from_secs(1), false),
+            ("photos", Duration::from_secs(1), true),
+            // the batch goes on while the pulls keep coming
+            ("docs", settle, false),
+            ("docs", settle * 3, true),
+        ];
+        for spec in test_values {
+            assert_eq!(batches.start(spec.0, start + spec.1), spec.2, "{spec:?}");
+        }
+
+        batches.forget("docs");
+        assert!(batches.start("docs", start + settle * 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_snapshots() -> Result<()> {
+        let taken = DateTime::parse_from_rfc3339("2024-05-01T10:20:30Z")?.to_utc();
+        assert_eq!(
+            get_snapshot_name("my docs/2", taken),
+            "fsy-my_docs_2-20240501102030"
+        );
+
+        let mut snapshots = FsSnapshots::default();
+        for i in 0..MAX_FS_SNAPSHOTS + 2 {
+            let name = format!("fsy-docs-{i}");
+            snapshots.add("docs", FsSnapshot { name, taken });
+        }
+        snapshots.add(
+            "photos",
+            FsSnapshot {
+                name: "fsy-photos-0".into(),
+                taken,
+            },
+        );
+
+        // the oldest ones are off the record
+        assert_eq!(snapshots.groups["docs"].len(), MAX_FS_SNAPSHOTS);
+        assert!(snapshots.get("docs", "fsy-docs-1").is_none());
+        assert!(snapshots.get("docs", "fsy-docs-2").is_some());
+        assert!(snapshots.get("photos", "fsy-docs-2").is_none());
+        assert_eq!(snapshots.of_group("photos").groups.len(), 1);
+        assert!(snapshots.of_group("foo").groups.is_empty());
+
+        Ok(())
+    }
+}
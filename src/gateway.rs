@@ -0,0 +1,363 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::control;
+use crate::logs::{log, log_error};
+use crate::{reserved, target};
+
+// a client taking longer to send its request is let go, it can't hold on
+// to the connection
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+// decode_url_path decodes the `%xx` escapes of the path of a request, none
+// if they don't make a valid path
+fn decode_url_path(url_path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(url_path.len());
+    let mut raw = url_path.bytes();
+    while let Some(b) = raw.next() {
+        if b != b'%' {
+            bytes.push(b);
+            continue;
+        }
+
+        let hex = [raw.next()?, raw.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+// encode_url_path escapes what can't go as is on a link
+fn encode_url_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn escape_html(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn get_content_type(file_path: &Path) -> &'static str {
+    let ext = file_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "txt" | "md" | "log" => "text/plain; charset=utf-8",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "mp4" | "m4v" => "video/mp4",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+// resolve_path retrieves the file or folder of the group the request is
+// for. none if it is outside of the group or on the fsy folder
+pub fn resolve_path(base_path: &Path, url_path: &str) -> Option<PathBuf> {
+    let decoded = decode_url_path(url_path)?;
+    let relative_path = Path::new(decoded.trim_start_matches('/'));
+    let is_valid = relative_path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if !is_valid || reserved::is_reserved_path(relative_path) {
+        return None;
+    }
+
+    // a single file group only serves the file
+    if base_path.is_file() {
+        let is_file = relative_path.as_os_str().is_empty()
+            || Some(relative_path.as_os_str()) == base_path.file_name();
+        return is_file.then(|| base_path.to_path_buf());
+    }
+
+    // links can't take it out of the group
+    let root = fs::canonicalize(base_path).ok()?;
+    let path = fs::canonicalize(root.join(relative_path)).ok()?;
+    path.starts_with(&root).then_some(path)
+}
+
+// get_listing retrieves the html page linking to what the folder has
+fn get_listing(dir_path: &Path, url_path: &str) -> Result<String> {
+    let mut entries: Vec<(String, bool)> = fs::read_dir(dir_path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            (name, entry.path().is_dir())
+        })
+        .filter(|(name, _)| name != reserved::FSY_DIR_NAME)
+        .collect();
+    entries.sort();
+
+    let base_url = url_path.trim_end_matches('/');
+    let title = escape_html(&decode_url_path(url_path).unwrap_or_default());
+    let mut body = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title></head><body>\n<h1>{title}</h1>\n<ul>\n"
+    );
+    if let Some((parent_url, _)) = base_url.rsplit_once('/') {
+        body.push_str(&format!("<li><a href=\"{parent_url}/\">../</a></li>\n"));
+    }
+    for (name, is_dir) in entries {
+        let suffix = if is_dir { "/" } else { "" };
+        body.push_str(&format!(
+            "<li><a href=\"{base_url}/{}{suffix}\">{}{suffix}</a></li>\n",
+            encode_url_path(&name),
+            escape_html(&name)
+        ));
+    }
+    body.push_str("</ul>\n</body></html>\n");
+
+    Ok(body)
+}
+
+async fn write_head<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    code: u16,
+    content_type: &str,
+    content_length: u64,
+) -> Result<()> {
+    let reason = match code {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        _ => "Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {code} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {content_length}\r\nConnection: close\r\n\r\n"
+    );
+    writer.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+async fn write_error<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    code: u16,
+    message: &str,
+) -> Result<()> {
+    write_head(
+        writer,
+        code,
+        "text/plain; charset=utf-8",
+        message.len() as u64,
+    )
+    .await?;
+    writer.write_all(message.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn handle_connection<S>(stream: S, base_path: &Path) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(stream);
+    let timeout = Duration::from_secs(REQUEST_TIMEOUT_SECS);
+    let request_line = tokio::time::timeout(timeout, control::read_message(&mut reader)).await;
+    let stream = reader.get_mut();
+    let request_line = match request_line {
+        Ok(Ok((request_line, _))) => request_line,
+        Ok(Err(_)) => return write_error(stream, 400, "bad request").await,
+        Err(_) => return write_error(stream, 408, "request timeout").await,
+    };
+
+    let mut spl = request_line.split_whitespace();
+    let (method, url_path) = (
+        spl.next().unwrap_or_default(),
+        spl.next().unwrap_or_default(),
+    );
+    if method != "GET" && method != "HEAD" {
+        return write_error(stream, 405, "only GET and HEAD are allowed").await;
+    }
+    let url_path = url_path.split_once('?').map_or(url_path, |(p, _)| p);
+    let Some(path) = resolve_path(base_path, url_path) else {
+        return write_error(stream, 404, "not found").await;
+    };
+
+    let is_head = method == "HEAD";
+    if path.is_dir() {
+        let body = get_listing(&path, url_path)?;
+        write_head(stream, 200, "text/html; charset=utf-8", body.len() as u64).await?;
+        if !is_head {
+            stream.write_all(body.as_bytes()).await?;
+        }
+    } else {
+        // NOTE: pulls are moved into place at once, a file is never served
+        //       half written
+        let mut file = tokio::fs::File::open(&path).await?;
+        let size = file.metadata().await?.len();
+        write_head(stream, 200, get_content_type(&path), size).await?;
+        if !is_head {
+            tokio::io::copy(&mut file, stream).await?;
+        }
+    }
+
+    stream.flush().await?;
+    Ok(())
+}
+
+// serve_group serves what the group has, read only, over http on the bind
+// address so devices without fsy (a tv reading a media folder) can use it
+pub async fn serve_group(group: target::TargetGroup, bind: String) -> Result<()> {
+    let listener = TcpListener::bind(&bind).await?;
+    log!("- serving group \"{}\" on http://{bind}", group.name);
+
+    let base_path = PathBuf::from(group.get_path());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let base_path = base_path.clone();
+        let group_name = group.name.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &base_path).await {
+                log_error!("- http error on group \"{group_name}\": {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_url_path() -> Result<()> {
+        let test_values = [
+            // (raw, decoded)
+            ("/a/b.txt", Some("/a/b.txt")),
+            ("/my%20docs/%C3%A1.txt", Some("/my docs/á.txt")),
+            ("/a%2", None),
+            ("/a%zz", None),
+            ("/%FF", None),
+        ];
+
+        for spec in test_values {
+            let decoded = decode_url_path(spec.0);
+            assert_eq!(decoded.as_deref(), spec.1, "{}", spec.0);
+            if let Some(decoded) = decoded {
+                assert_eq!(decode_url_path(&encode_url_path(&decoded)), Some(decoded));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_path() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_gateway_resolve");
+        let _ = fs::remove_dir_all(&dir);
+        let base_path = dir.join("media");
+        fs::create_dir_all(base_path.join("shows"))?;
+        fs::create_dir_all(base_path.join(".fsy/swaps"))?;
+        fs::write(base_path.join("shows/a b.mkv"), "a")?;
+        fs::write(base_path.join(".fsy/swaps/a.mkv"), "a")?;
+        fs::write(dir.join("secret.txt"), "secret")?;
+
+        let test_values = [
+            // (url path, is served)
+            ("/", true),
+            ("/shows", true),
+            ("/shows/a%20b.mkv", true),
+            ("/shows/none.mkv", false),
+            ("/../secret.txt", false),
+            ("/shows/%2E%2E/%2E%2E/secret.txt", false),
+            ("/.fsy/swaps/a.mkv", false),
+        ];
+
+        for spec in test_values {
+            let res = resolve_path(&base_path, spec.0);
+            assert_eq!(res.is_some(), spec.1, "{}", spec.0);
+        }
+
+        // a single file group only serves the file
+        let file_path = base_path.join("shows/a b.mkv");
+        assert_eq!(resolve_path(&file_path, "/"), Some(file_path.clone()));
+        assert_eq!(
+            resolve_path(&file_path, "/a%20b.mkv"),
+            Some(file_path.clone())
+        );
+        assert_eq!(resolve_path(&file_path, "/other.mkv"), None);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_connection() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_gateway_connection");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("shows"))?;
+        fs::write(dir.join("shows/a.txt"), "foo bar")?;
+
+        let test_values = [
+            // (request line, expected start, expected body)
+            ("GET /shows/a.txt HTTP/1.1", "HTTP/1.1 200", Some("foo bar")),
+            ("HEAD /shows/a.txt HTTP/1.1", "HTTP/1.1 200", Some("")),
+            ("GET /shows HTTP/1.1", "HTTP/1.1 200", None),
+            ("GET /none.txt HTTP/1.1", "HTTP/1.1 404", None),
+            ("PUT /shows/a.txt HTTP/1.1", "HTTP/1.1 405", None),
+        ];
+
+        for spec in test_values {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let (mut client_read, mut client_write) = tokio::io::split(client);
+            client_write
+                .write_all(format!("{}\r\nHost: tv\r\n\r\n", spec.0).as_bytes())
+                .await?;
+            handle_connection(server, &dir).await?;
+
+            let mut response = String::new();
+            client_read.read_to_string(&mut response).await?;
+            assert!(response.starts_with(spec.1), "{}: {response}", spec.0);
+            if let Some(body) = spec.2 {
+                assert!(response.ends_with(&format!("\r\n\r\n{body}")), "{}", spec.0);
+            }
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_connection_timeout() -> Result<()> {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        client_write
+            .write_all(b"GET / HTTP/1.1\r\nHost: tv\r\n")
+            .await?;
+
+        // the headers never end, the client is let go
+        handle_connection(server, Path::new("/nonexistent")).await?;
+        let mut response = String::new();
+        client_read.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+
+        Ok(())
+    }
+}
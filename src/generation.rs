@@ -0,0 +1,217 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::storage;
+
+pub const GENERATIONS_FILE_NAME: &str = "generations.toml";
+
+// how often the push groups are announced to their pullers when not set, the
+// ones that missed a generation reconcile
+pub const DEFAULT_ANNOUNCE_SECS: u64 = 60 * 60;
+
+// Generations: a counter per group going up with every batch of changes it
+// pushes, so a puller can tell it missed some ("I have 41, you announce
+// 45") without relying on the clocks of the nodes
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Generations {
+    #[serde(default)]
+    pub local: BTreeMap<String, u64>, // group -> generation of what we push
+    #[serde(default)]
+    pub remote: BTreeMap<String, BTreeMap<String, u64>>, // node id -> group -> last seen
+    #[serde(default)]
+    pub reconciling: BTreeMap<String, Vec<String>>, // node id -> groups asked for their manifest
+}
+
+impl Generations {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: Generations = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = toml::to_string(self)?;
+        storage::write_atomic(path, content.as_bytes())
+    }
+
+    // update loads, changes and saves the generations, the other lanes wait
+    // so none of their changes are lost
+    pub fn update<T>(path: &Path, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let _lock = storage::lock_state();
+        let mut generations = Self::load(path)?;
+        let res = change(&mut generations)?;
+        generations.save(path)?;
+        Ok(res)
+    }
+
+    // bump moves the group to its next generation, returning it
+    pub fn bump(&mut self, group_name: &str) -> u64 {
+        let generation = self.local.entry(group_name.to_owned()).or_default();
+        *generation += 1;
+        *generation
+    }
+
+    // get_next retrieves the generation the next batch of changes of the
+    // group goes out on
+    pub fn get_next(&self, group_name: &str) -> u64 {
+        self.local.get(group_name).copied().unwrap_or_default() + 1
+    }
+
+    // see notes the generation the node announced for the group, returning
+    // if generations were missed. one going back means the node lost its
+    // state, what it has can't be trusted to be what we have either
+    pub fn see(&mut self, node_id: &str, group_name: &str, generation: u64) -> bool {
+        let last = self
+            .remote
+            .entry(node_id.to_owned())
+            .or_default()
+            .insert(group_name.to_owned(), generation);
+
+        match last {
+            Some(last) => generation > last + 1 || generation < last,
+            None => generation > 1,
+        }
+    }
+
+    // see_current notes the generation the node is at as it connects,
+    // returning if it isn't the last one seen. unlike the announces, the
+    // next one went by while we were away as well
+    pub fn see_current(&mut self, node_id: &str, group_name: &str, generation: u64) -> bool {
+        let last = self
+            .remote
+            .entry(node_id.to_owned())
+            .or_default()
+            .insert(group_name.to_owned(), generation);
+
+        last.unwrap_or_default() != generation
+    }
+
+    // get_current retrieves the generation of the last batch of changes the
+    // group pushed
+    pub fn get_current(&self, group_name: &str) -> u64 {
+        self.local.get(group_name).copied().unwrap_or_default()
+    }
+
+    pub fn set_reconciling(&mut self, node_id: &str, group_name: &str) {
+        let groups = self.reconciling.entry(node_id.to_owned()).or_default();
+        if !groups.iter().any(|g| g == group_name) {
+            groups.push(group_name.to_owned());
+        }
+    }
+
+    // take_reconciling checks if the group was being reconciled with the
+    // node, it isn't anymore
+    pub fn take_reconciling(&mut self, node_id: &str, group_name: &str) -> bool {
+        let Some(groups) = self.reconciling.get_mut(node_id) else {
+            return false;
+        };
+
+        let len = groups.len();
+        groups.retain(|g| g != group_name);
+        let was_reconciling = groups.len() != len;
+        if groups.is_empty() {
+            self.reconciling.remove(node_id);
+        }
+        was_reconciling
+    }
+}
+
+// bump_generation moves the group to its next generation on the storage
+pub fn bump_generation(storage_path: &Path, group_name: &str) -> Result<u64> {
+    let path = storage_path.join(GENERATIONS_FILE_NAME);
+    Generations::update(&path, |generations| Ok(generations.bump(group_name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_see() -> Result<()> {
+        let test_values = [
+            // (seen generations, missed on the last)
+            (vec![1], false),
+            (vec![5], true),
+            (vec![1, 2], false),
+            (vec![1, 2, 2], false),
+            (vec![41, 42], false),
+            (vec![41, 45], true),
+            (vec![41, 3], true),
+        ];
+
+        for spec in test_values {
+            let mut generations = Generations::default();
+            let mut missed = false;
+            for generation in &spec.0 {
+                missed = generations.see("foo", "docs", *generation);
+            }
+            assert_eq!(missed, spec.1, "{:?}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_see_current() -> Result<()> {
+        let test_values = [
+            // (seen generations, differs on the last)
+            (vec![0], false),
+            (vec![1], true),
+            (vec![41, 41], false),
+            (vec![41, 42], true),
+            (vec![41, 3], true),
+        ];
+
+        for spec in test_values {
+            let mut generations = Generations::default();
+            let mut differs = false;
+            for generation in &spec.0 {
+                differs = generations.see_current("foo", "docs", *generation);
+            }
+            assert_eq!(differs, spec.1, "{:?}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bump_and_reconciling() -> Result<()> {
+        let mut generations = Generations::default();
+        assert_eq!(generations.get_next("docs"), 1);
+        assert_eq!(generations.bump("docs"), 1);
+        assert_eq!(generations.bump("docs"), 2);
+        assert_eq!(generations.bump("other"), 1);
+        assert_eq!(generations.get_next("docs"), 3);
+        assert_eq!(generations.get_current("docs"), 2);
+        assert_eq!(generations.get_current("foo"), 0);
+
+        generations.set_reconciling("foo", "docs");
+        generations.set_reconciling("foo", "docs");
+        assert!(!generations.take_reconciling("bar", "docs"));
+        assert!(generations.take_reconciling("foo", "docs"));
+        assert!(!generations.take_reconciling("foo", "docs"));
+        assert!(generations.reconciling.is_empty());
+
+        let dir = std::env::temp_dir().join("fsy_test_generations");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join(GENERATIONS_FILE_NAME);
+        generations.see("foo", "docs", 3);
+        generations.save(&path)?;
+        assert_eq!(Generations::load(&path)?, generations);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+// is_match checks if a path of a group matches the pattern. `*` takes any
+// part of a name, `?` a single character and `**` any number of folders,
+// `photos/2024/**` matches everything under `photos/2024`
+pub fn is_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    matches_segments(&pattern, &path)
+}
+
+fn matches_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| matches_segments(rest, &path[i..])),
+        Some((segment, rest)) => path.split_first().is_some_and(|(name, path_rest)| {
+            let segment: Vec<char> = segment.chars().collect();
+            let name: Vec<char> = name.chars().collect();
+            matches_name(&segment, &name) && matches_segments(rest, path_rest)
+        }),
+    }
+}
+
+fn matches_name(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| matches_name(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && matches_name(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && matches_name(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_is_match() -> Result<()> {
+        let test_values = [
+            // (pattern, path, expected)
+            ("photos/2024/**", "photos/2024/a.jpg", true),
+            ("photos/2024/**", "photos/2024/trip/a.jpg", true),
+            ("photos/2024/**", "photos/2024", true),
+            ("photos/2024/**", "photos/2023/a.jpg", false),
+            ("photos/2024/**", "photos/2024b/a.jpg", false),
+            ("*.pdf", "a.pdf", true),
+            ("*.pdf", "docs/a.pdf", false),
+            ("**/*.pdf", "docs/a.pdf", true),
+            ("**/*.pdf", "a.pdf", true),
+            ("docs/?.txt", "docs/a.txt", true),
+            ("docs/?.txt", "docs/ab.txt", false),
+            ("docs/a.txt", "docs/a.txt", true),
+            ("docs/*", "docs/sub/a.txt", false),
+        ];
+
+        for (pattern, path, expected) in test_values {
+            assert_eq!(is_match(pattern, path), expected, "{pattern} {path}");
+        }
+
+        Ok(())
+    }
+}
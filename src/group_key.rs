@@ -0,0 +1,386 @@
+use anyhow::{Result, bail};
+use iroh::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::key;
+use crate::target;
+
+pub const GROUP_KEYS_FILE_NAME: &str = "group_keys.toml";
+
+// words of the eff wordlist on a group key
+const KEY_WORD_COUNT: u8 = 8;
+
+// GroupKey: the key the nodes of a group share, made by the node pushing it
+// and sent to the pullers on every rotation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupKey {
+    pub key: String,
+    pub version: u64, // goes up with every rotation
+    #[serde(default)]
+    pub confirmed: Vec<String>, // pullers that know this version
+}
+
+// GroupKeys: the keys of the groups, kept on the storage where only the user
+// can read them
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GroupKeys {
+    #[serde(default)]
+    pub groups: BTreeMap<String, GroupKey>, // group -> key
+}
+
+impl GroupKeys {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: GroupKeys = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        key::write_private(path, &toml::to_string(self)?)
+    }
+
+    pub fn get(&self, group_name: &str) -> Option<&GroupKey> {
+        self.groups.get(group_name)
+    }
+
+    // rotate makes the next key of the group (the first one if it has none),
+    // no puller knows it yet
+    pub fn rotate(&mut self, group_name: &str) -> GroupKey {
+        let version = self.get(group_name).map(|k| k.version).unwrap_or_default() + 1;
+        let group_key = GroupKey {
+            key: key::get_random_key(KEY_WORD_COUNT),
+            version,
+            confirmed: vec![],
+        };
+        self.groups.insert(group_name.to_owned(), group_key.clone());
+        group_key
+    }
+
+    // receive keeps the key the node pushing the group sent, returning if
+    // this node knows that version now. an older one is never taken back
+    pub fn receive(&mut self, group_name: &str, version: u64, key: &str) -> bool {
+        let current = self.get(group_name).map(|k| k.version).unwrap_or_default();
+        if version < current {
+            return false;
+        }
+
+        if version > current {
+            let group_key = GroupKey {
+                key: key.to_owned(),
+                version,
+                confirmed: vec![],
+            };
+            self.groups.insert(group_name.to_owned(), group_key);
+        }
+        true
+    }
+
+    // confirm notes the puller knows the version of the key, returning if
+    // it is the current one
+    pub fn confirm(&mut self, group_name: &str, node_id: &str, version: u64) -> bool {
+        let Some(group_key) = self.groups.get_mut(group_name) else {
+            return false;
+        };
+        if group_key.version != version {
+            return false;
+        }
+
+        if !group_key.confirmed.iter().any(|id| id == node_id) {
+            group_key.confirmed.push(node_id.to_owned());
+        }
+        true
+    }
+}
+
+// get_group_key_payload is what gets signed, prefixed so a signature of
+// something else can't be taken as a group key
+fn get_group_key_payload(group_name: &str, version: u64, key: &str) -> Vec<u8> {
+    format!("fsy-group-key;{group_name};{version};{key}").into_bytes()
+}
+
+// sign_group_key signs the key of the group with the key of the node, the
+// pullers can tell it comes from the node pushing the group
+pub fn sign_group_key(secret_key: &[u8; 32], group_name: &str, version: u64, key: &str) -> String {
+    let secret_key = SecretKey::from_bytes(secret_key);
+    let signature = secret_key.sign(&get_group_key_payload(group_name, version, key));
+    hex::encode(signature.to_bytes())
+}
+
+// verify_group_key checks the key of the group was signed by the node
+pub fn verify_group_key(
+    node_id: &str,
+    group_name: &str,
+    version: u64,
+    key: &str,
+    signature: &str,
+) -> Result<()> {
+    let public_key = PublicKey::from_str(node_id)?;
+    let signature = hex::decode(signature)?;
+    let signature = signature.as_slice().try_into()?;
+    public_key.verify(&get_group_key_payload(group_name, version, key), &signature)?;
+    Ok(())
+}
+
+// ShownKey: the outcome of `fsy key show <group>`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ShownKey {
+    pub group_name: String,
+    pub key: String,
+    pub version: u64,
+    pub pending: Vec<String>, // pullers that don't know this version yet, by name
+}
+
+impl fmt::Display for ShownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.key)?;
+        writeln!(
+            f,
+            "version {} of the key of \"{}\"",
+            self.version, self.group_name
+        )?;
+        if !self.pending.is_empty() {
+            writeln!(f, "not confirmed yet by {}", self.pending.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+// show_key retrieves the key of the group, with the pullers that don't know
+// it yet when the group is pushed from here
+pub fn show_key(config: &Config, group_name: &str) -> Result<ShownKey> {
+    let push_group = target::get_push_group_with_name(&config.target_groups, group_name);
+    let is_pulled = target::get_pull_group_with_name(&config.target_groups, group_name).is_some();
+    if push_group.is_none() && !is_pulled {
+        bail!("no group \"{group_name}\" on the config");
+    }
+
+    let keys = GroupKeys::load(&config.get_storage_path().join(GROUP_KEYS_FILE_NAME))?;
+    let Some(group_key) = keys.get(group_name) else {
+        match push_group {
+            Some(_) => bail!(
+                "no key for \"{group_name}\" yet, make one with `fsy key rotate {group_name}`"
+            ),
+            None => bail!("no key for \"{group_name}\" yet, it comes from the node pushing it"),
+        }
+    };
+
+    let modes = [target::TargetMode::Push, target::TargetMode::PushPull];
+    let pending = match push_group {
+        Some(group) => group
+            .get_node_ids(&config.nodes, &modes)
+            .into_iter()
+            .filter(|node_id| !group_key.confirmed.iter().any(|id| id == node_id.as_str()))
+            .map(|node_id| target::get_node_name(&config.nodes, &node_id))
+            .collect(),
+        None => vec![],
+    };
+
+    Ok(ShownKey {
+        group_name: group_name.to_owned(),
+        key: group_key.key.clone(),
+        version: group_key.version,
+        pending,
+    })
+}
+
+// RotatedKey: the outcome of `fsy key rotate <group>`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RotatedKey {
+    pub group_name: String,
+    pub version: u64,
+    pub node_ids: Vec<String>, // pullers the key is sent to
+}
+
+impl fmt::Display for RotatedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "version {} of the key of \"{}\" sent to {} nodes, see who confirmed it with `fsy key show {}`",
+            self.version,
+            self.group_name,
+            self.node_ids.len(),
+            self.group_name
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_rotate_and_receive() -> Result<()> {
+        let mut keys = GroupKeys::default();
+        let first = keys.rotate("docs");
+        assert_eq!(first.version, 1);
+        assert_eq!(first.key.split(' ').count(), KEY_WORD_COUNT as usize);
+        let second = keys.rotate("docs");
+        assert_eq!(second.version, 2);
+        assert_ne!(second.key, first.key);
+        assert_eq!(keys.get("docs"), Some(&second));
+
+        let test_values = [
+            // (received (version, key), knows it, kept version)
+            (vec![(1, "a")], true, 1),
+            (vec![(1, "a"), (1, "b")], true, 1),
+            (vec![(2, "a"), (3, "b")], true, 3),
+            (vec![(3, "a"), (2, "b")], false, 3),
+        ];
+
+        for spec in test_values {
+            let mut keys = GroupKeys::default();
+            let mut knows = false;
+            for (version, key) in &spec.0 {
+                knows = keys.receive("docs", *version, key);
+            }
+            assert_eq!(knows, spec.1, "{spec:?}");
+            assert_eq!(
+                keys.get("docs").map(|k| k.version),
+                Some(spec.2),
+                "{spec:?}"
+            );
+        }
+
+        // a key is never replaced by another of the same version
+        let mut keys = GroupKeys::default();
+        keys.receive("docs", 1, "a");
+        keys.receive("docs", 1, "b");
+        assert_eq!(keys.get("docs").map(|k| k.key.as_str()), Some("a"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirm() -> Result<()> {
+        let mut keys = GroupKeys::default();
+        assert!(!keys.confirm("docs", "foo", 1));
+
+        keys.rotate("docs");
+        keys.rotate("docs");
+        assert!(!keys.confirm("docs", "foo", 1));
+        assert!(keys.confirm("docs", "foo", 2));
+        assert!(keys.confirm("docs", "foo", 2));
+        assert_eq!(keys.get("docs").map(|k| k.confirmed.len()), Some(1));
+
+        // a new rotation needs them to confirm again
+        keys.rotate("docs");
+        assert_eq!(keys.get("docs").map(|k| k.confirmed.len()), Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_group_key() -> Result<()> {
+        let secret_key = key::generate_node_secret_key();
+        let node_id = secret_key.public().to_string();
+        let other_id = key::generate_node_secret_key().public().to_string();
+        let signature = sign_group_key(&secret_key.to_bytes(), "docs", 2, "foo bar");
+
+        let test_values = [
+            // (node_id, group_name, version, key, is_valid)
+            (&node_id, "docs", 2, "foo bar", true),
+            (&other_id, "docs", 2, "foo bar", false),
+            (&node_id, "photos", 2, "foo bar", false),
+            (&node_id, "docs", 3, "foo bar", false),
+            (&node_id, "docs", 2, "foo baz", false),
+        ];
+
+        for spec in test_values {
+            let res = verify_group_key(spec.0, spec.1, spec.2, spec.3, &signature);
+            assert_eq!(res.is_ok(), spec.4, "{spec:?}");
+        }
+        assert!(verify_group_key(&node_id, "docs", 2, "foo bar", "zz").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_key() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_show_key");
+        let _ = fs::remove_dir_all(&dir);
+        let mut config = Config::default();
+        config.local.storage_path = Some(dir.to_string_lossy().to_string());
+        config.nodes = ["laptop", "nas"]
+            .iter()
+            .map(|name| target::NodeData {
+                name: name.to_string(),
+                id: format!("{name}_id").as_str().into(),
+                ..Default::default()
+            })
+            .collect();
+        config.target_groups = [
+            ("docs", target::TargetMode::Push),
+            ("photos", target::TargetMode::Pull),
+        ]
+        .iter()
+        .map(|(name, mode)| target::TargetGroup {
+            name: (*name).into(),
+            targets: ["laptop", "nas"]
+                .iter()
+                .map(|node_name| target::Target {
+                    mode: mode.clone(),
+                    node_name: node_name.to_string(),
+                    include: vec![],
+                })
+                .collect(),
+            ..Default::default()
+        })
+        .collect();
+
+        // no key until the node pushing the group makes one
+        assert!(show_key(&config, "docs").is_err());
+        assert!(show_key(&config, "photos").is_err());
+        assert!(show_key(&config, "music").is_err());
+
+        let path = dir.join(GROUP_KEYS_FILE_NAME);
+        let mut keys = GroupKeys::default();
+        let docs_key = keys.rotate("docs");
+        keys.confirm("docs", "nas_id", 1);
+        keys.receive("photos", 4, "foo bar");
+        keys.save(&path)?;
+
+        let shown = show_key(&config, "docs")?;
+        assert_eq!(shown.key, docs_key.key);
+        assert_eq!(shown.version, 1);
+        assert_eq!(shown.pending, vec!["laptop"]);
+        let shown = show_key(&config, "photos")?;
+        assert_eq!(shown.key, "foo bar");
+        assert_eq!(shown.version, 4);
+        assert!(shown.pending.is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_group_keys() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("fsy_test_group_keys");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join(GROUP_KEYS_FILE_NAME);
+        assert_eq!(GroupKeys::load(&path)?, GroupKeys::default());
+
+        let mut keys = GroupKeys::default();
+        keys.rotate("docs");
+        keys.save(&path)?;
+        assert_eq!(GroupKeys::load(&path)?, keys);
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+use crate::connection::NetworkHealth;
+use crate::status::Status;
+
+// how often the event loop checks the health of the daemon
+pub const HEALTH_CHECK_SECS: u64 = 10;
+
+// an event loop that didn't check for this long is taken as wedged
+const STALE_CHECK_SECS: i64 = 60;
+
+// Health: the last health check of the event loop
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Health {
+    pub checked_at: i64, // seconds since the epoch, 0 until the first check
+    pub network: NetworkHealth,
+}
+
+// get_problems retrieves why the daemon is not healthy (the event loop hangs,
+// the endpoint isn't bound or a watcher fails), one worth restarting. being
+// ready also needs the nodes to be able to find it
+pub fn get_problems(status: &Status, now: i64, is_ready: bool) -> Vec<String> {
+    let health = &status.health;
+    let mut problems = vec![];
+
+    // NOTE: a daemon starting up is alive, but not ready yet
+    if health.checked_at == 0 {
+        if is_ready {
+            problems.push("starting".to_string());
+        }
+        return problems;
+    }
+
+    let since_check = now - health.checked_at;
+    if since_check > STALE_CHECK_SECS {
+        problems.push(format!("event loop stuck for {since_check}s"));
+    }
+    if !health.network.bound {
+        problems.push("endpoint not bound".to_string());
+    }
+    for group in &status.groups {
+        if let Some(reason) = &group.watcher {
+            problems.push(format!("{}: watcher failing, {reason}", group.name));
+        }
+    }
+    if is_ready && !health.network.discovery {
+        problems.push("discovery not working".to_string());
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::TargetGroup;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_problems() -> Result<()> {
+        let target_groups = vec![TargetGroup {
+            name: "docs".into(),
+            ..Default::default()
+        }];
+        let healthy = Health {
+            checked_at: 1000,
+            network: NetworkHealth {
+                bound: true,
+                discovery: true,
+            },
+        };
+        let undiscovered = Health {
+            network: NetworkHealth {
+                bound: true,
+                discovery: false,
+            },
+            ..healthy.clone()
+        };
+        let unbound = Health {
+            network: NetworkHealth::default(),
+            ..healthy.clone()
+        };
+
+        let test_values = [
+            // (health, watcher, now, expected (alive, ready))
+            (Health::default(), None, 1000, (0, 1)),
+            (healthy.clone(), None, 1010, (0, 0)),
+            (healthy.clone(), None, 1100, (1, 1)),
+            (healthy.clone(), Some("restarted"), 1010, (1, 1)),
+            (undiscovered, None, 1010, (0, 1)),
+            (unbound, None, 1010, (1, 2)),
+        ];
+
+        for (health, watcher, now, expected) in test_values {
+            let mut status = Status::new("foo", &target_groups);
+            status.health = health;
+            status.set_group_watcher("docs", watcher);
+            let problems = (
+                get_problems(&status, now, false).len(),
+                get_problems(&status, now, true).len(),
+            );
+            assert_eq!(problems, expected, "{:?} {watcher:?} {now}", status.health);
+        }
+
+        Ok(())
+    }
+}
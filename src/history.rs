@@ -0,0 +1,248 @@
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+pub const HISTORY_FILE_NAME: &str = "history.log";
+
+// once the history gets this big it moves to `history.log.1` (replacing
+// the previous one) and starts over, both are read for the recent ones
+const MAX_HISTORY_BYTES: u64 = 1024 * 1024;
+
+// synced paths shown by `fsy recent` when not told
+pub const DEFAULT_RECENT_COUNT: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    Pull, // pulled from the node
+    Push, // the node pulled it from us
+}
+
+impl Direction {
+    // get_arrow retrieves how the direction shows next to the node
+    pub fn get_arrow(&self) -> &'static str {
+        match self {
+            Self::Pull => "<-",
+            Self::Push => "->",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pull => write!(f, "pull"),
+            Self::Push => write!(f, "push"),
+        }
+    }
+}
+
+// Synced: a path of a group that went to or came from a node
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Synced {
+    pub timestamp: DateTime<Utc>,
+    pub direction: Direction,
+    pub node_id: String,
+    pub node_name: String,
+    pub group_name: String,
+    pub relative_path: String,
+}
+
+impl Synced {
+    pub fn new(
+        direction: Direction,
+        node_id: &str,
+        node_name: &str,
+        group_name: &str,
+        relative_path: &str,
+    ) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            direction,
+            node_id: node_id.to_owned(),
+            node_name: node_name.to_owned(),
+            group_name: group_name.to_owned(),
+            relative_path: relative_path.to_owned(),
+        }
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.timestamp.to_rfc3339(),
+            self.direction,
+            self.node_id,
+            self.node_name,
+            self.group_name,
+            self.relative_path
+        )
+    }
+
+    // decode retrieves the synced path of the line, none if it isn't one.
+    // NOTE: the path goes last, it is the only one that can have tabs
+    fn decode(line: &str) -> Option<Self> {
+        let mut spl = line.splitn(6, '\t');
+        let timestamp = DateTime::parse_from_rfc3339(spl.next()?).ok()?;
+        let direction = match spl.next()? {
+            "pull" => Direction::Pull,
+            "push" => Direction::Push,
+            _ => return None,
+        };
+
+        Some(Self {
+            timestamp: timestamp.with_timezone(&Utc),
+            direction,
+            node_id: spl.next()?.to_owned(),
+            node_name: spl.next()?.to_owned(),
+            group_name: spl.next()?.to_owned(),
+            relative_path: spl.next()?.to_owned(),
+        })
+    }
+}
+
+// Recent: the last paths synced, the most recent first
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Recent {
+    pub synced: Vec<Synced>,
+}
+
+impl fmt::Display for Recent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.synced.is_empty() {
+            return writeln!(f, "nothing synced yet");
+        }
+
+        for synced in &self.synced {
+            let timestamp = synced.timestamp.with_timezone(&Local);
+            writeln!(
+                f,
+                "{} {}: {} {} {}",
+                timestamp.format("%Y-%m-%d %H:%M:%S"),
+                synced.group_name,
+                synced.relative_path,
+                synced.direction.get_arrow(),
+                synced.node_name
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// add keeps the path synced on the history of the storage, moving it aside
+// when too big
+pub fn add(storage_path: &Path, synced: &Synced) -> Result<()> {
+    let history_path = storage_path.join(HISTORY_FILE_NAME);
+    let is_full = fs::metadata(&history_path).is_ok_and(|m| m.len() >= MAX_HISTORY_BYTES);
+    if is_full {
+        fs::rename(&history_path, get_rotated_path(storage_path))?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history_path)?;
+    writeln!(file, "{}", synced.encode())?;
+    Ok(())
+}
+
+// get_recent retrieves the last paths synced of the group (or all of them),
+// up to the count
+pub fn get_recent(storage_path: &Path, group_name: Option<&str>, count: usize) -> Recent {
+    let mut content = fs::read_to_string(get_rotated_path(storage_path)).unwrap_or_default();
+    content.push_str(&fs::read_to_string(storage_path.join(HISTORY_FILE_NAME)).unwrap_or_default());
+
+    let synced = content
+        .lines()
+        .rev()
+        .filter_map(Synced::decode)
+        .filter(|s| group_name.is_none_or(|g| s.group_name == g))
+        .take(count)
+        .collect();
+    Recent { synced }
+}
+
+fn get_rotated_path(storage_path: &Path) -> std::path::PathBuf {
+    storage_path.join(format!("{HISTORY_FILE_NAME}.1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_synced(direction: Direction, group_name: &str, relative_path: &str) -> Synced {
+        Synced {
+            timestamp: DateTime::from_timestamp(1700000000, 0).unwrap(),
+            direction,
+            node_id: "1234".into(),
+            node_name: "server".into(),
+            group_name: group_name.into(),
+            relative_path: relative_path.into(),
+        }
+    }
+
+    #[test]
+    fn test_synced_line() -> Result<()> {
+        let synced = get_synced(Direction::Push, "docs", "a\tb.txt");
+        assert_eq!(Synced::decode(&synced.encode()), Some(synced));
+
+        let test_values = [
+            // (line, decoded)
+            ("", false),
+            ("2023-11-14T22:13:20+00:00\tpull\t1234\tserver\tdocs", false),
+            (
+                "2023-11-14T22:13:20+00:00\tsend\t1234\tserver\tdocs\ta.txt",
+                false,
+            ),
+            ("yesterday\tpull\t1234\tserver\tdocs\ta.txt", false),
+            (
+                "2023-11-14T22:13:20+00:00\tpull\t1234\tserver\tdocs\ta.txt",
+                true,
+            ),
+        ];
+        for spec in test_values {
+            assert_eq!(Synced::decode(spec.0).is_some(), spec.1, "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_recent() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_history");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        assert!(get_recent(&dir, None, 10).synced.is_empty());
+
+        add(&dir, &get_synced(Direction::Pull, "docs", "a.txt"))?;
+        add(&dir, &get_synced(Direction::Push, "photos", "b.jpg"))?;
+        // the rotated one is read too, before the current one
+        fs::rename(dir.join(HISTORY_FILE_NAME), get_rotated_path(&dir))?;
+        add(&dir, &get_synced(Direction::Pull, "docs", "c.txt"))?;
+
+        let test_values = [
+            // (group, count, paths)
+            (None, 10, vec!["c.txt", "b.jpg", "a.txt"]),
+            (None, 2, vec!["c.txt", "b.jpg"]),
+            (Some("docs"), 10, vec!["c.txt", "a.txt"]),
+            (Some("music"), 10, vec![]),
+        ];
+        for spec in test_values {
+            let recent = get_recent(&dir, spec.0, spec.1);
+            let paths: Vec<&str> = recent
+                .synced
+                .iter()
+                .map(|s| s.relative_path.as_str())
+                .collect();
+            assert_eq!(paths, spec.2, "{spec:?}");
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
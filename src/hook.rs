@@ -0,0 +1,222 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::Instant;
+
+use crate::logs::{log_error, log_warning};
+
+// how long the pulls of a group from a node wait for more before the
+// synced event runs with all of them
+pub const SYNCED_SETTLE_SECS: u64 = 5;
+
+// command run on the daemon events, set on init
+static HOOK: Mutex<Option<String>> = Mutex::new(None);
+
+// pulls the hook wasn't told about yet
+static SYNCED: Mutex<Option<Synced>> = Mutex::new(None);
+
+// HookContext: what the hook gets about the event, as json on stdin
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct HookContext {
+    pub event: String,
+    pub group: String,
+    pub message: String,
+    pub peer: Option<String>, // name of the node the event came from, if any
+    pub paths: Vec<String>,   // relative paths of the group the event is about
+}
+
+// Synced: the files pulled of each group from each node, along with when
+// the last one was
+#[derive(Debug, Default)]
+pub struct Synced {
+    batches: HashMap<(String, String), (Instant, Vec<String>)>,
+}
+
+impl Synced {
+    pub fn add(&mut self, group_name: &str, peer: &str, relative_path: &str, now: Instant) {
+        let key = (group_name.to_owned(), peer.to_owned());
+        let (last, paths) = self.batches.entry(key).or_insert((now, vec![]));
+        *last = now;
+        if !paths.iter().any(|p| p == relative_path) {
+            paths.push(relative_path.to_owned());
+        }
+    }
+
+    // take_settled retrieves the batches without a pull in a while, as the
+    // context of their synced event
+    pub fn take_settled(&mut self, now: Instant) -> Vec<HookContext> {
+        let settle = Duration::from_secs(SYNCED_SETTLE_SECS);
+        let settled: Vec<(String, String)> = self
+            .batches
+            .iter()
+            .filter(|(_, (last, _))| now.duration_since(*last) >= settle)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut contexts: Vec<HookContext> = settled
+            .into_iter()
+            .filter_map(|key| {
+                let (_, paths) = self.batches.remove(&key)?;
+                let (group, peer) = key;
+                Some(HookContext {
+                    event: "synced".to_owned(),
+                    message: format!("{} files pulled from {peer}", paths.len()),
+                    group,
+                    peer: Some(peer),
+                    paths,
+                })
+            })
+            .collect();
+        contexts.sort_by(|a, b| (&a.group, &a.peer).cmp(&(&b.group, &b.peer)));
+        contexts
+    }
+}
+
+// init sets the command to run on the daemon events, if any
+pub fn init(command: Option<&str>) {
+    if let Ok(mut hook) = HOOK.lock() {
+        *hook = command.map(|c| c.to_owned());
+    }
+}
+
+fn get_command() -> Option<String> {
+    HOOK.lock().ok().and_then(|h| h.clone())
+}
+
+// run runs the hook command (through the shell) letting it know about the
+// event, in the background so the daemon never waits on it
+pub fn run(event: &str, group_name: &str, message: &str) {
+    run_with_context(HookContext {
+        event: event.to_owned(),
+        group: group_name.to_owned(),
+        message: message.to_owned(),
+        ..Default::default()
+    });
+}
+
+// run_with_context runs the hook with the event on the env vars and the
+// whole context (the paths, ...) as json on stdin
+pub fn run_with_context(context: HookContext) {
+    let Some(command) = get_command() else {
+        return;
+    };
+
+    let mut cmd = get_shell_command(&command);
+    cmd.env("FSY_EVENT", &context.event)
+        .env("FSY_GROUP", &context.group)
+        .env("FSY_MESSAGE", &context.message)
+        .env("FSY_PEER", context.peer.as_deref().unwrap_or_default())
+        .env("FSY_PATHS_COUNT", context.paths.len().to_string())
+        .stdin(Stdio::piped());
+
+    match cmd.spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                // NOTE: hooks not reading stdin close it, that is fine
+                if let (Some(mut stdin), Ok(json)) =
+                    (child.stdin.take(), serde_json::to_vec(&context))
+                {
+                    let _ = stdin.write_all(&json).await;
+                }
+
+                let event = &context.event;
+                match child.wait().await {
+                    Ok(status) if !status.success() => {
+                        log_warning!("- hook failed on {event}: {status}")
+                    }
+                    Err(e) => log_warning!("- hook failed on {event}: {e}"),
+                    _ => {}
+                }
+            });
+        }
+        Err(e) => log_error!("- unable to run the hook: {e}"),
+    }
+}
+
+// get_shell_command retrieves the command run through the shell of the
+// system, as the user would type it
+pub fn get_shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+// add_synced keeps the pulled file for the synced event of its group and
+// node, only when there is a hook to tell
+pub fn add_synced(group_name: &str, peer: &str, relative_path: &str) {
+    if get_command().is_none() {
+        return;
+    }
+
+    if let Ok(mut synced) = SYNCED.lock() {
+        synced
+            .get_or_insert_default()
+            .add(group_name, peer, relative_path, Instant::now());
+    }
+}
+
+// run_synced runs the synced event of the pulls that settled
+pub fn run_synced() {
+    let contexts = match SYNCED.lock() {
+        Ok(mut synced) => synced.get_or_insert_default().take_settled(Instant::now()),
+        Err(_) => return,
+    };
+
+    for context in contexts {
+        run_with_context(context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_synced_take_settled() -> Result<()> {
+        let start = Instant::now();
+        let settle = Duration::from_secs(SYNCED_SETTLE_SECS);
+        let mut synced = Synced::default();
+        synced.add("docs", "laptop", "a.txt", start);
+        synced.add("docs", "laptop", "b.txt", start);
+        synced.add("docs", "laptop", "a.txt", start);
+        synced.add("docs", "desktop", "c.txt", start);
+        synced.add("photos", "laptop", "d.jpg", start + settle);
+
+        // nothing settled yet
+        assert!(synced.take_settled(start).is_empty());
+
+        let contexts = synced.take_settled(start + settle);
+        let test_values = [
+            // (group, peer, paths)
+            ("docs", "desktop", vec!["c.txt"]),
+            ("docs", "laptop", vec!["a.txt", "b.txt"]),
+        ];
+        assert_eq!(contexts.len(), test_values.len());
+        for (context, spec) in contexts.iter().zip(test_values) {
+            assert_eq!(context.event, "synced");
+            assert_eq!(context.group, spec.0);
+            assert_eq!(context.peer.as_deref(), Some(spec.1));
+            assert_eq!(context.paths, spec.2);
+        }
+
+        // a batch still getting pulls waits for them
+        synced.add("photos", "laptop", "e.jpg", start + settle * 2);
+        assert!(synced.take_settled(start + settle * 2).is_empty());
+        let contexts = synced.take_settled(start + settle * 3);
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(contexts[0].paths, vec!["d.jpg", "e.jpg"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::ffi::OsStr;
+use std::fmt;
+use std::ops::Deref;
+use std::path::Path;
+
+// string_id makes a string newtype, so the ids of the protocol (nodes,
+// groups, paths, tickets) can't be passed one for the other. they read as
+// strings everywhere a `&str` is taken
+macro_rules! string_id {
+    ($name:ident) => {
+        #[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[serde(transparent)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        // NOTE: shown as the string, the captures and logs read the same
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&self.0, f)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> Self {
+                Self(value.to_owned())
+            }
+        }
+
+        impl From<&String> for $name {
+            fn from(value: &String) -> Self {
+                Self(value.clone())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl PartialEq<String> for $name {
+            fn eq(&self, other: &String) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl PartialEq<$name> for String {
+            fn eq(&self, other: &$name) -> bool {
+                *self == other.0
+            }
+        }
+
+        impl PartialEq<$name> for str {
+            fn eq(&self, other: &$name) -> bool {
+                self == other.0
+            }
+        }
+
+        impl PartialEq<$name> for &str {
+            fn eq(&self, other: &$name) -> bool {
+                *self == other.0
+            }
+        }
+    };
+}
+
+// PeerId: the id of a node, its public key
+string_id!(PeerId);
+
+// GroupName: the name of a target group, the same on every node
+string_id!(GroupName);
+
+// RelPath: a path relative to the root of a group, empty for a single file
+string_id!(RelPath);
+
+// TicketId: a blob ticket, what a node downloads a file or manifest with
+string_id!(TicketId);
+
+impl AsRef<Path> for RelPath {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl AsRef<OsStr> for RelPath {
+    fn as_ref(&self) -> &OsStr {
+        OsStr::new(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_string_id() -> Result<()> {
+        let peer_id = PeerId::from("abc");
+        assert_eq!(peer_id, "abc");
+        assert_eq!(peer_id.as_str(), "abc");
+        assert_eq!(peer_id.to_string(), "abc");
+        assert_eq!(format!("{peer_id:?}"), "\"abc\"");
+        assert_eq!(String::from(peer_id.clone()), "abc".to_string());
+        assert!(peer_id.starts_with("ab"));
+
+        // serialized as the bare string
+        let encoded = serde_json::to_string(&peer_id)?;
+        assert_eq!(encoded, "\"abc\"");
+        assert_eq!(serde_json::from_str::<PeerId>(&encoded)?, peer_id);
+
+        let relative_path = RelPath::from("docs/a.txt");
+        assert_eq!(
+            Path::new("/g").join(&relative_path),
+            Path::new("/g/docs/a.txt")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Result, bail};
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// InstanceLock: makes sure only one daemon runs per config, and per storage
+// as the daemons of other configs can't share it (the blob store, the
+// control socket). the lock is released by the os when the process ends,
+// even on a crash
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+const LOCK_FILE_PREFIX: &str = "instance-";
+const LOCK_FILE_EXTENSION: &str = "lock";
+
+fn get_lock_path(storage_path: &Path, config_path: &OsStr) -> PathBuf {
+    // NOTE: the config path can't be used as a name as is, a stable hash can
+    let hash = iroh_blobs::Hash::new(config_path.as_encoded_bytes()).to_hex();
+    storage_path.join(format!(
+        "{LOCK_FILE_PREFIX}{}.{LOCK_FILE_EXTENSION}",
+        &hash[..16]
+    ))
+}
+
+// is_lock_file checks if the file is the lock of a daemon, never moved
+// along with the storage
+pub fn is_lock_file(file_path: &Path) -> bool {
+    let is_lock_name = file_path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(LOCK_FILE_PREFIX));
+    is_lock_name && file_path.extension() == Some(OsStr::new(LOCK_FILE_EXTENSION))
+}
+
+// is_storage_in_use checks if a daemon, of any config, holds the storage
+pub fn is_storage_in_use(storage_path: &Path) -> bool {
+    get_held_lock_path(storage_path, None).is_some()
+}
+
+// get_held_lock_path retrieves the lock of a daemon holding the storage,
+// other than the one of the path
+fn get_held_lock_path(storage_path: &Path, except_path: Option<&Path>) -> Option<PathBuf> {
+    let entries = fs::read_dir(storage_path).ok()?;
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| is_lock_file(path) && Some(path.as_path()) != except_path)
+        .find(|path| {
+            let Ok(file) = File::open(path) else {
+                return false;
+            };
+            matches!(file.try_lock(), Err(TryLockError::WouldBlock))
+        })
+}
+
+// read_pid retrieves the process that wrote the lock, none if unreadable
+fn read_pid(lock_path: &Path) -> Option<u32> {
+    fs::read_to_string(lock_path).ok()?.trim().parse().ok()
+}
+
+// is_process_alive checks if the process is still running, even if it
+// belongs to someone else
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks the process exists, nothing is sent
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// NOTE: there is no way to tell without the os apis, taken as running
+#[cfg(not(unix))]
+fn is_process_alive(_pid: u32) -> bool {
+    true
+}
+
+fn open_lock(lock_path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path)?;
+    Ok(file)
+}
+
+impl InstanceLock {
+    // acquire locks the config to this process. with force, a lock left
+    // behind by a process no longer running (a stale lock on a network
+    // drive) is taken over by starting a new lock file. a running daemon
+    // keeps it, forced or not
+    pub fn acquire(storage_path: &Path, config_path: &OsStr, force: bool) -> Result<Self> {
+        fs::create_dir_all(storage_path)?;
+        let lock_path = get_lock_path(storage_path, config_path);
+
+        let mut file = open_lock(&lock_path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = read_pid(&lock_path);
+                let is_stale = pid.is_some_and(|pid| pid != std::process::id())
+                    && !pid.is_some_and(is_process_alive);
+                if !force || !is_stale {
+                    let pid = pid.map(|pid| pid.to_string()).unwrap_or_default();
+                    let hint = match is_stale {
+                        true => ", use --force to take over its stale lock",
+                        false => "",
+                    };
+                    bail!(
+                        "fsy is already running for {} (pid {pid}){hint}",
+                        Path::new(config_path).display(),
+                    );
+                }
+
+                fs::remove_file(&lock_path)?;
+                file = open_lock(&lock_path)?;
+                file.try_lock()?;
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        // NOTE: checked once locked, two daemons starting at once see each
+        //       other and neither goes on
+        if let Some(held_path) = get_held_lock_path(storage_path, Some(&lock_path)) {
+            let pid = fs::read_to_string(&held_path).unwrap_or_default();
+            bail!(
+                "the storage at {} is used by the daemon of another config (pid {}), set a storage_path of its own",
+                storage_path.display(),
+                pid.trim()
+            );
+        }
+
+        // let the user know who is holding it
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_instance_lock() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_instance_lock");
+        let _ = fs::remove_dir_all(&dir);
+        let config_a = OsStr::new("/foo/config.toml");
+        let config_b = OsStr::new("/bar/config.toml");
+
+        let lock = InstanceLock::acquire(&dir, config_a, false)?;
+        assert!(InstanceLock::acquire(&dir, config_a, false).is_err());
+        assert!(is_storage_in_use(&dir));
+
+        // the storage is taken by the daemon of another config
+        assert!(InstanceLock::acquire(&dir, config_b, false).is_err());
+        assert!(InstanceLock::acquire(&dir, config_b, true).is_err());
+
+        // a running daemon keeps it, forced or not
+        assert!(InstanceLock::acquire(&dir, config_a, true).is_err());
+
+        // released once dropped
+        drop(lock);
+        let lock = InstanceLock::acquire(&dir, config_a, false)?;
+        drop(lock);
+        let forced_lock = InstanceLock::acquire(&dir, config_a, true)?;
+        assert!(InstanceLock::acquire(&dir, config_a, false).is_err());
+
+        drop(forced_lock);
+
+        // a lock left by a process no longer running is taken over if forced
+        #[cfg(unix)]
+        {
+            let mut child = std::process::Command::new("true").spawn()?;
+            let dead_pid = child.id();
+            child.wait()?;
+
+            let lock = InstanceLock::acquire(&dir, config_a, false)?;
+            fs::write(get_lock_path(&dir, config_a), dead_pid.to_string())?;
+            assert!(InstanceLock::acquire(&dir, config_a, false).is_err());
+            let forced_lock = InstanceLock::acquire(&dir, config_a, true)?;
+            assert!(InstanceLock::acquire(&dir, config_a, true).is_err());
+            drop((lock, forced_lock));
+        }
+
+        let other_lock = InstanceLock::acquire(&dir, config_b, false)?;
+        drop(other_lock);
+        assert!(!is_storage_in_use(&dir));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
+use anyhow::Result;
 use iroh::SecretKey;
 use rand::Rng;
+use std::fs;
+use std::path::Path;
 
 const EFF_DICE_LIST: &str = include_str!("./static/eff_large_wordlist.txt");
 
@@ -29,8 +32,118 @@ pub fn generate_node_secret_key() -> SecretKey {
     SecretKey::generate(rand::rngs::OsRng)
 }
 
+// check_private_dir makes sure the folder, when there is one already, is
+// the user's and no one else can write to it. another user making it first
+// could read or swap what goes on it
+#[cfg(unix)]
+pub fn check_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(meta) = fs::metadata(dir) else {
+        return Ok(());
+    };
+    // SAFETY: getuid has no preconditions and never fails
+    let uid = unsafe { libc::getuid() };
+    if meta.uid() != uid {
+        anyhow::bail!("{} belongs to another user", dir.display());
+    }
+    if meta.mode() & 0o002 != 0 {
+        anyhow::bail!("{} can be written by other users", dir.display());
+    }
+    Ok(())
+}
+
+// NOTE: windows has no modes, a folder gets the access of the one it is in
+//       (the user's own for the state dir) and that is left as it is
+#[cfg(not(unix))]
+pub fn check_private_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+// create_private_dir makes the folder only the user can go into, checking
+// the one there already as `check_private_dir`
+#[cfg(unix)]
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    check_private_dir(dir)?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+// write_private writes the file readable only by the user, on a folder only
+// the user can go into when it has to be created
+#[cfg(unix)]
+pub fn write_private(path: &Path, content: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if let Some(dir) = path.parent() {
+        create_private_dir(dir)?;
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // NOTE: the mode only applies to new files, one saved before may not have it
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+// NOTE: the file gets the access of the folder it is in, windows has no
+//       modes to make it the user's only
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        create_private_dir(dir)?;
+    }
+
+    fs::write(path, content)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    #[cfg(unix)]
+    #[test]
+    fn test_check_private_dir() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("fsy_test_private_dir");
+        let _ = super::fs::remove_dir_all(&dir);
+        super::check_private_dir(&dir)?;
+
+        let test_values = [
+            // (mode, is usable)
+            (0o700, true),
+            (0o755, true),
+            (0o777, false),
+            (0o1777, false),
+        ];
+        super::create_private_dir(&dir)?;
+        for spec in test_values {
+            super::fs::set_permissions(&dir, super::fs::Permissions::from_mode(spec.0))?;
+            let res = super::write_private(&dir.join("a.txt"), "foo");
+            assert_eq!(res.is_ok(), spec.1, "{:o}", spec.0);
+        }
+
+        super::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
     #[test]
     fn test_get_random_key() {
         for i in 1..20 {
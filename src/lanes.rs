@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use crate::ids::GroupName;
+use crate::target::TargetGroup;
+
+// actions of a group running at once when the group doesn't set it, one
+// keeps them on the order they were queued
+pub const DEFAULT_MAX_PARALLEL_ACTIONS: usize = 1;
+
+// Lanes: the actions running of each group, so a big transfer or a failing
+// group only holds its own actions. the ones about no group (hellos, key
+// rotations...) go on a lane of their own
+#[derive(Debug, Default)]
+pub struct Lanes {
+    running: HashMap<Option<GroupName>, usize>,
+    limits: HashMap<GroupName, usize>,
+}
+
+impl Lanes {
+    pub fn new(target_groups: &[TargetGroup]) -> Self {
+        let limits = target_groups
+            .iter()
+            .filter_map(|group| Some((group.name.clone(), group.max_parallel_actions?)))
+            .collect();
+
+        Self {
+            running: HashMap::new(),
+            limits,
+        }
+    }
+
+    // has_room checks if another action of the group can start
+    pub fn has_room(&self, group_name: Option<&GroupName>) -> bool {
+        let limit = group_name
+            .and_then(|name| self.limits.get(name).copied())
+            .unwrap_or(DEFAULT_MAX_PARALLEL_ACTIONS)
+            .max(1);
+        let running = self
+            .running
+            .get(&group_name.cloned())
+            .copied()
+            .unwrap_or_default();
+        running < limit
+    }
+
+    pub fn start(&mut self, group_name: Option<GroupName>) {
+        *self.running.entry(group_name).or_default() += 1;
+    }
+
+    pub fn end(&mut self, group_name: Option<GroupName>) {
+        if let Some(running) = self.running.get_mut(&group_name) {
+            *running = running.saturating_sub(1);
+            if *running == 0 {
+                self.running.remove(&group_name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_lanes() -> Result<()> {
+        let target_groups = vec![
+            TargetGroup {
+                name: "docs".into(),
+                ..Default::default()
+            },
+            TargetGroup {
+                name: "photos".into(),
+                max_parallel_actions: Some(2),
+                ..Default::default()
+            },
+        ];
+        let mut lanes = Lanes::new(&target_groups);
+        let docs = GroupName::from("docs");
+        let photos = GroupName::from("photos");
+
+        let test_values = [
+            // (started group, has room after (docs, photos, none))
+            (Some(&docs), (false, true, true)),
+            (Some(&photos), (false, true, true)),
+            (Some(&photos), (false, false, true)),
+            (None, (false, false, false)),
+        ];
+        for (group_name, expected) in test_values {
+            lanes.start(group_name.cloned());
+            let has_room = (
+                lanes.has_room(Some(&docs)),
+                lanes.has_room(Some(&photos)),
+                lanes.has_room(None),
+            );
+            assert_eq!(has_room, expected, "{group_name:?}");
+        }
+
+        // a lane frees up once its action ends, the others stay
+        lanes.end(Some(docs.clone()));
+        assert!(lanes.has_room(Some(&docs)));
+        assert!(!lanes.has_room(Some(&photos)));
+        lanes.end(Some(photos.clone()));
+        assert!(lanes.has_room(Some(&photos)));
+
+        Ok(())
+    }
+}
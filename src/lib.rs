@@ -0,0 +1,2808 @@
+mod action;
+mod admission;
+mod append;
+mod approval;
+mod archive;
+mod bandwidth;
+mod blocklist;
+mod capture;
+mod chaos;
+mod checksum;
+mod cli;
+mod client;
+mod config;
+mod config_check;
+mod conflict;
+mod connection;
+mod control;
+mod diff;
+mod events;
+mod example;
+mod export;
+mod frozen;
+mod fs_snapshot;
+mod gateway;
+mod generation;
+mod glob;
+mod group_key;
+mod health;
+mod history;
+mod hook;
+mod ids;
+mod instance;
+mod key;
+mod lanes;
+mod limits;
+mod logs;
+mod manifest;
+mod merkle;
+mod outbox;
+mod path_watcher;
+mod pending;
+mod permissions;
+mod power;
+mod providers;
+mod pulled;
+mod purge;
+mod queue;
+mod reserved;
+mod resources;
+mod rotation;
+mod sanitize;
+mod scan;
+mod seed;
+mod sequence;
+mod share;
+mod snapshot;
+mod space;
+mod standby;
+mod state;
+mod status;
+mod storage;
+mod syncthing;
+mod tags;
+mod target;
+mod ticketed;
+mod transform;
+mod tray;
+mod websocket;
+mod xattrs;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use chrono::Utc;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::sync::{Mutex, Notify, watch::Sender, watch::channel};
+use tokio::time::{Instant, sleep};
+
+use self::action::{is_target_locked, perform_action, CommAction};
+use self::admission::Admission;
+use self::bandwidth::Bandwidth;
+use self::cli::{Cli, Command};
+use self::blocklist::Blocklist;
+use self::connection::{Connection, ConnectionHandle, DiscoveryMode};
+use self::events::Event;
+use self::history::Synced;
+use self::ids::{GroupName, PeerId};
+use self::lanes::Lanes;
+use self::limits::Holds;
+use self::logs::{LogLevel, log, log_debug, log_error, log_warning};
+use self::manifest::Manifest;
+use self::path_watcher::{ChangeKind, ChangedTarget, PathWatcher};
+use self::resources::Resources;
+use self::sequence::Reorder;
+use self::state::SharedState;
+use self::status::Status;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// messages kept for a node while it is unreachable, the ones after are lost
+const MAX_DEFERRED_PER_NODE: usize = queue::MAX_CAPACITY;
+
+// check_namespaced_msg parses a message as coming from another node, for the
+// fuzzing of `fuzz/`. it never panics and nothing with an unsafe path gets
+// through
+#[doc(hidden)]
+pub fn check_namespaced_msg(raw_msg: &str) {
+    let action = CommAction::from_namespaced_msg("1234", raw_msg);
+    if let Some(relative_path) = action.get_relative_path() {
+        assert!(sanitize::is_safe_relative_path(relative_path), "{raw_msg}");
+    }
+    let _ = action.get_target_name();
+}
+
+// main is the whole of the `fsy` command, on the library so `fuzz/` can
+// reach the parsing of the messages as well
+#[tokio::main]
+pub async fn main() -> Result<()> {
+    let cli = match Cli::from_env() {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    // NOTE: loaded on demand, checking needs to work on configs that don't load
+    let profile = cli.profile.as_deref();
+    let config_path = cli.config.as_deref();
+    let load_config = || match config::Config::new("", profile, config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    match cli.command {
+        Command::Run { force, log } => run(load_config(), force, log).await,
+        Command::Status { tag } => print_status(&load_config(), tag.as_deref(), cli.json).await,
+        Command::Notify { group_name, path } => {
+            notify(&load_config(), &group_name, &path, cli.json).await
+        }
+        Command::Announce { group_name } => {
+            announce(&load_config(), group_name.as_deref(), cli.json).await
+        }
+        Command::Diff { group_name, node } => {
+            diff_group(&load_config(), &group_name, &node, cli.json)
+        }
+        Command::ManifestExport { group_name } => {
+            export_checksums(&load_config(), &group_name, cli.json)
+        }
+        Command::Health { ready } => check_health(&load_config(), ready, cli.json).await,
+        Command::Logs { follow } => print_logs(&load_config(), follow, cli.json).await,
+        Command::Events => print_events(&load_config(), cli.json).await,
+        Command::Pause { paused } => pause_sync(&load_config(), paused, cli.json).await,
+        Command::Tray => tray::run(&load_config()).await,
+        Command::Recent { group, count } => {
+            print_recent(&load_config(), group.as_deref(), count, cli.json)
+        }
+        Command::Id { qr } => print_id(&load_config(), qr, cli.json),
+        Command::Confirm { group_name } => {
+            confirm_group(&load_config(), &group_name, cli.json)
+        }
+        Command::Rollback { group, to } => {
+            rollback_group(&load_config(), &group, to.as_deref(), cli.json).await
+        }
+        Command::FrozenList => list_frozen(&load_config(), cli.json),
+        Command::Freeze { group, path } => {
+            freeze_path(&load_config(), &group, &path, true, cli.json)
+        }
+        Command::Unfreeze { group, path } => {
+            freeze_path(&load_config(), &group, &path, false, cli.json)
+        }
+        Command::ChangesPending => list_pending_changes(&load_config(), cli.json),
+        Command::Approve { group, path } => {
+            approve_changes(&load_config(), &group, path.as_deref(), cli.json).await
+        }
+        Command::ConfigCheck => check_config(profile, config_path, cli.json),
+        Command::ConfigExample { topology } => print_example(&topology, cli.json),
+        Command::ImportSyncthing { path } => import_syncthing(&path, cli.json),
+        Command::ConflictsList => list_conflicts(&load_config(), cli.json),
+        Command::ConflictsResolve { index, use_copy } => {
+            resolve_conflict(&load_config(), index, use_copy, cli.json)
+        }
+        Command::KeyRotate { force } => rotate_key(&load_config(), force, cli.json).await,
+        Command::KeyShow { group_name } => show_group_key(&load_config(), &group_name, cli.json),
+        Command::KeyRotateGroup { group_name } => {
+            rotate_group_key(&load_config(), &group_name, cli.json).await
+        }
+        Command::DebugCapture { on } => debug_capture(&load_config(), on, cli.json),
+        Command::NodeBlock { node } => block_node(&load_config(), &node, true, cli.json).await,
+        Command::NodeUnblock { node } => block_node(&load_config(), &node, false, cli.json).await,
+        Command::SeedImport { group_name, path } => {
+            seed_import(&load_config(), &group_name, &path, cli.json)
+        }
+        Command::StorageMigrate { from } => migrate_storage(&load_config(), &from, cli.json),
+        Command::PeersPending => list_pending_peers(&load_config(), cli.json),
+        Command::PeersApprove { id, name } => {
+            approve_peer(&load_config(), &id, name.as_deref(), cli.json)
+        }
+        Command::TagsToggle { tag, enabled } => toggle_tag(&load_config(), &tag, enabled, cli.json),
+        Command::Promote => promote_standby(&load_config(), cli.json),
+        Command::Share { group_name } => share_group(&load_config(), &group_name, cli.json),
+        Command::Accept { token, path } => {
+            accept_share(&load_config(), &token, path.as_deref(), cli.json)
+        }
+    }
+}
+
+fn check_config(profile: Option<&str>, explicit_path: Option<&str>, json: bool) -> Result<()> {
+    let config_path = config::find_config_path("", profile, explicit_path)?;
+    let Ok(content) = std::fs::read_to_string(&config_path) else {
+        bail!("unable to read config at {}", Path::new(&config_path).display());
+    };
+
+    let config_dir = Path::new(&config_path).parent().unwrap_or(Path::new(""));
+    let report = config_check::check_config(&content, config_dir);
+    cli::print_output(&report, json)?;
+
+    // let scripts know the config won't work
+    if report.has_errors() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+// print_example outputs the commented config of the topology, generated out
+// of the config structs so it is always one fsy reads
+fn print_example(topology: &str, json: bool) -> Result<()> {
+    let topology = example::Topology::from_name(topology)?;
+    cli::print_output(&example::get_example(topology)?, json)
+}
+
+fn import_syncthing(path: &str, json: bool) -> Result<()> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        bail!("unable to read syncthing config at {path}");
+    };
+
+    // NOTE: the report goes to stderr so the output can be appended to
+    //       the config as is
+    let imported = syncthing::import_syncthing(&content)?;
+    if !json {
+        eprint!("{}", imported.report);
+    }
+
+    cli::print_output(&imported, json)
+}
+
+// notify lets the pullers of the group know the path changed, through the
+// running daemon
+async fn notify(config: &config::Config, group_name: &str, path: &str, json: bool) -> Result<()> {
+    let body = serde_json::json!({
+        "action": "target-changed",
+        "group": group_name,
+        "path": path,
+    });
+    let (code, res) = client::request(config, "POST", "/actions", &body.to_string()).await?;
+    if code != 200 {
+        bail!("{}", res["error"].as_str().unwrap_or("unable to notify"));
+    }
+
+    if json {
+        println!("{res}");
+    } else {
+        println!("notified {} nodes", res["queued"]);
+    }
+    Ok(())
+}
+
+fn list_pending_changes(config: &config::Config, json: bool) -> Result<()> {
+    let approvals_path = config
+        .get_storage_path()
+        .join(approval::APPROVALS_FILE_NAME);
+    let pending = approval::PendingChanges::load(&approvals_path)?;
+    cli::print_output(&pending, json)
+}
+
+// approve_changes lets the daemon pull the changes pending on the group, the
+// one of the path if set
+async fn approve_changes(
+    config: &config::Config,
+    group_name: &str,
+    path: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let body = serde_json::json!({ "group": group_name, "path": path });
+    let (code, res) = client::request(config, "POST", "/approve", &body.to_string()).await?;
+    if code != 200 {
+        bail!("{}", res["error"].as_str().unwrap_or("unable to approve"));
+    }
+
+    if json {
+        println!("{res}");
+    } else {
+        println!("queued {} requests to the pushers", res["queued"]);
+    }
+    Ok(())
+}
+
+// check_health asks the daemon if it is healthy (or ready), failing with
+// its problems if not so the exit code tells
+async fn check_health(config: &config::Config, ready: bool, json: bool) -> Result<()> {
+    let path = if ready { "/readyz" } else { "/healthz" };
+    let (code, res) = client::request(config, "GET", path, "").await?;
+    if json {
+        println!("{res}");
+    }
+    if code != 200 {
+        let problems: Vec<&str> = res["problems"]
+            .as_array()
+            .map(|problems| problems.iter().filter_map(|p| p.as_str()).collect())
+            .unwrap_or_default();
+        match problems.is_empty() {
+            true => bail!(
+                "{}",
+                res["error"].as_str().unwrap_or("unable to check health")
+            ),
+            false => bail!("{}", problems.join(", ")),
+        }
+    }
+
+    if !json {
+        println!("{}", if ready { "ready" } else { "healthy" });
+    }
+    Ok(())
+}
+
+// announce asks the daemon to let the pullers of the push groups (or the
+// group) know their generation
+async fn announce(config: &config::Config, group_name: Option<&str>, json: bool) -> Result<()> {
+    let body = serde_json::json!({ "group": group_name });
+    let (code, res) = client::request(config, "POST", "/announce", &body.to_string()).await?;
+    if code != 200 {
+        bail!("{}", res["error"].as_str().unwrap_or("unable to announce"));
+    }
+
+    if json {
+        println!("{res}");
+    } else {
+        println!("announced {} groups", res["announced"]);
+    }
+    Ok(())
+}
+
+// pause_sync pauses (or resumes) the sync of the running daemon, it doesn't
+// last over a restart
+async fn pause_sync(config: &config::Config, paused: bool, json: bool) -> Result<()> {
+    let body = serde_json::json!({ "paused": paused });
+    let (code, res) = client::request(config, "POST", "/pause", &body.to_string()).await?;
+    if code != 200 {
+        bail!("{}", res["error"].as_str().unwrap_or("unable to pause"));
+    }
+
+    if json {
+        println!("{res}");
+    } else if paused {
+        println!("sync paused, `fsy resume` to go on");
+    } else {
+        println!("sync resumed");
+    }
+    Ok(())
+}
+
+// migrate_storage moves the data of the storage at the path into the one of
+// the config, with the daemon stopped so nothing uses either
+fn migrate_storage(config: &config::Config, from: &str, json: bool) -> Result<()> {
+    let storage_path = config.get_storage_path();
+    let Ok(_instance_lock) =
+        instance::InstanceLock::acquire(&storage_path, &config.config_path, false)
+    else {
+        bail!("the daemon is running, stop it to migrate the storage");
+    };
+
+    let from = config::resolve_path(from, &std::env::current_dir()?)?;
+    let migration = storage::migrate_storage(Path::new(&from), &storage_path)?;
+    cli::print_output(&migration, json)
+}
+
+// seed_import records the copy of the group at the path as pulled, with the
+// daemon stopped so it doesn't take the copied files as local changes
+fn seed_import(config: &config::Config, group_name: &str, path: &str, json: bool) -> Result<()> {
+    let Some(group) = target::get_pull_group_with_name(&config.target_groups, group_name) else {
+        bail!("no group \"{group_name}\" pulling");
+    };
+
+    let storage_path = config.get_storage_path();
+    let Ok(_instance_lock) =
+        instance::InstanceLock::acquire(&storage_path, &config.config_path, false)
+    else {
+        bail!("the daemon is running, stop it to seed {group_name}");
+    };
+
+    let summary = seed::seed_group(&storage_path, &group, Path::new(path))?;
+    cli::print_output(&summary, json)
+}
+
+// rollback_group rolls the group back to the snapshot, with the daemon
+// stopped so it doesn't take what changed as local changes meanwhile.
+// listing the snapshots taken without one
+async fn rollback_group(
+    config: &config::Config,
+    group_name: &str,
+    snapshot_name: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let Some(group) = config.target_groups.iter().find(|g| g.name == group_name) else {
+        bail!("no group \"{group_name}\"");
+    };
+
+    let storage_path = config.get_storage_path();
+    let snapshots_path = storage_path.join(fs_snapshot::FS_SNAPSHOTS_FILE_NAME);
+    let snapshots = fs_snapshot::FsSnapshots::load(&snapshots_path)?;
+    let Some(snapshot_name) = snapshot_name else {
+        return cli::print_output(&snapshots.of_group(group_name), json);
+    };
+
+    let Some(snapshot) = snapshots.get(group_name, snapshot_name) else {
+        bail!(
+            "no snapshot \"{snapshot_name}\" of {group_name}, `fsy rollback {group_name}` lists them"
+        );
+    };
+    let Some(command) = &group.fs_rollback_command else {
+        bail!("{group_name} has no fs_rollback_command");
+    };
+    let Ok(_instance_lock) =
+        instance::InstanceLock::acquire(&storage_path, &config.config_path, false)
+    else {
+        bail!("the daemon is running, stop it to roll {group_name} back");
+    };
+
+    fs_snapshot::run_command(command, group, &snapshot.name).await?;
+    match json {
+        true => println!("{}", serde_json::to_string(snapshot)?),
+        false => println!("{group_name} rolled back to {}", snapshot.name),
+    }
+    Ok(())
+}
+
+fn list_frozen(config: &config::Config, json: bool) -> Result<()> {
+    cli::print_output(&frozen::load_frozen(&config.get_storage_path()), json)
+}
+
+// freeze_path leaves the path of the group out of the sync or takes it back
+// in, the running daemon goes by it right away
+fn freeze_path(
+    config: &config::Config,
+    group_name: &str,
+    path: &str,
+    freeze: bool,
+    json: bool,
+) -> Result<()> {
+    if !config.target_groups.iter().any(|g| g.name == group_name) {
+        bail!("no group \"{group_name}\"");
+    }
+
+    let frozen_path = config.get_storage_path().join(frozen::FROZEN_FILE_NAME);
+    let mut frozen = frozen::Frozen::load(&frozen_path)?;
+    let changed = match freeze {
+        true => frozen.freeze(group_name, path)?,
+        false => frozen.unfreeze(group_name, path),
+    };
+    frozen.save(&frozen_path)?;
+
+    let toggle = frozen::FreezeToggle {
+        group_name: group_name.to_owned(),
+        path: path.trim_end_matches('/').to_owned(),
+        frozen: freeze,
+        changed,
+    };
+    cli::print_output(&toggle, json)
+}
+
+// print_recent shows the last paths synced of the group (or all of them),
+// offline
+fn print_recent(
+    config: &config::Config,
+    group_name: Option<&str>,
+    count: usize,
+    json: bool,
+) -> Result<()> {
+    if let Some(group_name) = group_name
+        && !config.target_groups.iter().any(|g| g.name == group_name)
+    {
+        bail!("no group \"{group_name}\"");
+    }
+
+    let recent = history::get_recent(&config.get_storage_path(), group_name, count);
+    cli::print_output(&recent, json)
+}
+
+// diff_group shows how the group differs from the last list of files the
+// node sent of it, offline
+fn diff_group(config: &config::Config, group_name: &str, node: &str, json: bool) -> Result<()> {
+    let Some(group) = config.target_groups.iter().find(|g| g.name == group_name) else {
+        bail!("no group \"{group_name}\"");
+    };
+
+    let node_id = blocklist::get_node_id(&config.nodes, node)?;
+    let node_name = target::get_node_name(&config.nodes, &node_id);
+    let diff = diff::diff_group(&config.get_storage_path(), group, &node_id, &node_name)?;
+    cli::print_output(&diff, json)
+}
+
+// export_checksums shows the sha256 of the files of the group as they are
+// now, in the format of `sha256sum`
+fn export_checksums(config: &config::Config, group_name: &str, json: bool) -> Result<()> {
+    let Some(group) = config.target_groups.iter().find(|g| g.name == group_name) else {
+        bail!("no group \"{group_name}\"");
+    };
+
+    let checksums = checksum::get_checksums(group)?;
+    cli::print_output(&checksums, json)
+}
+
+fn list_conflicts(config: &config::Config, json: bool) -> Result<()> {
+    let conflicts_path = config.get_storage_path().join(conflict::CONFLICTS_FILE_NAME);
+    let mut conflicts = conflict::Conflicts::load(&conflicts_path)?;
+    conflicts.prune();
+    cli::print_output(&conflicts, json)
+}
+
+// resolve_conflict keeps one of the versions of a conflict, either the
+// pulled one (removing the copy) or the copy (moving it over the file)
+fn resolve_conflict(
+    config: &config::Config,
+    index: usize,
+    use_copy: bool,
+    json: bool,
+) -> Result<()> {
+    let conflicts_path = config.get_storage_path().join(conflict::CONFLICTS_FILE_NAME);
+    let mut conflicts = conflict::Conflicts::load(&conflicts_path)?;
+    conflicts.prune();
+    if index == 0 || index > conflicts.conflicts.len() {
+        bail!("no conflict {index}, run `fsy conflicts list` to see them");
+    }
+
+    let resolved = conflicts.conflicts.remove(index - 1);
+    let copy_path = Path::new(&resolved.copy_path);
+    if use_copy {
+        let Some(group) = config
+            .target_groups
+            .iter()
+            .find(|g| g.name == resolved.group_name)
+        else {
+            bail!("group \"{}\" is no longer on the config", resolved.group_name);
+        };
+
+        let Some(file_path) = group.get_file_path(&resolved.relative_path) else {
+            bail!(
+                "{} is no longer on group \"{}\"",
+                resolved.relative_path,
+                group.name
+            );
+        };
+        export::move_into_place(copy_path, &file_path, false)?;
+    } else {
+        std::fs::remove_file(copy_path)?;
+    }
+    conflicts.save(&conflicts_path)?;
+
+    let resolution = conflict::Resolution {
+        group_name: resolved.group_name,
+        relative_path: resolved.relative_path,
+        use_copy,
+    };
+    cli::print_output(&resolution, json)
+}
+
+// rotate_key announces a new key to the nodes through the running daemon,
+// it is used once every node confirmed it knows it (or right away if forced).
+// running it again announces the same key to the nodes that didn't confirm
+async fn rotate_key(config: &config::Config, force: bool, json: bool) -> Result<()> {
+    let rotation_path = config.get_storage_path().join(rotation::ROTATION_FILE_NAME);
+    let rotation = match rotation::Rotation::load(&rotation_path)? {
+        Some(rotation) => rotation,
+        None => {
+            let blocklist = load_blocklist(config)?;
+            let node_ids = config
+                .nodes
+                .iter()
+                .filter(|n| !blocklist.is_blocked(&n.id))
+                .map(|n| n.id.to_string())
+                .collect();
+            let rotation = rotation::Rotation::new(&config.local.secret_key, node_ids);
+            rotation.save(&rotation_path)?;
+            rotation
+        }
+    };
+
+    let pending = rotation.get_pending();
+    let body = serde_json::json!({
+        "public_key": rotation.public_key,
+        "signature": rotation.signature,
+        "node_ids": pending,
+    });
+    let is_announced = matches!(
+        client::request(config, "POST", "/rotation", &body.to_string()).await,
+        Ok((200, _))
+    );
+    if !is_announced && !force {
+        bail!("unable to reach the daemon, it needs to be running to announce the new key");
+    }
+
+    // nodes that don't confirm need the new node id set by hand
+    let is_applied = force || pending.is_empty();
+    if is_applied {
+        rotation::apply_rotation(Path::new(&config.config_path), &rotation)?;
+        std::fs::remove_file(&rotation_path)?;
+    }
+
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "node_id": rotation.public_key,
+                "pending": pending,
+                "applied": is_applied,
+            })
+        );
+        return Ok(());
+    }
+
+    println!("new node id: {}", rotation.public_key);
+    match is_applied {
+        true => println!("the config uses it now, restart the daemon to apply it"),
+        false => println!(
+            "announced to {} nodes, it is used once all of them confirm (see `fsy logs`)",
+            pending.len()
+        ),
+    }
+    Ok(())
+}
+
+fn show_group_key(config: &config::Config, group_name: &str, json: bool) -> Result<()> {
+    let shown = group_key::show_key(config, group_name)?;
+    cli::print_output(&shown, json)
+}
+
+// rotate_group_key makes the next key of the group, sent signed to its
+// pullers through the running daemon. it is only kept once sent
+async fn rotate_group_key(config: &config::Config, group_name: &str, json: bool) -> Result<()> {
+    let Some(group) = target::get_push_group_with_name(&config.target_groups, group_name) else {
+        bail!("no group \"{group_name}\" pushing, its key is rotated by the node pushing it");
+    };
+
+    let keys_path = config
+        .get_storage_path()
+        .join(group_key::GROUP_KEYS_FILE_NAME);
+    let mut keys = group_key::GroupKeys::load(&keys_path)?;
+    let rotated = keys.rotate(group_name);
+    let blocklist = load_blocklist(config)?;
+    let modes = [target::TargetMode::Push, target::TargetMode::PushPull];
+    let node_ids: Vec<String> = group
+        .get_node_ids(&config.nodes, &modes)
+        .into_iter()
+        .filter(|node_id| !blocklist.is_blocked(node_id))
+        .map(|node_id| node_id.to_string())
+        .collect();
+    let signature = group_key::sign_group_key(
+        &config.local.secret_key,
+        group_name,
+        rotated.version,
+        &rotated.key,
+    );
+
+    let body = serde_json::json!({
+        "group": group_name,
+        "version": rotated.version,
+        "key": rotated.key,
+        "signature": signature,
+        "node_ids": node_ids,
+    });
+    match client::request(config, "POST", "/group-key", &body.to_string()).await {
+        Ok((200, _)) => {}
+        Ok((_, res)) => bail!(
+            "{}",
+            res["error"].as_str().unwrap_or("unable to send the key")
+        ),
+        Err(_) => bail!("unable to reach the daemon, it needs to be running to send the new key"),
+    }
+    keys.save(&keys_path)?;
+
+    let rotated = group_key::RotatedKey {
+        group_name: group_name.to_owned(),
+        version: rotated.version,
+        node_ids,
+    };
+    cli::print_output(&rotated, json)
+}
+
+// record_pending keeps the unknown node waiting for approval, letting the
+// user know the first time it shows up
+fn record_pending(storage_path: &Path, node_id: &str, group_name: Option<&str>) -> Result<()> {
+    let pending_path = storage_path.join(pending::PENDING_FILE_NAME);
+    let mut pending = pending::PendingPeers::load(&pending_path)?;
+    let is_new = pending.record(node_id, group_name, Utc::now());
+    pending.save(&pending_path)?;
+
+    if is_new {
+        log_warning!(
+            "- warning: unknown node {node_id} wants to sync, approve it with `fsy peers approve {node_id}`"
+        );
+        hook::run(
+            "peer-pending",
+            group_name.unwrap_or_default(),
+            &format!("unknown node {node_id} wants to sync"),
+        );
+    }
+    Ok(())
+}
+
+// load_blocklist retrieves the nodes blocked on the config and through
+// `fsy node block`
+fn load_blocklist(config: &config::Config) -> Result<Blocklist> {
+    let blocklist_path = config.get_storage_path().join(blocklist::BLOCKLIST_FILE_NAME);
+    let mut blocklist = Blocklist::load(&blocklist_path)?;
+    for node_id in &config.blocked_nodes {
+        blocklist.pin(node_id);
+    }
+
+    Ok(blocklist)
+}
+
+// block_node blocks (or unblocks) the node, the running daemon applies it
+// right away, otherwise it does on its next start
+async fn block_node(config: &config::Config, node: &str, blocked: bool, json: bool) -> Result<()> {
+    let node_id = blocklist::get_node_id(&config.nodes, node)?;
+    if !blocked && config.blocked_nodes.contains(&node_id) {
+        bail!("{node} is blocked on the config, remove it from `blocked_nodes` there");
+    }
+
+    let blocklist_path = config.get_storage_path().join(blocklist::BLOCKLIST_FILE_NAME);
+    let mut blocklist = Blocklist::load(&blocklist_path)?;
+    match blocked {
+        true => blocklist.block(&node_id),
+        false => blocklist.unblock(&node_id),
+    };
+    blocklist.save(&blocklist_path)?;
+
+    let body = serde_json::to_string(&load_blocklist(config)?)?;
+    let is_applied = matches!(
+        client::request(config, "POST", "/blocklist", &body).await,
+        Ok((200, _))
+    );
+
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "node_id": node_id, "blocked": blocked, "applied": is_applied })
+        );
+        return Ok(());
+    }
+
+    let action = if blocked { "blocked" } else { "unblocked" };
+    match is_applied {
+        true => println!("{node} {action}"),
+        false => println!("{node} {action}, the daemon applies it on its next start"),
+    }
+    Ok(())
+}
+
+fn list_pending_peers(config: &config::Config, json: bool) -> Result<()> {
+    let pending_path = config.get_storage_path().join(pending::PENDING_FILE_NAME);
+    let pending = pending::PendingPeers::load(&pending_path)?;
+    cli::print_output(&pending, json)
+}
+
+fn approve_peer(
+    config: &config::Config,
+    node_id: &str,
+    name: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let approved = pending::approve_peer(config, node_id, name)?;
+    cli::print_output(&approved, json)
+}
+
+fn debug_capture(config: &config::Config, on: bool, json: bool) -> Result<()> {
+    let storage_path = config.get_storage_path();
+    capture::set_capture(&storage_path, on)?;
+
+    let toggle = capture::CaptureToggle {
+        on,
+        capture_path: storage_path
+            .join(capture::CAPTURE_FILE_NAME)
+            .to_string_lossy()
+            .to_string(),
+    };
+    cli::print_output(&toggle, json)
+}
+
+// toggle_tag enables or disables the groups with the tag at once, the
+// daemon applies it on its next start
+fn toggle_tag(config: &config::Config, tag: &str, enabled: bool, json: bool) -> Result<()> {
+    let group_names: Vec<String> = config
+        .target_groups
+        .iter()
+        .filter(|g| g.tags.iter().any(|t| t == tag))
+        .map(|g| g.name.to_string())
+        .collect();
+    if group_names.is_empty() {
+        bail!("no group with the tag \"{tag}\"");
+    }
+
+    let disabled_tags_path = config
+        .get_storage_path()
+        .join(tags::DISABLED_TAGS_FILE_NAME);
+    let mut disabled_tags = tags::DisabledTags::load(&disabled_tags_path)?;
+    let changed = disabled_tags.set_enabled(tag, enabled);
+    disabled_tags.save(&disabled_tags_path)?;
+
+    let toggle = tags::TagToggle {
+        tag: tag.to_owned(),
+        enabled,
+        changed,
+        group_names,
+    };
+    cli::print_output(&toggle, json)
+}
+
+// promote_standby lets the standby push its groups from the next start of
+// the daemon, the node it was the standby of is gone
+fn promote_standby(config: &config::Config, json: bool) -> Result<()> {
+    if !config.local.standby {
+        bail!("this node isn't a standby, set standby = true on its config first");
+    }
+
+    let promoted_path = config.get_storage_path().join(standby::PROMOTED_FILE_NAME);
+    let (changed, promoted) = match standby::Promoted::load(&promoted_path)? {
+        Some(promoted) => (false, promoted),
+        None => {
+            let promoted = standby::Promoted {
+                promoted_at: Utc::now(),
+            };
+            promoted.save(&promoted_path)?;
+            (true, promoted)
+        }
+    };
+
+    let promotion = standby::Promotion {
+        changed,
+        promoted_at: promoted.promoted_at,
+        group_names: config
+            .target_groups
+            .iter()
+            .map(|g| g.name.to_string())
+            .collect(),
+    };
+    cli::print_output(&promotion, json)
+}
+
+// share_group outputs the token of the group, for the nodes that pull it
+fn share_group(config: &config::Config, group_name: &str, json: bool) -> Result<()> {
+    let token = share::ShareToken::new(config, group_name)?.encode()?;
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "group": group_name, "token": token })
+        );
+        return Ok(());
+    }
+
+    println!("{token}");
+    println!(
+        "run `fsy accept <token>` on the node that pulls, then add it as a target of the group"
+    );
+    Ok(())
+}
+
+// accept_share adds the group shared by another node to the config, the
+// daemon pulls it on its next start
+fn accept_share(
+    config: &config::Config,
+    token: &str,
+    path: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let accepted = share::accept_token(config, token, path)?;
+    cli::print_output(&accepted, json)
+}
+
+fn confirm_group(config: &config::Config, group_name: &str, json: bool) -> Result<()> {
+    if !config.target_groups.iter().any(|g| g.name == group_name) {
+        bail!("unknown target group \"{group_name}\"");
+    }
+
+    limits::request_confirmation(&config.get_storage_path(), group_name)?;
+    let confirmation = limits::Confirmation {
+        group_name: group_name.to_owned(),
+    };
+    cli::print_output(&confirmation, json)
+}
+
+fn print_id(config: &config::Config, qr: bool, json: bool) -> Result<()> {
+    let node_id = &config.local.public_key;
+    if json {
+        println!("{}", serde_json::json!({ "node_id": node_id }));
+        return Ok(());
+    }
+
+    if qr {
+        let code = qrcode::QrCode::new(node_id.as_bytes())?;
+        let image = code
+            .render::<qrcode::render::unicode::Dense1x2>()
+            .dark_color(qrcode::render::unicode::Dense1x2::Light)
+            .light_color(qrcode::render::unicode::Dense1x2::Dark)
+            .build();
+        println!("{image}");
+    }
+
+    println!("{node_id}");
+    Ok(())
+}
+
+async fn print_logs(config: &config::Config, follow: bool, json: bool) -> Result<()> {
+    let logs_path = config.get_storage_path().join(logs::LOGS_FILE_NAME);
+    if !logs_path.exists() && !follow {
+        bail!("no logs found, the daemon has not run yet");
+    }
+
+    let mut last_line: Option<String> = None;
+    loop {
+        let content = std::fs::read_to_string(&logs_path).unwrap_or_default();
+        let lines = logs::get_new_lines(&content, last_line.as_deref());
+        for line in &lines {
+            if json {
+                println!("{}", serde_json::json!({ "line": line }));
+            } else {
+                println!("{line}");
+            }
+        }
+        if let Some(line) = lines.last() {
+            last_line = Some(line.to_string());
+        }
+
+        if !follow {
+            return Ok(());
+        }
+
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+// print_events shows what the running daemon does as it happens, until it
+// stops
+async fn print_events(config: &config::Config, json: bool) -> Result<()> {
+    client::follow(config, "/events", |line| {
+        let event: Event = serde_json::from_str(line)?;
+        cli::print_output(&event, json)
+    })
+    .await
+}
+
+async fn print_status(config: &config::Config, tag: Option<&str>, json: bool) -> Result<()> {
+    // the running daemon knows best, the file is there when it isn't running
+    let path = match tag {
+        Some(tag) => format!("/status?tag={tag}"),
+        None => "/status".to_owned(),
+    };
+    if let Ok((200, status)) = client::request(config, "GET", &path, "").await {
+        let status: Status = serde_json::from_value(status)?;
+        return cli::print_output(&status, json);
+    }
+
+    let status_path = config.get_storage_path().join(status::STATUS_FILE_NAME);
+    if !status_path.exists() {
+        bail!("no status found, the daemon has not run yet");
+    }
+
+    let mut status = Status::load(&status_path)?;
+    if let Some(tag) = tag {
+        status.filter_tag(tag);
+    }
+    cli::print_output(&status, json)
+}
+
+async fn run(mut config: config::Config, force: bool, log_level: Option<LogLevel>) -> Result<()> {
+    // make sure we are the only daemon of this config, two would watch and
+    // transfer everything twice. the lock is held until the daemon ends
+    let tmp_dir = config.get_storage_path();
+    // NOTE: the storage has the keys of the groups, a folder someone else
+    //       made or can write to could have them read or swapped
+    key::create_private_dir(&tmp_dir)?;
+    let _instance_lock = instance::InstanceLock::acquire(&tmp_dir, &config.config_path, force)?;
+
+    // a `storage_path` set on the config takes the data of the default
+    // storage along (or of the one older versions kept on the temp dir),
+    // instead of starting empty and downloading it all again
+    for from in [
+        config.get_legacy_storage_path(),
+        config.get_default_storage_path(),
+    ] {
+        if !storage::needs_migration(&from, &tmp_dir) {
+            continue;
+        }
+        let migration =
+            key::check_private_dir(&from).and_then(|_| storage::migrate_storage(&from, &tmp_dir));
+        match migration {
+            Ok(migration) => log!("{}", migration.to_string().trim_end()),
+            Err(e) => log_warning!("- warning: storage not migrated, {e}"),
+        }
+    }
+
+    // keep the logs on the storage so `fsy logs` can show them
+    logs::init(&tmp_dir);
+    logs::set_level(
+        log_level.or(config.local.log_level).unwrap_or_default(),
+        logs::parse_filters(&config.local.log_filters)?,
+    );
+    capture::init(&tmp_dir);
+    hook::init(config.local.hook.as_deref());
+
+    // groups of the disabled tags are left out, see `fsy tags disable`
+    let disabled_tags_path = tmp_dir.join(tags::DISABLED_TAGS_FILE_NAME);
+    let disabled_tags = tags::DisabledTags::load(&disabled_tags_path)?;
+    let disabled = disabled_tags.take_disabled(&mut config.target_groups);
+    if !disabled.is_empty() {
+        let disabled = disabled.join(", ");
+        log!("leaving out the groups of disabled tags: {disabled}");
+    }
+
+    // a standby only pulls until promoted, see `fsy promote`
+    if config.local.standby {
+        let promoted_path = tmp_dir.join(standby::PROMOTED_FILE_NAME);
+        let is_promoted = standby::Promoted::load(&promoted_path)?.is_some();
+        let max_versions = config
+            .local
+            .standby_versions
+            .unwrap_or(standby::DEFAULT_STANDBY_VERSIONS);
+        standby::apply_standby(&mut config.target_groups, max_versions, is_promoted);
+        match is_promoted {
+            true => log!("standby promoted, pushing every group"),
+            false => log!("standby, only pulling every group"),
+        }
+    }
+
+    // setup the connection
+    log!("starting connection");
+    let discovery = if config.local.local_only {
+        log!("- local only, no relays and no public discovery");
+        DiscoveryMode::Lan(config.local.local_port)
+    } else {
+        DiscoveryMode::N0
+    };
+    let max_message_bytes = config
+        .local
+        .max_message_bytes
+        .unwrap_or(connection::DEFAULT_MAX_MESSAGE_BYTES);
+    let conn = Connection::new(
+        &config.local.secret_key,
+        &tmp_dir,
+        discovery,
+        max_message_bytes,
+        config.local.purge_blobs,
+        config.local.chaos.clone().unwrap_or_default(),
+    )
+    .await?;
+    for node in config.nodes.iter().filter(|n| !n.addrs.is_empty()) {
+        if let Err(e) = conn.add_direct_addrs(&node.id, &node.addrs) {
+            log_error!("- error adding the addrs of {}: {e}", node.name);
+        }
+    }
+    let conn = conn.spawn();
+    let node_id = conn.get_node_id();
+    log!("- waiting for requests. public id: {node_id}");
+
+    // setup the status so we know what is going on with each group
+    let status_path = tmp_dir.join(status::STATUS_FILE_NAME);
+    let status = SharedState::new(Status::new(&node_id, &config.target_groups), status_path);
+
+    // setup the bandwidth accounting, keeping what was used before
+    let bandwidth_path = tmp_dir.join(bandwidth::BANDWIDTH_FILE_NAME);
+    let bandwidth = Bandwidth::load(&bandwidth_path).unwrap_or_default();
+    status
+        .update(|status| status.set_bandwidth(&config.nodes, &bandwidth, Utc::now().date_naive()))
+        .await?;
+    let bandwidth = Arc::new(Mutex::new(bandwidth));
+
+    // blocked nodes are never talked to
+    let blocklist = Arc::new(Mutex::new(load_blocklist(&config)?));
+
+    // messages to unreachable nodes wait until they are reachable again
+    let deferred = Arc::new(Mutex::new(Holds::default()));
+    for node_id in outbox::get_node_ids(&tmp_dir) {
+        log_debug!("- notifications waiting for {node_id} since the last run");
+        conn.watch_peer(&node_id);
+    }
+
+    // go through the groups so we know what is there, huge folders take a
+    // while so the progress shows up on the logs and status
+    let scan_target_groups = config.target_groups.clone();
+    let scan_status = status.clone();
+    let scan_files_per_sec = config.local.scan_files_per_sec;
+    tokio::spawn(async move {
+        for group in scan_target_groups {
+            if let Err(e) = run_scan(&group, scan_files_per_sec, &scan_status).await {
+                log_error!("- error scanning {}: {e}", group.name);
+                let _ = scan_status
+                    .update_state(|state| {
+                        state.status.set_group_scan(&group.name, None);
+                        state.add_error(Some(&group.name), &format!("unable to scan: {e}"));
+                    })
+                    .await;
+            }
+        }
+    });
+
+    // keep the manifests of the push groups from the watcher events, checked
+    // against the disk every now and then (and on startup, for what changed
+    // while not running) so the missed changes go on as if they were seen
+    let manifests = Arc::new(Mutex::new(load_manifests(&config, &tmp_dir)));
+    let (missed_tx, mut missed_rx) = unbounded_channel();
+    let verify_target_groups = config.target_groups.clone();
+    let verify_manifests = manifests.clone();
+    let verify_storage_path = tmp_dir.clone();
+    let verify_secs = config
+        .local
+        .manifest_verify_secs
+        .unwrap_or(manifest::DEFAULT_VERIFY_SECS);
+    // the watcher asks for a verification once it restarts
+    let verify_now = Arc::new(Notify::new());
+    let verify_notified = verify_now.clone();
+    tokio::spawn(async move {
+        loop {
+            for group in &verify_target_groups {
+                if let Err(e) = run_manifest_verification(
+                    group,
+                    &verify_target_groups,
+                    &verify_storage_path,
+                    &verify_manifests,
+                    &missed_tx,
+                )
+                .await
+                {
+                    log_error!("- error verifying the manifest of {}: {e}", group.name);
+                }
+            }
+
+            // NOTE: 0 only verifies on startup and on watcher restarts
+            if verify_secs == 0 {
+                verify_notified.notified().await;
+                continue;
+            }
+            tokio::select! {
+                _ = sleep(Duration::from_secs(verify_secs)) => {}
+                _ = verify_notified.notified() => {}
+            }
+        }
+    });
+
+    // setup the queues
+    let actions_queue: queue::Queue<CommAction> = queue::Queue::new(queue::MAX_CAPACITY);
+    let actions_queue: Arc<Mutex<queue::Queue<CommAction>>> =
+        Arc::new(Mutex::new(actions_queue.clone()));
+
+    // downloads wait for room before taking a place on the queue
+    let max_in_flight_bytes = config
+        .local
+        .max_in_flight_bytes
+        .unwrap_or(admission::DEFAULT_MAX_IN_FLIGHT_BYTES);
+    let admission = Arc::new(Mutex::new(Admission::new(max_in_flight_bytes)));
+
+    // transfers wait for open files too, the limit is raised as much as it
+    // goes first
+    let process_limit = resources::raise_open_files_limit();
+    let max_open_files = resources::get_max_open_files(config.local.max_open_files, process_limit);
+    let resources = Resources::new(max_open_files);
+    log_debug!(
+        "- {max_open_files} open files, {} transfers at once",
+        resources.get_max_actions()
+    );
+
+    // let the nodes know who we are, they reply with who they are
+    let hellos: Vec<CommAction> = config
+        .nodes
+        .iter()
+        .map(|node| action::get_hello(&config.target_groups, &node.id, true))
+        .collect();
+    // NOTE: what changed while we were away comes from the replies, see
+    //       `ManifestDigest`. the outbox of a node goes out once its hello
+    //       does, or waits on it being reachable
+    actions_queue.lock().await.push_multiple(hellos);
+
+    // let the cli and external tooling talk to the daemon
+    let (announce_tx, mut announce_rx) = unbounded_channel();
+    let (pause_tx, mut pause_rx) = unbounded_channel();
+    let control_state = Arc::new(control::ControlState {
+        target_groups: config.target_groups.clone(),
+        nodes: config.nodes.clone(),
+        actions_queue: actions_queue.clone(),
+        status: status.clone(),
+        blocklist: blocklist.clone(),
+        announce_tx,
+        pause_tx,
+        storage_path: tmp_dir.clone(),
+    });
+    #[cfg(unix)]
+    {
+        let socket_path = tmp_dir.join(control::CONTROL_SOCKET_FILE_NAME);
+        let control_state = control_state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control::serve_unix(&socket_path, control_state).await {
+                log_error!("- error on the control socket: {e}");
+            }
+        });
+    }
+    if let Some(port) = config.local.api_port {
+        tokio::spawn(async move {
+            if let Err(e) = control::serve_tcp(port, control_state).await {
+                log_error!("- error on the control api: {e}");
+            }
+        });
+    }
+
+    // let devices without fsy read the groups served over http
+    for group in config.target_groups.iter().filter(|g| g.serve_http) {
+        let Some(bind) = group.http_bind.clone() else {
+            continue;
+        };
+        let group = group.clone();
+        tokio::spawn(async move {
+            let group_name = group.name.clone();
+            if let Err(e) = gateway::serve_group(group, bind).await {
+                log_error!("- error serving group \"{group_name}\" over http: {e}");
+            }
+        });
+    }
+
+    // NOTE: controller if the app is running or not
+    let (is_running_tx, is_running_rx) = channel(true);
+    // NOTE: reason why the sync waits (paused by the user, low battery,
+    //       metered), none if it goes on
+    let (paused_tx, paused_rx) = channel(None::<String>);
+
+    // loop receivers of events into queues
+    let event_is_running_rx = is_running_rx.clone();
+    let event_deferred = deferred.clone();
+    let event_local = config.local.clone();
+    let event_context = EventContext {
+        conn: conn.clone(),
+        nodes: config.nodes.clone(),
+        target_groups: config.target_groups.clone(),
+        actions_queue: actions_queue.clone(),
+        admission: admission.clone(),
+        bandwidth: bandwidth.clone(),
+        manifests: manifests.clone(),
+        storage_path: tmp_dir.clone(),
+        status: status.clone(),
+        blocklist: blocklist.clone(),
+        trust_on_first_use: config.local.trust_on_first_use,
+    };
+    tokio::spawn(async move {
+        log!("starting watcher sync");
+        let push_groups = target::get_push_group_paths(&event_context.target_groups);
+        let push_debounce = config.local.push_debounce_millisecs;
+        let path_debounces = target::get_push_group_debounces(&event_context.target_groups);
+        let delete_graces = target::get_push_group_delete_graces(&event_context.target_groups);
+        let max_pending_changes = config
+            .local
+            .max_pending_changes
+            .unwrap_or(resources::DEFAULT_MAX_PENDING_CHANGES);
+        let mut path_watcher = PathWatcher::new(
+            push_groups,
+            push_debounce,
+            path_debounces,
+            delete_graces,
+            max_pending_changes,
+        )
+        .unwrap();
+        for (path, e) in path_watcher.start() {
+            log_error!("- error watching {path}: {e}");
+            let groups = target::get_push_groups_with_path(&event_context.target_groups, &path);
+            let _ = event_context.status
+                .update_state(|state| {
+                    for group in groups {
+                        state.add_error(Some(&group.name), &format!("unable to watch path: {e}"));
+                    }
+                })
+                .await;
+        }
+
+        // actions of groups paused by the safety limits
+        let mut holds = Holds::default();
+        // actions for peers out of space for a group, see `get_peer_hold_key`
+        let mut peer_holds = Holds::default();
+        // messages of the nodes put back on the order they were sent
+        let mut reorder = Reorder::default();
+        // groups whose changes were dropped while the queue was full, or
+        // to be announced
+        let mut dirty: BTreeSet<GroupName> = BTreeSet::new();
+        let announce_secs = event_local
+            .announce_secs
+            .unwrap_or(generation::DEFAULT_ANNOUNCE_SECS);
+        let mut last_announce = Instant::now();
+        let mut last_space_check = Instant::now();
+        let mut last_path_check = Instant::now();
+        let mut last_power_check: Option<Instant> = None;
+        // paused through `fsy pause` until resumed or restarted
+        let mut is_paused_by_user = false;
+        let mut last_metrics_check = Instant::now();
+        let mut last_progress_check = Instant::now();
+        let mut last_watcher_check = Instant::now();
+        let mut last_health_check: Option<Instant> = None;
+        path_watcher.touch_canaries();
+
+        log!("looping event checker");
+        loop {
+            if !*event_is_running_rx.borrow() {
+                break;
+            }
+
+            // the user pausing or resuming applies right away
+            if take_pause_requests(&mut pause_rx, &mut is_paused_by_user) {
+                last_power_check = None;
+            }
+            let power_check_secs = Duration::from_secs(power::POWER_CHECK_SECS);
+            if last_power_check.is_none_or(|last| last.elapsed() >= power_check_secs) {
+                last_power_check = Some(Instant::now());
+                if let Err(e) =
+                    run_power_check(&event_local, is_paused_by_user, &paused_tx, &event_context.status)
+                        .await
+                {
+                    log_error!("- error: {e}");
+                }
+            }
+            let is_paused = paused_tx.borrow().is_some();
+
+            take_missed_changes(&mut missed_rx, &mut path_watcher);
+
+            if let Err(e) = run_event_check(
+                &event_context,
+                &mut path_watcher,
+                &mut holds,
+                &mut peer_holds,
+                &mut reorder,
+                &mut dirty,
+                is_paused,
+            )
+            .await
+            {
+                log_error!("- error: {e}");
+            }
+
+            run_admission_check(&event_context.target_groups, &event_context.admission, &event_context.actions_queue).await;
+            run_reachable_check(
+                &event_context.conn,
+                &event_deferred,
+                &event_context.storage_path,
+                &event_context.actions_queue,
+            )
+            .await;
+
+            if let Err(e) =
+                run_offense_check(&event_context.conn, &event_context.blocklist, &event_context.storage_path).await
+            {
+                log_error!("- error: {e}");
+            }
+            run_stale_ticket_check(
+                &event_context.conn,
+                &event_context.target_groups,
+                &event_context.storage_path,
+                &event_context.actions_queue,
+            )
+            .await;
+            run_completed_ticket_check(&event_context.conn, &event_context.nodes, &event_context.storage_path).await;
+
+            // announced groups go out as the dirty ones, with a new generation
+            take_announcements(&mut announce_rx, &mut dirty);
+            if announce_secs > 0 && last_announce.elapsed() >= Duration::from_secs(announce_secs) {
+                last_announce = Instant::now();
+                dirty.extend(target::get_push_group_names(&event_context.target_groups));
+            }
+
+            // the dirty groups reconcile once the sync resumes
+            if !is_paused
+                && let Err(e) = run_dirty_check(
+                    &event_context.nodes,
+                    &event_context.target_groups,
+                    &event_context.storage_path,
+                    &mut dirty,
+                    &event_context.actions_queue,
+                )
+                .await
+            {
+                log_error!("- error: {e}");
+            }
+
+            if let Err(e) = run_confirmation_check(
+                &event_context.storage_path,
+                &mut holds,
+                &event_context.actions_queue,
+                &event_context.status,
+            )
+            .await
+            {
+                log_error!("- error: {e}");
+            }
+
+            if let Err(e) = run_bandwidth_check(
+                &event_context.conn,
+                &event_context.nodes,
+                &event_context.bandwidth,
+                &bandwidth_path,
+                &event_context.status,
+            )
+            .await
+            {
+                log_error!("- error: {e}");
+            }
+
+            if last_space_check.elapsed() >= Duration::from_secs(space::SPACE_CHECK_SECS) {
+                last_space_check = Instant::now();
+                if let Err(e) = run_space_check(
+                    &event_context.target_groups,
+                    &event_context.nodes,
+                    &event_context.storage_path,
+                    &event_context.actions_queue,
+                    &event_context.status,
+                )
+                .await
+                {
+                    log_error!("- error: {e}");
+                }
+            }
+
+            if last_metrics_check.elapsed() >= Duration::from_secs(queue::METRICS_CHECK_SECS) {
+                last_metrics_check = Instant::now();
+                if let Err(e) = run_metrics_check(&event_context.actions_queue, &event_context.status).await {
+                    log_error!("- error: {e}");
+                }
+            }
+
+            let progress_check_secs = Duration::from_secs(admission::PROGRESS_CHECK_SECS);
+            if last_progress_check.elapsed() >= progress_check_secs {
+                last_progress_check = Instant::now();
+                if let Err(e) = run_progress_check(&event_context.admission, &event_context.status).await {
+                    log_error!("- error: {e}");
+                }
+            }
+
+            let health_check_secs = Duration::from_secs(health::HEALTH_CHECK_SECS);
+            if last_health_check.is_none_or(|last| last.elapsed() >= health_check_secs) {
+                last_health_check = Some(Instant::now());
+                if let Err(e) = run_health_check(&event_context.conn, &event_context.status).await {
+                    log_error!("- error: {e}");
+                }
+            }
+
+            let watcher_check_secs = Duration::from_secs(path_watcher::HEALTH_CHECK_SECS);
+            if last_watcher_check.elapsed() >= watcher_check_secs {
+                last_watcher_check = Instant::now();
+                if let Err(e) = run_watcher_check(
+                    &mut path_watcher,
+                    &event_context.target_groups,
+                    &event_context.status,
+                    &verify_now,
+                )
+                .await
+                {
+                    log_error!("- error: {e}");
+                }
+            }
+
+            if last_path_check.elapsed() >= Duration::from_secs(connection::PATH_CHECK_SECS) {
+                last_path_check = Instant::now();
+                if let Err(e) = run_path_check(&event_context.conn, &event_context.nodes, &event_context.status).await {
+                    log_error!("- error: {e}");
+                }
+            }
+
+            // the hook hears about the pulls once they settle
+            hook::run_synced();
+            sleep(Duration::from_millis(config.local.loop_debounce_millisecs)).await;
+        }
+
+        path_watcher.close().unwrap();
+    });
+
+    // handle the queues
+    let queue_is_running_rx = is_running_rx.clone();
+    let queue_queue = actions_queue.clone();
+    let queue_conn = conn.clone();
+    let queue_status = status.clone();
+    let queue_deferred = deferred.clone();
+    let queue_context = Arc::new(QueueContext {
+        target_groups: config.target_groups.clone(),
+        nodes: config.nodes.clone(),
+        status: status.clone(),
+        storage_path: tmp_dir.clone(),
+        config_path: PathBuf::from(&config.config_path),
+        conn: conn.clone(),
+        actions_queue: actions_queue.clone(),
+        deferred: deferred.clone(),
+        admission: admission.clone(),
+        blocklist: blocklist.clone(),
+        lanes: Mutex::new(Lanes::new(&config.target_groups)),
+        resources: Mutex::new(resources),
+    });
+    tokio::spawn(async move {
+        log!("looping queues");
+        loop {
+            if !*queue_is_running_rx.borrow() {
+                break;
+            }
+
+            // the transfers wait while the sync is paused, the queue keeps
+            // what comes in for when it resumes
+            if paused_rx.borrow().is_some() {
+                sleep(Duration::from_millis(config.local.loop_debounce_millisecs)).await;
+                continue;
+            }
+
+            run_queue_check(&queue_context).await;
+
+            run_send_results_check(
+                &queue_conn,
+                &queue_deferred,
+                &queue_context.storage_path,
+                &queue_queue,
+                &queue_status,
+            )
+            .await;
+
+            sleep(Duration::from_millis(config.local.loop_debounce_millisecs)).await;
+        }
+    });
+
+    // wait for all the keyboard events
+    // included will be the signal exit
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to listen for event");
+    log!("closing");
+
+    // shut the threads
+    is_running_tx.send(false).unwrap();
+
+    // NOTE: when it arrives here, it means we should close all
+    conn.close().await.unwrap();
+
+    Ok(())
+}
+
+// EventContext: what the checks of the event loop run with, the state they
+// change (holds, dirty groups...) is kept by the loop
+struct EventContext {
+    conn: ConnectionHandle,
+    nodes: Vec<target::NodeData>,
+    target_groups: Vec<target::TargetGroup>,
+    actions_queue: Arc<Mutex<queue::Queue<CommAction>>>,
+    admission: Arc<Mutex<Admission>>,
+    bandwidth: Arc<Mutex<Bandwidth>>,
+    manifests: Arc<Mutex<BTreeMap<GroupName, Manifest>>>,
+    storage_path: PathBuf,
+    status: SharedState,
+    blocklist: Arc<Mutex<Blocklist>>,
+    trust_on_first_use: bool,
+}
+
+// run_event_check is run when there is an event on the connection
+// or the sync process. For example:
+// - a received message through the connection
+//   - it parses then the message to be of the type of action
+// - targets have changed on the syncing process
+//   - it creates then actions to send through the connection
+async fn run_event_check(
+    context: &EventContext,
+    path_watcher: &mut PathWatcher,
+    holds: &mut Holds,
+    peer_holds: &mut Holds,
+    reorder: &mut Reorder,
+    dirty: &mut BTreeSet<GroupName>,
+    is_paused: bool,
+) -> Result<()> {
+    let EventContext {
+        conn,
+        nodes,
+        target_groups,
+        actions_queue,
+        admission,
+        bandwidth,
+        manifests,
+        storage_path,
+        status,
+        blocklist,
+        ..
+    } = context;
+
+    // check for events on the connection
+    let conn_event = conn.get_events().await;
+
+    // messages that waited too long on a missing one go on without it
+    let mut received = reorder.take_expired(Instant::now());
+
+    // check for events on the connection
+    if let Some(connection::ConnEvent::ReceivedMessage(node_id, raw_msg)) = conn_event {
+        capture::record(capture::Direction::Inbound, &node_id, &raw_msg);
+
+        // blocked nodes are dropped, only noting they keep trying
+        let contacts = {
+            let mut blocklist = blocklist.lock().await;
+            match blocklist.is_blocked(&node_id) {
+                true => Some(blocklist.record_contact(&node_id)),
+                false => None,
+            }
+        };
+        match contacts {
+            Some(contacts) if blocklist::should_log_contact(contacts) => {
+                log_warning!(
+                    "- warning: dropped message from blocked node {node_id} ({contacts} so far)"
+                );
+            }
+            Some(_) => {}
+            // unknown nodes wait for the user to approve them
+            None if context.trust_on_first_use && !nodes.iter().any(|n| n.id == node_id) => {
+                let action = action::CommAction::from_namespaced_msg(&node_id, &raw_msg);
+                let group_name = action.get_target_name();
+                if let Err(e) = record_pending(storage_path, &node_id, group_name.as_deref()) {
+                    log_error!("- error: {e}");
+                }
+            }
+            None => {
+                log_debug!("[event_check][conn] message received: {node_id}");
+                let action = action::CommAction::from_namespaced_msg(&node_id, &raw_msg);
+                let sequenced = action::get_sequenced_msg(&raw_msg);
+                match (sequenced, action.get_target_name()) {
+                    (Some((session, seq, _)), Some(target_name)) => received.extend(
+                        reorder.receive(&node_id, &target_name, session, seq, action, Instant::now()),
+                    ),
+                    _ => received.push(action),
+                }
+            }
+        }
+    }
+
+    for action in received {
+        match action {
+            // NOTE: handled right away, they are about the transfers
+            //       that would be ahead of them on the queue
+            CommAction::TargetPaused(..) | CommAction::TargetResumed(..) => {
+                if let Err(e) =
+                    on_peer_space(action, nodes, target_groups, peer_holds, actions_queue, status)
+                        .await
+                {
+                    log_error!("- error: {e}");
+                }
+            }
+            // NOTE: downloads without room wait aside, not on the queue
+            action => {
+                let target_name = action.get_target_name();
+                let free_space = get_group_free_space(target_groups, target_name.as_deref());
+                match admission.lock().await.admit(action, free_space) {
+                    Some(action) => actions_queue.lock().await.push(action),
+                    None => log_debug!("[event_check][admission] download waiting for room"),
+                }
+            }
+        }
+    }
+
+    // check if watcher has changed targets events
+    if let Some(targets) = path_watcher.get_changed_targets() {
+        log!("[event_check][watcher] targets changed: {}", targets.len());
+        for kind in [ChangeKind::Create, ChangeKind::Modify, ChangeKind::Remove] {
+            let count = targets.iter().filter(|t| t.kind == kind).count();
+            if count > 0 {
+                log!("- {kind}: {count}");
+            }
+        }
+
+        // keep the manifests up to date with what changed
+        if let Err(e) = update_manifests(manifests, target_groups, storage_path, &targets).await {
+            log_warning!("- warning: unable to update the manifests: {e}");
+        }
+
+        // nodes over their soft quota don't get pushes for now
+        let today = Utc::now().date_naive();
+        let paused_node_ids: Vec<PeerId> = {
+            let bandwidth = bandwidth.lock().await;
+            nodes
+                .iter()
+                .filter(|node| bandwidth.is_over_quota(node, today))
+                .map(|node| node.id.clone())
+                .collect()
+        };
+
+        // gather the changed targets of each affected target group
+        let mut group_targets: Vec<(target::TargetGroup, Vec<ChangedTarget>)> = vec![];
+        for changed_target in targets {
+            // check if we have a lock in place, if we have, there is an update going,
+            // we don't want to create a change upon that
+            let base_path = Path::new(&changed_target.base_path);
+            let file_path = base_path.join(&changed_target.relative_path);
+            if is_target_locked(base_path, &file_path) {
+                continue;
+            }
+
+            let groups =
+                target::get_push_groups_with_path(target_groups, &changed_target.base_path);
+            for group in groups {
+                match group_targets.iter_mut().find(|(g, _)| g.name == group.name) {
+                    Some((_, changed)) => changed.push(changed_target.clone()),
+                    None => group_targets.push((group, vec![changed_target.clone()])),
+                }
+            }
+        }
+
+        // retrieve nodes of the affected target groups and map to the action
+        let near_capacity = actions_queue.lock().await.is_near_capacity();
+        let mut target_actions: Vec<CommAction> = vec![];
+        for (group, changed_targets) in group_targets {
+            // with the queue about to overwrite what it has (or the sync
+            // paused), the group is only marked dirty. the batch still takes a
+            // generation, the pullers see it missing and reconcile once there
+            // is room
+            if near_capacity || is_paused || dirty.contains(&group.name) {
+                if dirty.insert(group.name.clone()) {
+                    let reason = if is_paused { "sync paused" } else { "queue near capacity" };
+                    log_warning!("- warning: {reason}, {} marked dirty", group.name);
+                }
+                if let Err(e) = generation::bump_generation(storage_path, &group.name) {
+                    log_error!("- unable to bump the generation of {}: {e}", group.name);
+                }
+                continue;
+            }
+
+            let node_ids: Vec<PeerId> = group
+                .get_node_ids(
+                    nodes,
+                    &[target::TargetMode::Push, target::TargetMode::PushPull],
+                )
+                .into_iter()
+                .filter(|node_id| !paused_node_ids.contains(node_id))
+                .collect();
+            let frozen = frozen::load_frozen(storage_path);
+            let mut actions: Vec<CommAction> = node_ids
+                .iter()
+                .flat_map(|node_id| {
+                    changed_targets.iter().filter_map(|changed_target| {
+                        // NOTE: the paths go as they are on the group, with
+                        //       the namespace of their root if it spans many
+                        let root = group.get_root_with_path(&changed_target.base_path)?;
+                        let relative_path =
+                            root.get_group_relative_path(&changed_target.relative_path);
+                        if !group.includes_path(nodes, node_id, &relative_path)
+                            || frozen.is_frozen(&group.name, &relative_path)
+                        {
+                            return None;
+                        }
+
+                        Some(
+                            CommAction::TargetHasChanged(
+                                node_id.to_owned(),
+                                group.name.clone(),
+                                relative_path.into(),
+                            )
+                            .to_send_message(),
+                        )
+                    })
+                })
+                .collect();
+
+            // the generation goes after the batch, the pullers can tell from
+            // it if they missed one
+            if !node_ids.is_empty() {
+                match generation::bump_generation(storage_path, &group.name) {
+                    Ok(generation) => actions.extend(node_ids.iter().map(|node_id| {
+                        CommAction::TargetGeneration(
+                            node_id.to_owned(),
+                            group.name.clone(),
+                            generation,
+                        )
+                        .to_send_message()
+                    })),
+                    Err(e) => {
+                        log_error!("- unable to bump the generation of {}: {e}", group.name)
+                    }
+                }
+            }
+
+            // peers out of space for the group get the changes once they resume
+            let mut offered = vec![];
+            for action in actions {
+                let key = get_peer_hold_key(action.get_node_id().unwrap_or_default(), &group.name);
+                match peer_holds.is_held(&key) {
+                    true => peer_holds.hold(&key, vec![action]),
+                    false => offered.push(action),
+                }
+            }
+            let actions = offered;
+
+            // a paused group keeps holding until the user confirms
+            if holds.is_held(&group.name) {
+                holds.hold(&group.name, actions);
+                continue;
+            }
+
+            // the batch is over the safety limits, hold it and let the user know
+            if let Some(reason) = limits::check_limits(&group, &changed_targets) {
+                log_warning!(
+                    "- warning: {} paused, {reason}. run `fsy confirm {}` to sync it",
+                    group.name, group.name
+                );
+                holds.hold(&group.name, actions);
+                if let Err(e) = status
+                    .update(|status| status.set_group_paused(&group.name, Some(&reason)))
+                    .await
+                {
+                    log_error!("- unable to show {} paused on the status: {e}", group.name);
+                }
+                continue;
+            }
+
+            // pin the changed files as they are now so the pullers don't
+            // get some of them before and some after an ongoing change
+            if group.snapshot {
+                let file_paths: Vec<PathBuf> = changed_targets
+                    .iter()
+                    .filter(|t| t.kind != ChangeKind::Remove)
+                    .map(|t| Path::new(&t.base_path).join(&t.relative_path))
+                    .collect();
+                let get_sent_path =
+                    |file_path: &Path| transform::get_sent_path(storage_path, &group, file_path);
+                match snapshot::take_snapshot(conn, &file_paths, get_sent_path).await {
+                    Ok(true) => {}
+                    Ok(false) => log_warning!(
+                        "- warning: {} kept changing while taking the snapshot, sending the last one",
+                        group.name
+                    ),
+                    Err(e) => log_warning!("- warning: unable to snapshot {}: {e}", group.name),
+                }
+            }
+
+            target_actions.extend(actions);
+        }
+
+        // cache all the actions to be sent
+        if !target_actions.is_empty() {
+            actions_queue.lock().await.push_multiple(target_actions);
+        }
+    }
+
+    Ok(())
+}
+
+// run_send_results_check goes through how the messages handed to the senders
+// of the nodes went. the ones that couldn't be sent wait for the node to be
+// reachable, one going through means it is reachable again
+async fn run_send_results_check(
+    conn: &ConnectionHandle,
+    deferred: &Arc<Mutex<Holds>>,
+    storage_path: &Path,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+    status: &SharedState,
+) {
+    let send_results = conn.take_send_results().await;
+    for send_result in send_results {
+        let node_id = send_result.node_id;
+        let e = match send_result.res {
+            Ok(_) => {
+                flush_deferred(deferred, storage_path, actions_queue, &node_id).await;
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        // NOTE: the message keeps its sequence, it goes out again on its place
+        let action = CommAction::SendMessage(node_id.as_str().into(), send_result.msg);
+        let target_name = action.get_target_name();
+        log_error!("- error sending to {node_id}: {e}");
+        let _ = status
+            .update_state(|state| state.add_error(target_name.as_deref(), &e.to_string()))
+            .await;
+        defer_action(conn, deferred, storage_path, &node_id, action).await;
+    }
+}
+
+// defer_action keeps the message aside until the node is reachable again,
+// watching for it the first time. notifications wait on its outbox, so
+// they outlive a restart
+async fn defer_action(
+    conn: &ConnectionHandle,
+    deferred: &Arc<Mutex<Holds>>,
+    storage_path: &Path,
+    node_id: &str,
+    action: CommAction,
+) {
+    let mut deferred = deferred.lock().await;
+    if !deferred.is_held(node_id) && !outbox::has_messages(storage_path, node_id) {
+        log!("- {node_id} is unreachable, deferring its messages");
+        conn.watch_peer(node_id);
+    }
+
+    if let CommAction::SendMessage(_, msg) = &action
+        && outbox::is_notification(node_id, msg)
+    {
+        match outbox::add_message(storage_path, node_id, msg) {
+            Ok(_) => return,
+            Err(e) => log_error!("- unable to keep the message on the outbox of {node_id}: {e}"),
+        }
+    }
+
+    if deferred.is_holding(node_id, &action) || deferred.count(node_id) >= MAX_DEFERRED_PER_NODE {
+        return;
+    }
+    deferred.hold(node_id, vec![action]);
+}
+
+// run_reachable_check flushes the deferred messages of the nodes that became
+// reachable, without waiting for a new change
+async fn run_reachable_check(
+    conn: &ConnectionHandle,
+    deferred: &Arc<Mutex<Holds>>,
+    storage_path: &Path,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+) {
+    let node_ids = conn.take_reachable_peers().await;
+    for node_id in node_ids {
+        flush_deferred(deferred, storage_path, actions_queue, &node_id).await;
+    }
+}
+
+// get_group_free_space retrieves the free space on the disk of the group
+// (the least of its paths), none if it can't be known
+fn get_group_free_space(
+    target_groups: &[target::TargetGroup],
+    target_name: Option<&str>,
+) -> Option<u64> {
+    let group = target_groups
+        .iter()
+        .find(|g| Some(g.name.as_str()) == target_name)?;
+    group
+        .get_roots()
+        .iter()
+        .filter_map(|root| space::get_free_space(Path::new(&root.path)))
+        .min()
+}
+
+// run_admission_check queues the waiting downloads that have room now
+async fn run_admission_check(
+    target_groups: &[target::TargetGroup],
+    admission: &Arc<Mutex<Admission>>,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+) {
+    let mut admission = admission.lock().await;
+    let admitted = admission
+        .take_admitted(|target_name| get_group_free_space(target_groups, Some(target_name)));
+    if !admitted.is_empty() {
+        log_debug!(
+            "[admission] {} downloads have room now, {} waiting",
+            admitted.len(),
+            admission.get_waiting_count()
+        );
+        actions_queue.lock().await.push_multiple(admitted);
+    }
+}
+
+// run_offense_check counts the offenses of the nodes, blocking the ones
+// that keep at it (as `fsy node block` does)
+async fn run_offense_check(
+    conn: &ConnectionHandle,
+    blocklist: &Arc<Mutex<Blocklist>>,
+    storage_path: &Path,
+) -> Result<()> {
+    let node_ids = conn.take_offenses().await;
+    for node_id in node_ids {
+        if !blocklist.lock().await.record_offense(&node_id) {
+            continue;
+        }
+
+        log_warning!(
+            "- warning: blocked node {node_id}, {} oversized or invalid messages",
+            blocklist::OFFENSES_TO_BLOCK
+        );
+        let blocklist_path = storage_path.join(blocklist::BLOCKLIST_FILE_NAME);
+        let mut stored = Blocklist::load(&blocklist_path)?;
+        stored.block(&node_id);
+        stored.save(&blocklist_path)?;
+    }
+
+    Ok(())
+}
+
+// run_completed_ticket_check keeps the files the nodes finished pulling
+// from us on the history, see `fsy recent`
+async fn run_completed_ticket_check(
+    conn: &ConnectionHandle,
+    nodes: &[target::NodeData],
+    storage_path: &Path,
+) {
+    for file in conn.take_completed_tickets().await {
+        let node_name = target::get_node_name(nodes, &file.node_id);
+        let synced = Synced::new(
+            history::Direction::Push,
+            &file.node_id,
+            &node_name,
+            &file.target_name,
+            &file.relative_path,
+        );
+        if let Err(e) = history::add(storage_path, &synced) {
+            log_error!(
+                "- unable to keep {} on the history: {e}",
+                file.relative_path
+            );
+        }
+    }
+}
+
+// run_stale_ticket_check sends a new ticket to the nodes downloading a file
+// that changed after its ticket was made, they would get what it was before
+async fn run_stale_ticket_check(
+    conn: &ConnectionHandle,
+    target_groups: &[target::TargetGroup],
+    storage_path: &Path,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+) {
+    let stale = conn.take_stale_tickets().await;
+    for file in stale {
+        let Some(target) = target::get_push_group_with_name(target_groups, &file.target_name)
+        else {
+            continue;
+        };
+
+        log!(
+            "- {} changed before {} downloaded it, sending a new ticket",
+            file.relative_path,
+            file.node_id
+        );
+        let res = action::get_download_target(
+            conn,
+            &target,
+            storage_path,
+            file.node_id,
+            file.relative_path,
+            0,
+        )
+        .await;
+        match res {
+            Ok(action) => actions_queue.lock().await.push(action),
+            Err(e) => log_error!("- error: {e}"),
+        }
+    }
+}
+
+// flush_deferred queues the messages deferred for the node ahead of the
+// rest, they are older than any queued. the ones of its outbox go first
+async fn flush_deferred(
+    deferred: &Arc<Mutex<Holds>>,
+    storage_path: &Path,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+    node_id: &str,
+) {
+    let mut deferred = deferred.lock().await;
+    let msgs = match outbox::take_messages(storage_path, node_id) {
+        Ok(msgs) => msgs,
+        Err(e) => {
+            log_error!("- unable to read the outbox of {node_id}: {e}");
+            vec![]
+        }
+    };
+    let mut actions: Vec<CommAction> = msgs
+        .into_iter()
+        .map(|msg| CommAction::SendMessage(node_id.into(), msg))
+        .collect();
+    actions.extend(deferred.release(node_id));
+    drop(deferred);
+    if actions.is_empty() {
+        return;
+    }
+
+    log!(
+        "- {node_id} is reachable, flushing {} deferred messages",
+        actions.len()
+    );
+    actions_queue.lock().await.push_front_multiple(actions);
+}
+
+// load_manifests retrieves the manifests kept of the push groups, the ones
+// without it yet get it on their first verification
+fn load_manifests(config: &config::Config, storage_path: &Path) -> BTreeMap<GroupName, Manifest> {
+    config
+        .target_groups
+        .iter()
+        .filter_map(|group| {
+            let manifest_path = Manifest::get_path(storage_path, &group.name);
+            let manifest = Manifest::load(&manifest_path).ok()?;
+            Some((group.name.clone(), manifest))
+        })
+        .collect()
+}
+
+// update_manifests applies the changed targets to the manifests of their
+// groups, saving the ones that changed. the files removed are tombstones of
+// the generation the changes go out on
+async fn update_manifests(
+    manifests: &Arc<Mutex<BTreeMap<GroupName, Manifest>>>,
+    target_groups: &[target::TargetGroup],
+    storage_path: &Path,
+    targets: &[ChangedTarget],
+) -> Result<()> {
+    let generations_path = storage_path.join(generation::GENERATIONS_FILE_NAME);
+    let generations = generation::Generations::load(&generations_path)?;
+    let mut manifests = manifests.lock().await;
+    let mut changed_groups: BTreeSet<GroupName> = BTreeSet::new();
+    for changed_target in targets {
+        let base_path = Path::new(&changed_target.base_path);
+        for group in target::get_push_groups_with_path(target_groups, &changed_target.base_path) {
+            let (Some(manifest), Some(root)) = (
+                manifests.get_mut(&group.name),
+                group.get_root_with_path(&changed_target.base_path),
+            ) else {
+                continue;
+            };
+            let (relative_path, kind) = (&changed_target.relative_path, changed_target.kind);
+            let generation = generations.get_next(&group.name);
+            if manifest.apply(base_path, &root.namespace, relative_path, kind, generation)? {
+                changed_groups.insert(group.name.clone());
+            }
+
+            let horizon = group
+                .tombstone_generations
+                .unwrap_or(manifest::DEFAULT_TOMBSTONE_GENERATIONS);
+            if manifest.prune_tombstones(generation, horizon) {
+                changed_groups.insert(group.name);
+            }
+        }
+    }
+
+    for group_name in changed_groups {
+        if let Some(manifest) = manifests.get(&group_name) {
+            manifest.save(&Manifest::get_path(storage_path, &group_name))?;
+        }
+    }
+
+    Ok(())
+}
+
+// run_manifest_verification checks the manifest of a push group against the
+// disk, passing on the changes the watcher missed. a group without one yet
+// gets it, with nothing to pass on
+async fn run_manifest_verification(
+    group: &target::TargetGroup,
+    target_groups: &[target::TargetGroup],
+    storage_path: &Path,
+    manifests: &Arc<Mutex<BTreeMap<GroupName, Manifest>>>,
+    missed_tx: &UnboundedSender<(PathBuf, ChangeKind)>,
+) -> Result<()> {
+    // NOTE: single file groups have nothing to list
+    let roots = group.get_roots();
+    let is_push = target::get_push_group_with_name(target_groups, &group.name).is_some();
+    if !is_push || !roots.iter().all(|root| Path::new(&root.path).is_dir()) {
+        return Ok(());
+    }
+
+    let scan_roots = roots.clone();
+    let entries =
+        tokio::task::spawn_blocking(move || manifest::list_group_entries(&scan_roots)).await??;
+
+    // NOTE: the files found gone go out on the next generation
+    let generations_path = storage_path.join(generation::GENERATIONS_FILE_NAME);
+    let generation = generation::Generations::load(&generations_path)?.get_next(&group.name);
+    let horizon = group
+        .tombstone_generations
+        .unwrap_or(manifest::DEFAULT_TOMBSTONE_GENERATIONS);
+
+    let missed = {
+        let mut manifests = manifests.lock().await;
+        let known = manifests.remove(&group.name);
+        let missed = known
+            .as_ref()
+            .map(|manifest| manifest.get_missed(&entries))
+            .unwrap_or_default();
+        let mut manifest = known.unwrap_or_default().with_entries(entries, generation);
+        manifest.prune_tombstones(generation, horizon);
+        let saved = manifest.save(&Manifest::get_path(storage_path, &group.name));
+        manifests.insert(group.name.clone(), manifest);
+        saved?;
+        missed
+    };
+
+    if !missed.is_empty() {
+        let count = missed.len();
+        log!("- {}: {count} changes missed, syncing them", group.name);
+    }
+    for (relative_path, kind) in missed {
+        if let Some(file_path) = group.get_file_path(&relative_path) {
+            let _ = missed_tx.send((file_path, kind));
+        }
+    }
+
+    Ok(())
+}
+
+// take_missed_changes passes the changes found by the manifest verification
+// to the watcher, they go through as the ones it sees
+fn take_missed_changes(
+    missed_rx: &mut UnboundedReceiver<(PathBuf, ChangeKind)>,
+    path_watcher: &mut PathWatcher,
+) {
+    while let Ok((changed_path, kind)) = missed_rx.try_recv() {
+        path_watcher.add_change(changed_path, kind);
+    }
+}
+
+// run_metrics_check passes the metrics of the queue on to the status, so
+// the actions it dropped after wrapping around don't go unnoticed
+async fn run_metrics_check(
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+    status: &SharedState,
+) -> Result<()> {
+    let metrics = actions_queue.lock().await.get_metrics();
+    let known = status.get().await.queue;
+    if metrics == known {
+        return Ok(());
+    }
+
+    if metrics.dropped > known.dropped {
+        log_warning!(
+            "- warning: queue full, {} actions dropped ({} so far)",
+            metrics.dropped - known.dropped,
+            metrics.dropped
+        );
+    }
+    status.update(|status| status.queue = metrics).await?;
+
+    Ok(())
+}
+
+// run_progress_check passes what is left to pull of each group on to the
+// status, with how long it should take
+async fn run_progress_check(admission: &Arc<Mutex<Admission>>, status: &SharedState) -> Result<()> {
+    let progress = admission.lock().await.get_progress(Instant::now());
+    let known = status.get().await;
+    let is_known = known
+        .groups
+        .iter()
+        .all(|group| group.pull.as_ref() == progress.get(group.name.as_str()));
+    if is_known {
+        return Ok(());
+    }
+
+    status
+        .update(|status| {
+            for group in status.groups.iter_mut() {
+                group.pull = progress.get(group.name.as_str()).cloned();
+            }
+        })
+        .await
+}
+
+// run_health_check records that the event loop goes on, along with what the
+// endpoint can do, for `/healthz` and `/readyz`
+async fn run_health_check(conn: &ConnectionHandle, status: &SharedState) -> Result<()> {
+    let network = conn.get_network_health().await;
+    let known = status.get().await.health.network;
+    if known.bound && !network.bound {
+        log_warning!("- warning: endpoint not bound anymore");
+    }
+    if known.discovery && !network.discovery {
+        log_warning!("- warning: discovery not working, nodes may not find us");
+    }
+
+    status
+        .update(|status| {
+            status.health = health::Health {
+                checked_at: Utc::now().timestamp(),
+                network,
+            }
+        })
+        .await
+}
+
+// run_power_check pauses the sync while the user asks for it, or on low
+// battery or a metered connection (as the config asks), resuming it once it
+// is not anymore
+async fn run_power_check(
+    local: &config::LocalNodeData,
+    is_paused_by_user: bool,
+    paused_tx: &Sender<Option<String>>,
+    status: &SharedState,
+) -> Result<()> {
+    let reason = if is_paused_by_user {
+        Some(power::USER_PAUSE_REASON.to_owned())
+    } else if local.pause_on_battery_below.is_none() && !local.pause_on_metered {
+        None
+    } else {
+        power::get_pause_reason(local, &power::get_power_state().await)
+    };
+    if *paused_tx.borrow() == reason {
+        return Ok(());
+    }
+
+    match &reason {
+        Some(reason) => log_warning!("- warning: sync paused, {reason}"),
+        None => log!("- sync resumed"),
+    }
+    paused_tx.send_replace(reason.clone());
+    status.update(|status| status.paused = reason).await?;
+
+    Ok(())
+}
+
+// take_pause_requests keeps the last pause (or resume) the user asked for
+// through the control api, true if there was any
+fn take_pause_requests(pause_rx: &mut UnboundedReceiver<bool>, is_paused: &mut bool) -> bool {
+    let mut is_requested = false;
+    while let Ok(paused) = pause_rx.try_recv() {
+        *is_paused = paused;
+        is_requested = true;
+    }
+    is_requested
+}
+
+// take_announcements marks the groups asked to be announced through the
+// control api as dirty
+fn take_announcements(
+    announce_rx: &mut UnboundedReceiver<GroupName>,
+    dirty: &mut BTreeSet<GroupName>,
+) {
+    while let Ok(group_name) = announce_rx.try_recv() {
+        dirty.insert(group_name);
+    }
+}
+
+// run_dirty_check lets the pullers of the dirty groups know their generation
+// once the queue has room again, having missed some they reconcile
+async fn run_dirty_check(
+    nodes: &[target::NodeData],
+    target_groups: &[target::TargetGroup],
+    storage_path: &Path,
+    dirty: &mut BTreeSet<GroupName>,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+) -> Result<()> {
+    if dirty.is_empty() || !actions_queue.lock().await.has_room() {
+        return Ok(());
+    }
+
+    let mut actions = vec![];
+    let mut failed = BTreeSet::new();
+    for group in target_groups.iter().filter(|g| dirty.contains(&g.name)) {
+        // NOTE: it stays dirty, tried again on the next check
+        let generation = match generation::bump_generation(storage_path, &group.name) {
+            Ok(generation) => generation,
+            Err(e) => {
+                log_error!("- unable to bump the generation of {}: {e}", group.name);
+                failed.insert(group.name.clone());
+                continue;
+            }
+        };
+        log!("- {}: announcing generation {generation}", group.name);
+        let modes = [target::TargetMode::Push, target::TargetMode::PushPull];
+        for node_id in group.get_node_ids(nodes, &modes) {
+            let action = CommAction::TargetGeneration(node_id, group.name.clone(), generation);
+            actions.push(action.to_send_message());
+        }
+    }
+    *dirty = failed;
+
+    actions_queue.lock().await.push_multiple(actions);
+    Ok(())
+}
+
+// run_confirmation_check releases the held actions of the groups
+// confirmed by the user
+async fn run_confirmation_check(
+    storage_path: &Path,
+    holds: &mut Holds,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+    status: &SharedState,
+) -> Result<()> {
+    for group_name in limits::take_confirmations(storage_path) {
+        let actions = holds.release(&group_name);
+        log!("[confirmation_check] {group_name} confirmed: {}", actions.len());
+        if !actions.is_empty() {
+            actions_queue.lock().await.push_multiple(actions);
+        }
+
+        status
+            .update(|status| status.set_group_paused(&group_name, None))
+            .await?;
+    }
+
+    Ok(())
+}
+
+// get_peer_hold_key is the key of the actions held for a peer out of space
+// for a group, the peer holds reuse the holds of the safety limits
+fn get_peer_hold_key(node_id: &str, group_name: &str) -> String {
+    format!("{node_id}/{group_name}")
+}
+
+// on_peer_space holds the changes of a group for a peer out of space for it
+// (the change it failed to pull included) and sends them once it resumes
+async fn on_peer_space(
+    action: CommAction,
+    nodes: &[target::NodeData],
+    target_groups: &[target::TargetGroup],
+    peer_holds: &mut Holds,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+    status: &SharedState,
+) -> Result<()> {
+    let (CommAction::TargetPaused(node_id, group_name, _)
+    | CommAction::TargetResumed(node_id, group_name)) = &action
+    else {
+        return Ok(());
+    };
+
+    // only the pullers of the group can pause it
+    let modes = [target::TargetMode::Push, target::TargetMode::PushPull];
+    let is_puller = target::get_push_group_with_name(target_groups, group_name)
+        .is_some_and(|group| group.get_node_ids(nodes, &modes).contains(node_id));
+    if !is_puller {
+        return Ok(());
+    }
+
+    let key = get_peer_hold_key(node_id, group_name);
+    match &action {
+        CommAction::TargetPaused(_, _, relative_path) => {
+            if !peer_holds.is_held(&key) {
+                log!("- {node_id} is out of space for {group_name}, holding its changes");
+            }
+            let change = CommAction::TargetHasChanged(
+                node_id.clone(),
+                group_name.clone(),
+                relative_path.clone(),
+            )
+            .to_send_message();
+            let changes = match relative_path.is_empty() || peer_holds.is_holding(&key, &change) {
+                true => vec![],
+                false => vec![change],
+            };
+            peer_holds.hold(&key, changes);
+            status
+                .update(|status| status.set_peer_group_paused(node_id, group_name, true))
+                .await?;
+        }
+        _ => {
+            let changes = peer_holds.release(&key);
+            log!("- {node_id} resumed {group_name}, sending {} held changes", changes.len());
+            actions_queue.lock().await.push_multiple(changes);
+            status
+                .update(|status| status.set_peer_group_paused(node_id, group_name, false))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// run_space_check resumes the groups degraded by a full disk once there
+// is space again, letting their pushers know
+async fn run_space_check(
+    target_groups: &[target::TargetGroup],
+    nodes: &[target::NodeData],
+    storage_path: &Path,
+    actions_queue: &Arc<Mutex<queue::Queue<CommAction>>>,
+    status: &SharedState,
+) -> Result<()> {
+    for group_name in status.get().await.get_degraded_groups() {
+        let Some(group) = target_groups.iter().find(|g| g.name == group_name) else {
+            continue;
+        };
+        let mut paths: Vec<&Path> = vec![storage_path];
+        let roots = group.get_roots();
+        paths.extend(roots.iter().map(|root| Path::new(&root.path)));
+        if !space::has_space(&paths) {
+            continue;
+        }
+
+        log!("- {group_name}: there is disk space again, resuming");
+        status
+            .update(|status| status.set_group_degraded(&group_name, None))
+            .await?;
+        hook::run("disk-space-recovered", &group_name, "there is disk space again, the group resumed");
+
+        let modes = [target::TargetMode::Pull, target::TargetMode::PushPull];
+        let actions: Vec<CommAction> = group
+            .get_node_ids(nodes, &modes)
+            .into_iter()
+            .map(|node_id| CommAction::TargetResumed(node_id, group.name.clone()).to_send_message())
+            .collect();
+        actions_queue.lock().await.push_multiple(actions);
+    }
+
+    Ok(())
+}
+
+// run_bandwidth_check accounts the bytes transferred with each node
+// since the last check, keeping the status up to date
+async fn run_bandwidth_check(
+    conn: &ConnectionHandle,
+    nodes: &[target::NodeData],
+    bandwidth: &Arc<Mutex<Bandwidth>>,
+    bandwidth_path: &Path,
+    status: &SharedState,
+) -> Result<()> {
+    let transfer_bytes = conn.take_transfer_bytes().await;
+    if transfer_bytes.is_empty() {
+        return Ok(());
+    }
+
+    let today = Utc::now().date_naive();
+    let mut bandwidth = bandwidth.lock().await;
+    for (node_id, (sent, received)) in transfer_bytes {
+        bandwidth.add(&node_id, sent, received, today);
+    }
+    bandwidth.save(bandwidth_path)?;
+
+    status
+        .update(|status| status.set_bandwidth(nodes, &bandwidth, today))
+        .await?;
+
+    Ok(())
+}
+
+// run_path_check keeps how the data to each node goes on the status, so a
+// slow sync can be told apart from a relayed one
+async fn run_path_check(
+    conn: &ConnectionHandle,
+    nodes: &[target::NodeData],
+    status: &SharedState,
+) -> Result<()> {
+    let mut paths: Vec<(&target::NodeData, Option<connection::PeerPath>)> = vec![];
+    for node in nodes {
+        paths.push((node, conn.get_peer_path(&node.id).await));
+    }
+
+    status
+        .update_state(|state| {
+            for (node, path) in paths {
+                if !state.status.set_peer_path(&node.id, path.clone()) {
+                    continue;
+                }
+                state.publish(Event::PeerPath {
+                    node_id: node.id.to_string(),
+                    path: path.as_ref().map(|path| path.kind.to_string()),
+                });
+
+                // relays are the fallback when nat traversal fails
+                let Some(path) = path else {
+                    continue;
+                };
+                let addr = path.addr.unwrap_or_default();
+                match path.kind {
+                    connection::PathKind::Relay => {
+                        log!(
+                            "- path to {} is relayed now ({addr}), no direct connection",
+                            node.name
+                        )
+                    }
+                    kind => log!("- path to {} is {kind} now ({addr})", node.name),
+                }
+            }
+        })
+        .await
+}
+
+// run_watcher_check restarts the watcher when it stopped seeing the changes
+// of some path, marking its groups on the status meanwhile. a verification
+// of the manifests then catches up with what was missed
+async fn run_watcher_check(
+    path_watcher: &mut PathWatcher,
+    target_groups: &[target::TargetGroup],
+    status: &SharedState,
+    verify_now: &Notify,
+) -> Result<()> {
+    let failing = path_watcher.take_failing_paths();
+    let mut failing_groups: BTreeSet<GroupName> = BTreeSet::new();
+    for path in &failing {
+        for group in target::get_push_groups_with_path(target_groups, path) {
+            failing_groups.insert(group.name.clone());
+        }
+    }
+
+    let mut restart_errors = vec![];
+    if !failing.is_empty() {
+        log_warning!("- watcher not seeing changes of {failing:?}, restarting it");
+        restart_errors = path_watcher.restart()?;
+        for (path, e) in &restart_errors {
+            log_error!("- error watching {path}: {e}");
+        }
+        verify_now.notify_one();
+    }
+    path_watcher.touch_canaries();
+
+    // the changes dropped are found going through the groups
+    if path_watcher.take_overflowed() {
+        log_warning!("- too many changes at once, verifying the groups against the disk");
+        verify_now.notify_one();
+    }
+
+    status
+        .update(|status| {
+            for group in target_groups {
+                let reason = match failing_groups.contains(&group.name) {
+                    true if restart_errors.is_empty() => Some("restarted it"),
+                    true => Some("unable to restart it"),
+                    false => None,
+                };
+                status.set_group_watcher(&group.name, reason);
+            }
+        })
+        .await
+}
+
+// run_scan scans the group on a blocking thread, the progress it reports
+// is passed on to the logs and status as it comes. a group of many paths
+// adds up all of them
+async fn run_scan(
+    group: &target::TargetGroup,
+    files_per_sec: Option<u64>,
+    status: &SharedState,
+) -> Result<()> {
+    // NOTE: pull groups might not have anything yet
+    let paths: Vec<PathBuf> = group
+        .get_roots()
+        .iter()
+        .map(|root| PathBuf::from(&root.path))
+        .filter(|path| path.exists())
+        .collect();
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut summary = scan::ScanSummary::default();
+    for path in paths {
+        let root_summary = run_scan_path(group, path, files_per_sec, status).await?;
+        summary.files += root_summary.files;
+        summary.bytes += root_summary.bytes;
+    }
+
+    status
+        .update(|status| {
+            status.set_group_scan(&group.name, None);
+            status.set_group_inventory(&group.name, &summary);
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn run_scan_path(
+    group: &target::TargetGroup,
+    path: PathBuf,
+    files_per_sec: Option<u64>,
+    status: &SharedState,
+) -> Result<scan::ScanSummary> {
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let scan = tokio::task::spawn_blocking(move || {
+        scan::scan_files(&path, files_per_sec, |progress| {
+            let _ = progress_tx.send(progress.clone());
+        })
+    });
+
+    while let Some(progress) = progress_rx.recv().await {
+        log!(
+            "[scan] {}: {}/{} files {}",
+            group.name,
+            progress.scanned,
+            progress.total,
+            progress.current_path
+        );
+        status
+            .update(|status| status.set_group_scan(&group.name, Some(&progress)))
+            .await?;
+    }
+
+    scan.await?
+}
+
+// QueueContext: what the actions of the queue run with, shared by the
+// lanes of the groups
+struct QueueContext {
+    target_groups: Vec<target::TargetGroup>,
+    nodes: Vec<target::NodeData>,
+    status: SharedState,
+    storage_path: PathBuf,
+    config_path: PathBuf,
+    conn: ConnectionHandle,
+    actions_queue: Arc<Mutex<queue::Queue<CommAction>>>,
+    deferred: Arc<Mutex<Holds>>,
+    admission: Arc<Mutex<Admission>>,
+    blocklist: Arc<Mutex<Blocklist>>,
+    lanes: Mutex<Lanes>,
+    resources: Mutex<Resources>,
+}
+
+// run_queue_check starts the queue items we have, each group on its own
+// lane so a big transfer or a failure of a group doesn't hold the others.
+// higher priority groups go first, the rest keeps the order
+async fn run_queue_check(context: &Arc<QueueContext>) {
+    loop {
+        // NOTE: the lanes stay locked until the action takes its place
+        let mut lanes = context.lanes.lock().await;
+        let mut resources = context.resources.lock().await;
+        let action = context.actions_queue.lock().await.pop_max_by_key_where(
+            |action| {
+                lanes.has_room(action.get_target_name().as_ref()) && resources.has_room(action)
+            },
+            |action| {
+                let target_name = action.get_target_name();
+                target::get_group_priority(&context.target_groups, target_name.as_deref())
+            },
+        );
+        let Some(action) = action else {
+            return;
+        };
+        let target_name = action.get_target_name();
+        lanes.start(target_name.clone());
+        let opens_files = resources.start(&action);
+        drop(resources);
+        drop(lanes);
+
+        let context = context.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_queue_action(&context, action).await {
+                // NOTE: we don't want to mess the process if an error comes in, keep doing it
+                log_error!("- error: {e}");
+
+                // fewer transfers at once from now on, the rest wait their turn
+                if resources::is_out_of_files(&e) {
+                    context.resources.lock().await.shrink();
+                }
+
+                // keep track of the error (on the group, if any) so it is visible
+                let _ = context
+                    .status
+                    .update_state(|state| state.add_error(target_name.as_deref(), &e.to_string()))
+                    .await;
+            }
+            context.lanes.lock().await.end(target_name);
+            if opens_files {
+                context.resources.lock().await.end();
+            }
+        });
+    }
+}
+
+// run_queue_action runs a queue item be it for the connection or the
+// syncing process. for example:
+// - if on the connection, it converts the action and sends a message
+// - if on the sync, it consumes an action and performs
+async fn run_queue_action(context: &QueueContext, action: CommAction) -> Result<()> {
+    if let CommAction::Unknown = action {
+        return Ok(());
+    }
+
+    // pulled or not, its bytes make room for the waiting downloads
+    let ticket_id = match &action {
+        CommAction::DownloadTarget(_, _, _, ticket_id, _, _, _, _) => Some(ticket_id.clone()),
+        _ => None,
+    };
+
+    // queued before the node was blocked, nothing goes to it anymore
+    if let Some(node_id) = action.get_node_id()
+        && context.blocklist.lock().await.is_blocked(node_id)
+    {
+        if let Some(ticket_id) = ticket_id {
+            context.admission.lock().await.done(&ticket_id, false);
+        }
+        return Ok(());
+    }
+
+    // the messages deferred for the node go out first, a newer one waits
+    // behind them until the node is reachable
+    if let CommAction::SendMessage(node_id, _) = &action {
+        let node_id = node_id.to_string();
+        let is_deferring = context.deferred.lock().await.is_held(&node_id)
+            || outbox::has_messages(&context.storage_path, &node_id);
+        if is_deferring {
+            defer_action(
+                &context.conn,
+                &context.deferred,
+                &context.storage_path,
+                &node_id,
+                action,
+            )
+            .await;
+            return Ok(());
+        }
+    }
+
+    let start = Utc::now().timestamp_millis();
+    log_debug!("[queue_check][action] start...");
+    let res = perform_action(
+        &context.target_groups,
+        &context.nodes,
+        &context.conn,
+        &context.actions_queue,
+        &context.status,
+        &context.storage_path,
+        &context.config_path,
+        action,
+    )
+    .await;
+    let time_spent = Utc::now().timestamp_millis() - start;
+    log_debug!("[queue_check][action] end ({time_spent}ms)");
+    if let Some(ticket_id) = ticket_id {
+        context.admission.lock().await.done(&ticket_id, res.is_ok());
+    }
+
+    res
+}
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::action::CommAction;
+use crate::path_watcher::ChangedTarget;
+use crate::target::TargetGroup;
+
+const CONFIRM_DIR_NAME: &str = "confirm";
+
+// check_limits verifies a batch of changes of a group against its safety
+// limits, returning the reason when any is exceeded
+pub fn check_limits(group: &TargetGroup, changed_targets: &[ChangedTarget]) -> Option<String> {
+    if let Some(max_files) = group.max_files_per_batch {
+        let mut relative_paths: Vec<&str> = changed_targets
+            .iter()
+            .map(|t| t.relative_path.as_str())
+            .collect();
+        relative_paths.sort();
+        relative_paths.dedup();
+
+        if relative_paths.len() > max_files {
+            return Some(format!(
+                "{} files changed at once, over the limit of {max_files}",
+                relative_paths.len()
+            ));
+        }
+    }
+
+    if let Some(max_size) = group.max_file_size {
+        for changed_target in changed_targets {
+            let file_path = Path::new(&changed_target.base_path).join(&changed_target.relative_path);
+            let Ok(meta) = fs::metadata(&file_path) else {
+                continue;
+            };
+
+            if meta.is_file() && meta.len() > max_size {
+                return Some(format!(
+                    "{} has {} bytes, over the limit of {max_size}",
+                    file_path.display(),
+                    meta.len()
+                ));
+            }
+        }
+    }
+
+    None
+}
+
+// Holds: actions of paused groups kept aside until the user confirms them
+#[derive(Default)]
+pub struct Holds {
+    held: HashMap<String, Vec<CommAction>>,
+}
+
+impl Holds {
+    pub fn is_held(&self, group_name: &str) -> bool {
+        self.held.contains_key(group_name)
+    }
+
+    pub fn hold(&mut self, group_name: &str, actions: Vec<CommAction>) {
+        self.held
+            .entry(group_name.to_owned())
+            .or_default()
+            .extend(actions);
+    }
+
+    pub fn count(&self, group_name: &str) -> usize {
+        self.held
+            .get(group_name)
+            .map(|actions| actions.len())
+            .unwrap_or_default()
+    }
+
+    pub fn is_holding(&self, group_name: &str, action: &CommAction) -> bool {
+        self.held
+            .get(group_name)
+            .is_some_and(|actions| actions.contains(action))
+    }
+
+    // release returns the held actions of the group, the group is no
+    // longer on hold after it
+    pub fn release(&mut self, group_name: &str) -> Vec<CommAction> {
+        self.held.remove(group_name).unwrap_or_default()
+    }
+}
+
+fn get_confirm_dir(storage_path: &Path) -> PathBuf {
+    storage_path.join(CONFIRM_DIR_NAME)
+}
+
+// request_confirmation lets the running daemon know the user confirmed
+// the group held changes
+pub fn request_confirmation(storage_path: &Path, group_name: &str) -> Result<()> {
+    let confirm_dir = get_confirm_dir(storage_path);
+    fs::create_dir_all(&confirm_dir)?;
+    fs::write(confirm_dir.join(group_name), b"")?;
+    Ok(())
+}
+
+// Confirmation: the outcome of `fsy confirm`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Confirmation {
+    pub group_name: String,
+}
+
+impl fmt::Display for Confirmation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} confirmed, the daemon will sync it shortly",
+            self.group_name
+        )
+    }
+}
+
+// take_confirmations retrieves the groups confirmed since the last call
+pub fn take_confirmations(storage_path: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(get_confirm_dir(storage_path)) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            fs::remove_file(entry.path()).ok()?;
+            entry.file_name().into_string().ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path_watcher::ChangeKind;
+    use anyhow::Result;
+
+    fn get_changed_targets(base_path: &str, relative_paths: &[&str]) -> Vec<ChangedTarget> {
+        relative_paths
+            .iter()
+            .map(|p| ChangedTarget {
+                base_path: base_path.to_string(),
+                relative_path: p.to_string(),
+                kind: ChangeKind::Modify,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_check_limits() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_limits");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("small"), b"foo")?;
+        fs::write(dir.join("big"), b"foo bar zed")?;
+        let base_path = dir.to_string_lossy().to_string();
+
+        let test_values = [
+            // (max_file_size, max_files_per_batch, relative_paths, exceeded)
+            (None, None, vec!["small", "big"], false),
+            (Some(5), None, vec!["small"], false),
+            (Some(5), None, vec!["small", "big"], true),
+            (Some(5), None, vec!["missing"], false),
+            (None, Some(2), vec!["small", "big"], false),
+            (None, Some(2), vec!["small", "big", "small"], false),
+            (None, Some(1), vec!["small", "big"], true),
+        ];
+
+        for spec in test_values {
+            let group = TargetGroup {
+                name: "foo".into(),
+                path: base_path.clone(),
+                max_file_size: spec.0,
+                max_files_per_batch: spec.1,
+                ..Default::default()
+            };
+            let changed_targets = get_changed_targets(&base_path, &spec.2);
+            let res = check_limits(&group, &changed_targets);
+            assert_eq!(res.is_some(), spec.3, "{:?}", spec.2);
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_holds() -> Result<()> {
+        let mut holds = Holds::default();
+        assert!(!holds.is_held("foo"));
+
+        holds.hold("foo", vec![CommAction::Unknown]);
+        holds.hold("foo", vec![CommAction::Unknown]);
+        assert!(holds.is_held("foo"));
+        assert!(!holds.is_held("bar"));
+        assert!(holds.is_holding("foo", &CommAction::Unknown));
+        assert!(!holds.is_holding("bar", &CommAction::Unknown));
+
+        assert_eq!(holds.release("foo").len(), 2);
+        assert!(!holds.is_held("foo"));
+        assert!(holds.release("foo").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirmations() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_confirmations");
+        let _ = fs::remove_dir_all(&dir);
+        assert!(take_confirmations(&dir).is_empty());
+
+        request_confirmation(&dir, "foo")?;
+        request_confirmation(&dir, "foo")?;
+        assert_eq!(take_confirmations(&dir), vec!["foo".to_string()]);
+        assert!(take_confirmations(&dir).is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
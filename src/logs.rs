@@ -0,0 +1,317 @@
+use anyhow::{Result, bail};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+pub const LOGS_FILE_NAME: &str = "logs.log";
+
+// how many log lines the daemon keeps around
+const MAX_LOG_LINES: usize = 5000;
+
+// log prints a line of the daemon and keeps it so `fsy logs` can show it
+// even when stdout goes somewhere else (systemd, launchd, ...)
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logs::write(module_path!(), $crate::logs::LogLevel::Info, format_args!($($arg)*))
+    };
+}
+pub(crate) use log;
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logs::write(module_path!(), $crate::logs::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+pub(crate) use log_error;
+
+macro_rules! log_warning {
+    ($($arg:tt)*) => {
+        $crate::logs::write(module_path!(), $crate::logs::LogLevel::Warning, format_args!($($arg)*))
+    };
+}
+pub(crate) use log_warning;
+
+// log_debug is for what only helps when looking into an issue, hidden
+// unless the level (or the filter of the module) is debug
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::logs::write(module_path!(), $crate::logs::LogLevel::Debug, format_args!($($arg)*))
+    };
+}
+pub(crate) use log_debug;
+
+// LogLevel: how much the daemon tells, each level shows the ones before it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    #[serde(rename = "error")]
+    Error,
+    #[serde(rename = "warning")]
+    Warning,
+    #[default]
+    #[serde(rename = "info")]
+    Info,
+    #[serde(rename = "debug")]
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warning" => Ok(LogLevel::Warning),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => bail!("invalid log level \"{s}\", use error, warning, info or debug"),
+        }
+    }
+}
+
+// LogFilter: the level of a module (and its submodules), as
+// `connection=debug`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    pub module: String,
+    pub level: LogLevel,
+}
+
+impl FromStr for LogFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((module, level)) = s.split_once('=') else {
+            bail!("invalid log filter \"{s}\", use <module>=<level> as \"connection=debug\"");
+        };
+        if module.is_empty() {
+            bail!("invalid log filter \"{s}\", the module is missing");
+        }
+
+        Ok(Self {
+            module: module.to_owned(),
+            level: level.parse()?,
+        })
+    }
+}
+
+// parse_filters retrieves the filters out of the ones on the config
+pub fn parse_filters(filters: &[String]) -> Result<Vec<LogFilter>> {
+    filters.iter().map(|filter| filter.parse()).collect()
+}
+
+// get_module_level retrieves the level a module logs at, the filter of the
+// module closest to it or the level otherwise. module paths are as
+// `module_path!` gives them, the name of the crate goes
+fn get_module_level(level: LogLevel, filters: &[LogFilter], module_path: &str) -> LogLevel {
+    let module = module_path.split_once("::").map(|(_, m)| m).unwrap_or("");
+    filters
+        .iter()
+        .filter(|f| {
+            module == f.module
+                || module
+                    .strip_prefix(f.module.as_str())
+                    .is_some_and(|rest| rest.starts_with("::"))
+        })
+        .max_by_key(|f| f.module.len())
+        .map(|f| f.level)
+        .unwrap_or(level)
+}
+
+// Logs: ring buffer of the last log lines, mirrored to a file on the
+// storage when set. the file is rewritten from the buffer every
+// MAX_LOG_LINES appends so it never grows past twice that
+struct Logs {
+    lines: VecDeque<String>,
+    path: Option<PathBuf>,
+    appended: usize, // lines appended to the file since it was rewritten
+    level: LogLevel,
+    filters: Vec<LogFilter>,
+}
+
+static LOGS: Mutex<Logs> = Mutex::new(Logs {
+    lines: VecDeque::new(),
+    path: None,
+    appended: 0,
+    level: LogLevel::Info,
+    filters: vec![],
+});
+
+impl Logs {
+    fn push(&mut self, line: String) -> Result<()> {
+        if self.lines.len() >= MAX_LOG_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        self.appended += 1;
+        if self.appended >= MAX_LOG_LINES {
+            self.appended = 0;
+            let content: Vec<&str> = self.lines.iter().map(|l| l.as_str()).collect();
+            fs::write(path, content.join("\n") + "\n")?;
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", self.lines.back().unwrap())?;
+        Ok(())
+    }
+}
+
+// init mirrors the logs to the storage from now on
+pub fn init(storage_path: &Path) {
+    if let Ok(mut logs) = LOGS.lock() {
+        logs.path = Some(storage_path.join(LOGS_FILE_NAME));
+    }
+}
+
+// set_level changes how much is logged from now on
+pub fn set_level(level: LogLevel, filters: Vec<LogFilter>) {
+    if let Ok(mut logs) = LOGS.lock() {
+        logs.level = level;
+        logs.filters = filters;
+    }
+}
+
+pub fn write(module_path: &str, level: LogLevel, args: fmt::Arguments) {
+    let is_enabled = LOGS
+        .lock()
+        .is_ok_and(|logs| level <= get_module_level(logs.level, &logs.filters, module_path));
+    if !is_enabled {
+        return;
+    }
+
+    let line = args.to_string();
+    println!("{line}");
+
+    let line = format!("{} {line}", Utc::now().format("%Y-%m-%d %H:%M:%S"));
+    if let Ok(mut logs) = LOGS.lock()
+        && let Err(e) = logs.push(line)
+    {
+        // NOTE: can't log a failure to log, stderr is the best we have
+        eprintln!("unable to keep log: {e}");
+    }
+}
+
+// get_new_lines retrieves the lines after the last one seen, all of
+// them if it isn't there anymore (or nothing was seen yet)
+pub fn get_new_lines<'a>(content: &'a str, last_line: Option<&str>) -> Vec<&'a str> {
+    let lines: Vec<&str> = content.lines().collect();
+    let start = last_line
+        .and_then(|last_line| lines.iter().rposition(|l| *l == last_line))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    lines[start..].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_logs_push() -> Result<()> {
+        let path = std::env::temp_dir().join("fsy_test_logs.log");
+        let _ = fs::remove_file(&path);
+        let mut logs = Logs {
+            lines: VecDeque::new(),
+            path: Some(path.clone()),
+            appended: 0,
+            level: LogLevel::Info,
+            filters: vec![],
+        };
+
+        for i in 0..MAX_LOG_LINES - 1 {
+            logs.push(format!("line {i}"))?;
+        }
+        assert_eq!(fs::read_to_string(&path)?.lines().count(), MAX_LOG_LINES - 1);
+
+        // the file gets rewritten from the buffer
+        logs.push("foo".to_string())?;
+        let content = fs::read_to_string(&path)?;
+        assert_eq!(content.lines().count(), MAX_LOG_LINES);
+        assert_eq!(content.lines().last(), Some("foo"));
+
+        // the buffer drops the oldest lines
+        logs.push("bar".to_string())?;
+        assert_eq!(logs.lines.len(), MAX_LOG_LINES);
+        assert_eq!(logs.lines.front().unwrap(), "line 1");
+        let content = fs::read_to_string(&path)?;
+        assert_eq!(content.lines().last(), Some("bar"));
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_module_level() -> Result<()> {
+        let filters = parse_filters(&[
+            "connection=debug".to_string(),
+            "action=error".to_string(),
+            "action::pull=warning".to_string(),
+        ])?;
+        let test_values = [
+            // (module path, expected)
+            ("fsy", LogLevel::Info),
+            ("fsy::connection", LogLevel::Debug),
+            ("fsy::connection::peer", LogLevel::Debug),
+            ("fsy::connections", LogLevel::Info),
+            ("fsy::action", LogLevel::Error),
+            ("fsy::action::pull", LogLevel::Warning),
+            ("fsy::queue", LogLevel::Info),
+        ];
+
+        for spec in test_values {
+            assert_eq!(
+                get_module_level(LogLevel::Info, &filters, spec.0),
+                spec.1,
+                "{}",
+                spec.0
+            );
+        }
+
+        let test_values = [
+            // (filter, is_valid)
+            ("connection=debug", true),
+            ("connection=warning", true),
+            ("connection=verbose", false),
+            ("connection", false),
+            ("=debug", false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(spec.0.parse::<LogFilter>().is_ok(), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_new_lines() -> Result<()> {
+        let test_values = [
+            // (content, last_line, expected)
+            ("", None, vec![]),
+            ("foo\nbar\n", None, vec!["foo", "bar"]),
+            ("foo\nbar\nzed\n", Some("foo"), vec!["bar", "zed"]),
+            ("foo\nbar\n", Some("bar"), vec![]),
+            ("foo\nbar\n", Some("zed"), vec!["foo", "bar"]),
+            ("foo\nbar\nfoo\nzed\n", Some("foo"), vec!["zed"]),
+        ];
+
+        for spec in test_values {
+            assert_eq!(get_new_lines(spec.0, spec.1), spec.2, "{:?}", spec.1);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,643 @@
+use anyhow::Result;
+use chrono::Utc;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::path_watcher::ChangeKind;
+use crate::target::GroupRoot;
+use crate::{export, reserved};
+
+pub const MANIFESTS_DIR_NAME: &str = "manifests";
+pub const TRASH_DIR_NAME: &str = "trash";
+
+// how often the manifests of the push groups are checked against the disk,
+// to catch the changes the watcher missed
+pub const DEFAULT_VERIFY_SECS: u64 = 60 * 60;
+
+// generations of the group a removed file is remembered for when the group
+// doesn't set it, a puller away for longer doesn't learn of the removal
+pub const DEFAULT_TOMBSTONE_GENERATIONS: u64 = 1000;
+
+// tombstones go on the manifests as `-\t<generation>\t<path>`
+const TOMBSTONE_PREFIX: &str = "-\t";
+
+// FileEntry: what a file on the manifest is known to be, a file whose size
+// or modification is not the same anymore changed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileEntry {
+    pub size: u64,
+    pub modified: i64, // millis since the epoch
+}
+
+impl From<&fs::Metadata> for FileEntry {
+    fn from(meta: &fs::Metadata) -> Self {
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        Self {
+            size: meta.len(),
+            modified,
+        }
+    }
+}
+
+// to_manifest_path retrieves the relative path separated by `/` no matter
+// the os so they can be compared between nodes
+fn to_manifest_path(relative_path: &Path) -> String {
+    let components: Vec<String> = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    components.join("/")
+}
+
+// walk_files calls f with the relative path and entry of all the files
+// under the base, leaving out what fsy keeps there
+fn walk_files(
+    base_path: &Path,
+    mut f: impl FnMut(String, &fs::DirEntry) -> Result<()>,
+) -> Result<()> {
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(relative_dir) = dirs.pop() {
+        for entry in fs::read_dir(base_path.join(&relative_dir))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let relative_path = relative_dir.join(&name);
+            if reserved::is_reserved_path(&relative_path) {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() {
+                dirs.push(relative_path);
+                continue;
+            }
+
+            f(to_manifest_path(&relative_path), &entry)?;
+        }
+    }
+
+    Ok(())
+}
+
+// list_files retrieves the relative paths of all the files under the base,
+// separated by `/` no matter the os so they can be compared between nodes
+pub fn list_files(base_path: &Path) -> Result<Vec<String>> {
+    let mut files = vec![];
+    walk_files(base_path, |relative_path, _| {
+        files.push(relative_path);
+        Ok(())
+    })?;
+
+    files.sort();
+    Ok(files)
+}
+
+// list_entries retrieves all the files under the base with what they are
+// now, see `list_files`
+pub fn list_entries(base_path: &Path) -> Result<BTreeMap<String, FileEntry>> {
+    let mut entries = BTreeMap::new();
+    walk_files(base_path, |relative_path, entry| {
+        entries.insert(relative_path, FileEntry::from(&entry.metadata()?));
+        Ok(())
+    })?;
+
+    Ok(entries)
+}
+
+// list_group_files retrieves the files of all the roots of a group, as
+// paths in the group, see `list_files`
+pub fn list_group_files(roots: &[GroupRoot]) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for root in roots {
+        let root_files = list_files(Path::new(&root.path))?;
+        files.extend(root_files.iter().map(|f| root.get_group_relative_path(f)));
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+// list_group_entries retrieves the files of all the roots of a group with
+// what they are now, see `list_group_files`
+pub fn list_group_entries(roots: &[GroupRoot]) -> Result<BTreeMap<String, FileEntry>> {
+    let mut entries = BTreeMap::new();
+    for root in roots {
+        for (relative_path, entry) in list_entries(Path::new(&root.path))? {
+            entries.insert(root.get_group_relative_path(&relative_path), entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+// Manifest: the files of a push group kept on the storage and updated from
+// the watcher events, so the group isn't gone through on every request for
+// it. a verification scan every now and then catches the missed events
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub entries: BTreeMap<String, FileEntry>,
+    pub tombstones: BTreeMap<String, u64>, // removed files -> generation they were removed on
+}
+
+impl Manifest {
+    pub fn get_path(storage_path: &Path, group_name: &str) -> PathBuf {
+        storage_path
+            .join(MANIFESTS_DIR_NAME)
+            .join(format!("{group_name}.files"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let entries = content
+            .lines()
+            .filter(|line| !line.starts_with(TOMBSTONE_PREFIX))
+            .filter_map(|line| {
+                let mut spl = line.splitn(3, '\t');
+                let size = spl.next()?.parse().ok()?;
+                let modified = spl.next()?.parse().ok()?;
+                let relative_path = spl.next().filter(|p| !p.is_empty())?;
+                Some((relative_path.to_owned(), FileEntry { size, modified }))
+            })
+            .collect();
+
+        Ok(Self {
+            entries,
+            tombstones: decode_tombstones(&content),
+        })
+    }
+
+    // save writes the manifest as a whole before putting it in place, the
+    // requests for it never read it half written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(relative_path, entry)| {
+                format!("{}\t{}\t{relative_path}\n", entry.size, entry.modified)
+            })
+            .chain(encode_tombstones(&self.tombstones).map(|line| line + "\n"))
+            .collect();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn get_files(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    // apply updates the manifest with a change seen by the watcher, telling
+    // if anything changed. a folder moved in comes as a single change, its
+    // files are listed then. the namespace is the one of the root of the
+    // group the base is, see `GroupRoot`. the files removed are left as
+    // tombstones of the generation the change goes out on
+    pub fn apply(
+        &mut self,
+        base_path: &Path,
+        namespace: &str,
+        relative_path: &str,
+        kind: ChangeKind,
+        generation: u64,
+    ) -> Result<bool> {
+        let manifest_path = to_manifest_path(Path::new(relative_path));
+        if manifest_path.is_empty() {
+            return Ok(false);
+        }
+        let manifest_path = match namespace.is_empty() {
+            true => manifest_path,
+            false => format!("{namespace}/{manifest_path}"),
+        };
+
+        let file_path = base_path.join(relative_path);
+        let Ok(meta) = fs::metadata(&file_path) else {
+            let prefix = format!("{manifest_path}/");
+            let removed: Vec<String> = self
+                .entries
+                .keys()
+                .filter(|p| **p == manifest_path || p.starts_with(&prefix))
+                .cloned()
+                .collect();
+            for p in &removed {
+                self.entries.remove(p);
+                self.tombstones.insert(p.clone(), generation);
+            }
+            return Ok(!removed.is_empty());
+        };
+
+        if meta.is_dir() {
+            if kind != ChangeKind::Create {
+                return Ok(false);
+            }
+
+            let mut changed = false;
+            for (p, entry) in list_entries(&file_path)? {
+                let p = format!("{manifest_path}/{p}");
+                self.tombstones.remove(&p);
+                changed |= self.entries.insert(p, entry) != Some(entry);
+            }
+            return Ok(changed);
+        }
+
+        let entry = FileEntry::from(&meta);
+        self.tombstones.remove(&manifest_path);
+        Ok(self.entries.insert(manifest_path, entry) != Some(entry))
+    }
+
+    // with_entries retrieves the manifest with the files on the disk now,
+    // the ones it had that are gone become tombstones of the generation
+    pub fn with_entries(&self, entries: BTreeMap<String, FileEntry>, generation: u64) -> Self {
+        let mut tombstones: BTreeMap<String, u64> = self
+            .tombstones
+            .iter()
+            .filter(|(p, _)| !entries.contains_key(*p))
+            .map(|(p, g)| (p.clone(), *g))
+            .collect();
+        for p in self.entries.keys().filter(|p| !entries.contains_key(*p)) {
+            tombstones.insert(p.clone(), generation);
+        }
+
+        Self {
+            entries,
+            tombstones,
+        }
+    }
+
+    // prune_tombstones forgets the files removed more than the horizon of
+    // generations ago, telling if any was
+    pub fn prune_tombstones(&mut self, generation: u64, horizon: u64) -> bool {
+        let count = self.tombstones.len();
+        self.tombstones
+            .retain(|_, g| generation.saturating_sub(*g) < horizon);
+        count != self.tombstones.len()
+    }
+
+    // get_missed retrieves the changes between the manifest and the files
+    // on the disk, what the watcher didn't tell about
+    pub fn get_missed(&self, entries: &BTreeMap<String, FileEntry>) -> Vec<(String, ChangeKind)> {
+        let mut missed: Vec<(String, ChangeKind)> = entries
+            .iter()
+            .filter_map(|(p, entry)| match self.entries.get(p) {
+                None => Some((p.clone(), ChangeKind::Create)),
+                Some(known) if known != entry => Some((p.clone(), ChangeKind::Modify)),
+                _ => None,
+            })
+            .collect();
+        missed.extend(
+            self.entries
+                .keys()
+                .filter(|p| !entries.contains_key(*p))
+                .map(|p| (p.clone(), ChangeKind::Remove)),
+        );
+
+        missed
+    }
+}
+
+// get_remote_path is where the last manifest a node sent of a group is kept
+pub fn get_remote_path(storage_path: &Path, group_name: &str, node_id: &str) -> PathBuf {
+    storage_path
+        .join(MANIFESTS_DIR_NAME)
+        .join(format!("{group_name}.{node_id}.remote"))
+}
+
+// get_digest retrieves the digest of the content of a manifest, nodes
+// compare them to tell if they diverged without sending it over
+pub fn get_digest(content: &str) -> String {
+    blake3::hash(content.as_bytes()).to_hex().to_string()
+}
+
+// get_remote_digest retrieves the digest of the last manifest the node sent
+// of the group, none when there isn't one
+pub fn get_remote_digest(storage_path: &Path, group_name: &str, node_id: &str) -> Option<String> {
+    let path = get_remote_path(storage_path, group_name, node_id);
+    fs::read_to_string(path).ok().map(|c| get_digest(&c))
+}
+
+// encode_manifest puts together the files and the tombstones of the files
+// removed, so a puller away when they were removed removes them too
+pub fn encode_manifest(files: &[String], tombstones: &BTreeMap<String, u64>) -> String {
+    let lines: Vec<String> = files
+        .iter()
+        .cloned()
+        .chain(encode_tombstones(tombstones))
+        .collect();
+    lines.join("\n")
+}
+
+pub fn decode_manifest(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with(TOMBSTONE_PREFIX))
+        .map(|l| l.to_owned())
+        .collect()
+}
+
+fn encode_tombstones(tombstones: &BTreeMap<String, u64>) -> impl Iterator<Item = String> {
+    tombstones.iter().map(|(relative_path, generation)| {
+        format!("{TOMBSTONE_PREFIX}{generation}\t{relative_path}")
+    })
+}
+
+// decode_tombstones retrieves the files removed of a manifest with the
+// generation they were removed on
+pub fn decode_tombstones(content: &str) -> BTreeMap<String, u64> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut spl = line.strip_prefix(TOMBSTONE_PREFIX)?.splitn(2, '\t');
+            let generation = spl.next()?.parse().ok()?;
+            let relative_path = spl.next().filter(|p| !p.is_empty())?;
+            Some((relative_path.to_owned(), generation))
+        })
+        .collect()
+}
+
+// get_line_path retrieves the path of a line of a manifest, of a file or
+// of a tombstone
+pub fn get_line_path(line: &str) -> &str {
+    match line.strip_prefix(TOMBSTONE_PREFIX) {
+        Some(tombstone) => tombstone
+            .split_once('\t')
+            .map(|(_, p)| p)
+            .unwrap_or_default(),
+        None => line,
+    }
+}
+
+// get_extraneous_files retrieves the local files the remote doesn't have
+pub fn get_extraneous_files(local_files: &[String], remote_files: &[String]) -> Vec<String> {
+    let remote_files: HashSet<&str> = remote_files.iter().map(|f| f.as_str()).collect();
+    local_files
+        .iter()
+        .filter(|f| !remote_files.contains(f.as_str()))
+        .cloned()
+        .collect()
+}
+
+// move_to_trash moves the files out of the group into the storage trash,
+// so a mistake can still be undone. folders left empty are removed
+pub fn move_to_trash(
+    storage_path: &Path,
+    group_name: &str,
+    base_path: &Path,
+    relative_paths: &[String],
+) -> Result<()> {
+    let trash_path = storage_path
+        .join(TRASH_DIR_NAME)
+        .join(group_name)
+        .join(Utc::now().format("%Y%m%d%H%M%S").to_string());
+
+    for relative_path in relative_paths {
+        let src = base_path.join(relative_path);
+        let dst = trash_path.join(relative_path);
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        export::move_into_place(&src, &dst, false)?;
+
+        // NOTE: removing a folder that isn't empty fails, which is what we want
+        let mut dir = src.parent();
+        while let Some(d) = dir {
+            if d == base_path || !d.starts_with(base_path) || fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_list_files() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_manifest_list");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("foo/bar"))?;
+        fs::create_dir_all(dir.join("empty"))?;
+        fs::write(dir.join("a.txt"), b"")?;
+        fs::write(dir.join("foo/b.txt"), b"")?;
+        fs::write(dir.join("foo/bar/c.txt"), b"")?;
+        fs::create_dir_all(dir.join(reserved::FSY_DIR_NAME))?;
+        fs::write(dir.join(reserved::FSY_DIR_NAME).join("d.txt"), b"")?;
+
+        let files = list_files(&dir)?;
+        assert_eq!(files, vec!["a.txt", "foo/b.txt", "foo/bar/c.txt"]);
+        assert_eq!(
+            decode_manifest(&encode_manifest(&files, &BTreeMap::new())),
+            files
+        );
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_group_files() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_manifest_roots");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("config/lua"))?;
+        fs::create_dir_all(dir.join("share"))?;
+        fs::write(dir.join("config/init.lua"), b"")?;
+        fs::write(dir.join("config/lua/a.lua"), b"")?;
+        fs::write(dir.join("share/b.txt"), b"")?;
+
+        let roots = [
+            GroupRoot {
+                namespace: "0".to_string(),
+                path: dir.join("config").to_string_lossy().to_string(),
+            },
+            GroupRoot {
+                namespace: "1".to_string(),
+                path: dir.join("share").to_string_lossy().to_string(),
+            },
+        ];
+        let files = list_group_files(&roots)?;
+        assert_eq!(files, vec!["0/init.lua", "0/lua/a.lua", "1/b.txt"]);
+
+        // the changes of a root go with its namespace too
+        let mut manifest = Manifest {
+            entries: list_group_entries(&roots)?,
+            ..Default::default()
+        };
+        assert_eq!(manifest.get_files(), files);
+        fs::write(dir.join("share/c.txt"), b"")?;
+        let share_path = dir.join("share");
+        assert!(manifest.apply(&share_path, "1", "c.txt", ChangeKind::Create, 1)?);
+        assert_eq!(manifest.get_files().last().unwrap(), "1/c.txt");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_manifest_incremental");
+        let _ = fs::remove_dir_all(&dir);
+        let base_path = dir.join("group");
+        fs::create_dir_all(base_path.join("foo"))?;
+        fs::write(base_path.join("a.txt"), b"a")?;
+        fs::write(base_path.join("foo/b.txt"), b"b")?;
+
+        let mut manifest = Manifest {
+            entries: list_entries(&base_path)?,
+            ..Default::default()
+        };
+        assert_eq!(manifest.get_files(), vec!["a.txt", "foo/b.txt"]);
+        assert!(manifest.get_missed(&list_entries(&base_path)?).is_empty());
+
+        // a folder moved in, a file changed and another removed
+        fs::create_dir_all(base_path.join("bar/zed"))?;
+        fs::write(base_path.join("bar/c.txt"), b"c")?;
+        fs::write(base_path.join("bar/zed/d.txt"), b"d")?;
+        fs::write(base_path.join("a.txt"), b"aaa")?;
+        fs::remove_file(base_path.join("foo/b.txt"))?;
+        let test_values = [
+            // (relative path, kind, changed)
+            ("bar", ChangeKind::Create, true),
+            ("bar", ChangeKind::Modify, false),
+            ("a.txt", ChangeKind::Modify, true),
+            ("a.txt", ChangeKind::Modify, false),
+            ("foo/b.txt", ChangeKind::Remove, true),
+            ("foo/b.txt", ChangeKind::Remove, false),
+            ("", ChangeKind::Modify, false),
+        ];
+
+        let missed = manifest.get_missed(&list_entries(&base_path)?);
+        assert_eq!(missed.len(), 4);
+        for spec in test_values {
+            let changed = manifest.apply(&base_path, "", spec.0, spec.1, 1)?;
+            assert_eq!(changed, spec.2, "{:?}", spec);
+        }
+        assert_eq!(
+            manifest.get_files(),
+            vec!["a.txt", "bar/c.txt", "bar/zed/d.txt"]
+        );
+        assert!(manifest.get_missed(&list_entries(&base_path)?).is_empty());
+
+        // a removed folder takes its files with it, they are all left as
+        // tombstones of the generation
+        fs::remove_dir_all(base_path.join("bar"))?;
+        assert!(manifest.apply(&base_path, "", "bar", ChangeKind::Remove, 2)?);
+        assert_eq!(manifest.get_files(), vec!["a.txt"]);
+        let tombstones: Vec<(&str, u64)> = manifest
+            .tombstones
+            .iter()
+            .map(|(p, g)| (p.as_str(), *g))
+            .collect();
+        assert_eq!(
+            tombstones,
+            vec![("bar/c.txt", 2), ("bar/zed/d.txt", 2), ("foo/b.txt", 1)]
+        );
+
+        // a file back isn't removed anymore
+        fs::write(base_path.join("foo/b.txt"), b"b")?;
+        assert!(manifest.apply(&base_path, "", "foo/b.txt", ChangeKind::Create, 3)?);
+        assert!(!manifest.tombstones.contains_key("foo/b.txt"));
+
+        let manifest_path = Manifest::get_path(&dir.join("storage"), "foo");
+        manifest.save(&manifest_path)?;
+        assert_eq!(Manifest::load(&manifest_path)?, manifest);
+
+        // what the verification finds gone becomes a tombstone too
+        fs::remove_file(base_path.join("a.txt"))?;
+        let mut manifest = manifest.with_entries(list_entries(&base_path)?, 4);
+        assert_eq!(manifest.get_files(), vec!["foo/b.txt"]);
+        assert_eq!(manifest.tombstones.get("a.txt"), Some(&4));
+        assert_eq!(manifest.tombstones.len(), 3);
+
+        let test_values = [
+            // (generation, horizon, pruned, tombstones left)
+            (4, 10, false, 3),
+            (11, 10, false, 3),
+            (12, 10, true, 1),
+            (14, 10, true, 0),
+        ];
+        for spec in test_values {
+            assert_eq!(
+                manifest.prune_tombstones(spec.0, spec.1),
+                spec.2,
+                "{:?}",
+                spec
+            );
+            assert_eq!(manifest.tombstones.len(), spec.3, "{:?}", spec);
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_manifest() -> Result<()> {
+        let files: Vec<String> = ["a.txt", "foo/b.txt"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        let tombstones = BTreeMap::from([("c.txt".to_string(), 3), ("foo/d e.txt".to_string(), 5)]);
+
+        let content = encode_manifest(&files, &tombstones);
+        assert_eq!(content, "a.txt\nfoo/b.txt\n-\t3\tc.txt\n-\t5\tfoo/d e.txt");
+        assert_eq!(decode_manifest(&content), files);
+        assert_eq!(decode_tombstones(&content), tombstones);
+        assert!(decode_tombstones("a.txt\n-\tfoo\tb.txt\n-\t3\t").is_empty());
+        let paths: Vec<&str> = content.lines().map(get_line_path).collect();
+        assert_eq!(paths, vec!["a.txt", "foo/b.txt", "c.txt", "foo/d e.txt"]);
+
+        // the same content is the same digest, no matter the node
+        let digest = get_digest(&content);
+        assert_eq!(digest, get_digest(&encode_manifest(&files, &tombstones)));
+        assert_ne!(
+            digest,
+            get_digest(&encode_manifest(&files, &BTreeMap::new()))
+        );
+        assert_eq!(digest.len(), 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_extraneous_files() -> Result<()> {
+        let local: Vec<String> = ["a", "b", "c/d"].iter().map(|f| f.to_string()).collect();
+        let remote: Vec<String> = ["a", "c/e"].iter().map(|f| f.to_string()).collect();
+        assert_eq!(get_extraneous_files(&local, &remote), vec!["b", "c/d"]);
+        assert!(get_extraneous_files(&remote, &remote).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_to_trash() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_manifest_trash");
+        let _ = fs::remove_dir_all(&dir);
+        let base_path = dir.join("group");
+        let storage_path = dir.join("storage");
+        fs::create_dir_all(base_path.join("foo/bar"))?;
+        fs::write(base_path.join("a.txt"), b"a")?;
+        fs::write(base_path.join("foo/b.txt"), b"b")?;
+        fs::write(base_path.join("foo/bar/c.txt"), b"c")?;
+
+        let trashed = vec!["a.txt".to_string(), "foo/bar/c.txt".to_string()];
+        move_to_trash(&storage_path, "foo", &base_path, &trashed)?;
+
+        assert_eq!(list_files(&base_path)?, vec!["foo/b.txt"]);
+        assert!(!base_path.join("foo/bar").exists());
+        assert_eq!(list_files(&storage_path)?.len(), 2);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{self, FileEntry};
+
+// nodes with it send the tree of a group before its manifest, pullers that
+// have the previous one only ask for the branches that changed (see
+// `RequestTree`)
+pub const TREE_FEATURE: &str = "tree";
+
+// biggest list of folders asked for at once, past it the whole manifest is
+// asked for, most of the group changed anyway
+pub const MAX_BRANCHES_BYTES: usize = 64 * 1024;
+
+// Branch: the hashes of a folder of the group, of all that is under it and
+// of the files right in it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Branch {
+    pub hash: String,
+    pub files_hash: String,
+}
+
+// Tree: the branches of a group by folder (`""` being the group itself), a
+// change of a file changes the hash of every folder up to the group, the
+// rest stays the same
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tree {
+    pub branches: BTreeMap<String, Branch>,
+}
+
+impl Tree {
+    // build retrieves the tree of the files of a group, as they are on its
+    // manifest, along with the files removed
+    pub fn build(
+        entries: &BTreeMap<String, FileEntry>,
+        tombstones: &BTreeMap<String, u64>,
+    ) -> Self {
+        let mut lines: BTreeMap<String, Vec<String>> = BTreeMap::from([(String::new(), vec![])]);
+        for (relative_path, entry) in entries {
+            let line = format!("f\t{}\t{}\t{relative_path}", entry.size, entry.modified);
+            lines
+                .entry(get_dir(relative_path).to_owned())
+                .or_default()
+                .push(line);
+        }
+        for (relative_path, generation) in tombstones {
+            let line = format!("t\t{generation}\t{relative_path}");
+            lines
+                .entry(get_dir(relative_path).to_owned())
+                .or_default()
+                .push(line);
+        }
+
+        // folders with just folders in them are branches too
+        let dirs: Vec<String> = lines.keys().cloned().collect();
+        for dir in dirs {
+            let mut dir = dir.as_str();
+            while !dir.is_empty() {
+                dir = get_dir(dir);
+                lines.entry(dir.to_owned()).or_default();
+            }
+        }
+
+        // the deepest go first, each folder hashes the ones right under it
+        let mut dirs: Vec<&String> = lines.keys().collect();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(get_depth(dir)));
+        let mut branches: BTreeMap<String, Branch> = BTreeMap::new();
+        let mut children: BTreeMap<&str, BTreeMap<&str, String>> = BTreeMap::new();
+        for dir in dirs {
+            let files_hash = blake3::hash(lines[dir].join("\n").as_bytes());
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(files_hash.as_bytes());
+            for (child, hash) in children.remove(dir.as_str()).unwrap_or_default() {
+                hasher.update(format!("\n{child}\t{hash}").as_bytes());
+            }
+
+            let branch = Branch {
+                hash: hasher.finalize().to_hex().to_string(),
+                files_hash: files_hash.to_hex().to_string(),
+            };
+            if !dir.is_empty() {
+                let siblings = children.entry(get_dir(dir)).or_default();
+                siblings.insert(dir.as_str(), branch.hash.clone());
+            }
+            branches.insert(dir.clone(), branch);
+        }
+
+        Self { branches }
+    }
+
+    pub fn encode(&self) -> String {
+        let lines: Vec<String> = self
+            .branches
+            .iter()
+            .map(|(dir, branch)| format!("{}\t{}\t{dir}", branch.hash, branch.files_hash))
+            .collect();
+        lines.join("\n")
+    }
+
+    pub fn decode(content: &str) -> Self {
+        let branches = content
+            .lines()
+            .filter_map(|line| {
+                let mut spl = line.splitn(3, '\t');
+                let hash = spl.next().filter(|h| !h.is_empty())?;
+                let files_hash = spl.next().filter(|h| !h.is_empty())?;
+                let dir = spl.next()?;
+                let branch = Branch {
+                    hash: hash.to_owned(),
+                    files_hash: files_hash.to_owned(),
+                };
+                Some((dir.to_owned(), branch))
+            })
+            .collect();
+
+        Self { branches }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(Self::decode(&content))
+    }
+
+    // get_diverged retrieves the folders whose files aren't the same as on
+    // the previous tree, going only through the branches that changed
+    pub fn get_diverged(&self, previous: &Tree) -> Vec<String> {
+        let mut children: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for dir in self.branches.keys().chain(previous.branches.keys()) {
+            if !dir.is_empty() {
+                children.entry(get_dir(dir)).or_default().insert(dir);
+            }
+        }
+
+        let mut diverged = vec![];
+        let mut pending = vec![""];
+        while let Some(dir) = pending.pop() {
+            let (branch, previous_branch) = (self.branches.get(dir), previous.branches.get(dir));
+            if branch.map(|b| &b.hash) == previous_branch.map(|b| &b.hash) {
+                continue;
+            }
+
+            if branch.map(|b| &b.files_hash) != previous_branch.map(|b| &b.files_hash) {
+                diverged.push(dir.to_owned());
+            }
+            pending.extend(children.get(dir).into_iter().flatten());
+        }
+
+        diverged.sort();
+        diverged
+    }
+}
+
+// get_dir retrieves the folder of the path on the group, `""` for the ones
+// right in it
+pub fn get_dir(relative_path: &str) -> &str {
+    relative_path
+        .rsplit_once('/')
+        .map(|(dir, _)| dir)
+        .unwrap_or_default()
+}
+
+fn get_depth(dir: &str) -> usize {
+    match dir.is_empty() {
+        true => 0,
+        false => dir.matches('/').count() + 1,
+    }
+}
+
+// get_tree_path is where the last tree a node sent of a group is kept, the
+// one coming is kept aside until the branches that changed are pulled
+pub fn get_tree_path(storage_path: &Path, group_name: &str, node_id: &str) -> PathBuf {
+    storage_path
+        .join(manifest::MANIFESTS_DIR_NAME)
+        .join(format!("{group_name}.{node_id}.remote.tree"))
+}
+
+pub fn get_pending_tree_path(storage_path: &Path, group_name: &str, node_id: &str) -> PathBuf {
+    get_tree_path(storage_path, group_name, node_id).with_extension("tree.pending")
+}
+
+// keep_pending_tree puts the tree that came in place of the last one, once
+// what changed on it is pulled
+pub fn keep_pending_tree(storage_path: &Path, group_name: &str, node_id: &str) -> Result<()> {
+    let pending_path = get_pending_tree_path(storage_path, group_name, node_id);
+    if pending_path.exists() {
+        fs::rename(
+            pending_path,
+            get_tree_path(storage_path, group_name, node_id),
+        )?;
+    }
+
+    Ok(())
+}
+
+// encode_dirs puts together the folders asked for on a message, one per
+// line as a folder can have anything but a line break
+pub fn encode_dirs(dirs: &[String]) -> String {
+    dirs.join("\n")
+}
+
+pub fn decode_dirs(content: &str) -> Vec<String> {
+    content.split('\n').map(|dir| dir.to_owned()).collect()
+}
+
+// get_branches retrieves the lines of the manifest of the files right in
+// the folders
+pub fn get_branches(content: &str, dirs: &[String]) -> String {
+    let dirs: BTreeSet<&str> = dirs.iter().map(|dir| dir.as_str()).collect();
+    let lines: Vec<&str> = content
+        .lines()
+        .filter(|line| dirs.contains(get_dir(manifest::get_line_path(line))))
+        .collect();
+    lines.join("\n")
+}
+
+// merge_branches retrieves the manifest with the lines of the folders taken
+// from the branches that came, the rest stays as it was
+pub fn merge_branches(content: &str, branches: &str, dirs: &[String]) -> String {
+    let dirs: BTreeSet<&str> = dirs.iter().map(|dir| dir.as_str()).collect();
+    let mut files = vec![];
+    let mut tombstones = BTreeMap::new();
+    let kept = content
+        .lines()
+        .filter(|line| !dirs.contains(get_dir(manifest::get_line_path(line))));
+    for line in kept.chain(branches.lines()) {
+        let merged = manifest::decode_manifest(line);
+        files.extend(merged);
+        tombstones.extend(manifest::decode_tombstones(line));
+    }
+
+    files.sort();
+    manifest::encode_manifest(&files, &tombstones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_entries(files: &[(&str, u64)]) -> BTreeMap<String, FileEntry> {
+        files
+            .iter()
+            .map(|(f, size)| {
+                let entry = FileEntry {
+                    size: *size,
+                    modified: 1700,
+                };
+                (f.to_string(), entry)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_get_dir() -> Result<()> {
+        let test_values = [
+            // (path, dir)
+            ("a.txt", ""),
+            ("a/b.txt", "a"),
+            ("a/b/c.txt", "a/b"),
+            ("a/b", "a"),
+            ("", ""),
+        ];
+        for spec in test_values {
+            assert_eq!(get_dir(spec.0), spec.1, "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree() -> Result<()> {
+        let files = [
+            ("a.txt", 1),
+            ("docs/b.txt", 2),
+            ("docs/x/y/c.txt", 3),
+            ("e/f.txt", 4),
+        ];
+        let tree = Tree::build(&get_entries(&files), &BTreeMap::new());
+        let dirs: Vec<&str> = tree.branches.keys().map(|d| d.as_str()).collect();
+        assert_eq!(dirs, vec!["", "docs", "docs/x", "docs/x/y", "e"]);
+        assert_eq!(Tree::decode(&tree.encode()), tree);
+        assert!(tree.get_diverged(&tree).is_empty());
+
+        let test_values = [
+            // (changed file, size, none when removed, diverged)
+            ("docs/x/y/c.txt", Some(9), vec!["docs/x/y"]),
+            ("a.txt", Some(5), vec![""]),
+            ("e/f.txt", None, vec!["e"]),
+            ("g/h.txt", Some(1), vec!["g"]),
+        ];
+        for spec in test_values {
+            let mut entries = get_entries(&files);
+            let mut tombstones = BTreeMap::new();
+            match spec.1 {
+                Some(size) => {
+                    let entry = FileEntry {
+                        size,
+                        modified: 1700,
+                    };
+                    entries.insert(spec.0.to_string(), entry);
+                }
+                None => {
+                    entries.remove(spec.0);
+                    tombstones.insert(spec.0.to_string(), 7);
+                }
+            }
+            let changed = Tree::build(&entries, &tombstones);
+            assert_eq!(changed.get_diverged(&tree), spec.2, "{spec:?}");
+        }
+
+        // a folder gone along with its tombstones diverged too
+        let changed = Tree::build(&get_entries(&files[..3]), &BTreeMap::new());
+        assert_eq!(changed.get_diverged(&tree), vec!["e"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_branches() -> Result<()> {
+        let tombstones = BTreeMap::from([("docs/old.txt".to_string(), 3)]);
+        let files: Vec<String> = ["a.txt", "docs/b.txt", "docs/x/c.txt", "e/f.txt"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        let content = manifest::encode_manifest(&files, &tombstones);
+
+        let dirs = vec!["docs".to_string(), "g".to_string()];
+        assert_eq!(decode_dirs(&encode_dirs(&dirs)), dirs);
+        assert_eq!(
+            get_branches(&content, &dirs),
+            "docs/b.txt\n-\t3\tdocs/old.txt"
+        );
+
+        // what the folders had is replaced by what came, the rest is kept
+        let branches = "docs/d.txt\ng/h.txt\n-\t5\tdocs/b.txt";
+        let merged = merge_branches(&content, branches, &dirs);
+        assert_eq!(
+            manifest::decode_manifest(&merged),
+            vec!["a.txt", "docs/d.txt", "docs/x/c.txt", "e/f.txt", "g/h.txt"]
+        );
+        assert_eq!(
+            manifest::decode_tombstones(&merged),
+            BTreeMap::from([("docs/b.txt".to_string(), 5)])
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::action::CommAction;
+
+pub const OUTBOX_DIR_NAME: &str = "outbox";
+
+// notifications kept per node, the oldest are dropped first. a node away
+// that long catches up with the manifests on its hello anyway
+pub const MAX_OUTBOX_MESSAGES: usize = 10_000;
+
+// the outbox of a node is only appended to, once this big it is rewritten
+// with just what is kept
+const MAX_OUTBOX_BYTES: u64 = 8 * 1024 * 1024;
+
+// Outbox: the notifications to a node that couldn't be sent, kept on the
+// storage until it is reachable so a restart doesn't lose them. a newer
+// one of the same path (or generation of a group) replaces the previous
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outbox {
+    messages: BTreeMap<u64, (String, String)>, // (key, message) by when added
+    keys: HashMap<String, u64>,
+    next: u64,
+}
+
+impl Outbox {
+    // load retrieves the outbox of the node, a message per line as json so
+    // line breaks in it don't matter
+    pub fn load(path: &Path, node_id: &str) -> Result<Self> {
+        let mut outbox = Self::default();
+        if !path.exists() {
+            return Ok(outbox);
+        }
+
+        for line in fs::read_to_string(path)?.lines() {
+            if let Ok(msg) = serde_json::from_str::<String>(line) {
+                outbox.add(node_id, &msg);
+            }
+        }
+        Ok(outbox)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut content = String::new();
+        for msg in self.get_messages() {
+            content.push_str(&serde_json::to_string(&msg)?);
+            content.push('\n');
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    // add keeps the message of the node, coalesced with the ones waiting.
+    // only notifications are kept
+    pub fn add(&mut self, node_id: &str, msg: &str) {
+        let Some(key) = get_coalesce_key(node_id, msg) else {
+            return;
+        };
+
+        if let Some(previous) = self.keys.insert(key.clone(), self.next) {
+            self.messages.remove(&previous);
+        }
+        self.messages.insert(self.next, (key, msg.to_owned()));
+        self.next += 1;
+
+        if self.messages.len() > MAX_OUTBOX_MESSAGES
+            && let Some((_, (key, _))) = self.messages.pop_first()
+        {
+            self.keys.remove(&key);
+        }
+    }
+
+    // get_messages retrieves the messages kept, the oldest first
+    pub fn get_messages(&self) -> Vec<String> {
+        self.messages.values().map(|(_, msg)| msg.clone()).collect()
+    }
+}
+
+// is_notification checks if the message lets the node know of a change, the
+// ones worth keeping across restarts. the rest (tickets, replies) are only
+// of use while the daemon runs
+pub fn is_notification(node_id: &str, msg: &str) -> bool {
+    get_coalesce_key(node_id, msg).is_some()
+}
+
+// get_coalesce_key retrieves what the notification is about, a newer one
+// about the same tells all the previous one did
+fn get_coalesce_key(node_id: &str, msg: &str) -> Option<String> {
+    match CommAction::from_namespaced_msg(node_id, msg) {
+        CommAction::TargetHasChanged(_, target_name, relative_path)
+        | CommAction::OfferTarget(_, target_name, relative_path, _, _) => {
+            Some(format!("path\t{target_name}\t{relative_path}"))
+        }
+        CommAction::TargetGeneration(_, target_name, _) => {
+            Some(format!("generation\t{target_name}"))
+        }
+        _ => None,
+    }
+}
+
+pub fn get_outbox_path(storage_path: &Path, node_id: &str) -> PathBuf {
+    storage_path
+        .join(OUTBOX_DIR_NAME)
+        .join(format!("{node_id}.jsonl"))
+}
+
+// has_messages checks if notifications wait for the node on the storage
+pub fn has_messages(storage_path: &Path, node_id: &str) -> bool {
+    get_outbox_path(storage_path, node_id).exists()
+}
+
+// add_message keeps the notification to the node on its outbox, appended
+// so it is cheap however many wait. it is coalesced as it is read
+pub fn add_message(storage_path: &Path, node_id: &str, msg: &str) -> Result<()> {
+    let path = get_outbox_path(storage_path, node_id);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(msg)?)?;
+    drop(file);
+
+    if fs::metadata(&path).is_ok_and(|m| m.len() >= MAX_OUTBOX_BYTES) {
+        Outbox::load(&path, node_id)?.save(&path)?;
+    }
+    Ok(())
+}
+
+// take_messages retrieves the notifications waiting for the node, emptying
+// its outbox
+pub fn take_messages(storage_path: &Path, node_id: &str) -> Result<Vec<String>> {
+    let path = get_outbox_path(storage_path, node_id);
+    let outbox = Outbox::load(&path, node_id)?;
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+
+    Ok(outbox.get_messages())
+}
+
+// get_node_ids retrieves the nodes with notifications waiting
+pub fn get_node_ids(storage_path: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(storage_path.join(OUTBOX_DIR_NAME)) else {
+        return vec![];
+    };
+
+    let mut node_ids: Vec<String> = entries
+        .filter_map(|entry| {
+            let file_name = entry.ok()?.file_name();
+            let node_id = file_name.to_str()?.strip_suffix(".jsonl")?;
+            Some(node_id.to_owned())
+        })
+        .collect();
+    node_ids.sort();
+    node_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_msg(action: CommAction) -> String {
+        match action.to_send_message() {
+            CommAction::SendMessage(_, msg) => msg,
+            action => panic!("not a message {action:?}"),
+        }
+    }
+
+    #[test]
+    fn test_outbox_add() -> Result<()> {
+        let changed_a = get_msg(CommAction::TargetHasChanged(
+            "1234".into(),
+            "docs".into(),
+            "a.txt".into(),
+        ));
+        let changed_b = get_msg(CommAction::TargetHasChanged(
+            "1234".into(),
+            "docs".into(),
+            "b.txt".into(),
+        ));
+        let offer_a = get_msg(CommAction::OfferTarget(
+            "1234".into(),
+            "docs".into(),
+            "a.txt".into(),
+            100,
+            "abc".into(),
+        ));
+        let generation_1 = get_msg(CommAction::TargetGeneration(
+            "1234".into(),
+            "docs".into(),
+            1,
+        ));
+        let generation_2 = get_msg(CommAction::TargetGeneration(
+            "1234".into(),
+            "docs".into(),
+            2,
+        ));
+        let manifest = get_msg(CommAction::RequestManifest("1234".into(), "docs".into()));
+
+        let test_values = [
+            // (messages added, messages kept)
+            (vec![&changed_a, &changed_b], vec![&changed_a, &changed_b]),
+            // the newest of a path is kept
+            (
+                vec![&changed_a, &changed_b, &offer_a],
+                vec![&changed_b, &offer_a],
+            ),
+            (vec![&changed_a, &changed_a], vec![&changed_a]),
+            (
+                vec![&generation_1, &changed_a, &generation_2],
+                vec![&changed_a, &generation_2],
+            ),
+        ];
+        for spec in test_values {
+            let mut outbox = Outbox::default();
+            for msg in &spec.0 {
+                outbox.add("1234", msg);
+            }
+            let kept = outbox.get_messages();
+            assert_eq!(kept.iter().collect::<Vec<_>>(), spec.1, "{spec:?}");
+        }
+
+        assert!(is_notification("1234", &changed_a));
+        assert!(!is_notification("1234", &manifest));
+        let mut outbox = Outbox::default();
+        outbox.add("1234", &manifest);
+        assert!(outbox.get_messages().is_empty());
+
+        // only the most recent are kept
+        let mut outbox = Outbox::default();
+        for i in 0..=MAX_OUTBOX_MESSAGES {
+            let msg = get_msg(CommAction::TargetHasChanged(
+                "1234".into(),
+                "docs".into(),
+                format!("{i}.txt").into(),
+            ));
+            outbox.add("1234", &msg);
+        }
+        let messages = outbox.get_messages();
+        assert_eq!(messages.len(), MAX_OUTBOX_MESSAGES);
+        assert!(messages[0].ends_with(";1.txt"), "{}", messages[0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_outbox_storage() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_outbox");
+        let _ = fs::remove_dir_all(&dir);
+        let msg = get_msg(CommAction::TargetHasChanged(
+            "1234".into(),
+            "docs".into(),
+            "a.txt".into(),
+        ));
+
+        assert!(!has_messages(&dir, "1234"));
+        add_message(&dir, "1234", &msg)?;
+        add_message(&dir, "1234", &msg)?;
+        assert!(has_messages(&dir, "1234"));
+        let path = get_outbox_path(&dir, "1234");
+        assert_eq!(
+            Outbox::load(&path, "1234")?.get_messages(),
+            vec![msg.clone()]
+        );
+        assert_eq!(get_node_ids(&dir), vec!["1234"]);
+
+        assert_eq!(take_messages(&dir, "1234")?, vec![msg]);
+        assert!(!has_messages(&dir, "1234"));
+        assert!(take_messages(&dir, "1234")?.is_empty());
+        assert!(get_node_ids(&dir).is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
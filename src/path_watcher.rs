@@ -1,125 +1,762 @@
 use anyhow::Result;
+use chrono::Utc;
 
-use notify::RecommendedWatcher;
-use notify_debouncer_mini::{DebounceEventResult, DebouncedEventKind, Debouncer, new_debouncer};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, PollWatcher, RecommendedWatcher, Watcher};
 
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::{
-    fs,
-    sync::mpsc::{self, Receiver},
+    fmt, fs,
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
 };
+use tokio::time::Instant;
+
+use crate::logs::{log_error, log_warning};
+use crate::{reserved, resources};
+
+// how often the watcher is checked to still see changes, a canary written
+// on each watched folder should be seen before the next check
+pub const HEALTH_CHECK_SECS: u64 = 60;
+
+// file written on the fsy folder of the watched folders, see `touch_canaries`
+const CANARY_FILE_NAME: &str = "watcher.canary";
+
+// the notify watcher along with where its changes and errors come through
+type FileWatcher<W = RecommendedWatcher> = (W, Receiver<(PathBuf, ChangeKind)>, Receiver<String>);
+
+// ChangeKind: what happened to a changed target, all of its changes within
+// the debounce merged into one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Remove,
+}
+
+impl ChangeKind {
+    // from_event retrieves the kind of change of each path of the event,
+    // reads and other accesses aren't changes
+    fn from_event(event: &Event) -> Vec<(PathBuf, Self)> {
+        let kind = match event.kind {
+            EventKind::Create(_) => Self::Create,
+            EventKind::Remove(_) => Self::Remove,
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Self::Remove,
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => Self::Create,
+            // NOTE: the paths go from and to
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                return event
+                    .paths
+                    .iter()
+                    .zip([Self::Remove, Self::Create])
+                    .map(|(path, kind)| (path.clone(), kind))
+                    .collect();
+            }
+            EventKind::Modify(_) | EventKind::Any => Self::Modify,
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => Self::Modify,
+            EventKind::Access(_) | EventKind::Other => return vec![],
+        };
+
+        event.paths.iter().map(|path| (path.clone(), kind)).collect()
+    }
+
+    // merge retrieves the kind of a change followed by another one
+    fn merge(self, next: Self) -> Self {
+        match (self, next) {
+            (Self::Create, Self::Modify) => Self::Create,
+            (Self::Remove, Self::Create) => Self::Modify,
+            (_, next) => next,
+        }
+    }
+
+    // settle checks the kind against what is on the disk, some platforms
+    // don't tell which side of a rename a path was
+    fn settle(self, exists: bool) -> Self {
+        match (self, exists) {
+            (Self::Remove, true) => Self::Modify,
+            (Self::Create | Self::Modify, false) => Self::Remove,
+            (kind, _) => kind,
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Create => write!(f, "create"),
+            Self::Modify => write!(f, "modify"),
+            Self::Remove => write!(f, "remove"),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct ChangedTarget {
     pub base_path: String,
     pub relative_path: String,
+    pub kind: ChangeKind,
 }
 
 pub struct PathWatcher {
-    file_watcher: Debouncer<RecommendedWatcher>,
-    file_watcher_rx: Receiver<Option<PathBuf>>,
+    file_watcher: RecommendedWatcher,
+    file_watcher_rx: Receiver<(PathBuf, ChangeKind)>,
+    file_watcher_errors_rx: Receiver<String>,
+    poll_watcher: Option<FileWatcher<PollWatcher>>, // paths over the limits of the watcher
+    is_failing: bool, // the watcher errored or stopped since the last check
+    canaries: HashMap<PathBuf, String>, // canaries not seen yet, with their watch path
     watch_paths: Vec<String>,
+    debounce: Duration,
+    path_debounces: HashMap<String, Duration>, // watch paths with their own debounce
+    delete_graces: HashMap<String, Duration>,  // watch paths whose removals wait longer
+    pending: HashMap<PathBuf, (ChangeKind, Instant)>, // changes within the debounce
+    max_pending: usize,
+    is_overflowed: bool, // changes were dropped for being too many since the last check
 }
 
 impl PathWatcher {
-    pub fn new(push_paths: Vec<String>, push_debounce_millisecs: u64) -> Result<Self> {
-        let (watcher_tx, watcher_rx) = mpsc::channel();
-
-        // initialize the watcher
-        let watcher = new_debouncer(
-            Duration::from_millis(push_debounce_millisecs),
-            move |res: DebounceEventResult| match res {
-                Ok(events) => events.iter().for_each(|e| {
-                    if e.kind != DebouncedEventKind::Any {
-                        return;
-                    }
-
-                    watcher_tx.send(Some(e.path.clone())).unwrap();
-                }),
-                Err(e) => println!("-> watcher error {e}"),
-            },
-        )?;
+    pub fn new(
+        push_paths: Vec<String>,
+        push_debounce_millisecs: u64,
+        path_debounces: HashMap<String, u64>,
+        delete_graces: HashMap<String, u64>,
+        max_pending_changes: usize,
+    ) -> Result<Self> {
+        let (watcher, watcher_rx, watcher_errors_rx) = new_file_watcher()?;
 
         // construct the final struct
         let s = Self {
             watch_paths: push_paths,
             file_watcher: watcher,
             file_watcher_rx: watcher_rx,
+            file_watcher_errors_rx: watcher_errors_rx,
+            poll_watcher: None,
+            is_failing: false,
+            canaries: HashMap::new(),
+            debounce: Duration::from_millis(push_debounce_millisecs),
+            path_debounces: path_debounces
+                .into_iter()
+                .map(|(path, millis)| (path, Duration::from_millis(millis)))
+                .collect(),
+            delete_graces: delete_graces
+                .into_iter()
+                .map(|(path, millis)| (path, Duration::from_millis(millis)))
+                .collect(),
+            pending: HashMap::new(),
+            max_pending: max_pending_changes,
+            is_overflowed: false,
         };
 
         Ok(s)
     }
 
-    pub fn start(&mut self) -> Result<()> {
-        // listen to file changes
+    // start listens to file changes on all paths. paths that fail to be
+    // watched don't stop the others, they are returned with the error instead
+    pub fn start(&mut self) -> Vec<(String, anyhow::Error)> {
         self.set_watcher_files()
     }
 
-    pub fn get_changed_targets(&self) -> Option<Vec<ChangedTarget>> {
-        let changed_path = self.file_watcher_rx.try_recv();
-        if let Ok(Some(changed_path)) = changed_path {
-            let targets = get_push_targets_with_file(&self.watch_paths, changed_path.to_str()?);
-            if targets.is_empty() {
-                return None;
+    // add_change takes a change the watcher didn't tell about (found by the
+    // manifest verification) as if it did
+    pub fn add_change(&mut self, changed_path: PathBuf, kind: ChangeKind) {
+        self.add_pending(changed_path, kind, Instant::now());
+    }
+
+    // add_pending keeps the change until it settles. past the changes kept
+    // in memory the new paths are dropped, the groups are gone through
+    // instead (see `take_overflowed`)
+    fn add_pending(&mut self, changed_path: PathBuf, kind: ChangeKind, now: Instant) {
+        let kind = match self.pending.get(&changed_path) {
+            Some((pending_kind, _)) => pending_kind.merge(kind),
+            None if self.pending.len() >= self.max_pending => {
+                if !self.is_overflowed {
+                    log_warning!(
+                        "-> over {} changes waiting, dropping the new ones",
+                        self.max_pending
+                    );
+                }
+                self.is_overflowed = true;
+                return;
             }
+            None => kind,
+        };
+        self.pending.insert(changed_path, (kind, now));
+    }
+
+    // take_overflowed checks if changes were dropped since the last check
+    pub fn take_overflowed(&mut self) -> bool {
+        std::mem::take(&mut self.is_overflowed)
+    }
+
+    // get_changed_targets drains the changes that settled for the debounce,
+    // making them a batch of changed targets.
+    // NOTE: the debounce goes by the clock of tokio, tests pause and advance it
+    pub fn get_changed_targets(&mut self) -> Option<Vec<ChangedTarget>> {
+        let now = Instant::now();
+        let mut changes = vec![];
+        loop {
+            match self.file_watcher_rx.try_recv() {
+                Ok(change) => changes.push(change),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.is_failing = true;
+                    break;
+                }
+            };
+        }
+        if let Some((_, poll_rx, _)) = &self.poll_watcher {
+            changes.extend(poll_rx.try_iter());
+        }
 
-            return Some(targets);
+        for (changed_path, kind) in changes {
+            if self.take_canary(&changed_path) {
+                continue;
+            }
+            self.add_pending(changed_path, kind, now);
         }
 
-        None
+        let mut settled: Vec<(PathBuf, ChangeKind, Instant)> = vec![];
+        self.pending.retain(|path, (kind, last)| {
+            let mut debounce = get_path_debounce(&self.path_debounces, self.debounce, path);
+            // a removed file coming back within the grace is a change of it
+            // instead (editors saving, a restore right after a rm)
+            if *kind == ChangeKind::Remove {
+                let grace = get_path_debounce(&self.delete_graces, Duration::ZERO, path);
+                debounce = debounce.max(grace);
+            }
+            if now.duration_since(*last) < debounce {
+                return true;
+            }
+
+            settled.push((path.clone(), *kind, *last));
+            false
+        });
+        settled.sort_by_key(|(_, _, last)| *last);
+
+        let mut targets: Vec<ChangedTarget> = vec![];
+        for (changed_path, kind, _) in settled {
+            let kind = kind.settle(changed_path.exists());
+            let Some(changed_path) = changed_path.to_str() else {
+                continue;
+            };
+
+            targets.extend(get_push_targets_with_file(&self.watch_paths, changed_path, kind));
+        }
+
+        if targets.is_empty() {
+            return None;
+        }
+
+        Some(targets)
     }
 
     // close handles the unsetup of the whole watcher
     pub fn close(&mut self) -> Result<()> {
         for sync_path in self.watch_paths.iter() {
             let p = std::path::Path::new(&sync_path);
-            // TODO: we just want to ignore error and unwatch all
-            self.file_watcher.watcher().unwatch(p)?;
+            // NOTE: we just want to ignore error and unwatch all, some
+            //       paths might have never been watched
+            let _ = self.file_watcher.unwatch(p);
         }
+        self.poll_watcher = None;
 
         Ok(())
     }
 
-    fn set_watcher_files(&mut self) -> Result<()> {
-        for sync_path in self.watch_paths.iter() {
-            // set the watch on path
-            let meta = fs::metadata(sync_path)?;
-            let recurse = if meta.is_dir() {
-                notify::RecursiveMode::Recursive
-            } else {
-                notify::RecursiveMode::NonRecursive
-            };
+    // touch_canaries writes a canary on the fsy folder of each watched folder,
+    // a watcher that works sees it. single files have nowhere to put one
+    pub fn touch_canaries(&mut self) {
+        for watch_path in &self.watch_paths {
+            if !Path::new(watch_path).is_dir() {
+                continue;
+            }
 
-            let p = std::path::Path::new(&sync_path);
-            self.file_watcher.watcher().watch(p, recurse)?;
+            // NOTE: a folder just created might not be watched yet, its
+            //       canary goes on the next check
+            let fsy_dir = Path::new(watch_path).join(reserved::FSY_DIR_NAME);
+            if !fsy_dir.exists() {
+                if let Err(e) = fs::create_dir_all(&fsy_dir) {
+                    log_error!("-> unable to write the canary of {watch_path}: {e}");
+                }
+                continue;
+            }
+
+            let canary_path = fsy_dir.join(CANARY_FILE_NAME);
+            if let Err(e) = fs::write(&canary_path, Utc::now().to_rfc3339()) {
+                log_error!("-> unable to write the canary of {watch_path}: {e}");
+                continue;
+            }
+
+            // kept resolved, as some platforms tell the changes (`/private/var`
+            // on macos)
+            let canary_path = fs::canonicalize(&canary_path).unwrap_or(canary_path);
+            self.canaries.insert(canary_path, watch_path.clone());
+        }
+    }
+
+    // take_canary checks if the change is of a canary, which is then seen
+    fn take_canary(&mut self, changed_path: &Path) -> bool {
+        if changed_path.file_name() != Some(CANARY_FILE_NAME.as_ref()) {
+            return false;
+        }
+
+        let changed_path = fs::canonicalize(changed_path).unwrap_or(changed_path.to_path_buf());
+        self.canaries.remove(&changed_path);
+        true
+    }
+
+    // take_failing_paths retrieves the watch paths the watcher isn't seeing
+    // the changes of: all of them once it errored or stopped, the ones with
+    // a canary not seen otherwise
+    pub fn take_failing_paths(&mut self) -> Vec<String> {
+        if self.file_watcher_errors_rx.try_iter().count() > 0 {
+            self.is_failing = true;
+        }
+        if let Some((_, _, poll_errors_rx)) = &self.poll_watcher
+            && poll_errors_rx.try_iter().count() > 0
+        {
+            self.is_failing = true;
+        }
+
+        let canaries = std::mem::take(&mut self.canaries);
+        if std::mem::take(&mut self.is_failing) {
+            return self.watch_paths.clone();
+        }
+
+        let mut failing: Vec<String> = canaries.into_values().collect();
+        failing.sort();
+        failing.dedup();
+        failing
+    }
+
+    // restart sets up the watcher again from scratch, the changes already
+    // seen are kept. paths that fail to be watched are returned as on start
+    pub fn restart(&mut self) -> Result<Vec<(String, anyhow::Error)>> {
+        let _ = self.close();
+        let (watcher, watcher_rx, watcher_errors_rx) = new_file_watcher()?;
+        self.file_watcher = watcher;
+        self.file_watcher_rx = watcher_rx;
+        self.file_watcher_errors_rx = watcher_errors_rx;
+        self.is_failing = false;
+        self.canaries.clear();
+
+        Ok(self.set_watcher_files())
+    }
+
+    fn set_watcher_files(&mut self) -> Vec<(String, anyhow::Error)> {
+        let mut failed = vec![];
+        for sync_path in self.watch_paths.clone() {
+            if let Err(e) = self.set_watcher_file(&sync_path) {
+                failed.push((sync_path, e));
+            }
+        }
+
+        failed
+    }
+
+    fn set_watcher_file(&mut self, sync_path: &str) -> Result<()> {
+        // set the watch on path
+        let meta = fs::metadata(sync_path)?;
+        let recurse = if meta.is_dir() {
+            notify::RecursiveMode::Recursive
+        } else {
+            notify::RecursiveMode::NonRecursive
+        };
+
+        let p = std::path::Path::new(sync_path);
+        let Err(e) = self.file_watcher.watch(p, recurse) else {
+            return Ok(());
+        };
+        if !resources::is_watch_limit(&e) {
+            return Err(e.into());
+        }
+
+        // over the limits of the system, the path is gone through every
+        // while instead.
+        // NOTE: the part watched already before failing is unwatched, the
+        //       poll watcher sees all of it
+        let _ = self.file_watcher.unwatch(p);
+        log_warning!(
+            "-> {sync_path} is over the limits of the watcher ({e}), polling it every {}s",
+            resources::POLL_WATCH_SECS
+        );
+        if self.poll_watcher.is_none() {
+            self.poll_watcher = Some(new_poll_watcher()?);
+        }
+        if let Some((poll_watcher, _, _)) = &mut self.poll_watcher {
+            poll_watcher.watch(p, recurse)?;
         }
 
         Ok(())
     }
 }
 
-fn get_push_targets_with_file(push_paths: &[String], file_path: &str) -> Vec<ChangedTarget> {
-    push_paths.iter().filter_map(|base_path| {
-        if !file_path.contains(base_path) {
-            return None;
+// new_file_watcher sets up the notify watcher, the changes are debounced as
+// they are taken and the errors are checked on the health checks
+fn new_file_watcher() -> Result<FileWatcher> {
+    let (watcher_tx, watcher_rx) = mpsc::channel();
+    let (errors_tx, errors_rx) = mpsc::channel();
+    let watcher = notify::recommended_watcher(get_event_handler(watcher_tx, errors_tx))?;
+
+    Ok((watcher, watcher_rx, errors_rx))
+}
+
+// new_poll_watcher sets up a watcher going through the paths every while,
+// for the ones the notify watcher can't take
+fn new_poll_watcher() -> Result<FileWatcher<PollWatcher>> {
+    let (watcher_tx, watcher_rx) = mpsc::channel();
+    let (errors_tx, errors_rx) = mpsc::channel();
+    let config = notify::Config::default()
+        .with_poll_interval(Duration::from_secs(resources::POLL_WATCH_SECS));
+    let watcher = PollWatcher::new(get_event_handler(watcher_tx, errors_tx), config)?;
+
+    Ok((watcher, watcher_rx, errors_rx))
+}
+
+fn get_event_handler(
+    watcher_tx: Sender<(PathBuf, ChangeKind)>,
+    errors_tx: Sender<String>,
+) -> impl Fn(notify::Result<Event>) {
+    move |res: notify::Result<Event>| match res {
+        Ok(event) => ChangeKind::from_event(&event).into_iter().for_each(|change| {
+            let _ = watcher_tx.send(change);
+        }),
+        Err(e) => {
+            log_error!("-> watcher error {e}");
+            let _ = errors_tx.send(e.to_string());
         }
+    }
+}
+
+// get_path_debounce retrieves how long the changes of the path wait to
+// settle, the longest of the watch paths it is in so they all get it at once
+fn get_path_debounce(
+    path_debounces: &HashMap<String, Duration>,
+    default: Duration,
+    changed_path: &Path,
+) -> Duration {
+    path_debounces
+        .iter()
+        .filter(|(base_path, _)| changed_path.starts_with(base_path))
+        .map(|(_, debounce)| *debounce)
+        .max()
+        .unwrap_or(default)
+}
+
+fn get_push_targets_with_file(
+    push_paths: &[String],
+    file_path: &str,
+    kind: ChangeKind,
+) -> Vec<ChangedTarget> {
+    push_paths.iter().filter_map(|base_path| {
+        // NOTE: the file needs to be the base path or inside of it, being the
+        //       same, the relative path is empty
+        let relative_path = Path::new(file_path).strip_prefix(base_path).ok()?;
 
-        // this means the file is the same
-        // TODO: need to actually test this
-        if base_path == file_path {
-            return Some(ChangedTarget{
-                base_path: base_path.to_owned(),
-                relative_path: "".to_owned(),
-            });
+        // what fsy writes on the target isn't a change
+        if reserved::is_reserved_path(relative_path) {
+            return None;
         }
 
-        // being a directory, we know we have a relative path
-        let relative_path = file_path.replace(base_path, "");
+        // being a directory, we know we have a relative path. it is kept
+        // relative so joining it to the base path stays inside of it
         Some(ChangedTarget{
             base_path: base_path.to_owned(),
-            relative_path,
+            relative_path: relative_path.to_string_lossy().to_string(),
+            kind,
         })
     })
     .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_push_targets_with_file() -> Result<()> {
+        let push_paths = vec!["/foo/bar".to_string(), "/foo/bar.txt".to_string()];
+        let test_values = [
+            // (file_path, [(base_path, relative_path)])
+            ("/foo/bar", vec![("/foo/bar", "")]),
+            ("/foo/bar.txt", vec![("/foo/bar.txt", "")]),
+            ("/foo/bar/zed.txt", vec![("/foo/bar", "zed.txt")]),
+            ("/foo/bar/zed/zinga.txt", vec![("/foo/bar", "zed/zinga.txt")]),
+            ("/foo/barzed.txt", vec![]),
+            ("/foo/bar/.fsy/locks/zed.txt.lock", vec![]),
+            ("/zed/foo/bar", vec![]),
+        ];
+
+        for spec in test_values {
+            let res: Vec<(String, String)> = get_push_targets_with_file(&push_paths, spec.0, ChangeKind::Modify)
+                .into_iter()
+                .map(|t| (t.base_path, t.relative_path))
+                .collect();
+            let expected: Vec<(String, String)> = spec
+                .1
+                .iter()
+                .map(|(b, r)| (b.to_string(), r.to_string()))
+                .collect();
+            assert_eq!(res, expected, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_failing_paths() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_watcher_canary");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let watch_path = dir.to_str().unwrap().to_string();
+
+        let mut watcher = PathWatcher::new(
+            vec![watch_path.clone()],
+            0,
+            HashMap::new(),
+            HashMap::new(),
+            10,
+        )?;
+        assert!(watcher.start().is_empty());
+
+        // the fsy folder is made first, with no canary yet
+        watcher.touch_canaries();
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(watcher.get_changed_targets().is_none());
+        assert!(watcher.take_failing_paths().is_empty());
+
+        // the canary isn't seen until the changes are taken
+        watcher.touch_canaries();
+        assert_eq!(watcher.take_failing_paths(), vec![watch_path.clone()]);
+
+        // seen, and not taken as a change
+        watcher.touch_canaries();
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(watcher.get_changed_targets().is_none());
+        assert!(watcher.take_failing_paths().is_empty());
+
+        // a restarted watcher sees them again
+        assert!(watcher.restart()?.is_empty());
+        watcher.touch_canaries();
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(watcher.get_changed_targets().is_none());
+        assert!(watcher.take_failing_paths().is_empty());
+
+        watcher.close()?;
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_get_changed_targets() -> Result<()> {
+        let path_debounces = HashMap::from([("/code/docs".to_string(), 2000)]);
+        let mut watcher = PathWatcher::new(
+            vec!["/code".to_string()],
+            500,
+            path_debounces,
+            HashMap::new(),
+            10,
+        )?;
+
+        let test_values = [
+            // (changed path, advance millisecs, settled paths)
+            (Some("/code/a.txt"), 0, vec![]),
+            (Some("/code/docs/b.txt"), 400, vec![]),
+            // a new change of the path waits the whole debounce again
+            (Some("/code/a.txt"), 100, vec![]),
+            (None, 400, vec!["a.txt"]),
+            (None, 1099, vec![]),
+            (None, 1, vec!["docs/b.txt"]),
+        ];
+
+        for spec in test_values {
+            if let Some(changed_path) = spec.0 {
+                watcher.add_change(changed_path.into(), ChangeKind::Remove);
+            }
+            tokio::time::advance(Duration::from_millis(spec.1)).await;
+            let settled: Vec<String> = watcher
+                .get_changed_targets()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|t| t.relative_path)
+                .collect();
+            assert_eq!(settled, spec.2, "{:?}", spec);
+        }
+
+        watcher.close()?;
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delete_grace() -> Result<()> {
+        let delete_graces = HashMap::from([("/code/docs".to_string(), 5000)]);
+        let mut watcher = PathWatcher::new(
+            vec!["/code".to_string()],
+            500,
+            HashMap::new(),
+            delete_graces,
+            10,
+        )?;
+
+        let test_values = [
+            // (changed path, kind, advance millisecs, settled paths)
+            (Some("/code/a.txt"), ChangeKind::Remove, 500, vec!["a.txt"]),
+            (Some("/code/docs/b.txt"), ChangeKind::Remove, 500, vec![]),
+            (None, ChangeKind::Remove, 4499, vec![]),
+            (None, ChangeKind::Remove, 1, vec!["docs/b.txt"]),
+            // coming back within the grace, it is a change of the file
+            (Some("/code/docs/c.txt"), ChangeKind::Remove, 1000, vec![]),
+            (
+                Some("/code/docs/c.txt"),
+                ChangeKind::Create,
+                500,
+                vec!["docs/c.txt"],
+            ),
+        ];
+
+        for spec in test_values {
+            if let Some(changed_path) = spec.0 {
+                watcher.add_change(changed_path.into(), spec.1);
+            }
+            tokio::time::advance(Duration::from_millis(spec.2)).await;
+            let settled: Vec<String> = watcher
+                .get_changed_targets()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|t| t.relative_path)
+                .collect();
+            assert_eq!(settled, spec.3, "{:?}", spec);
+        }
+
+        watcher.close()?;
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_take_overflowed() -> Result<()> {
+        let mut watcher = PathWatcher::new(
+            vec!["/code".to_string()],
+            500,
+            HashMap::new(),
+            HashMap::new(),
+            2,
+        )?;
+        watcher.add_change("/code/a.txt".into(), ChangeKind::Create);
+        watcher.add_change("/code/b.txt".into(), ChangeKind::Create);
+        assert!(!watcher.take_overflowed());
+
+        // the paths waiting already still merge, the new ones are dropped
+        watcher.add_change("/code/a.txt".into(), ChangeKind::Modify);
+        watcher.add_change("/code/c.txt".into(), ChangeKind::Create);
+        assert!(watcher.take_overflowed());
+        assert!(!watcher.take_overflowed());
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        let mut settled: Vec<String> = watcher
+            .get_changed_targets()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|t| t.relative_path)
+            .collect();
+        settled.sort();
+        assert_eq!(settled, vec!["a.txt", "b.txt"]);
+
+        watcher.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_path_debounce() -> Result<()> {
+        let path_debounces: HashMap<String, Duration> = [
+            ("/code".to_string(), Duration::from_millis(5000)),
+            ("/code/docs".to_string(), Duration::from_millis(1000)),
+            ("/notes.md".to_string(), Duration::from_millis(100)),
+        ]
+        .into_iter()
+        .collect();
+        let default = Duration::from_millis(500);
+        let test_values = [
+            // (changed path, debounce millis)
+            ("/code/src/main.rs", 5000),
+            ("/code/docs/a.md", 5000),
+            ("/notes.md", 100),
+            ("/notes.md.swp", 500),
+            ("/other/a.txt", 500),
+        ];
+
+        for spec in test_values {
+            let debounce = get_path_debounce(&path_debounces, default, Path::new(spec.0));
+            assert_eq!(debounce, Duration::from_millis(spec.1), "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_kind_merge() -> Result<()> {
+        let test_values = [
+            // (kinds seen, merged kind)
+            (vec![ChangeKind::Create], ChangeKind::Create),
+            (vec![ChangeKind::Create, ChangeKind::Modify], ChangeKind::Create),
+            (vec![ChangeKind::Modify, ChangeKind::Modify], ChangeKind::Modify),
+            (vec![ChangeKind::Modify, ChangeKind::Remove], ChangeKind::Remove),
+            (vec![ChangeKind::Create, ChangeKind::Remove], ChangeKind::Remove),
+            (vec![ChangeKind::Remove, ChangeKind::Create], ChangeKind::Modify),
+        ];
+
+        for spec in test_values {
+            let merged = spec.0[1..].iter().fold(spec.0[0], |k, next| k.merge(*next));
+            assert_eq!(merged, spec.1, "{:?}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_kind_from_event() -> Result<()> {
+        let test_values = [
+            // (event kind, paths, [(path, kind)])
+            (
+                EventKind::Create(notify::event::CreateKind::File),
+                vec!["/foo"],
+                vec![("/foo", ChangeKind::Create)],
+            ),
+            (
+                EventKind::Remove(notify::event::RemoveKind::File),
+                vec!["/foo"],
+                vec![("/foo", ChangeKind::Remove)],
+            ),
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec!["/foo", "/bar"],
+                vec![("/foo", ChangeKind::Remove), ("/bar", ChangeKind::Create)],
+            ),
+            (
+                EventKind::Access(AccessKind::Close(AccessMode::Write)),
+                vec!["/foo"],
+                vec![("/foo", ChangeKind::Modify)],
+            ),
+            (
+                EventKind::Access(AccessKind::Open(AccessMode::Any)),
+                vec!["/foo"],
+                vec![],
+            ),
+        ];
+
+        for spec in test_values {
+            let mut event = Event::new(spec.0);
+            for path in &spec.1 {
+                event = event.add_path(PathBuf::from(path));
+            }
+            let expected: Vec<(PathBuf, ChangeKind)> = spec
+                .2
+                .iter()
+                .map(|(p, k)| (PathBuf::from(p), *k))
+                .collect();
+            assert_eq!(ChangeKind::from_event(&event), expected, "{:?}", spec.0);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use iroh::NodeId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::share;
+
+pub const PENDING_FILE_NAME: &str = "pending.toml";
+
+// PendingPeer: a node not on the config that contacted this one, waiting
+// to be approved (`fsy peers approve`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingPeer {
+    pub node_id: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub contacts: u64,       // messages received from it
+    pub groups: Vec<String>, // groups it sent messages about
+}
+
+// PendingPeers: the nodes waiting for approval, with trust_on_first_use
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PendingPeers {
+    pub peers: Vec<PendingPeer>,
+}
+
+impl fmt::Display for PendingPeers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.peers.is_empty() {
+            return writeln!(f, "no pending peers");
+        }
+
+        for peer in &self.peers {
+            let groups = match peer.groups.is_empty() {
+                true => "no group".to_owned(),
+                false => peer.groups.join(", "),
+            };
+            writeln!(
+                f,
+                "{}: {} messages since {} ({groups})",
+                peer.node_id,
+                peer.contacts,
+                peer.first_seen.format("%Y-%m-%d %H:%M:%S UTC"),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PendingPeers {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: PendingPeers = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    // record notes a message of the node, about the group if any,
+    // returning if the node is new
+    pub fn record(&mut self, node_id: &str, group_name: Option<&str>, now: DateTime<Utc>) -> bool {
+        let is_new = !self.peers.iter().any(|p| p.node_id == node_id);
+        if is_new {
+            self.peers.push(PendingPeer {
+                node_id: node_id.to_owned(),
+                first_seen: now,
+                last_seen: now,
+                contacts: 0,
+                groups: vec![],
+            });
+        }
+
+        let Some(peer) = self.peers.iter_mut().find(|p| p.node_id == node_id) else {
+            return is_new;
+        };
+        peer.last_seen = now;
+        peer.contacts += 1;
+        if let Some(group_name) = group_name
+            && !peer.groups.iter().any(|g| g == group_name)
+        {
+            peer.groups.push(group_name.to_owned());
+        }
+
+        is_new
+    }
+
+    // remove takes the node out, if it was there
+    pub fn remove(&mut self, node_id: &str) -> Option<PendingPeer> {
+        let index = self.peers.iter().position(|p| p.node_id == node_id)?;
+        Some(self.peers.remove(index))
+    }
+}
+
+// Approved: the node added to the config out of the pending ones
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Approved {
+    pub node_name: String,
+    pub node_id: String,
+    pub groups: Vec<String>, // groups it asked about, it needs to be a target of them
+}
+
+impl fmt::Display for Approved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "node \"{}\" added to the config, restart the daemon to talk to it",
+            self.node_name
+        )?;
+        if !self.groups.is_empty() {
+            writeln!(
+                f,
+                "add it as a target of the groups it asked about to sync them: {}",
+                self.groups.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// add_approved adds the node to the content of the config, appended so the
+// comments of the user are kept
+pub fn add_approved(
+    content: &str,
+    config: &Config,
+    peer: &PendingPeer,
+    node_name: &str,
+) -> Result<(String, Approved)> {
+    if config.nodes.iter().any(|n| n.id == peer.node_id) {
+        bail!("the node is on the config already");
+    }
+    if config.nodes.iter().any(|n| n.name == node_name) {
+        bail!("there is a node \"{node_name}\" already, use another name");
+    }
+
+    let mut content = share::remove_empty_lists(content, &["nodes"]);
+    content.push_str(&share::get_node_table(node_name, &peer.node_id)?);
+
+    // make sure the config still loads before it is written
+    if let Err(e) = toml::from_str::<Config>(&content) {
+        bail!("unable to add the node to the config: {e}");
+    }
+
+    let approved = Approved {
+        node_name: node_name.to_owned(),
+        node_id: peer.node_id.clone(),
+        groups: peer.groups.clone(),
+    };
+    Ok((content, approved))
+}
+
+// approve_peer adds the pending node to the config, named as given or
+// after its id
+pub fn approve_peer(config: &Config, node_id: &str, name: Option<&str>) -> Result<Approved> {
+    if NodeId::from_str(node_id).is_err() {
+        bail!("invalid node id \"{node_id}\"");
+    }
+
+    let pending_path = config.get_storage_path().join(PENDING_FILE_NAME);
+    let mut pending = PendingPeers::load(&pending_path)?;
+    let Some(peer) = pending.remove(node_id) else {
+        bail!("no pending peer {node_id}, run `fsy peers pending` to see them");
+    };
+
+    let node_name = match name {
+        Some(name) => name.to_owned(),
+        None => format!("node-{}", &node_id[..node_id.len().min(8)]),
+    };
+    let config_path = Path::new(&config.config_path);
+    let content = fs::read_to_string(config_path)?;
+    let (content, approved) = add_approved(&content, config, &peer, &node_name)?;
+    fs::write(config_path, content)?;
+    pending.save(&pending_path)?;
+
+    Ok(approved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key;
+    use anyhow::Result;
+
+    #[test]
+    fn test_pending_peers() -> Result<()> {
+        let now = Utc::now();
+        let mut pending = PendingPeers::default();
+        let test_values = [
+            // (node_id, group, is_new)
+            ("foo", Some("docs"), true),
+            ("foo", Some("docs"), false),
+            ("foo", None, false),
+            ("foo", Some("photos"), false),
+            ("bar", None, true),
+        ];
+
+        for spec in test_values {
+            assert_eq!(pending.record(spec.0, spec.1, now), spec.2, "{:?}", spec);
+        }
+
+        assert_eq!(pending.peers.len(), 2);
+        assert_eq!(pending.peers[0].contacts, 4);
+        assert_eq!(pending.peers[0].groups, vec!["docs", "photos"]);
+        assert!(pending.peers[1].groups.is_empty());
+
+        assert!(pending.remove("foo").is_some());
+        assert!(pending.remove("foo").is_none());
+        assert_eq!(pending.peers.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_approved() -> Result<()> {
+        let config = Config::default();
+        let content = toml::to_string(&config)?;
+        let node_id = key::generate_node_secret_key().public().to_string();
+        let mut pending = PendingPeers::default();
+        pending.record(&node_id, Some("docs"), Utc::now());
+        let peer = pending.peers[0].clone();
+
+        let (content, approved) = add_approved(&content, &config, &peer, "laptop")?;
+        let updated: Config = toml::from_str(&content)?;
+        assert_eq!(updated.nodes.len(), 1);
+        assert_eq!(updated.nodes[0].name, "laptop");
+        assert_eq!(updated.nodes[0].id, node_id);
+        assert_eq!(approved.groups, vec!["docs"]);
+
+        // the node or the name is there already
+        assert!(add_approved(&content, &updated, &peer, "desktop").is_err());
+        let other_peer = PendingPeer {
+            node_id: key::generate_node_secret_key().public().to_string(),
+            ..peer
+        };
+        assert!(add_approved(&content, &updated, &other_peer, "laptop").is_err());
+
+        // the name is quoted by toml, whatever it has
+        let (content, _) = add_approved(&content, &updated, &other_peer, "my \"pc\" \\ 2")?;
+        let updated: Config = toml::from_str(&content)?;
+        assert_eq!(updated.nodes[1].name, "my \"pc\" \\ 2");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{Result, bail};
+use std::path::Path;
+
+use crate::target::TargetGroup;
+
+// mode of new files before the umask takes from it
+const DEFAULT_FILE_MODE: u32 = 0o666;
+
+// parse_mode retrieves the permission bits of an octal mode, "640", "0640"
+// or "0o640"
+pub fn parse_mode(raw: &str) -> Result<u32> {
+    let digits = raw.strip_prefix("0o").unwrap_or(raw);
+    let Ok(mode) = u32::from_str_radix(digits, 8) else {
+        bail!("invalid mode \"{raw}\", it needs to be octal (\"640\")");
+    };
+    if digits.is_empty() || mode > 0o7777 {
+        bail!("invalid mode \"{raw}\", it needs to be octal (\"640\")");
+    }
+
+    Ok(mode)
+}
+
+// parse_owner splits the owner into its user and group, either can be left
+// out ("user", ":group", "user:group")
+pub fn parse_owner(raw: &str) -> Result<(Option<&str>, Option<&str>)> {
+    let (user, group) = raw.split_once(":").unwrap_or((raw, ""));
+    let user = Some(user).filter(|u| !u.is_empty());
+    let group = Some(group).filter(|g| !g.is_empty());
+    if user.is_none() && group.is_none() {
+        bail!("invalid owner \"{raw}\", it needs to be \"user\", \":group\" or \"user:group\"");
+    }
+
+    Ok((user, group))
+}
+
+// get_file_mode retrieves the mode the pulled files of the group get, the
+// chmod of the group over its umask. none leaves them as they are
+pub fn get_file_mode(group: &TargetGroup) -> Result<Option<u32>> {
+    if let Some(chmod) = &group.chmod {
+        return Ok(Some(parse_mode(chmod)?));
+    }
+
+    match &group.umask {
+        Some(umask) => Ok(Some(DEFAULT_FILE_MODE & !parse_mode(umask)?)),
+        None => Ok(None),
+    }
+}
+
+// apply_permissions sets the mode and owner the group asks for on a pulled
+// file, so whoever uses it on this side can right away. changing the owner
+// to someone else usually needs privileges
+#[cfg(unix)]
+pub fn apply_permissions(group: &TargetGroup, file_path: &Path) -> Result<()> {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(mode) = get_file_mode(group)? {
+        fs::set_permissions(file_path, Permissions::from_mode(mode))?;
+    }
+
+    if let Some(owner) = &group.chown {
+        let (user, group) = parse_owner(owner)?;
+        let uid = user.map(get_uid).transpose()?;
+        let gid = group.map(get_gid).transpose()?;
+        std::os::unix::fs::chown(file_path, uid, gid)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn apply_permissions(_group: &TargetGroup, _file_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+// get_uid retrieves the id of the user, given by name or id
+#[cfg(unix)]
+fn get_uid(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse::<u32>() {
+        return Ok(uid);
+    }
+
+    let name = std::ffi::CString::new(user)?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut found: *mut libc::passwd = std::ptr::null_mut();
+    // SAFETY: the name is nul terminated, passwd and buf outlive the call
+    let res = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if res != 0 || found.is_null() {
+        bail!("unknown user \"{user}\"");
+    }
+
+    Ok(passwd.pw_uid)
+}
+
+// get_gid retrieves the id of the group, given by name or id
+#[cfg(unix)]
+fn get_gid(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+
+    let name = std::ffi::CString::new(group)?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut found: *mut libc::group = std::ptr::null_mut();
+    // SAFETY: the name is nul terminated, entry and buf outlive the call
+    let res = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if res != 0 || found.is_null() {
+        bail!("unknown group \"{group}\"");
+    }
+
+    Ok(entry.gr_gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parse_mode() -> Result<()> {
+        let test_values = [
+            // (raw, mode)
+            ("640", Some(0o640)),
+            ("0640", Some(0o640)),
+            ("0o640", Some(0o640)),
+            ("2775", Some(0o2775)),
+            ("", None),
+            ("rw-r-----", None),
+            ("680", None),
+            ("17777", None),
+        ];
+
+        for spec in test_values {
+            assert_eq!(parse_mode(spec.0).ok(), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_owner() -> Result<()> {
+        let test_values = [
+            // (raw, (user, group))
+            ("www", Some((Some("www"), None))),
+            ("www:web", Some((Some("www"), Some("web")))),
+            (":web", Some((None, Some("web")))),
+            ("1000:1000", Some((Some("1000"), Some("1000")))),
+            ("", None),
+            (":", None),
+        ];
+
+        for spec in test_values {
+            assert_eq!(parse_owner(spec.0).ok(), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_file_mode() -> Result<()> {
+        let test_values = [
+            // (chmod, umask, mode)
+            (None, None, None),
+            (Some("640"), None, Some(0o640)),
+            (None, Some("027"), Some(0o640)),
+            (None, Some("002"), Some(0o664)),
+            (Some("600"), Some("002"), Some(0o600)),
+        ];
+
+        for spec in test_values {
+            let group = TargetGroup {
+                chmod: spec.0.map(|m| m.to_string()),
+                umask: spec.1.map(|m| m.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(get_file_mode(&group)?, spec.2, "{:?}", spec);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply_permissions() -> Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = std::env::temp_dir().join("fsy_test_permissions");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let file_path = dir.join("foo.txt");
+        std::fs::write(&file_path, "foo")?;
+
+        // the owner it already has needs no privileges
+        let meta = std::fs::metadata(&file_path)?;
+        let group = TargetGroup {
+            chmod: Some("640".to_string()),
+            chown: Some(format!("{}:{}", meta.uid(), meta.gid())),
+            ..Default::default()
+        };
+        apply_permissions(&group, &file_path)?;
+        let mode = std::fs::metadata(&file_path)?.permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+
+        let group = TargetGroup {
+            chown: Some("fsy_no_such_user".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_permissions(&group, &file_path).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::config::LocalNodeData;
+
+// how often the battery and the network are checked
+pub const POWER_CHECK_SECS: u64 = 30;
+
+// why the sync waits after `fsy pause`, it only resumes with `fsy resume`
+pub const USER_PAUSE_REASON: &str = "by the user";
+
+// where linux lists its batteries and chargers
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+// PowerState: what the system tells about its battery and network, unknown
+// counts as plugged in and not metered so the sync never stops on it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PowerState {
+    pub on_battery: bool,
+    pub battery_percent: Option<u8>,
+    pub metered: bool,
+}
+
+// parse_battery retrieves if a linux power supply is a battery running on
+// itself and its charge, out of its `type`, `status` and `capacity`
+pub fn parse_battery(kind: &str, status: &str, capacity: &str) -> Option<(bool, Option<u8>)> {
+    if kind.trim() != "Battery" {
+        return None;
+    }
+
+    let on_battery = status.trim() == "Discharging";
+    Some((on_battery, capacity.trim().parse::<u8>().ok()))
+}
+
+// parse_pmset retrieves the battery state out of `pmset -g batt` (macos)
+pub fn parse_pmset(output: &str) -> (bool, Option<u8>) {
+    let on_battery = output
+        .lines()
+        .next()
+        .is_some_and(|line| line.contains("'Battery Power'"));
+    let percent = output
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .find_map(|word| word.strip_suffix('%')?.parse::<u8>().ok());
+
+    (on_battery, percent)
+}
+
+// parse_nmcli_metered checks if any device is metered out of
+// `nmcli -t -f GENERAL.METERED dev show` (linux with networkmanager)
+pub fn parse_nmcli_metered(output: &str) -> bool {
+    output.lines().any(|line| {
+        line.split_once(':')
+            .is_some_and(|(_, value)| value.trim().starts_with("yes"))
+    })
+}
+
+async fn get_command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().await.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// get_linux_battery retrieves if any battery is discharging and the lowest
+// charge of them
+fn get_linux_battery() -> (bool, Option<u8>) {
+    let Ok(entries) = fs::read_dir(POWER_SUPPLY_DIR) else {
+        return (false, None);
+    };
+
+    let read = |dir: &Path, name: &str| fs::read_to_string(dir.join(name)).unwrap_or_default();
+    let batteries: Vec<(bool, Option<u8>)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let dir = entry.path();
+            parse_battery(
+                &read(&dir, "type"),
+                &read(&dir, "status"),
+                &read(&dir, "capacity"),
+            )
+        })
+        .collect();
+
+    let on_battery = batteries.iter().any(|(on_battery, _)| *on_battery);
+    let percent = batteries.iter().filter_map(|(_, percent)| *percent).min();
+    (on_battery, percent)
+}
+
+// get_power_state retrieves the battery and network state from the system,
+// where there is a way to know it
+pub async fn get_power_state() -> PowerState {
+    if cfg!(target_os = "linux") {
+        let (on_battery, battery_percent) = get_linux_battery();
+        let metered = get_command_output("nmcli", &["-t", "-f", "GENERAL.METERED", "dev", "show"])
+            .await
+            .is_some_and(|output| parse_nmcli_metered(&output));
+        return PowerState {
+            on_battery,
+            battery_percent,
+            metered,
+        };
+    }
+
+    if cfg!(target_os = "macos") {
+        let (on_battery, battery_percent) = get_command_output("pmset", &["-g", "batt"])
+            .await
+            .map(|output| parse_pmset(&output))
+            .unwrap_or_default();
+        return PowerState {
+            on_battery,
+            battery_percent,
+            metered: false,
+        };
+    }
+
+    PowerState::default()
+}
+
+// get_pause_reason retrieves why the sync should wait with the state the
+// system is in, none if it can go on
+pub fn get_pause_reason(local: &LocalNodeData, state: &PowerState) -> Option<String> {
+    if local.pause_on_metered && state.metered {
+        return Some("on a metered connection".to_owned());
+    }
+
+    match (local.pause_on_battery_below, state.battery_percent) {
+        (Some(below), Some(percent)) if state.on_battery && percent < below => {
+            Some(format!("on battery at {percent}%"))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use anyhow::Result;
+
+    #[test]
+    fn test_parse_battery() -> Result<()> {
+        let test_values = [
+            // (type, status, capacity, expected)
+            ("Battery\n", "Discharging\n", "42\n", Some((true, Some(42)))),
+            ("Battery", "Charging", "42", Some((false, Some(42)))),
+            ("Battery", "Full", "", Some((false, None))),
+            ("Mains", "", "", None),
+            ("USB", "Discharging", "10", None),
+        ];
+
+        for spec in test_values {
+            assert_eq!(parse_battery(spec.0, spec.1, spec.2), spec.3, "{:?}", spec);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_pmset() -> Result<()> {
+        let test_values = [
+            // (output, expected)
+            (
+                "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t18%; discharging; 0:52 remaining present: true\n",
+                (true, Some(18)),
+            ),
+            (
+                "Now drawing from 'AC Power'\n -InternalBattery-0 (id=4653155)\t100%; charged; 0:00 remaining present: true\n",
+                (false, Some(100)),
+            ),
+            ("Now drawing from 'AC Power'\n", (false, None)),
+            ("", (false, None)),
+        ];
+
+        for spec in test_values {
+            assert_eq!(parse_pmset(spec.0), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_nmcli_metered() -> Result<()> {
+        let test_values = [
+            // (output, is_metered)
+            (
+                "GENERAL.METERED:no (guessed)\nGENERAL.METERED:unknown\n",
+                false,
+            ),
+            (
+                "GENERAL.METERED:yes (guessed)\nGENERAL.METERED:unknown\n",
+                true,
+            ),
+            ("GENERAL.METERED:yes\n", true),
+            ("", false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(parse_nmcli_metered(spec.0), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_pause_reason() -> Result<()> {
+        let test_values = [
+            // (pause below, pause on metered, on battery, percent, metered, is paused)
+            (None, false, true, Some(5), true, false),
+            (Some(20), false, true, Some(15), false, true),
+            (Some(20), false, true, Some(20), false, false),
+            (Some(20), false, false, Some(15), false, false),
+            (Some(20), false, true, None, false, false),
+            (None, true, false, None, true, true),
+            (None, true, false, None, false, false),
+        ];
+
+        for spec in test_values {
+            let mut local = Config::default().local;
+            local.pause_on_battery_below = spec.0;
+            local.pause_on_metered = spec.1;
+            let state = PowerState {
+                on_battery: spec.2,
+                battery_percent: spec.3,
+                metered: spec.4,
+            };
+            assert_eq!(
+                get_pause_reason(&local, &state).is_some(),
+                spec.5,
+                "{:?}",
+                spec
+            );
+        }
+
+        Ok(())
+    }
+}
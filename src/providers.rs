@@ -0,0 +1,93 @@
+use std::collections::{HashMap, VecDeque};
+
+// nodes kept per hash, the most recent ones
+const MAX_PROVIDERS_PER_HASH: usize = 8;
+
+// hashes kept, the oldest are forgotten first
+const MAX_HASHES: usize = 10_000;
+
+// Providers: the nodes known to have the content of each hash, so a
+// download can fetch it from any of them and not only from the pusher
+#[derive(Debug, Clone, Default)]
+pub struct Providers {
+    providers: HashMap<String, Vec<String>>,
+    order: VecDeque<String>, // hashes by when they were first known
+}
+
+impl Providers {
+    pub fn add(&mut self, hash: &str, node_id: &str) {
+        if !self.providers.contains_key(hash) {
+            if self.order.len() >= MAX_HASHES
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.providers.remove(&oldest);
+            }
+            self.order.push_back(hash.to_owned());
+        }
+
+        let node_ids = self.providers.entry(hash.to_owned()).or_default();
+        node_ids.retain(|id| id != node_id);
+        node_ids.push(node_id.to_owned());
+        if node_ids.len() > MAX_PROVIDERS_PER_HASH {
+            node_ids.remove(0);
+        }
+    }
+
+    // get retrieves the nodes known to have the hash, most recent first
+    pub fn get(&self, hash: &str) -> Vec<String> {
+        self.providers
+            .get(hash)
+            .map(|node_ids| node_ids.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_providers() -> Result<()> {
+        let mut providers = Providers::default();
+        providers.add("abc", "foo");
+        providers.add("abc", "bar");
+        providers.add("abc", "foo");
+        providers.add("zed", "bar");
+
+        let test_values = [
+            // (hash, node ids)
+            ("abc", vec!["foo", "bar"]),
+            ("zed", vec!["bar"]),
+            ("none", vec![]),
+        ];
+
+        for spec in test_values {
+            assert_eq!(providers.get(spec.0), spec.1);
+        }
+
+        // only the most recent nodes are kept
+        for i in 0..MAX_PROVIDERS_PER_HASH {
+            providers.add("abc", &i.to_string());
+        }
+        let node_ids = providers.get("abc");
+        assert_eq!(node_ids.len(), MAX_PROVIDERS_PER_HASH);
+        assert_eq!(node_ids[0], (MAX_PROVIDERS_PER_HASH - 1).to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_providers_forget() -> Result<()> {
+        let mut providers = Providers::default();
+        for i in 0..=MAX_HASHES {
+            providers.add(&i.to_string(), "foo");
+        }
+
+        assert!(providers.get("0").is_empty());
+        assert_eq!(providers.get("1"), vec!["foo"]);
+        assert_eq!(providers.get(&MAX_HASHES.to_string()), vec!["foo"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::storage;
+
+pub const PULLED_FILE_NAME: &str = "pulled.toml";
+
+// PulledFile: the content last pulled into a file and how the file was left,
+// a change to the file after it means it isn't that content anymore
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PulledFile {
+    pub hash: String,
+    pub len: u64,
+    pub modified: DateTime<Utc>,
+    #[serde(default)]
+    pub version: u64, // version of the file the pusher sent, 0 when it sent none
+    #[serde(default)]
+    pub node_id: String, // node the version was pulled from
+}
+
+impl PulledFile {
+    // is_left_as checks if the file is still as it was left when pulled
+    fn is_left_as(&self, file_path: &Path) -> Option<bool> {
+        let meta = fs::metadata(file_path).ok()?;
+        let modified: DateTime<Utc> = meta.modified().ok()?.into();
+        Some(meta.len() == self.len && modified == self.modified)
+    }
+}
+
+// Arbitration: what a puller does with a version of a file, a group pushed
+// from several nodes can get versions of the same file from each of them
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arbitration {
+    Take,                      // newer than what the file has
+    Stale,                     // older than what the file has, a newer one came first
+    Concurrent { wins: bool }, // edited on two nodes at once, the higher node id wins
+}
+
+// Pulled: what was last pulled into each file of each group, kept on the
+// storage so notifications sent again (retries, reconnects) don't download
+// the same content again
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Pulled {
+    #[serde(default)]
+    pub groups: BTreeMap<String, BTreeMap<String, PulledFile>>,
+}
+
+impl Pulled {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: Pulled = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)?;
+        storage::write_atomic(path, content.as_bytes())
+    }
+
+    // update loads, changes and saves what was pulled, the other lanes wait
+    // so none of their changes are lost
+    pub fn update<T>(path: &Path, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let _lock = storage::lock_state();
+        let mut pulled = Self::load(path)?;
+        let res = change(&mut pulled)?;
+        pulled.save(path)?;
+        Ok(res)
+    }
+
+    // is_pulled checks if the file still has the content of the hash, as it
+    // was left when it was pulled
+    pub fn is_pulled(
+        &self,
+        group_name: &str,
+        relative_path: &str,
+        hash: &str,
+        file_path: &Path,
+    ) -> bool {
+        let pulled = self
+            .groups
+            .get(group_name)
+            .and_then(|files| files.get(relative_path));
+        let Some(pulled) = pulled else {
+            return false;
+        };
+
+        pulled.hash == hash && pulled.is_left_as(file_path) == Some(true)
+    }
+
+    // has_changed checks if the file isn't as it was left when it was last
+    // pulled, a file never pulled didn't change
+    pub fn has_changed(&self, group_name: &str, relative_path: &str, file_path: &Path) -> bool {
+        let pulled = self
+            .groups
+            .get(group_name)
+            .and_then(|files| files.get(relative_path));
+        let Some(pulled) = pulled else {
+            return false;
+        };
+
+        pulled.is_left_as(file_path) == Some(false)
+    }
+
+    // is_left_as_pulled checks if the file was pulled and is still as it was
+    // left then, nothing of it would be lost removing it
+    pub fn is_left_as_pulled(
+        &self,
+        group_name: &str,
+        relative_path: &str,
+        file_path: &Path,
+    ) -> bool {
+        self.groups
+            .get(group_name)
+            .and_then(|files| files.get(relative_path))
+            .and_then(|pulled| pulled.is_left_as(file_path))
+            .unwrap_or_default()
+    }
+
+    // forget stops keeping what was pulled into the file, it was removed
+    pub fn forget(&mut self, group_name: &str, relative_path: &str) {
+        if let Some(files) = self.groups.get_mut(group_name) {
+            files.remove(relative_path);
+            if files.is_empty() {
+                self.groups.remove(group_name);
+            }
+        }
+    }
+
+    // get_version retrieves the version a pusher sends of the file, the one
+    // it pulled or, edited since, the one after it. a file never pulled is
+    // the first version
+    pub fn get_version(&self, group_name: &str, relative_path: &str, file_path: &Path) -> u64 {
+        let pulled = self
+            .groups
+            .get(group_name)
+            .and_then(|files| files.get(relative_path));
+        let Some(pulled) = pulled else {
+            return 1;
+        };
+
+        let is_edited = pulled.is_left_as(file_path) == Some(false);
+        pulled.version.max(1) + u64::from(is_edited)
+    }
+
+    // arbitrate checks the version a node sent of the file against the one
+    // the file has: the one pulled or, when we push the group too
+    // (`local_node_id`) and the file was edited here since, our own
+    pub fn arbitrate(
+        &self,
+        group_name: &str,
+        relative_path: &str,
+        file_path: &Path,
+        version: u64,
+        node_id: &str,
+        local_node_id: Option<&str>,
+    ) -> Arbitration {
+        // NOTE: older nodes send no version, what they send is taken
+        if version == 0 {
+            return Arbitration::Take;
+        }
+
+        let pulled = self
+            .groups
+            .get(group_name)
+            .and_then(|files| files.get(relative_path));
+        let is_edited = match pulled {
+            Some(pulled) => pulled.is_left_as(file_path) == Some(false),
+            None => file_path.is_file(),
+        };
+        let (current_version, current_node_id) = match (local_node_id, pulled) {
+            (Some(local_node_id), _) if is_edited => (
+                self.get_version(group_name, relative_path, file_path),
+                local_node_id,
+            ),
+            (_, Some(pulled)) => (pulled.version, pulled.node_id.as_str()),
+            (_, None) => return Arbitration::Take,
+        };
+
+        match version.cmp(&current_version) {
+            Ordering::Greater => Arbitration::Take,
+            Ordering::Less => Arbitration::Stale,
+            // a later change of the same node, or a file pulled before versions
+            Ordering::Equal if current_node_id == node_id || current_node_id.is_empty() => {
+                Arbitration::Take
+            }
+            Ordering::Equal => Arbitration::Concurrent {
+                wins: node_id > current_node_id,
+            },
+        }
+    }
+
+    // set_pulled notes the content of the hash was pulled into the file, at
+    // the version the node sent
+    pub fn set_pulled(
+        &mut self,
+        group_name: &str,
+        relative_path: &str,
+        hash: &str,
+        file_path: &Path,
+        version: u64,
+        node_id: &str,
+    ) -> Result<()> {
+        let meta = fs::metadata(file_path)?;
+        let pulled = PulledFile {
+            hash: hash.to_owned(),
+            len: meta.len(),
+            modified: meta.modified()?.into(),
+            version,
+            node_id: node_id.to_owned(),
+        };
+        self.groups
+            .entry(group_name.to_owned())
+            .or_default()
+            .insert(relative_path.to_owned(), pulled);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_is_pulled() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_pulled");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let file_path = dir.join("foo.txt");
+        fs::write(&file_path, "foo")?;
+
+        let mut pulled = Pulled::default();
+        pulled.set_pulled("docs", "foo.txt", "abc", &file_path, 1, "foo")?;
+
+        let test_values = [
+            // (group_name, relative_path, hash, is_pulled)
+            ("docs", "foo.txt", "abc", true),
+            ("docs", "foo.txt", "zed", false),
+            ("docs", "bar.txt", "abc", false),
+            ("other", "foo.txt", "abc", false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(pulled.is_pulled(spec.0, spec.1, spec.2, &file_path), spec.3);
+        }
+
+        // the file changed after it was pulled
+        assert!(!pulled.has_changed("docs", "foo.txt", &file_path));
+        assert!(pulled.is_left_as_pulled("docs", "foo.txt", &file_path));
+        fs::write(&file_path, "foo bar")?;
+        assert!(!pulled.is_pulled("docs", "foo.txt", "abc", &file_path));
+        assert!(pulled.has_changed("docs", "foo.txt", &file_path));
+        assert!(!pulled.has_changed("docs", "bar.txt", &file_path));
+        assert!(!pulled.is_left_as_pulled("docs", "foo.txt", &file_path));
+        assert!(!pulled.is_left_as_pulled("docs", "bar.txt", &file_path));
+
+        // it survives a restart
+        let pulled_path = dir.join(PULLED_FILE_NAME);
+        pulled.set_pulled("docs", "foo.txt", "abc", &file_path, 1, "foo")?;
+        pulled.save(&pulled_path)?;
+        assert_eq!(Pulled::load(&pulled_path)?, pulled);
+
+        // a file removed is forgotten, the group with it once empty
+        pulled.forget("docs", "foo.txt");
+        assert!(pulled.groups.is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_arbitrate() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_pulled_arbitrate");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let file_path = dir.join("foo.txt");
+        fs::write(&file_path, "foo")?;
+
+        let mut pulled = Pulled::default();
+        pulled.set_pulled("docs", "foo.txt", "abc", &file_path, 3, "bbb")?;
+        assert_eq!(pulled.get_version("docs", "foo.txt", &file_path), 3);
+        assert_eq!(pulled.get_version("docs", "bar.txt", &file_path), 1);
+
+        let test_values = [
+            // (version, node_id, local_node_id, expected)
+            (0, "aaa", None, Arbitration::Take),
+            (4, "aaa", None, Arbitration::Take),
+            (2, "aaa", None, Arbitration::Stale),
+            (3, "bbb", None, Arbitration::Take),
+            (3, "ccc", None, Arbitration::Concurrent { wins: true }),
+            (3, "aaa", None, Arbitration::Concurrent { wins: false }),
+            // not edited here, our own version doesn't count
+            (
+                3,
+                "aaa",
+                Some("zzz"),
+                Arbitration::Concurrent { wins: false },
+            ),
+        ];
+
+        for (version, node_id, local_node_id, expected) in test_values {
+            let arbitration = pulled.arbitrate(
+                "docs",
+                "foo.txt",
+                &file_path,
+                version,
+                node_id,
+                local_node_id,
+            );
+            assert_eq!(
+                arbitration, expected,
+                "{version} {node_id} {local_node_id:?}"
+            );
+        }
+
+        // edited here after it was pulled, we push the next version
+        fs::write(&file_path, "foo bar")?;
+        assert_eq!(pulled.get_version("docs", "foo.txt", &file_path), 4);
+        let test_values = [
+            // (version, node_id, local_node_id, expected)
+            (
+                4,
+                "ccc",
+                Some("bbc"),
+                Arbitration::Concurrent { wins: true },
+            ),
+            (
+                4,
+                "aaa",
+                Some("bbc"),
+                Arbitration::Concurrent { wins: false },
+            ),
+            (5, "aaa", Some("bbc"), Arbitration::Take),
+            (3, "ccc", Some("bbc"), Arbitration::Stale),
+            (4, "aaa", None, Arbitration::Take),
+        ];
+
+        for (version, node_id, local_node_id, expected) in test_values {
+            let arbitration = pulled.arbitrate(
+                "docs",
+                "foo.txt",
+                &file_path,
+                version,
+                node_id,
+                local_node_id,
+            );
+            assert_eq!(
+                arbitration, expected,
+                "{version} {node_id} {local_node_id:?}"
+            );
+        }
+
+        // a file of our own never pulled is the first version
+        let arbitration = pulled.arbitrate("docs", "bar.txt", &file_path, 1, "aaa", Some("bbc"));
+        assert_eq!(arbitration, Arbitration::Concurrent { wins: false });
+        let arbitration = pulled.arbitrate("docs", "bar.txt", &file_path, 1, "aaa", None);
+        assert_eq!(arbitration, Arbitration::Take);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
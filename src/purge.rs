@@ -0,0 +1,113 @@
+use anyhow::Result;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::{append, transform};
+
+// what the blob store keeps on the storage, see `FsStore::load`, along with
+// the transformed copies of the files sent and the parts appended
+const STORE_ENTRIES: [&str; 5] = [
+    "blobs.db",
+    "data",
+    "temp",
+    transform::TRANSFORMED_DIR_NAME,
+    append::APPENDS_DIR_NAME,
+];
+
+// size of the zeros written over a file at once
+const WIPE_CHUNK_BYTES: usize = 64 * 1024;
+
+// wipe_file writes zeros over the content of the file before removing it,
+// so the content doesn't stay around on the disk
+fn wipe_file(path: &Path) -> Result<()> {
+    let len = fs::metadata(path)?.len();
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    let zeros = vec![0u8; WIPE_CHUNK_BYTES];
+    let mut left = len;
+    while left > 0 {
+        let chunk = left.min(WIPE_CHUNK_BYTES as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        left -= chunk as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+// wipe_path wipes the file, or every file under the folder, retrieving how
+// many were wiped. links are removed without being followed
+pub fn wipe_path(path: &Path) -> Result<u64> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(0);
+    };
+
+    if metadata.is_symlink() {
+        fs::remove_file(path)?;
+        return Ok(0);
+    }
+    if metadata.is_file() {
+        wipe_file(path)?;
+        return Ok(1);
+    }
+
+    let mut wiped = 0;
+    for entry in fs::read_dir(path)? {
+        wiped += wipe_path(&entry?.path())?;
+    }
+    fs::remove_dir(path)?;
+    Ok(wiped)
+}
+
+// purge_store wipes the blob store on the storage, it needs to be closed.
+// retrieves how many files were wiped
+pub fn purge_store(storage_path: &Path) -> Result<u64> {
+    let mut wiped = 0;
+    for entry in STORE_ENTRIES {
+        wiped += wipe_path(&storage_path.join(entry))?;
+    }
+
+    Ok(wiped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_purge_store() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_purge_store");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("data"))?;
+        fs::create_dir_all(dir.join(transform::TRANSFORMED_DIR_NAME).join("docs"))?;
+        fs::write(dir.join("blobs.db"), "db")?;
+        fs::write(
+            dir.join("data").join("a.data"),
+            vec![1u8; WIPE_CHUNK_BYTES + 1],
+        )?;
+        fs::write(dir.join("data").join("b.obao4"), "")?;
+        fs::write(
+            dir.join(transform::TRANSFORMED_DIR_NAME)
+                .join("docs")
+                .join("a.txt"),
+            "a",
+        )?;
+        fs::write(dir.join("status.toml"), "status")?;
+
+        assert_eq!(purge_store(&dir)?, 4);
+        assert!(!dir.join("blobs.db").exists());
+        assert!(!dir.join("data").exists());
+        assert!(!dir.join(transform::TRANSFORMED_DIR_NAME).exists());
+        // the rest of the storage is kept
+        assert!(dir.join("status.toml").exists());
+
+        // nothing left to purge
+        assert_eq!(purge_store(&dir)?, 0);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,11 +1,33 @@
+use serde::{Deserialize, Serialize};
+
 pub const MAX_CAPACITY: usize = 1000;
 
+// how often the metrics of the queue are passed on to the status
+pub const METRICS_CHECK_SECS: u64 = 5;
+
+// percent of the capacity where the queue is close to overwriting its oldest
+// items, and where it is back to having room after it
+const NEAR_CAPACITY_PERCENT: usize = 80;
+const ROOM_PERCENT: usize = 50;
+
+// QueueMetrics: what went through the queue since the start, dropped are
+// the items overwritten by a push once it wrapped around
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct QueueMetrics {
+    pub pushed: u64,
+    pub popped: u64,
+    pub dropped: u64,
+    pub depth: usize,     // items waiting now
+    pub max_depth: usize, // most items waiting at once
+}
+
 #[derive(Clone)]
 pub struct Queue<T> {
     capacity: usize,
     head: usize,
     tail: usize,
     buffer: [Option<T>; MAX_CAPACITY],
+    metrics: QueueMetrics,
 }
 
 impl<T> Queue<T> {
@@ -15,6 +37,7 @@ impl<T> Queue<T> {
             head: 0,
             tail: 0,
             buffer: std::array::from_fn(|_| None),
+            metrics: QueueMetrics::default(),
         }
     }
 
@@ -76,14 +99,32 @@ impl<T> Queue<T> {
         false
     }
 
+    pub fn len(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+
+        if self.tail >= self.head {
+            return self.tail - self.head + 1;
+        }
+
+        self.capacity - self.head + self.tail + 1
+    }
+
     pub fn push(&mut self, item: T) {
         if self.capacity == 0 {
             return;
         }
 
-        // tail should always be the last item
+        // tail should always be the last item, taking the place of the
+        // oldest one once wrapped around
         self.tail = self.get_next_position();
+        if self.buffer[self.tail].is_some() {
+            self.metrics.dropped += 1;
+        }
         self.buffer[self.tail] = Some(item);
+        self.metrics.pushed += 1;
+        self.metrics.max_depth = self.metrics.max_depth.max(self.len());
 
         // at this point, the tail has wrapped around and we want
         // the head to follow the tail
@@ -99,6 +140,33 @@ impl<T> Queue<T> {
         }
     }
 
+    // push_front puts the item ahead of the ones waiting, once full the
+    // last of them is dropped to make room
+    pub fn push_front(&mut self, item: T) {
+        if self.is_empty() {
+            self.push(item);
+            return;
+        }
+
+        let pos = (self.head + self.capacity - 1) % self.capacity;
+        if self.buffer[pos].is_some() {
+            self.metrics.dropped += 1;
+            self.tail = (self.tail + self.capacity - 1) % self.capacity;
+        }
+        self.buffer[pos] = Some(item);
+        self.head = pos;
+        self.metrics.pushed += 1;
+        self.metrics.max_depth = self.metrics.max_depth.max(self.len());
+    }
+
+    // push_front_multiple puts the items ahead of the ones waiting, on the
+    // order given
+    pub fn push_front_multiple(&mut self, item_list: Vec<T>) {
+        for item in item_list.into_iter().rev() {
+            self.push_front(item);
+        }
+    }
+
     pub fn pop(&mut self) -> Option<T> {
         if self.is_empty() {
             return None;
@@ -106,6 +174,9 @@ impl<T> Queue<T> {
 
         let first_pos = self.get_first_position();
         let item = self.buffer[first_pos].take();
+        if item.is_some() {
+            self.metrics.popped += 1;
+        }
 
         // change the head / tail positions
         self.head = self.get_next_first_position();
@@ -118,6 +189,64 @@ impl<T> Queue<T> {
         item
     }
 
+    // pop_max_by_key_where pops, of the items passing the filter, the first
+    // one with the highest key, keeping the order of the items left behind
+    pub fn pop_max_by_key_where<K: Ord>(
+        &mut self,
+        filter: impl Fn(&T) -> bool,
+        f: impl Fn(&T) -> K,
+    ) -> Option<T> {
+        let mut found: Option<(usize, K)> = None;
+        for i in 0..self.len() {
+            let pos = (self.head + i) % self.capacity;
+            if let Some(item) = &self.buffer[pos]
+                && filter(item)
+            {
+                let key = f(item);
+                if found.as_ref().is_none_or(|(_, found_key)| key > *found_key) {
+                    found = Some((i, key));
+                }
+            }
+        }
+
+        // move the found item to the head, the ones before it shift
+        // one position so we can pop it as usual
+        let (index, _) = found?;
+        for i in (1..=index).rev() {
+            let pos = (self.head + i) % self.capacity;
+            let prev_pos = (self.head + i - 1) % self.capacity;
+            self.buffer.swap(pos, prev_pos);
+        }
+
+        self.pop()
+    }
+
+    // is_near_capacity checks if the queue is close to wrapping around,
+    // pushing more would overwrite the oldest items
+    pub fn is_near_capacity(&self) -> bool {
+        self.len() * 100 >= self.capacity * NEAR_CAPACITY_PERCENT
+    }
+
+    // has_room checks if the queue drained enough to take changes again
+    pub fn has_room(&self) -> bool {
+        self.len() * 100 < self.capacity * ROOM_PERCENT
+    }
+
+    pub fn get_metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            depth: self.len(),
+            ..self.metrics.clone()
+        }
+    }
+
+    pub fn contains(&self, item: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        // NOTE: popped / unused positions are always empty
+        self.buffer.iter().any(|i| i.as_ref() == Some(item))
+    }
+
     #[allow(dead_code)]
     pub fn peek(&self) -> Option<&T> {
         self.buffer[self.get_first_position()].as_ref()
@@ -207,6 +336,48 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_push_front_multiple() -> Result<()> {
+        let test_values = [
+            // (capacity, pushed, pushed ahead, popped, dropped)
+            (5, vec![], vec![1, 2], vec![1, 2], 0),
+            (5, vec![3, 4], vec![1, 2], vec![1, 2, 3, 4], 0),
+            (5, vec![3, 4, 5], vec![1, 2], vec![1, 2, 3, 4, 5], 0),
+            (5, vec![3, 4, 5, 6], vec![1, 2], vec![1, 2, 3, 4, 5], 1),
+            (3, vec![3, 4, 5, 6], vec![1, 2], vec![1, 2, 4], 3),
+            (1, vec![3], vec![1, 2], vec![1], 2),
+        ];
+
+        for spec in test_values {
+            let mut queue: Queue<i32> = Queue::new(spec.0);
+            queue.push_multiple(spec.1.clone());
+            queue.push_front_multiple(spec.2.clone());
+            assert_eq!(queue.len(), spec.3.len(), "{spec:?}");
+
+            let mut popped = vec![];
+            while let Some(item) = queue.pop() {
+                popped.push(item);
+            }
+            assert_eq!(popped, spec.3, "{spec:?}");
+            assert_eq!(queue.get_metrics().dropped, spec.4, "{spec:?}");
+        }
+
+        // wrapped around, the items keep their order
+        let mut queue: Queue<i32> = Queue::new(4);
+        queue.push_multiple(vec![0, 0, 3, 4]);
+        queue.pop();
+        queue.pop();
+        queue.push(5);
+        queue.push_front_multiple(vec![2]);
+        let mut popped = vec![];
+        while let Some(item) = queue.pop() {
+            popped.push(item);
+        }
+        assert_eq!(popped, vec![2, 3, 4, 5]);
+
+        Ok(())
+    }
+
     #[test]
     fn test_pop_no_wrap() -> Result<()> {
         let mut queue: Queue<i32> = Queue::new(5);
@@ -276,6 +447,154 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_len() -> Result<()> {
+        let mut queue: Queue<i32> = Queue::new(5);
+        assert_eq!(queue.len(), 0);
+
+        let test_values = [
+            // (value, len)
+            (1, 1),
+            (10, 2),
+            (15, 3),
+            (20, 4),
+            (25, 5),
+            (30, 5), // tail wrap
+            (35, 5),
+        ];
+        for spec in test_values {
+            queue.push(spec.0);
+            assert_eq!(queue.len(), spec.1);
+        }
+
+        let _ = queue.pop();
+        assert_eq!(queue.len(), 4);
+
+        queue.clear();
+        assert_eq!(queue.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_near_capacity() -> Result<()> {
+        let mut queue: Queue<i32> = Queue::new(10);
+
+        let test_values = [
+            // (len, is_near_capacity, has_room)
+            (0, false, true),
+            (4, false, true),
+            (5, false, false),
+            (7, false, false),
+            (8, true, false),
+            (10, true, false),
+        ];
+        for spec in test_values {
+            while queue.len() < spec.0 {
+                queue.push(0);
+            }
+            assert_eq!(queue.is_near_capacity(), spec.1, "{}", spec.0);
+            assert_eq!(queue.has_room(), spec.2, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_metrics() -> Result<()> {
+        let mut queue: Queue<i32> = Queue::new(3);
+        assert_eq!(queue.get_metrics(), QueueMetrics::default());
+
+        let test_values = [
+            // (pushes, pops, (pushed, popped, dropped, depth, max depth))
+            (2, 0, (2, 0, 0, 2, 2)),
+            (1, 1, (3, 1, 0, 2, 3)),
+            (3, 0, (6, 1, 2, 3, 3)),
+            (0, 5, (6, 4, 2, 0, 3)),
+        ];
+        for spec in test_values {
+            (0..spec.0).for_each(|i| queue.push(i));
+            (0..spec.1).for_each(|_| {
+                let _ = queue.pop();
+            });
+
+            let metrics = queue.get_metrics();
+            let res = (
+                metrics.pushed,
+                metrics.popped,
+                metrics.dropped,
+                metrics.depth,
+                metrics.max_depth,
+            );
+            assert_eq!(res, spec.2, "{:?}", spec);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_max_by_key() -> Result<()> {
+        let mut queue: Queue<(i32, &str)> = Queue::new(5);
+        assert_eq!(queue.pop_max_by_key_where(|_| true, |item| item.0), None);
+
+        // prepare the test with pushes, (priority, value), wrapping once
+        let values = [(0, "a"), (0, "b"), (2, "c"), (1, "d"), (2, "e"), (0, "f")];
+        for val in values {
+            queue.push(val);
+        }
+
+        let test_values = [
+            // (value, len)
+            ("c", 4),
+            ("e", 3),
+            ("d", 2),
+            ("b", 1),
+            ("f", 0),
+        ];
+        for spec in test_values {
+            let res = queue.pop_max_by_key_where(|_| true, |item| item.0).unwrap();
+            assert_eq!(res.1, spec.0);
+            assert_eq!(queue.len(), spec.1);
+        }
+        assert_eq!(queue.pop_max_by_key_where(|_| true, |item| item.0), None);
+
+        // keeps working as a queue after
+        queue.push((0, "g"));
+        queue.push((0, "h"));
+        assert_eq!(queue.pop(), Some((0, "g")));
+        assert_eq!(queue.pop(), Some((0, "h")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_max_by_key_where() -> Result<()> {
+        let mut queue: Queue<(i32, &str)> = Queue::new(10);
+        for val in [(0, "a"), (2, "b"), (1, "c"), (2, "d")] {
+            queue.push(val);
+        }
+
+        let test_values = [
+            // (filtered out value, popped value, len)
+            ("b", "d", 3),
+            ("b", "c", 2),
+            ("a", "b", 1),
+            ("b", "a", 0),
+        ];
+        for spec in test_values {
+            let res = queue.pop_max_by_key_where(|item| item.1 != spec.0, |item| item.0);
+            assert_eq!(res.map(|item| item.1), Some(spec.1));
+            assert_eq!(queue.len(), spec.2);
+        }
+
+        // nothing passing the filter leaves the queue as is
+        queue.push((0, "e"));
+        assert_eq!(queue.pop_max_by_key_where(|_| false, |item| item.0), None);
+        assert_eq!(queue.len(), 1);
+
+        Ok(())
+    }
+
     #[test]
     fn test_peek() -> Result<()> {
         let mut queue: Queue<i32> = Queue::new(5);
@@ -298,6 +617,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_contains() -> Result<()> {
+        let mut queue: Queue<i32> = Queue::new(3);
+        assert!(!queue.contains(&1));
+
+        for val in [1, 10, 15, 20] {
+            queue.push(val);
+        }
+        assert!(!queue.contains(&1));
+        assert!(queue.contains(&10));
+        assert!(queue.contains(&20));
+
+        let _ = queue.pop();
+        assert!(!queue.contains(&10));
+        assert!(queue.contains(&15));
+
+        Ok(())
+    }
+
     #[test]
     fn test_clear() -> Result<()> {
         let mut queue: Queue<i32> = Queue::new(5);
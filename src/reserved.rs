@@ -0,0 +1,102 @@
+use std::path::{Component, Path, PathBuf};
+
+// folder on the target root that belongs to fsy (locks, swaps, versions...),
+// it is never watched nor transferred
+pub const FSY_DIR_NAME: &str = ".fsy";
+
+// get_root retrieves the folder of the target, a single file target lives
+// on the folder it is in
+pub fn get_root(base_path: &Path) -> PathBuf {
+    if base_path.is_dir() {
+        return base_path.to_path_buf();
+    }
+
+    base_path.parent().unwrap_or(base_path).to_path_buf()
+}
+
+// is_reserved_path checks if the path relative to the target root is
+// inside of the fsy folder, once its `.` and `..` are resolved
+pub fn is_reserved_path(relative_path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in relative_path.components() {
+        match component {
+            Component::Normal(name) if depth == 0 && name == FSY_DIR_NAME => return true,
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    false
+}
+
+// get_reserved_path retrieves where fsy keeps something about a file of the
+// target, under dir on the fsy folder. none if the file isn't on the target
+pub fn get_reserved_path(base_path: &Path, dir: &Path, file_path: &Path) -> Option<PathBuf> {
+    let root = get_root(base_path);
+    let relative_path = file_path.strip_prefix(&root).ok()?;
+    if relative_path.as_os_str().is_empty() || is_reserved_path(relative_path) {
+        return None;
+    }
+
+    Some(root.join(FSY_DIR_NAME).join(dir).join(relative_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_is_reserved_path() -> Result<()> {
+        let test_values = [
+            // (relative_path, expected)
+            (".fsy", true),
+            (".fsy/locks/a.txt.lock", true),
+            ("foo/.fsy/a.txt", false),
+            ("./.fsy/x", true),
+            ("a/../.fsy/x", true),
+            ("a/b/../.fsy/x", false),
+            (".fsyfoo", false),
+            ("a.txt", false),
+            ("", false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(is_reserved_path(Path::new(spec.0)), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_reserved_path() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_reserved_path");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("group"))?;
+        fs::write(dir.join("file.txt"), b"")?;
+
+        let test_values = [
+            // (base_path, file_path, expected)
+            ("group", "group/a.txt", Some("group/.fsy/locks/a.txt")),
+            ("group", "group/foo/a.txt", Some("group/.fsy/locks/foo/a.txt")),
+            ("file.txt", "file.txt", Some(".fsy/locks/file.txt")),
+            ("group", "group", None),
+            ("group", "group/.fsy/a.txt", None),
+            ("group", "other/a.txt", None),
+        ];
+
+        for spec in test_values {
+            let res = get_reserved_path(
+                &dir.join(spec.0),
+                Path::new("locks"),
+                &dir.join(spec.1),
+            );
+            assert_eq!(res, spec.2.map(|p| dir.join(p)), "{}", spec.1);
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use std::io;
+
+use crate::action::CommAction;
+use crate::logs::log_warning;
+
+// open files left for the connection, the watcher, the logs and the
+// storage, the rest goes to the actions opening files
+const RESERVED_OPEN_FILES: u64 = 128;
+
+// open files an action opening files is counted for: the file, its swap and
+// lock, and the blob
+const OPEN_FILES_PER_ACTION: u64 = 4;
+
+// the soft limit of open files is raised up to this on start, macos refuses
+// more whatever the hard limit says
+const MAX_RAISED_OPEN_FILES: u64 = 10240;
+
+// open files when the limit of the process can't be known
+pub const DEFAULT_MAX_OPEN_FILES: u64 = 1024;
+
+// changes waiting on the debounce of the watcher kept in memory when not
+// set, past it they are dropped and the groups gone through instead
+pub const DEFAULT_MAX_PENDING_CHANGES: usize = 100_000;
+
+// how often the paths over the limits of the watcher are gone through
+pub const POLL_WATCH_SECS: u64 = 30;
+
+// Resources: the actions opening files running at once, kept under the
+// open files the daemon can have. the rest wait on the queue
+#[derive(Debug)]
+pub struct Resources {
+    max_actions: usize,
+    running: usize,
+}
+
+impl Resources {
+    pub fn new(max_open_files: u64) -> Self {
+        let available = max_open_files.saturating_sub(RESERVED_OPEN_FILES);
+        let max_actions = (available / OPEN_FILES_PER_ACTION).max(1) as usize;
+        Self {
+            max_actions,
+            running: 0,
+        }
+    }
+
+    pub fn get_max_actions(&self) -> usize {
+        self.max_actions
+    }
+
+    // has_room checks if the action can start, only the ones opening files
+    // ever wait
+    pub fn has_room(&self, action: &CommAction) -> bool {
+        !opens_files(action) || self.running < self.max_actions
+    }
+
+    // start counts the action as running if it opens files, retrieving if
+    // it did so its end is counted too
+    pub fn start(&mut self, action: &CommAction) -> bool {
+        let opens_files = opens_files(action);
+        if opens_files {
+            self.running += 1;
+        }
+        opens_files
+    }
+
+    pub fn end(&mut self) {
+        self.running = self.running.saturating_sub(1);
+    }
+
+    // shrink halves the actions opening files running at once, the system
+    // ran out of them anyway (other processes have theirs too)
+    pub fn shrink(&mut self) {
+        let max_actions = (self.max_actions / 2).max(1);
+        if max_actions != self.max_actions {
+            log_warning!(
+                "- out of open files, running {max_actions} transfers at once from now on"
+            );
+        }
+        self.max_actions = max_actions;
+    }
+}
+
+// opens_files checks if the action opens files of a group as it runs, the
+// transfers and the tickets of them
+pub fn opens_files(action: &CommAction) -> bool {
+    matches!(
+        action,
+        CommAction::DownloadTarget(..)
+            | CommAction::RequestTarget(..)
+            | CommAction::RequestAppend(..)
+            | CommAction::AcceptOffer(..)
+    )
+}
+
+// is_out_of_files checks if the error comes from running out of open files,
+// of the process or of the system
+pub fn is_out_of_files(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        let is_io_limit = cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(is_open_files_limit);
+        let msg = cause.to_string();
+        is_io_limit || msg.contains("Too many open files")
+    })
+}
+
+// is_watch_limit checks if the watcher failed for being over the limits of
+// the system, of watches (inotify) or of open files (kqueue)
+pub fn is_watch_limit(e: &notify::Error) -> bool {
+    match &e.kind {
+        notify::ErrorKind::MaxFilesWatch => true,
+        notify::ErrorKind::Io(e) => is_open_files_limit(e),
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn is_open_files_limit(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+#[cfg(not(unix))]
+fn is_open_files_limit(_e: &io::Error) -> bool {
+    false
+}
+
+// raise_open_files_limit takes the soft limit of open files of the process
+// as high as it goes, retrieving it. none if it can't be known
+#[cfg(unix)]
+pub fn raise_open_files_limit() -> Option<u64> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    // SAFETY: limit is a valid rlimit to fill
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    let (current, max) = (limit.rlim_cur as u64, limit.rlim_max as u64);
+    let wanted = max.min(MAX_RAISED_OPEN_FILES);
+    if wanted <= current {
+        return Some(current);
+    }
+
+    limit.rlim_cur = wanted as libc::rlim_t;
+    // SAFETY: limit is a valid rlimit under the hard limit
+    match unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } {
+        0 => Some(wanted),
+        _ => Some(current),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn raise_open_files_limit() -> Option<u64> {
+    None
+}
+
+// get_max_open_files retrieves the open files the daemon keeps under, the
+// config can only lower the limit of the process
+pub fn get_max_open_files(configured: Option<u64>, process_limit: Option<u64>) -> u64 {
+    let process_limit = process_limit.unwrap_or(DEFAULT_MAX_OPEN_FILES);
+    configured.map_or(process_limit, |configured| configured.min(process_limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Result, anyhow};
+
+    #[test]
+    fn test_resources() -> Result<()> {
+        let download = CommAction::DownloadTarget(
+            "1234".into(),
+            "docs".into(),
+            "a.txt".into(),
+            "zed".into(),
+            "".into(),
+            1,
+            1,
+            0,
+        );
+        let hello = CommAction::Hello("1234".into(), "0.1.0".into(), "".into(), true);
+
+        let mut resources = Resources::new(RESERVED_OPEN_FILES + OPEN_FILES_PER_ACTION * 2);
+        assert_eq!(resources.get_max_actions(), 2);
+        for _ in 0..2 {
+            assert!(resources.has_room(&download));
+            assert!(resources.start(&download));
+        }
+
+        // only the actions opening files wait
+        assert!(!resources.has_room(&download));
+        assert!(resources.has_room(&hello));
+        assert!(!resources.start(&hello));
+        assert!(!resources.has_room(&download));
+        resources.end();
+        assert!(resources.has_room(&download));
+
+        resources.shrink();
+        assert_eq!(resources.get_max_actions(), 1);
+        resources.shrink();
+        assert_eq!(resources.get_max_actions(), 1);
+        assert_eq!(Resources::new(0).get_max_actions(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_max_open_files() -> Result<()> {
+        let test_values = [
+            // (configured, process limit, max open files)
+            (None, None, DEFAULT_MAX_OPEN_FILES),
+            (None, Some(4096), 4096),
+            (Some(512), Some(4096), 512),
+            (Some(8192), Some(4096), 4096),
+            (Some(512), None, 512),
+        ];
+        for spec in test_values {
+            assert_eq!(get_max_open_files(spec.0, spec.1), spec.2, "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_out_of_files() -> Result<()> {
+        assert!(is_out_of_files(&anyhow!(
+            "Too many open files (os error 24)"
+        )));
+        assert!(!is_out_of_files(&anyhow!("No space left on device")));
+        #[cfg(unix)]
+        {
+            let e = anyhow::Error::from(io::Error::from_raw_os_error(libc::EMFILE));
+            assert!(is_out_of_files(&e.context("unable to pull a.txt")));
+            let e = notify::Error::io(io::Error::from_raw_os_error(libc::EMFILE));
+            assert!(is_watch_limit(&e));
+        }
+        assert!(is_watch_limit(&notify::Error::new(
+            notify::ErrorKind::MaxFilesWatch
+        )));
+        assert!(!is_watch_limit(&notify::Error::path_not_found()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+use anyhow::{Result, bail};
+use iroh::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::key;
+
+pub const ROTATION_FILE_NAME: &str = "rotation.toml";
+
+// Rotation: a new node key waiting for the nodes to confirm they know it,
+// kept on the storage until it is applied to the config
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rotation {
+    pub public_key: String,
+    pub secret_key: [u8; 32],
+    pub signature: String,      // new public key signed by the current one
+    pub node_ids: Vec<String>,  // nodes that need to know the new key
+    pub confirmed: Vec<String>, // nodes that know the new key
+}
+
+impl Rotation {
+    // new generates the next key of the node, signed by the current one so
+    // the nodes can tell it really comes from us
+    pub fn new(current_secret_key: &[u8; 32], node_ids: Vec<String>) -> Self {
+        let secret_key = key::generate_node_secret_key();
+        let public_key = secret_key.public().to_string();
+        let signature = sign_rotation(current_secret_key, &public_key);
+
+        Self {
+            public_key,
+            secret_key: secret_key.to_bytes(),
+            signature,
+            node_ids,
+            confirmed: vec![],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: Rotation = toml::from_str(&content)?;
+        Ok(Some(parsed))
+    }
+
+    // save keeps the rotation where only the user can read it, it has the
+    // new key of the node
+    pub fn save(&self, path: &Path) -> Result<()> {
+        key::write_private(path, &toml::to_string(self)?)
+    }
+
+    // confirm notes the node knows the new key, returning if every node
+    // does already
+    pub fn confirm(&mut self, node_id: &str) -> bool {
+        let is_pending = self.node_ids.iter().any(|id| id == node_id);
+        if is_pending && !self.confirmed.iter().any(|id| id == node_id) {
+            self.confirmed.push(node_id.to_owned());
+        }
+
+        self.get_pending().is_empty()
+    }
+
+    // get_pending retrieves the nodes that don't know the new key yet
+    pub fn get_pending(&self) -> Vec<String> {
+        self.node_ids
+            .iter()
+            .filter(|id| !self.confirmed.contains(id))
+            .cloned()
+            .collect()
+    }
+}
+
+// get_rotation_payload is what gets signed, prefixed so a signature of
+// something else can't be taken as a rotation
+fn get_rotation_payload(new_node_id: &str) -> Vec<u8> {
+    format!("fsy-key-rotation;{new_node_id}").into_bytes()
+}
+
+fn sign_rotation(secret_key: &[u8; 32], new_node_id: &str) -> String {
+    let secret_key = SecretKey::from_bytes(secret_key);
+    let signature = secret_key.sign(&get_rotation_payload(new_node_id));
+    hex::encode(signature.to_bytes())
+}
+
+// verify_rotation checks the new node id was signed by the node
+pub fn verify_rotation(node_id: &str, new_node_id: &str, signature: &str) -> Result<()> {
+    let public_key = PublicKey::from_str(node_id)?;
+    if PublicKey::from_str(new_node_id).is_err() {
+        bail!("invalid new node id \"{new_node_id}\"");
+    }
+
+    let signature = hex::decode(signature)?;
+    let signature = signature.as_slice().try_into()?;
+    public_key.verify(&get_rotation_payload(new_node_id), &signature)?;
+    Ok(())
+}
+
+// replace_node_id swaps the node id on the config content, keeping the
+// rest of the file (comments, formatting) as it is
+pub fn replace_node_id(content: &str, node_id: &str, new_node_id: &str) -> String {
+    content.replace(&format!("\"{node_id}\""), &format!("\"{new_node_id}\""))
+}
+
+// replace_local_key swaps the key of the `[local]` section of the config
+// content, keeping the rest of the file as it is
+pub fn replace_local_key(content: &str, rotation: &Rotation) -> Result<String> {
+    let Some(local_start) = content.find("[local]") else {
+        bail!("no [local] section on the config");
+    };
+    let local_end = content[local_start + 1..]
+        .find("\n[")
+        .map(|i| local_start + 1 + i)
+        .unwrap_or(content.len());
+    let local = &content[local_start..local_end];
+
+    let mut public_key_line = None;
+    let mut secret_key_range = None;
+    let mut offset = 0;
+    for line in local.split_inclusive('\n') {
+        let key = line.split('=').next().unwrap_or_default().trim();
+        if key == "public_key" {
+            public_key_line = Some((offset, offset + line.trim_end().len()));
+        } else if key == "secret_key" {
+            // the array can span multiple lines
+            let end = local[offset..].find(']').map(|i| offset + i + 1);
+            secret_key_range = end.map(|end| (offset, end));
+        }
+        offset += line.len();
+    }
+    let (Some(public_key_line), Some(secret_key_range)) = (public_key_line, secret_key_range)
+    else {
+        bail!("no public_key or secret_key on the [local] section of the config");
+    };
+
+    let secret_key: Vec<String> = rotation.secret_key.iter().map(|b| b.to_string()).collect();
+    let mut replacements = [
+        (
+            public_key_line,
+            format!("public_key = \"{}\"", rotation.public_key),
+        ),
+        (
+            secret_key_range,
+            format!("secret_key = [{}]", secret_key.join(", ")),
+        ),
+    ];
+    // replace from the end so the ranges stay valid
+    replacements.sort_by_key(|r| std::cmp::Reverse(r.0.0));
+
+    let mut local = local.to_owned();
+    for ((start, end), replacement) in replacements {
+        local.replace_range(start..end, &replacement);
+    }
+
+    Ok(format!(
+        "{}{local}{}",
+        &content[..local_start],
+        &content[local_end..]
+    ))
+}
+
+// apply_rotation makes the new key the one of the node on the config
+pub fn apply_rotation(config_path: &Path, rotation: &Rotation) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let content = replace_local_key(&content, rotation)?;
+    fs::write(config_path, content)?;
+    Ok(())
+}
+
+// apply_node_rotation swaps the id of a node that rotated its key on the
+// config
+pub fn apply_node_rotation(config_path: &Path, node_id: &str, new_node_id: &str) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    fs::write(config_path, replace_node_id(&content, node_id, new_node_id))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_rotation() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("fsy_test_rotation");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("storage").join(ROTATION_FILE_NAME);
+        let rotation = Rotation::new(&key::generate_node_secret_key().to_bytes(), vec![]);
+
+        // one saved before with the default mode gets it too
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, "")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+        rotation.save(&path)?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        assert_eq!(Rotation::load(&path)?, Some(rotation.clone()));
+
+        fs::remove_dir_all(&dir)?;
+        let path = dir.join(ROTATION_FILE_NAME);
+        rotation.save(&path)?;
+        assert_eq!(fs::metadata(&dir)?.permissions().mode() & 0o777, 0o700);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_rotation() -> Result<()> {
+        let secret_key = key::generate_node_secret_key();
+        let node_id = secret_key.public().to_string();
+        let rotation = Rotation::new(&secret_key.to_bytes(), vec![]);
+        let other_id = key::generate_node_secret_key().public().to_string();
+
+        let test_values = [
+            // (node_id, new_node_id, signature, is_valid)
+            (&node_id, &rotation.public_key, rotation.signature.as_str(), true),
+            (&other_id, &rotation.public_key, rotation.signature.as_str(), false),
+            (&node_id, &other_id, rotation.signature.as_str(), false),
+            (&node_id, &rotation.public_key, "foo", false),
+            (&node_id, &rotation.public_key, "", false),
+        ];
+
+        for spec in test_values {
+            let res = verify_rotation(spec.0, spec.1, spec.2);
+            assert_eq!(res.is_ok(), spec.3, "{res:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_node_id() -> Result<()> {
+        let test_values = [
+            // (content, replaced)
+            (
+                "[[nodes]]\nname = \"foo\"\nid = \"abc\" # laptop\n",
+                "[[nodes]]\nname = \"foo\"\nid = \"zed\" # laptop\n",
+            ),
+            ("id = \"abcd\"\n", "id = \"abcd\"\n"),
+            ("", ""),
+        ];
+
+        for spec in test_values {
+            assert_eq!(replace_node_id(spec.0, "abc", "zed"), spec.1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_local_key() -> Result<()> {
+        let rotation = Rotation {
+            public_key: "zed".to_string(),
+            secret_key: [1; 32],
+            signature: "".to_string(),
+            node_ids: vec![],
+            confirmed: vec![],
+        };
+        let secret_key = format!("[{}]", vec!["1"; 32].join(", "));
+        let test_values = [
+            // (content, replaced)
+            (
+                "# me\n[local]\npublic_key = \"abc\"\nsecret_key = [\n    0,\n    2,\n]\nfoo = 1\n\n[[nodes]]\nid = \"abc\"\n".to_string(),
+                Some(format!(
+                    "# me\n[local]\npublic_key = \"zed\"\nsecret_key = {secret_key}\nfoo = 1\n\n[[nodes]]\nid = \"abc\"\n"
+                )),
+            ),
+            (
+                "[local]\nsecret_key = [0, 2]\npublic_key = \"abc\"".to_string(),
+                Some(format!("[local]\nsecret_key = {secret_key}\npublic_key = \"zed\"")),
+            ),
+            ("[local]\npublic_key = \"abc\"\n".to_string(), None),
+            ("public_key = \"abc\"\nsecret_key = []\n".to_string(), None),
+        ];
+
+        for spec in test_values {
+            let res = replace_local_key(&spec.0, &rotation);
+            match spec.1 {
+                Some(expected) => assert_eq!(res?, expected),
+                None => assert!(res.is_err()),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotation_confirm() -> Result<()> {
+        let node_ids = vec!["foo".to_string(), "bar".to_string()];
+        let mut rotation = Rotation::new(&[1; 32], node_ids.clone());
+        assert!(!rotation.confirm("foo"));
+        assert!(!rotation.confirm("foo"));
+        assert!(!rotation.confirm("zed"));
+        assert_eq!(rotation.get_pending(), vec!["bar".to_string()]);
+        assert!(rotation.confirm("bar"));
+        assert_eq!(rotation.confirmed, node_ids);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// longest relative path taken from another node, the common limit of
+// the systems for a whole path
+pub const MAX_RELATIVE_PATH_BYTES: usize = 4096;
+
+// paths longer than this need the `\\?\` prefix on windows
+const WINDOWS_MAX_PATH: usize = 260;
+const WINDOWS_LONG_PATH_PREFIX: &str = r"\\?\";
+const WINDOWS_INVALID_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// WindowsPathPolicy: what to do with paths windows can't handle
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum WindowsPathPolicy {
+    #[default]
+    #[serde(rename = "rename")]
+    Rename, // change the name into something valid
+    #[serde(rename = "skip")]
+    Skip, // don't pull the file
+    #[serde(rename = "fail")]
+    Fail, // error out, so it shows on the status
+}
+
+// sanitize_windows_relative_path makes a relative path valid on windows
+// following the policy. a `None` means the file should be skipped
+pub fn sanitize_windows_relative_path(
+    relative_path: &str,
+    policy: &WindowsPathPolicy,
+) -> Result<Option<String>> {
+    let components: Vec<&str> = relative_path.split(['/', '\\']).collect();
+    let sanitized: Vec<String> = components
+        .iter()
+        .map(|c| sanitize_windows_component(c))
+        .collect();
+
+    if sanitized.iter().zip(components.iter()).all(|(s, c)| s == c) {
+        return Ok(Some(components.join("\\")));
+    }
+
+    match policy {
+        WindowsPathPolicy::Rename => Ok(Some(sanitized.join("\\"))),
+        WindowsPathPolicy::Skip => Ok(None),
+        WindowsPathPolicy::Fail => bail!("\"{relative_path}\" is not a valid windows path"),
+    }
+}
+
+// get_windows_pulled_path retrieves the path a file of the pusher has once
+// pulled on windows, with `/` as the manifests have it. a path left out
+// (or failing) stays as it is, nothing was written for it
+pub fn get_windows_pulled_path(relative_path: &str, policy: &WindowsPathPolicy) -> String {
+    match sanitize_windows_relative_path(relative_path, policy) {
+        Ok(Some(sanitized)) => sanitized.replace('\\', "/"),
+        _ => relative_path.to_owned(),
+    }
+}
+
+fn sanitize_windows_component(component: &str) -> String {
+    // an empty component is the root of the target (single file groups)
+    if component.is_empty() {
+        return "".to_owned();
+    }
+
+    let mut sanitized: String = component
+        .chars()
+        .map(|c| {
+            if WINDOWS_INVALID_CHARS.contains(&c) || (c as u32) < 32 {
+                return '_';
+            }
+            c
+        })
+        .collect();
+
+    // windows drops trailing dots and spaces, making a different name
+    let trimmed_len = sanitized.trim_end_matches(['.', ' ']).len();
+    if trimmed_len != sanitized.len() {
+        sanitized.truncate(trimmed_len);
+        sanitized.push('_');
+    }
+
+    // reserved names are reserved with any extension too (`con.txt`)
+    let (stem, ext) = match sanitized.split_once('.') {
+        Some((stem, ext)) => (stem.to_owned(), Some(ext.to_owned())),
+        None => (sanitized.clone(), None),
+    };
+    if WINDOWS_RESERVED_NAMES.contains(&stem.to_uppercase().as_str()) {
+        sanitized = match ext {
+            Some(ext) => format!("{stem}_.{ext}"),
+            None => format!("{stem}_"),
+        };
+    }
+
+    sanitized
+}
+
+// get_windows_long_path prefixes absolute paths over the windows limit so
+// they can still be written
+pub fn get_windows_long_path(path: &str) -> String {
+    if path.len() < WINDOWS_MAX_PATH || path.starts_with(WINDOWS_LONG_PATH_PREFIX) {
+        return path.to_owned();
+    }
+
+    // only drive paths (`C:\...`) can be prefixed as is
+    let bytes = path.as_bytes();
+    if bytes.len() < 3 || !bytes[0].is_ascii_alphabetic() || bytes[1] != b':' {
+        return path.to_owned();
+    }
+
+    format!("{WINDOWS_LONG_PATH_PREFIX}{}", path.replace('/', "\\"))
+}
+
+// is_safe_relative_path checks that a relative path coming from another
+// node stays inside of the group once joined to its path: not absolute (on
+// any system), no `.` or `..` and no null bytes. empty is the group itself
+pub fn is_safe_relative_path(relative_path: &str) -> bool {
+    if relative_path.len() > MAX_RELATIVE_PATH_BYTES || relative_path.contains('\0') {
+        return false;
+    }
+    if relative_path.starts_with(['/', '\\']) {
+        return false;
+    }
+
+    // drive paths (`C:foo`, `C:\foo`) replace the base on windows
+    let bytes = relative_path.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return false;
+    }
+
+    relative_path.split(['/', '\\']).all(|c| c != "." && c != "..")
+}
+
+// get_contained_path joins the relative path of another node to the group
+// path, making sure the file lands inside of it. the folder it goes to is
+// resolved (symlinks included) so a link to somewhere else isn't followed
+pub fn get_contained_path(base_path: &Path, relative_path: &str) -> Result<PathBuf> {
+    if !is_safe_relative_path(relative_path) {
+        bail!("{relative_path} is outside of the group");
+    }
+
+    // a single file group is the file itself
+    let path = base_path.join(relative_path);
+    if path == base_path {
+        return Ok(path);
+    }
+
+    let Some(parent) = path.parent() else {
+        bail!("{relative_path} is outside of the group");
+    };
+    if !resolve_path(parent)?.starts_with(resolve_path(base_path)?) {
+        bail!("{relative_path} is outside of the group");
+    }
+
+    Ok(path)
+}
+
+// resolve_path retrieves the real path, only resolving the part of it that
+// exists already, the rest is taken as is
+fn resolve_path(path: &Path) -> Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = vec![];
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return Ok(missing.iter().rev().fold(resolved, |p, c| p.join(c)));
+        }
+
+        let (Some(name), Some(parent)) = (existing.file_name(), existing.parent()) else {
+            bail!("unable to resolve {}", path.display());
+        };
+        missing.push(name);
+        existing = parent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_is_safe_relative_path() -> Result<()> {
+        let long_path = "a/".repeat(MAX_RELATIVE_PATH_BYTES);
+        let test_values = [
+            // (relative_path, is_safe)
+            ("", true),
+            ("foo.txt", true),
+            ("foo/bar/zed.txt", true),
+            ("foo/..bar/zed..txt", true),
+            ("./foo.txt", false),
+            ("./.fsy/x", false),
+            ("foo/./bar.txt", false),
+            ("../../etc/passwd", false),
+            ("foo/../../bar", false),
+            ("foo\\..\\..\\bar", false),
+            ("..", false),
+            ("/etc/passwd", false),
+            ("\\\\server\\share", false),
+            ("C:\\Windows", false),
+            ("c:foo", false),
+            ("foo\0bar", false),
+            (long_path.as_str(), false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(is_safe_relative_path(spec.0), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_contained_path() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_contained_path");
+        let _ = std::fs::remove_dir_all(&dir);
+        let base_path = dir.join("group");
+        std::fs::create_dir_all(base_path.join("docs"))?;
+        std::fs::create_dir_all(dir.join("outside"))?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("outside"), base_path.join("link"))?;
+
+        let test_values = [
+            // (relative_path, is_contained)
+            ("", true),
+            ("a.txt", true),
+            ("docs/a.txt", true),
+            ("new/folder/a.txt", true),
+            ("../outside/a.txt", false),
+            ("docs/../../outside/a.txt", false),
+            ("/etc/passwd", false),
+            ("link/a.txt", cfg!(not(unix))),
+            ("link/new/a.txt", cfg!(not(unix))),
+        ];
+
+        for spec in test_values {
+            let res = get_contained_path(&base_path, spec.0);
+            assert_eq!(res.is_ok(), spec.1, "{}", spec.0);
+            if let Ok(path) = res {
+                assert_eq!(path, base_path.join(spec.0));
+            }
+        }
+
+        // the group doesn't need to be there yet
+        assert!(get_contained_path(&dir.join("new_group"), "a/b.txt").is_ok());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitize_windows_relative_path_rename() -> Result<()> {
+        let test_values = [
+            // (relative_path, expected)
+            ("", ""),
+            ("foo.txt", "foo.txt"),
+            ("foo/bar.txt", "foo\\bar.txt"),
+            ("CON", "CON_"),
+            ("con.txt", "con_.txt"),
+            ("foo/aux/bar", "foo\\aux_\\bar"),
+            ("console.txt", "console.txt"),
+            ("foo.", "foo_"),
+            ("foo. .", "foo_"),
+            ("what?.txt", "what_.txt"),
+            ("a<b>c:d\"e|f*g", "a_b_c_d_e_f_g"),
+            ("tab\there", "tab_here"),
+        ];
+
+        for spec in test_values {
+            let res = sanitize_windows_relative_path(spec.0, &WindowsPathPolicy::Rename)?;
+            assert_eq!(res, Some(spec.1.to_string()), "{}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitize_windows_relative_path_policies() -> Result<()> {
+        let res = sanitize_windows_relative_path("foo/bar.txt", &WindowsPathPolicy::Skip)?;
+        assert_eq!(res, Some("foo\\bar.txt".to_string()));
+        let res = sanitize_windows_relative_path("foo/con", &WindowsPathPolicy::Skip)?;
+        assert_eq!(res, None);
+
+        let res = sanitize_windows_relative_path("foo/bar.txt", &WindowsPathPolicy::Fail)?;
+        assert_eq!(res, Some("foo\\bar.txt".to_string()));
+        assert!(sanitize_windows_relative_path("foo/con", &WindowsPathPolicy::Fail).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_windows_pulled_path() -> Result<()> {
+        let test_values = [
+            // (relative_path, policy, expected)
+            ("foo/bar.txt", WindowsPathPolicy::Rename, "foo/bar.txt"),
+            ("foo/what?.txt", WindowsPathPolicy::Rename, "foo/what_.txt"),
+            ("foo/what?.txt", WindowsPathPolicy::Skip, "foo/what?.txt"),
+            ("foo/what?.txt", WindowsPathPolicy::Fail, "foo/what?.txt"),
+        ];
+
+        for spec in test_values {
+            assert_eq!(get_windows_pulled_path(spec.0, &spec.1), spec.2, "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_windows_long_path() -> Result<()> {
+        let long_dir = "a".repeat(WINDOWS_MAX_PATH);
+        let test_values = [
+            // (path, expected)
+            (
+                "C:\\foo\\bar.txt".to_string(),
+                "C:\\foo\\bar.txt".to_string(),
+            ),
+            (
+                format!("C:\\{long_dir}\\bar.txt"),
+                format!("\\\\?\\C:\\{long_dir}\\bar.txt"),
+            ),
+            (
+                format!("C:/{long_dir}/bar.txt"),
+                format!("\\\\?\\C:\\{long_dir}\\bar.txt"),
+            ),
+            (
+                format!("\\\\?\\C:\\{long_dir}"),
+                format!("\\\\?\\C:\\{long_dir}"),
+            ),
+            (
+                format!("{long_dir}\\bar.txt"),
+                format!("{long_dir}\\bar.txt"),
+            ),
+        ];
+
+        for spec in test_values {
+            assert_eq!(get_windows_long_path(&spec.0), spec.1);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::reserved;
+
+// how many files are scanned between progress reports
+const PROGRESS_EVERY_FILES: u64 = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ScanProgress {
+    pub scanned: u64,
+    pub total: u64,
+    pub current_path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ScanSummary {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+// walk_files calls f with every file under the base, the base itself when
+// it is a single file
+fn walk_files(base_path: &Path, mut f: impl FnMut(&Path, &fs::Metadata)) -> Result<()> {
+    let meta = fs::metadata(base_path)?;
+    if !meta.is_dir() {
+        f(base_path, &meta);
+        return Ok(());
+    }
+
+    let mut dirs = vec![PathBuf::from(base_path)];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if dir == base_path && entry.file_name() == reserved::FSY_DIR_NAME {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+
+            f(&path, &entry.metadata()?);
+        }
+    }
+
+    Ok(())
+}
+
+// get_throttle_delay retrieves how long to wait so the files scanned since
+// the start don't go over the files per second
+fn get_throttle_delay(scanned: u64, elapsed: Duration, files_per_sec: u64) -> Duration {
+    if files_per_sec == 0 {
+        return Duration::ZERO;
+    }
+
+    let expected = Duration::from_secs_f64(scanned as f64 / files_per_sec as f64);
+    expected.saturating_sub(elapsed)
+}
+
+// scan_files goes through the files of the base reporting the progress on
+// the way. the files are counted first so the total is known, a cheap pass
+// compared to reading the metadata of each. files_per_sec keeps the disk
+// from being pegged on huge folders
+pub fn scan_files(
+    base_path: &Path,
+    files_per_sec: Option<u64>,
+    mut on_progress: impl FnMut(&ScanProgress),
+) -> Result<ScanSummary> {
+    let mut total = 0;
+    walk_files(base_path, |_, _| total += 1)?;
+
+    let mut progress = ScanProgress {
+        total,
+        ..Default::default()
+    };
+    on_progress(&progress);
+
+    let start = Instant::now();
+    let mut summary = ScanSummary::default();
+    walk_files(base_path, |path, meta| {
+        summary.files += 1;
+        summary.bytes += meta.len();
+
+        progress.scanned += 1;
+        if progress.scanned.is_multiple_of(PROGRESS_EVERY_FILES) {
+            progress.current_path = path.to_string_lossy().to_string();
+            on_progress(&progress);
+        }
+
+        if let Some(files_per_sec) = files_per_sec {
+            thread::sleep(get_throttle_delay(progress.scanned, start.elapsed(), files_per_sec));
+        }
+    })?;
+
+    // the folder may have changed since it was counted
+    progress.total = progress.scanned;
+    progress.current_path = "".to_owned();
+    on_progress(&progress);
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_throttle_delay() -> Result<()> {
+        let test_values = [
+            // (scanned, elapsed_millisecs, files_per_sec, expected_millisecs)
+            (10, 0, 0, 0),
+            (10, 0, 10, 1000),
+            (10, 400, 10, 600),
+            (10, 2000, 10, 0),
+            (1, 0, 1000, 1),
+        ];
+
+        for spec in test_values {
+            let res = get_throttle_delay(spec.0, Duration::from_millis(spec.1), spec.2);
+            assert_eq!(res, Duration::from_millis(spec.3), "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_files() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_scan_files");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("foo/bar"))?;
+        fs::write(dir.join("a.txt"), b"aa")?;
+        fs::write(dir.join("foo/b.txt"), b"bbb")?;
+        fs::write(dir.join("foo/bar/c.txt"), b"c")?;
+        fs::create_dir_all(dir.join(reserved::FSY_DIR_NAME))?;
+        fs::write(dir.join(reserved::FSY_DIR_NAME).join("d.txt"), b"dddd")?;
+
+        let mut reports = vec![];
+        let summary = scan_files(&dir, None, |p| reports.push(p.clone()))?;
+        assert_eq!(summary, ScanSummary { files: 3, bytes: 6 });
+        assert_eq!(reports.first().map(|p| (p.scanned, p.total)), Some((0, 3)));
+        assert_eq!(reports.last().map(|p| (p.scanned, p.total)), Some((3, 3)));
+
+        // a single file target
+        let summary = scan_files(&dir.join("a.txt"), Some(1000), |_| {})?;
+        assert_eq!(summary, ScanSummary { files: 1, bytes: 2 });
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::{conflict, manifest, pulled, target};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SeedSummary {
+    pub group_name: String,
+    pub files: u64,           // files recorded as pulled
+    pub bytes: u64,           // size of the files recorded
+    pub copied: u64,          // files copied into the target
+    pub skipped: Vec<String>, // files left alone, the target has another version
+}
+
+impl fmt::Display for SeedSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} files seeded ({} bytes), {} copied into the target",
+            self.group_name, self.files, self.bytes, self.copied
+        )?;
+        for relative_path in &self.skipped {
+            writeln!(
+                f,
+                "- skipped {relative_path}, the target has another version"
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// hash_file retrieves the hash of the content of the file, the same the
+// tickets of its blob have
+pub fn hash_file(file_path: &Path) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(file_path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// seed_group records the files of a copy made out of band (a disk carried
+// over) as pulled, so the network sync only transfers what differs. a copy
+// somewhere else than the target is copied into it first, files the target
+// has a different version of are left alone
+pub fn seed_group(
+    storage_path: &Path,
+    group: &target::TargetGroup,
+    source_path: &Path,
+) -> Result<SeedSummary> {
+    if !source_path.is_dir() {
+        bail!("{} is not a folder", source_path.display());
+    }
+    if !group.paths.is_empty() {
+        bail!(
+            "group \"{}\" spans many paths, it can't be seeded",
+            group.name
+        );
+    }
+
+    let group_path = group.get_path();
+    let base_path = Path::new(&group_path);
+    let is_target = fs::canonicalize(source_path).ok() == fs::canonicalize(base_path).ok();
+    let pulled_path = storage_path.join(pulled::PULLED_FILE_NAME);
+    let mut pulled = pulled::Pulled::load(&pulled_path)?;
+    let mut summary = SeedSummary {
+        group_name: group.name.to_string(),
+        ..Default::default()
+    };
+
+    for relative_path in manifest::list_files(source_path)? {
+        let source_file_path = source_path.join(&relative_path);
+        let file_path = base_path.join(&relative_path);
+        let hash = hash_file(&source_file_path)?;
+
+        if !is_target {
+            if file_path.exists() {
+                if hash_file(&file_path)? != hash {
+                    summary.skipped.push(relative_path);
+                    continue;
+                }
+            } else {
+                if let Some(parent) = file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&source_file_path, &file_path)?;
+                summary.copied += 1;
+            }
+        }
+
+        // what is seeded is synced, changes after it are local ones
+        if group.conflict == conflict::ConflictPolicy::KeepBoth {
+            conflict::mark_synced(base_path, &file_path)?;
+        }
+
+        pulled.set_pulled(&group.name, &relative_path, &hash, &file_path, 0, "")?;
+        summary.files += 1;
+        summary.bytes += fs::metadata(&file_path)?.len();
+    }
+
+    fs::create_dir_all(storage_path)?;
+    pulled.save(&pulled_path)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_hash_file() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_seed_hash");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let file_path = dir.join("foo.txt");
+        fs::write(&file_path, "foo")?;
+
+        // same as the hash of the blob of the content
+        assert_eq!(
+            hash_file(&file_path)?,
+            iroh_blobs::Hash::new("foo").to_string()
+        );
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_seed_group() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_seed_group");
+        let _ = fs::remove_dir_all(&dir);
+        let (source_path, base_path) = (dir.join("disk"), dir.join("docs"));
+        fs::create_dir_all(source_path.join("a"))?;
+        fs::create_dir_all(&base_path)?;
+        fs::write(source_path.join("a/foo.txt"), "foo")?;
+        fs::write(source_path.join("bar.txt"), "bar")?;
+        fs::write(source_path.join("zed.txt"), "zed")?;
+        fs::write(base_path.join("bar.txt"), "bar")?;
+        fs::write(base_path.join("zed.txt"), "local zed")?;
+
+        let storage_path = dir.join("storage");
+        let group = target::TargetGroup {
+            name: "docs".into(),
+            path: base_path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let summary = seed_group(&storage_path, &group, &source_path)?;
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.copied, 1);
+        assert_eq!(summary.skipped, vec!["zed.txt".to_string()]);
+        assert_eq!(fs::read_to_string(base_path.join("a/foo.txt"))?, "foo");
+
+        let test_values = [
+            // (relative_path, content, is_pulled)
+            ("a/foo.txt", "foo", true),
+            ("bar.txt", "bar", true),
+            ("zed.txt", "zed", false),
+            ("zed.txt", "local zed", false),
+        ];
+
+        let pulled = pulled::Pulled::load(&storage_path.join(pulled::PULLED_FILE_NAME))?;
+        for spec in test_values {
+            let hash = iroh_blobs::Hash::new(spec.1).to_string();
+            let file_path = base_path.join(spec.0);
+            assert_eq!(pulled.is_pulled("docs", spec.0, &hash, &file_path), spec.2);
+        }
+
+        // seeding the target itself records what is there
+        let summary = seed_group(&storage_path, &group, &base_path)?;
+        assert_eq!((summary.files, summary.copied), (3, 0));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
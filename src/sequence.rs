@@ -0,0 +1,256 @@
+use chrono::Utc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::action::CommAction;
+use crate::logs::log;
+
+// nodes advertising it on their hello get the messages of the groups
+// sequenced, the others get them as they are
+pub const ORDERED_FEATURE: &str = "ordered";
+
+// a missing message (lost on a failed send) is waited for this long before
+// moving on without it
+pub const GAP_TIMEOUT_SECS: u64 = 10;
+
+// more messages than this waiting on a missing one moves on without it
+const MAX_PENDING: usize = 100;
+
+// session of this run, a node restarting starts its sequences over
+static SESSION: LazyLock<i64> = LazyLock::new(|| Utc::now().timestamp_millis());
+
+// last sequence sent to each (node id, group)
+static SENT: LazyLock<Mutex<HashMap<(String, String), u64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// get_next retrieves the session and the next sequence of the messages to
+// the node about the group
+pub fn get_next(node_id: &str, group_name: &str) -> (i64, u64) {
+    let Ok(mut sent) = SENT.lock() else {
+        return (*SESSION, 0);
+    };
+
+    let seq = sent
+        .entry((node_id.to_owned(), group_name.to_owned()))
+        .or_default();
+    *seq += 1;
+    (*SESSION, *seq)
+}
+
+// is_current_session tells if the session is of this run, a message of a
+// previous one (kept on the outbox) takes a new place on the sequence
+pub fn is_current_session(session: i64) -> bool {
+    session == *SESSION
+}
+
+// Stream: the messages received from a node about a group, on one of its
+// sessions
+#[derive(Debug)]
+struct Stream {
+    session: i64,
+    next: u64,                          // sequence expected next
+    pending: BTreeMap<u64, CommAction>, // arrived ahead of the expected one
+    waiting_since: Option<Instant>,     // since when pending waits
+}
+
+impl Stream {
+    // NOTE: the sequence starts on the first message received, the node may
+    //       have sent others before this one was listening (it restarted,
+    //       the node came back on the middle of a session)
+    fn new(session: i64, first_seq: u64) -> Self {
+        Self {
+            session,
+            next: first_seq,
+            pending: BTreeMap::new(),
+            waiting_since: None,
+        }
+    }
+
+    // take_ready takes the pending actions that are next on the sequence
+    fn take_ready(&mut self, now: Instant) -> Vec<CommAction> {
+        let mut ready = vec![];
+        while let Some(action) = self.pending.remove(&self.next) {
+            ready.push(action);
+            self.next += 1;
+        }
+
+        self.waiting_since = match self.pending.is_empty() {
+            true => None,
+            false if !ready.is_empty() => Some(now),
+            false => self.waiting_since.or(Some(now)),
+        };
+        ready
+    }
+
+    // skip_gap gives up on the missing messages, moving on to the first
+    // pending one
+    fn skip_gap(&mut self, now: Instant) -> Vec<CommAction> {
+        if let Some(first) = self.pending.keys().next() {
+            self.next = *first;
+        }
+        self.take_ready(now)
+    }
+}
+
+// Reorder: puts the messages received from each (node, group) back on the
+// order they were sent, a change can't be overtaken by an older one
+#[derive(Debug, Default)]
+pub struct Reorder {
+    streams: HashMap<(String, String), Stream>,
+}
+
+impl Reorder {
+    // receive takes the action on its place of the sequence, returning the
+    // actions that can be performed now, in order
+    pub fn receive(
+        &mut self,
+        node_id: &str,
+        group_name: &str,
+        session: i64,
+        seq: u64,
+        action: CommAction,
+        now: Instant,
+    ) -> Vec<CommAction> {
+        let stream = self
+            .streams
+            .entry((node_id.to_owned(), group_name.to_owned()))
+            .or_insert_with(|| Stream::new(session, seq));
+
+        // the node restarted, what was left of its previous run goes first
+        let mut ready = vec![];
+        if session > stream.session {
+            let previous = std::mem::replace(stream, Stream::new(session, seq));
+            ready.extend(previous.pending.into_values());
+        }
+
+        if session < stream.session || seq < stream.next {
+            log!("- dropping out of order message from {node_id} on {group_name} ({seq})");
+            return ready;
+        }
+
+        stream.pending.insert(seq, action);
+        ready.extend(stream.take_ready(now));
+        if stream.pending.len() > MAX_PENDING {
+            ready.extend(stream.skip_gap(now));
+        }
+        ready
+    }
+
+    // take_expired moves on from the messages that never arrived, returning
+    // the actions that were waiting on them
+    pub fn take_expired(&mut self, now: Instant) -> Vec<CommAction> {
+        let timeout = Duration::from_secs(GAP_TIMEOUT_SECS);
+        self.streams
+            .values_mut()
+            .filter(|s| s.waiting_since.is_some_and(|since| now - since >= timeout))
+            .flat_map(|s| s.skip_gap(now))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_action(seq: u64) -> CommAction {
+        CommAction::TargetHasChanged("foo".into(), "bar".into(), seq.to_string().into())
+    }
+
+    fn get_seqs(actions: &[CommAction]) -> Vec<u64> {
+        actions
+            .iter()
+            .filter_map(|a| match a {
+                CommAction::TargetHasChanged(_, _, seq) => seq.parse().ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_is_current_session() -> Result<()> {
+        let (session, _) = get_next("foo", "bar");
+        assert!(is_current_session(session));
+        assert!(!is_current_session(session - 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive() -> Result<()> {
+        let test_values = [
+            // (received (session, seq), delivered seqs)
+            (vec![(1, 1), (1, 2), (1, 3)], vec![1, 2, 3]),
+            (vec![(1, 1), (1, 3), (1, 2)], vec![1, 2, 3]),
+            (vec![(1, 1), (1, 3), (1, 4)], vec![1]),
+            (vec![(1, 1), (1, 1)], vec![1]),
+            (vec![(1, 1), (1, 3), (1, 2), (1, 2)], vec![1, 2, 3]),
+            // the first message received starts the sequence, the ones before
+            // it are late
+            (vec![(1, 5), (1, 6)], vec![5, 6]),
+            (vec![(1, 5), (1, 7), (1, 6)], vec![5, 6, 7]),
+            (vec![(1, 2), (1, 1), (1, 3)], vec![2, 3]),
+            // a restart of the node starts over
+            (vec![(1, 1), (1, 2), (2, 1)], vec![1, 2, 1]),
+            (vec![(1, 1), (1, 3), (2, 1)], vec![1, 3, 1]),
+            (vec![(1, 1), (2, 4), (2, 5)], vec![1, 4, 5]),
+            (vec![(2, 1), (1, 2)], vec![1]),
+        ];
+
+        for spec in test_values {
+            let mut reorder = Reorder::default();
+            let now = Instant::now();
+            let mut delivered = vec![];
+            for (session, seq) in spec.0 {
+                let actions = reorder.receive("foo", "bar", session, seq, get_action(seq), now);
+                delivered.extend(get_seqs(&actions));
+            }
+            assert_eq!(delivered, spec.1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_expired() -> Result<()> {
+        let mut reorder = Reorder::default();
+        let now = Instant::now();
+        let actions = reorder.receive("foo", "bar", 1, 1, get_action(1), now);
+        assert_eq!(get_seqs(&actions), vec![1]);
+        assert!(
+            reorder
+                .receive("foo", "bar", 1, 3, get_action(3), now)
+                .is_empty()
+        );
+        let actions = reorder.receive("foo", "zed", 1, 1, get_action(1), now);
+        assert_eq!(get_seqs(&actions), vec![1]);
+        assert!(reorder.take_expired(now).is_empty());
+
+        // the missing 2 is given up on, a late one is dropped
+        let later = now + Duration::from_secs(GAP_TIMEOUT_SECS);
+        assert_eq!(get_seqs(&reorder.take_expired(later)), vec![3]);
+        assert!(
+            reorder
+                .receive("foo", "bar", 1, 2, get_action(2), later)
+                .is_empty()
+        );
+        assert_eq!(
+            get_seqs(&reorder.receive("foo", "bar", 1, 4, get_action(4), later)),
+            vec![4]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_next() -> Result<()> {
+        let (session, first) = get_next("foo", "test_get_next");
+        assert_eq!(first, 1);
+        assert_eq!(get_next("foo", "test_get_next"), (session, 2));
+        assert_eq!(get_next("bar", "test_get_next"), (session, 1));
+
+        Ok(())
+    }
+}
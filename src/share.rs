@@ -0,0 +1,347 @@
+use anyhow::{Result, bail};
+use data_encoding::BASE32_NOPAD;
+use iroh::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::target::{self, TargetMode};
+
+// size of a node id on the token, its public key
+const NODE_ID_BYTES: usize = 32;
+
+// where an accepted group goes when no path is given, under the home
+const DEFAULT_GROUPS_DIR: &str = "~/fsy";
+
+// ShareToken: what a node needs to pull a group, copied over as a single
+// token (`fsy share`, `fsy accept`) instead of setting each part by hand
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShareToken {
+    pub group_name: String,
+    pub node_id: String, // public key of the node sharing the group
+}
+
+impl ShareToken {
+    // new retrieves the token of the group, it needs to push for others to
+    // pull from it
+    pub fn new(config: &Config, group_name: &str) -> Result<Self> {
+        if target::get_push_group_with_name(&config.target_groups, group_name).is_none() {
+            bail!("no group \"{group_name}\" pushing, only groups that push can be shared");
+        }
+
+        Ok(Self {
+            group_name: group_name.to_owned(),
+            node_id: config.local.public_key.clone(),
+        })
+    }
+
+    // encode retrieves the token as text, the key of the node followed by
+    // the name of the group in base32 so it stays short and easy to copy
+    pub fn encode(&self) -> Result<String> {
+        let mut raw = PublicKey::from_str(&self.node_id)?.as_bytes().to_vec();
+        raw.extend_from_slice(self.group_name.as_bytes());
+        Ok(BASE32_NOPAD.encode(&raw).to_lowercase())
+    }
+
+    pub fn decode(token: &str) -> Result<Self> {
+        let Ok(raw) = BASE32_NOPAD.decode(token.trim().to_uppercase().as_bytes()) else {
+            bail!("invalid token, copy it whole as `fsy share` outputs it");
+        };
+        if raw.len() <= NODE_ID_BYTES {
+            bail!("invalid token, copy it whole as `fsy share` outputs it");
+        }
+
+        let (node_id, group_name) = raw.split_at(NODE_ID_BYTES);
+        let Ok(node_id) = PublicKey::try_from(node_id) else {
+            bail!("invalid node id on the token");
+        };
+        let Ok(group_name) = String::from_utf8(group_name.to_vec()) else {
+            bail!("invalid group on the token");
+        };
+        // NOTE: the name becomes a folder by default, it can't point out of it
+        if !is_valid_group_name(&group_name) {
+            bail!("invalid group \"{group_name}\" on the token");
+        }
+
+        Ok(Self {
+            group_name,
+            node_id: node_id.to_string(),
+        })
+    }
+}
+
+// is_valid_group_name tells if the name is a single folder, no separators,
+// `..` nor absolute paths
+fn is_valid_group_name(group_name: &str) -> bool {
+    let mut components = Path::new(group_name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(name)), None) if name == group_name
+    )
+}
+
+// NodesTable, GroupsTable: what is appended to the config, serialized by
+// toml so any name or path is quoted as it should
+#[derive(Serialize)]
+struct NodesTable<'a> {
+    nodes: Vec<NodeTable<'a>>,
+}
+
+#[derive(Serialize)]
+struct NodeTable<'a> {
+    name: &'a str,
+    id: &'a str,
+}
+
+#[derive(Serialize)]
+struct GroupsTable<'a> {
+    target_groups: Vec<GroupTable<'a>>,
+}
+
+#[derive(Serialize)]
+struct GroupTable<'a> {
+    name: &'a str,
+    path: &'a str,
+    targets: Vec<TargetTable<'a>>,
+}
+
+#[derive(Serialize)]
+struct TargetTable<'a> {
+    mode: TargetMode,
+    node_name: &'a str,
+}
+
+// get_node_table retrieves the node as a table to append to the config
+pub fn get_node_table(name: &str, id: &str) -> Result<String> {
+    let nodes = NodesTable {
+        nodes: vec![NodeTable { name, id }],
+    };
+    Ok(format!("\n{}", toml::to_string(&nodes)?))
+}
+
+// Accepted: the pull group set up out of a token
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Accepted {
+    pub group_name: String,
+    pub path: String,
+    pub node_name: String,
+    pub node_id: String,
+    pub own_node_id: String, // the sharing node needs it as a target of the group
+}
+
+impl fmt::Display for Accepted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "group \"{}\" pulls from node \"{}\" into {}, restart the daemon to start",
+            self.group_name, self.node_name, self.path
+        )?;
+        writeln!(
+            f,
+            "the node sharing it pushes once it has this node as a target of the group, this node id is {}",
+            self.own_node_id
+        )
+    }
+}
+
+// get_node_name retrieves the name of the node on the config, a new one
+// based on its id if it isn't there yet, telling if it is new
+fn get_node_name(config: &Config, node_id: &str) -> (String, bool) {
+    if let Some(node) = config.nodes.iter().find(|n| n.id == node_id) {
+        return (node.name.clone(), false);
+    }
+
+    let short_id = &node_id[..node_id.len().min(8)];
+    (format!("node-{short_id}"), true)
+}
+
+// remove_empty_lists retrieves the content without the empty lists of the
+// keys. a new config has them inline and tables can't be added to them
+pub fn remove_empty_lists(content: &str, keys: &[&str]) -> String {
+    content
+        .lines()
+        .filter(|line| {
+            let line = line.replace(' ', "");
+            !keys.iter().any(|key| line == format!("{key}=[]"))
+        })
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+// add_accepted adds the group (and the node, if new) to the content of the
+// config. the text is appended so the comments of the user are kept
+pub fn add_accepted(
+    content: &str,
+    config: &Config,
+    token: &ShareToken,
+    path: &str,
+) -> Result<(String, Accepted)> {
+    if config
+        .target_groups
+        .iter()
+        .any(|g| g.name == token.group_name)
+    {
+        bail!("there is a group \"{}\" already", token.group_name);
+    }
+    if token.node_id == config.local.public_key {
+        bail!("the token is of this node, accept it on the node that pulls");
+    }
+
+    let mut content = remove_empty_lists(content, &["nodes", "target_groups"]);
+
+    let (node_name, is_new_node) = get_node_name(config, &token.node_id);
+    if is_new_node {
+        content.push_str(&get_node_table(&node_name, &token.node_id)?);
+    }
+    let groups = GroupsTable {
+        target_groups: vec![GroupTable {
+            name: &token.group_name,
+            path,
+            targets: vec![TargetTable {
+                mode: TargetMode::Pull,
+                node_name: &node_name,
+            }],
+        }],
+    };
+    content.push_str(&format!("\n{}", toml::to_string(&groups)?));
+
+    // make sure the config still loads before it is written
+    if let Err(e) = toml::from_str::<Config>(&content) {
+        bail!("unable to add the group to the config: {e}");
+    }
+
+    let accepted = Accepted {
+        group_name: token.group_name.clone(),
+        path: path.to_owned(),
+        node_name,
+        node_id: token.node_id.clone(),
+        own_node_id: config.local.public_key.clone(),
+    };
+    Ok((content, accepted))
+}
+
+// accept_token sets up the config to pull the group of the token, into the
+// path or a folder named as the group by default
+pub fn accept_token(config: &Config, token: &str, path: Option<&str>) -> Result<Accepted> {
+    let token = ShareToken::decode(token)?;
+    let path = match path {
+        Some(path) => path.to_owned(),
+        None => format!("{DEFAULT_GROUPS_DIR}/{}", token.group_name),
+    };
+
+    let config_path = Path::new(&config.config_path);
+    let content = fs::read_to_string(config_path)?;
+    let (content, accepted) = add_accepted(&content, config, &token, &path)?;
+    fs::write(config_path, content)?;
+
+    Ok(accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key;
+    use anyhow::Result;
+
+    fn get_token(group_name: &str) -> ShareToken {
+        ShareToken {
+            group_name: group_name.to_string(),
+            node_id: key::generate_node_secret_key().public().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_token() -> Result<()> {
+        let token = get_token("docs");
+        let encoded = token.encode()?;
+        assert_eq!(ShareToken::decode(&encoded)?, token);
+        assert_eq!(
+            ShareToken::decode(&format!(" {} ", encoded.to_uppercase()))?,
+            token
+        );
+
+        let test_values = [
+            // (token, is_valid)
+            (encoded.clone(), true),
+            (encoded[..encoded.len() - 4].to_string(), false),
+            ("foo".to_string(), false),
+            (BASE32_NOPAD.encode(&[0u8; NODE_ID_BYTES]), false),
+            (encoded[..NODE_ID_BYTES * 8 / 5].to_string() + "7777", false),
+            (String::new(), false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(ShareToken::decode(&spec.0).is_ok(), spec.1, "{}", spec.0);
+        }
+
+        let test_values = [
+            // (group_name, is_valid)
+            ("docs", true),
+            ("my docs.d", true),
+            ("..", false),
+            (".", false),
+            ("../docs", false),
+            ("docs/..", false),
+            ("a/b", false),
+            ("docs/", false),
+            ("/docs", false),
+        ];
+
+        for spec in test_values {
+            let encoded = get_token(spec.0).encode()?;
+            assert_eq!(ShareToken::decode(&encoded).is_ok(), spec.1, "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_accepted() -> Result<()> {
+        let config = Config::default();
+        let content = toml::to_string(&config)?;
+        let token = get_token("docs");
+
+        let (content, accepted) = add_accepted(&content, &config, &token, "~/fsy/docs")?;
+        let updated: Config = toml::from_str(&content)?;
+        assert_eq!(updated.nodes.len(), 1);
+        assert_eq!(updated.nodes[0].id, token.node_id);
+        assert_eq!(updated.target_groups.len(), 1);
+        assert_eq!(updated.target_groups[0].path, "~/fsy/docs");
+        assert_eq!(
+            updated.target_groups[0].targets[0].mode,
+            target::TargetMode::Pull
+        );
+        assert_eq!(
+            updated.target_groups[0].targets[0].node_name,
+            accepted.node_name
+        );
+
+        // a known node is used as it is
+        let other_token = ShareToken {
+            group_name: "photos".to_string(),
+            ..token.clone()
+        };
+        let (content, _) = add_accepted(&content, &updated, &other_token, "/photos")?;
+        let updated: Config = toml::from_str(&content)?;
+        assert_eq!(updated.nodes.len(), 1);
+        assert_eq!(updated.target_groups.len(), 2);
+
+        // the group is there already
+        assert!(add_accepted(&content, &updated, &token, "/docs").is_err());
+
+        // names and paths are quoted by toml, whatever they have
+        let other_token = get_token("say \"hi\" \\ \u{7f}");
+        let path = "C:\\fsy\\\"quoted\"\n[local]";
+        let (content, accepted) = add_accepted(&content, &updated, &other_token, path)?;
+        let updated: Config = toml::from_str(&content)?;
+        assert_eq!(updated.nodes.len(), 2);
+        assert_eq!(updated.nodes[1].name, accepted.node_name);
+        assert_eq!(updated.target_groups[2].name, other_token.group_name);
+        assert_eq!(updated.target_groups[2].path, path);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::connection::ConnectionHandle;
+use crate::logs::log;
+
+// how many times a snapshot is taken while files keep changing under it
+const SNAPSHOT_ATTEMPTS: usize = 3;
+const SNAPSHOT_RETRY_MILLISECS: u64 = 500;
+
+#[derive(Debug, Clone, PartialEq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+// get_stamps retrieves what tells if a file changed, none if it is gone
+fn get_stamps(file_paths: &[PathBuf]) -> Vec<Option<FileStamp>> {
+    file_paths
+        .iter()
+        .map(|file_path| {
+            let meta = fs::metadata(file_path).ok()?;
+            Some(FileStamp {
+                len: meta.len(),
+                modified: meta.modified().ok(),
+            })
+        })
+        .collect()
+}
+
+// take_snapshot imports the files into the store all at once, the tickets
+// of that moment are the ones the pullers get. files changing while they
+// are imported make it start over, so the pullers never get a mixture of
+// old and new files. false if the files never settled, the last attempt
+// is kept anyway so no older snapshot is sent. what is imported of each
+// file is retrieved by get_sent_path (its transformed copy, if any)
+pub async fn take_snapshot(
+    conn: &ConnectionHandle,
+    file_paths: &[PathBuf],
+    get_sent_path: impl Fn(&Path) -> Result<PathBuf>,
+) -> Result<bool> {
+    let mut attempt = 1;
+    loop {
+        let before = get_stamps(file_paths);
+        let mut tickets = vec![];
+        for file_path in file_paths.iter().filter(|p| p.is_file()) {
+            let ticket = conn
+                .get_file_ticket(get_sent_path(file_path)?.to_string_lossy().to_string())
+                .await?;
+            tickets.push((file_path.clone(), ticket));
+        }
+
+        let is_settled = get_stamps(file_paths) == before;
+        if is_settled || attempt == SNAPSHOT_ATTEMPTS {
+            conn.set_snapshot_tickets(tickets);
+            return Ok(is_settled);
+        }
+
+        log!("- files changed while taking the snapshot, attempt {attempt}");
+        tokio::time::sleep(Duration::from_millis(SNAPSHOT_RETRY_MILLISECS)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_stamps() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_snapshot_stamps");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("a.txt"), b"a")?;
+
+        let file_paths = vec![dir.join("a.txt"), dir.join("b.txt")];
+        let before = get_stamps(&file_paths);
+        assert!(before[0].is_some());
+        assert!(before[1].is_none());
+        assert_eq!(get_stamps(&file_paths), before);
+
+        fs::write(dir.join("a.txt"), b"aa")?;
+        assert_ne!(get_stamps(&file_paths), before);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
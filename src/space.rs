@@ -0,0 +1,90 @@
+use std::io;
+use std::path::Path;
+
+// a group degraded by a full disk resumes once this much is free again on
+// both the target and the storage
+pub const RESUME_FREE_BYTES: u64 = 64 * 1024 * 1024;
+
+// how often the disk of degraded groups is checked
+pub const SPACE_CHECK_SECS: u64 = 30;
+
+// is_disk_full checks if the error comes from running out of disk space,
+// the transfer errors don't always keep the io error around so the
+// message is checked too
+pub fn is_disk_full(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        let is_io_full = cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::StorageFull);
+        let msg = cause.to_string();
+        is_io_full || msg.contains("No space left on device") || msg.contains("os error 28")
+    })
+}
+
+// get_free_space retrieves the bytes available on the disk of the path,
+// none if it can't be known
+#[cfg(unix)]
+pub fn get_free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let raw_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is nul terminated and stat is a valid statvfs to fill
+    let res = unsafe { libc::statvfs(raw_path.as_ptr(), &mut stat) };
+    if res != 0 {
+        return None;
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn get_free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+// has_space checks there is enough free space to resume on every path,
+// unknown counts as enough so the pull is tried again
+pub fn has_space(paths: &[&Path]) -> bool {
+    paths
+        .iter()
+        .all(|path| get_free_space(path).is_none_or(|free| free >= RESUME_FREE_BYTES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Result, anyhow};
+
+    #[test]
+    fn test_is_disk_full() -> Result<()> {
+        let test_values = [
+            // (error, is_disk_full)
+            (anyhow::Error::from(io::Error::from(io::ErrorKind::StorageFull)), true),
+            (
+                anyhow::Error::from(io::Error::from_raw_os_error(28)).context("unable to export"),
+                cfg!(unix),
+            ),
+            (anyhow!("failed to write: No space left on device"), true),
+            (anyhow::Error::from(io::Error::from(io::ErrorKind::NotFound)), false),
+            (anyhow!("connection lost"), false),
+        ];
+
+        for spec in test_values {
+            assert_eq!(is_disk_full(&spec.0), spec.1, "{:?}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_free_space() -> Result<()> {
+        let free = get_free_space(&std::env::temp_dir());
+        assert_eq!(free.is_some(), cfg!(unix));
+        assert_eq!(get_free_space(Path::new("/fsy/not/there")), None);
+
+        Ok(())
+    }
+}
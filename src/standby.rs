@@ -0,0 +1,154 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::target::{TargetGroup, TargetMode};
+
+pub const PROMOTED_FILE_NAME: &str = "promoted.toml";
+
+// old versions of each file a standby keeps when not set
+pub const DEFAULT_STANDBY_VERSIONS: usize = 10;
+
+// Promoted: when the standby took over, from then on it pushes its groups
+// as the node it was the standby of did
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Promoted {
+    pub promoted_at: DateTime<Utc>,
+}
+
+impl Promoted {
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(path)?;
+        Ok(Some(toml::from_str(&content)?))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+// apply_standby makes the groups of a standby node only pull, mirroring
+// what it pulls from and keeping the old versions, so nothing is ever
+// lost nor sent from it. once promoted it pushes them instead, still
+// pulling from the nodes that come back
+pub fn apply_standby(groups: &mut [TargetGroup], max_versions: usize, is_promoted: bool) {
+    for group in groups {
+        for target in &mut group.targets {
+            target.mode = match is_promoted {
+                true => TargetMode::PushPull,
+                false => TargetMode::Pull,
+            };
+        }
+
+        if !is_promoted {
+            group.mirror = true;
+            group.archive = true;
+            group.max_versions = group.max_versions.or(Some(max_versions));
+        }
+    }
+}
+
+// Promotion: the outcome of `fsy promote`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Promotion {
+    pub changed: bool,
+    pub promoted_at: DateTime<Utc>,
+    pub group_names: Vec<String>,
+}
+
+impl fmt::Display for Promotion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.changed {
+            let promoted_at = self.promoted_at.format("%Y-%m-%d %H:%M:%S");
+            return writeln!(f, "promoted already on {promoted_at}");
+        }
+
+        writeln!(
+            f,
+            "promoted, restart the daemon to push its {} groups",
+            self.group_names.len()
+        )?;
+        for group_name in &self.group_names {
+            writeln!(f, "- {group_name}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::Target;
+    use anyhow::Result;
+
+    fn get_group(mode: TargetMode, max_versions: Option<usize>) -> TargetGroup {
+        TargetGroup {
+            name: "docs".into(),
+            path: "/docs".to_string(),
+            targets: vec![Target {
+                mode,
+                node_name: "primary".to_string(),
+                include: vec![],
+            }],
+            max_versions,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_apply_standby() -> Result<()> {
+        let test_values = [
+            // (mode, max versions, promoted, expected mode, expected max versions)
+            (TargetMode::Push, None, false, TargetMode::Pull, Some(5)),
+            (
+                TargetMode::PushPull,
+                Some(2),
+                false,
+                TargetMode::Pull,
+                Some(2),
+            ),
+            (TargetMode::Pull, None, false, TargetMode::Pull, Some(5)),
+            (TargetMode::Pull, None, true, TargetMode::PushPull, None),
+        ];
+        for spec in test_values {
+            let mut groups = vec![get_group(spec.0.clone(), spec.1)];
+            apply_standby(&mut groups, 5, spec.2);
+            assert_eq!(groups[0].targets[0].mode, spec.3, "{spec:?}");
+            assert_eq!(groups[0].max_versions, spec.4, "{spec:?}");
+            assert_eq!(groups[0].is_archive(), !spec.2, "{spec:?}");
+            assert_eq!(groups[0].is_mirror(), !spec.2, "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_promoted() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_promoted");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join(PROMOTED_FILE_NAME);
+        assert_eq!(Promoted::load(&path)?, None);
+
+        let promoted = Promoted {
+            promoted_at: DateTime::from_timestamp(1700000000, 0).unwrap(),
+        };
+        promoted.save(&path)?;
+        assert_eq!(Promoted::load(&path)?, Some(promoted));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
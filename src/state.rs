@@ -0,0 +1,223 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{RwLock, broadcast};
+
+use crate::events::{self, Event};
+use crate::status::Status;
+
+// most recent errors kept, the oldest are forgotten first
+const MAX_ERRORS: usize = 50;
+
+// Transfer: a download going on from a node
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub node_id: String,
+    pub group_name: String,
+    pub relative_path: String,
+    pub started: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DaemonError {
+    pub group_name: Option<String>, // group the error happened on, if any
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+// DaemonState: what the daemon knows while running. the tasks update it,
+// the cli and the control api read snapshots of it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DaemonState {
+    pub status: Status, // peers and groups, also saved to the storage
+    #[serde(default)]
+    pub transfers: Vec<Transfer>,
+    #[serde(default)]
+    pub errors: VecDeque<DaemonError>, // most recent last
+    #[serde(skip)]
+    events: Vec<Event>, // published once the update is done
+}
+
+impl DaemonState {
+    // publish lets the subscribers know of the event, see `SharedState::subscribe`
+    pub fn publish(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn start_transfer(&mut self, node_id: &str, group_name: &str, relative_path: &str) {
+        self.remove_transfer(node_id, group_name, relative_path);
+        self.transfers.push(Transfer {
+            node_id: node_id.to_owned(),
+            group_name: group_name.to_owned(),
+            relative_path: relative_path.to_owned(),
+            started: Utc::now(),
+        });
+        self.publish(Event::TransferStarted {
+            node_id: node_id.to_owned(),
+            group_name: group_name.to_owned(),
+            relative_path: relative_path.to_owned(),
+        });
+    }
+
+    pub fn end_transfer(&mut self, node_id: &str, group_name: &str, relative_path: &str) {
+        self.remove_transfer(node_id, group_name, relative_path);
+        self.publish(Event::TransferEnded {
+            node_id: node_id.to_owned(),
+            group_name: group_name.to_owned(),
+            relative_path: relative_path.to_owned(),
+        });
+    }
+
+    fn remove_transfer(&mut self, node_id: &str, group_name: &str, relative_path: &str) {
+        self.transfers.retain(|t| {
+            t.node_id != node_id || t.group_name != group_name || t.relative_path != relative_path
+        });
+    }
+
+    // add_error keeps the error around, the group shows it as its last one
+    pub fn add_error(&mut self, group_name: Option<&str>, message: &str) {
+        if let Some(group_name) = group_name {
+            self.status.set_group_error(group_name, message);
+        }
+
+        if self.errors.len() >= MAX_ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(DaemonError {
+            group_name: group_name.map(|g| g.to_owned()),
+            message: message.to_owned(),
+            timestamp: Utc::now(),
+        });
+        self.publish(Event::Error {
+            group_name: group_name.map(|g| g.to_owned()),
+            message: message.to_owned(),
+        });
+    }
+}
+
+// SharedState: the state shared across the daemon tasks. the status is
+// saved to the storage on every update of it so `fsy status` is always up
+// to date, even without the daemon answering. the events of the updates go
+// to the subscribers
+#[derive(Clone)]
+pub struct SharedState {
+    state: Arc<RwLock<DaemonState>>,
+    path: PathBuf,
+    events: broadcast::Sender<Event>,
+}
+
+impl SharedState {
+    pub fn new(status: Status, path: PathBuf) -> Self {
+        let state = DaemonState {
+            status,
+            ..Default::default()
+        };
+
+        let (events, _) = broadcast::channel(events::EVENTS_CAPACITY);
+        Self {
+            state: Arc::new(RwLock::new(state)),
+            path,
+            events,
+        }
+    }
+
+    pub async fn update(&self, f: impl FnOnce(&mut Status)) -> Result<()> {
+        let mut state = self.state.write().await;
+        f(&mut state.status);
+        state.status.save(&self.path)
+    }
+
+    pub async fn update_state(&self, f: impl FnOnce(&mut DaemonState)) -> Result<()> {
+        let mut state = self.state.write().await;
+        f(&mut state);
+
+        // NOTE: nobody subscribed is fine, the events are just dropped
+        for event in state.events.drain(..) {
+            let _ = self.events.send(event);
+        }
+        state.status.save(&self.path)
+    }
+
+    // subscribe retrieves the events of the updates from now on, in process
+    // or through `GET /events` of the control api
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    // get retrieves a snapshot of the status
+    pub async fn get(&self) -> Status {
+        self.state.read().await.status.clone()
+    }
+
+    // snapshot retrieves a snapshot of the whole state
+    pub async fn snapshot(&self) -> DaemonState {
+        self.state.read().await.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_shared_state() -> Result<()> {
+        let path = std::env::temp_dir().join("fsy_test_state_status.toml");
+        let state = SharedState::new(Status::default(), path.clone());
+        let mut events = state.subscribe();
+
+        state
+            .update_state(|state| {
+                state.start_transfer("foo", "docs", "a.txt");
+                state.start_transfer("foo", "docs", "b.txt");
+                state.start_transfer("foo", "docs", "a.txt");
+                state.end_transfer("foo", "docs", "b.txt");
+                state.add_error(None, "unable to send");
+            })
+            .await?;
+
+        let snapshot = state.snapshot().await;
+        let transfers: Vec<&str> = snapshot
+            .transfers
+            .iter()
+            .map(|t| t.relative_path.as_str())
+            .collect();
+        assert_eq!(transfers, vec!["a.txt"]);
+        assert_eq!(snapshot.errors.len(), 1);
+        assert_eq!(Status::load(&path)?, snapshot.status);
+
+        // the subscribers get the events of the update, in order
+        let mut received = vec![];
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(received.len(), 5);
+        assert_eq!(
+            received[3],
+            Event::TransferEnded {
+                node_id: "foo".into(),
+                group_name: "docs".into(),
+                relative_path: "b.txt".into(),
+            }
+        );
+        assert!(snapshot.events.is_empty());
+
+        // only the most recent errors are kept
+        state
+            .update_state(|state| {
+                for i in 0..MAX_ERRORS {
+                    state.add_error(Some("docs"), &i.to_string());
+                }
+            })
+            .await?;
+        let errors = state.snapshot().await.errors;
+        assert_eq!(errors.len(), MAX_ERRORS);
+        assert_eq!(errors[0].message, "0");
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
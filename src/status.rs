@@ -0,0 +1,700 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::admission::SyncProgress;
+use crate::bandwidth::{Bandwidth, Usage};
+use crate::connection::{PathKind, PeerPath};
+use crate::health::Health;
+use crate::power;
+use crate::queue::QueueMetrics;
+use crate::scan::{ScanProgress, ScanSummary};
+use crate::target::{self, NodeData, TargetGroup};
+
+pub const STATUS_FILE_NAME: &str = "status.toml";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupError {
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupStatus {
+    pub name: String,
+    pub last_error: Option<GroupError>, // most recent failure on the group
+    #[serde(default)]
+    pub paused: Option<String>, // reason why the group is waiting for confirmation
+    #[serde(default)]
+    pub scan: Option<ScanProgress>, // startup scan, while it is going
+    #[serde(default)]
+    pub inventory: Option<ScanSummary>, // what the startup scan found
+    #[serde(default)]
+    pub degraded: Option<String>, // reason why the group can't pull for now (disk full)
+    #[serde(default)]
+    pub watcher: Option<String>, // reason why changes of the group may go unseen
+    #[serde(default)]
+    pub pull: Option<SyncProgress>, // what is left to pull, while there is something
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerStatus {
+    pub name: String,
+    pub node_id: String,
+    pub today: Usage, // bytes transferred today
+    pub week: Usage,  // bytes transferred on the last 7 days
+    pub paused: bool, // over the soft quota, pushes are on hold
+    #[serde(default)]
+    pub version: Option<String>, // fsy version the peer told us on its hello
+    #[serde(default)]
+    pub features: Vec<String>, // features the peer has enabled
+    #[serde(default)]
+    pub paused_groups: Vec<String>, // groups the peer can't pull for now, out of space
+    #[serde(default)]
+    pub path: Option<PeerPath>, // how the data to the peer goes, none if never reached
+    #[serde(default)]
+    pub path_changes: u64, // times the path changed since the daemon started
+}
+
+// Status: what the daemon knows about itself, written to the storage
+// so other commands (`fsy status`) can read it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub node_id: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+    pub groups: Vec<GroupStatus>,
+    #[serde(default)]
+    pub peers: Vec<PeerStatus>,
+    #[serde(default)]
+    pub paused: Option<String>, // reason why the whole sync waits (the user, low battery, metered)
+    #[serde(default)]
+    pub queue: QueueMetrics, // actions through the queue, dropped ones are lost
+    #[serde(default)]
+    pub health: Health, // last health check, `fsy health`
+}
+
+impl Status {
+    pub fn new(node_id: &str, target_groups: &[TargetGroup]) -> Self {
+        let groups = target_groups
+            .iter()
+            .map(|group| GroupStatus {
+                name: group.name.to_string(),
+                last_error: None,
+                paused: None,
+                scan: None,
+                inventory: None,
+                degraded: None,
+                watcher: None,
+                pull: None,
+                description: group.description.clone(),
+                tags: group.tags.clone(),
+            })
+            .collect();
+
+        Self {
+            node_id: node_id.to_owned(),
+            version: crate::VERSION.to_owned(),
+            features: target::get_enabled_features(target_groups),
+            groups,
+            peers: vec![],
+            paused: None,
+            queue: QueueMetrics::default(),
+            health: Health::default(),
+        }
+    }
+
+    // filter_tag keeps only the groups with the tag
+    pub fn filter_tag(&mut self, tag: &str) {
+        self.groups.retain(|g| g.tags.iter().any(|t| t == tag));
+    }
+
+    pub fn set_bandwidth(&mut self, nodes: &[NodeData], bandwidth: &Bandwidth, date: NaiveDate) {
+        self.peers = nodes
+            .iter()
+            .map(|node| {
+                // what the peer told us about itself is kept
+                let known = self.peers.iter().find(|p| p.node_id == node.id);
+                PeerStatus {
+                    name: node.name.clone(),
+                    node_id: node.id.to_string(),
+                    today: bandwidth.get_usage(&node.id, date, 1),
+                    week: bandwidth.get_usage(&node.id, date, 7),
+                    paused: bandwidth.is_over_quota(node, date),
+                    version: known.and_then(|p| p.version.clone()),
+                    features: known.map(|p| p.features.clone()).unwrap_or_default(),
+                    paused_groups: known.map(|p| p.paused_groups.clone()).unwrap_or_default(),
+                    path: known.and_then(|p| p.path.clone()),
+                    path_changes: known.map(|p| p.path_changes).unwrap_or_default(),
+                }
+            })
+            .collect();
+    }
+
+    pub fn set_peer_info(&mut self, node_id: &str, version: &str, features: &[String]) {
+        let peer = self.peers.iter_mut().find(|p| p.node_id == node_id);
+        if let Some(peer) = peer {
+            peer.version = Some(version.to_owned());
+            peer.features = features.to_vec();
+        }
+    }
+
+    // set_peer_path keeps how the data to the peer goes, returning if it
+    // changed from the one it had (direct to relayed, another address...)
+    pub fn set_peer_path(&mut self, node_id: &str, path: Option<PeerPath>) -> bool {
+        let Some(peer) = self.peers.iter_mut().find(|p| p.node_id == node_id) else {
+            return false;
+        };
+
+        let has_changed = match (&peer.path, &path) {
+            (Some(last), Some(path)) => last.kind != path.kind || last.addr != path.addr,
+            _ => false,
+        };
+        if has_changed {
+            peer.path_changes += 1;
+        }
+        if path.is_some() {
+            peer.path = path;
+        }
+        has_changed
+    }
+
+    pub fn set_peer_group_paused(&mut self, node_id: &str, group_name: &str, paused: bool) {
+        let peer = self.peers.iter_mut().find(|p| p.node_id == node_id);
+        if let Some(peer) = peer {
+            peer.paused_groups.retain(|g| g != group_name);
+            if paused {
+                peer.paused_groups.push(group_name.to_owned());
+            }
+        }
+    }
+
+    pub fn set_group_error(&mut self, group_name: &str, message: &str) {
+        let group = self.groups.iter_mut().find(|g| g.name == group_name);
+        if let Some(group) = group {
+            group.last_error = Some(GroupError {
+                message: message.to_owned(),
+                timestamp: Utc::now(),
+            });
+        }
+    }
+
+    pub fn set_group_paused(&mut self, group_name: &str, reason: Option<&str>) {
+        let group = self.groups.iter_mut().find(|g| g.name == group_name);
+        if let Some(group) = group {
+            group.paused = reason.map(|r| r.to_owned());
+        }
+    }
+
+    pub fn set_group_degraded(&mut self, group_name: &str, reason: Option<&str>) {
+        let group = self.groups.iter_mut().find(|g| g.name == group_name);
+        if let Some(group) = group {
+            group.degraded = reason.map(|r| r.to_owned());
+        }
+    }
+
+    pub fn set_group_watcher(&mut self, group_name: &str, reason: Option<&str>) {
+        let group = self.groups.iter_mut().find(|g| g.name == group_name);
+        if let Some(group) = group {
+            group.watcher = reason.map(|r| r.to_owned());
+        }
+    }
+
+    // get_degraded_groups retrieves the names of the groups that can't pull
+    pub fn get_degraded_groups(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|g| g.degraded.is_some())
+            .map(|g| g.name.clone())
+            .collect()
+    }
+
+    pub fn set_group_scan(&mut self, group_name: &str, progress: Option<&ScanProgress>) {
+        let group = self.groups.iter_mut().find(|g| g.name == group_name);
+        if let Some(group) = group {
+            group.scan = progress.cloned();
+        }
+    }
+
+    pub fn set_group_inventory(&mut self, group_name: &str, summary: &ScanSummary) {
+        let group = self.groups.iter_mut().find(|g| g.name == group_name);
+        if let Some(group) = group {
+            group.inventory = Some(summary.clone());
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let parsed: Status = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "node id: {}", self.node_id)?;
+        writeln!(
+            f,
+            "version: {}{}",
+            self.version,
+            format_features(&self.features)
+        )?;
+        match &self.paused {
+            Some(reason) if reason == power::USER_PAUSE_REASON => {
+                writeln!(f, "sync: paused {reason}. run `fsy resume` to sync again")?;
+            }
+            Some(reason) => writeln!(f, "sync: paused, {reason}. it resumes on its own")?,
+            None => {}
+        }
+        writeln!(
+            f,
+            "queue: {} waiting (at most {}), {} queued, {} done, {} dropped",
+            self.queue.depth,
+            self.queue.max_depth,
+            self.queue.pushed,
+            self.queue.popped,
+            self.queue.dropped
+        )?;
+        writeln!(f, "groups:")?;
+        for group in &self.groups {
+            let mut about = group.description.clone().into_iter().collect::<Vec<_>>();
+            if !group.tags.is_empty() {
+                about.push(format!("[{}]", group.tags.join(", ")));
+            }
+            if !about.is_empty() {
+                writeln!(f, "- {}: {}", group.name, about.join(" "))?;
+            }
+
+            if let Some(reason) = &group.paused {
+                writeln!(
+                    f,
+                    "- {}: paused, {reason}. run `fsy confirm {}` to sync it",
+                    group.name, group.name
+                )?;
+            }
+
+            if let Some(reason) = &group.degraded {
+                writeln!(f, "- {}: degraded, {reason}", group.name)?;
+            }
+
+            if let Some(reason) = &group.watcher {
+                writeln!(f, "- {}: watcher failing, {reason}", group.name)?;
+            }
+
+            if let Some(pull) = &group.pull {
+                let eta = match pull.eta_secs {
+                    Some(secs) => format!("about {} to go", format_secs(secs)),
+                    None => "time left unknown yet".to_owned(),
+                };
+                writeln!(
+                    f,
+                    "- {}: pulling, {} files ({}) left, {eta}",
+                    group.name,
+                    pull.files,
+                    format_bytes(pull.bytes)
+                )?;
+            }
+
+            if let Some(scan) = &group.scan {
+                writeln!(
+                    f,
+                    "- {}: scanning, {}/{} files {}",
+                    group.name, scan.scanned, scan.total, scan.current_path
+                )?;
+            }
+
+            let inventory = match &group.inventory {
+                Some(inventory) => {
+                    format!(", {} files ({})", inventory.files, format_bytes(inventory.bytes))
+                }
+                None => "".to_owned(),
+            };
+            match &group.last_error {
+                Some(err) => writeln!(
+                    f,
+                    "- {}: last error at {}: {}",
+                    group.name,
+                    err.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                    err.message
+                )?,
+                None => writeln!(f, "- {}: ok{inventory}", group.name)?,
+            }
+        }
+
+        if !self.peers.is_empty() {
+            writeln!(f, "peers:")?;
+        }
+        for peer in &self.peers {
+            let paused = if peer.paused { " (paused, over quota)" } else { "" };
+            let paused_groups = match peer.paused_groups.is_empty() {
+                true => "".to_owned(),
+                false => format!(" (out of space for {})", peer.paused_groups.join(", ")),
+            };
+            let version = match &peer.version {
+                Some(version) if *version != self.version => {
+                    format!("{version} (differs){}", format_features(&peer.features))
+                }
+                Some(version) => format!("{version}{}", format_features(&peer.features)),
+                None => "unknown version".to_owned(),
+            };
+            let path = match &peer.path {
+                Some(path) => format!(", {}", format_path(path, peer.path_changes)),
+                None => "".to_owned(),
+            };
+            writeln!(
+                f,
+                "- {}: {version}{path}, today {} sent / {} received, week {} sent / {} received{paused}{paused_groups}",
+                peer.name,
+                format_bytes(peer.today.sent),
+                format_bytes(peer.today.received),
+                format_bytes(peer.week.sent),
+                format_bytes(peer.week.received),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn format_path(path: &PeerPath, changes: u64) -> String {
+    let mut formatted = path.kind.to_string();
+    match (&path.kind, &path.addr) {
+        (PathKind::Relay, Some(addr)) => formatted.push_str(&format!(" via {addr}")),
+        (_, Some(addr)) => formatted.push_str(&format!(" {addr}")),
+        _ => {}
+    }
+    if let Some(latency_ms) = path.latency_ms {
+        formatted.push_str(&format!(" {latency_ms}ms"));
+    }
+    if changes > 0 {
+        formatted.push_str(&format!(" ({changes} path changes)"));
+    }
+
+    formatted
+}
+
+fn format_features(features: &[String]) -> String {
+    if features.is_empty() {
+        return "".to_owned();
+    }
+
+    format!(" [{}]", features.join(", "))
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return format!("{bytes}B");
+    }
+
+    format!("{value:.1}{}", units[unit])
+}
+
+fn format_secs(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_groups() -> Vec<TargetGroup> {
+        ["foo", "bar"]
+            .iter()
+            .map(|name| TargetGroup {
+                name: (*name).into(),
+                path: format!("/tmp/{name}"),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_set_group_error() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+
+        status.set_group_error("foo", "permission denied");
+        status.set_group_error("unknown", "not there");
+
+        let foo = &status.groups[0];
+        assert_eq!(foo.last_error.as_ref().unwrap().message, "permission denied");
+        let bar = &status.groups[1];
+        assert_eq!(bar.last_error, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_group_paused() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+
+        status.set_group_paused("foo", Some("too big"));
+        assert_eq!(status.groups[0].paused, Some("too big".to_string()));
+        assert_eq!(status.groups[1].paused, None);
+
+        status.set_group_paused("foo", None);
+        assert_eq!(status.groups[0].paused, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_group_degraded() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+
+        status.set_group_degraded("bar", Some("disk full"));
+        assert_eq!(status.groups[1].degraded, Some("disk full".to_string()));
+        assert_eq!(status.get_degraded_groups(), vec!["bar".to_string()]);
+
+        status.set_group_degraded("bar", None);
+        assert!(status.get_degraded_groups().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_group_watcher() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+
+        status.set_group_watcher("foo", Some("restarted"));
+        assert_eq!(status.groups[0].watcher, Some("restarted".to_string()));
+        assert_eq!(status.groups[1].watcher, None);
+        let shown = status.to_string();
+        assert!(shown.contains("- foo: watcher failing, restarted"));
+
+        status.set_group_watcher("foo", None);
+        assert_eq!(status.groups[0].watcher, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_tag() -> Result<()> {
+        let mut groups = get_groups();
+        groups[0].tags = vec!["work".to_string(), "docs".to_string()];
+        groups[0].description = Some("reports".to_string());
+        let status = Status::new("1234", &groups);
+        assert_eq!(status.groups[0].description, Some("reports".to_string()));
+
+        let test_values = [
+            // (tag, expected groups)
+            ("work", vec!["foo"]),
+            ("docs", vec!["foo"]),
+            ("home", vec![]),
+        ];
+
+        for spec in test_values {
+            let mut status = status.clone();
+            status.filter_tag(spec.0);
+            let names: Vec<&str> = status.groups.iter().map(|g| g.name.as_str()).collect();
+            assert_eq!(names, spec.1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_group_scan() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+        let progress = ScanProgress {
+            scanned: 10,
+            total: 20,
+            current_path: "/tmp/foo/a.txt".to_string(),
+        };
+
+        status.set_group_scan("foo", Some(&progress));
+        assert_eq!(status.groups[0].scan, Some(progress));
+        assert!(status.to_string().contains("- foo: scanning, 10/20 files"));
+
+        status.set_group_scan("foo", None);
+        status.set_group_inventory("foo", &ScanSummary { files: 20, bytes: 2048 });
+        assert_eq!(status.groups[0].scan, None);
+        assert!(status.to_string().contains("- foo: ok, 20 files (2.0KB)"));
+        assert_eq!(status.groups[1].inventory, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_peer_info() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+        let nodes = vec![NodeData {
+            name: "foo".to_string(),
+            id: "5678".into(),
+            ..Default::default()
+        }];
+        let date = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        status.set_bandwidth(&nodes, &Bandwidth::default(), date);
+        assert_eq!(status.peers[0].version, None);
+
+        let features = vec!["xattrs".to_string()];
+        status.set_peer_info("5678", "0.2.0", &features);
+        status.set_peer_info("unknown", "0.2.0", &features);
+        assert_eq!(status.peers.len(), 1);
+        assert_eq!(status.peers[0].version, Some("0.2.0".to_string()));
+        assert_eq!(status.peers[0].features, features);
+
+        // the bandwidth refresh keeps what the peer told us
+        status.set_bandwidth(&nodes, &Bandwidth::default(), date);
+        assert_eq!(status.peers[0].version, Some("0.2.0".to_string()));
+        assert!(status.to_string().contains("0.2.0 (differs) [xattrs]"));
+
+        status.set_peer_group_paused("5678", "bar", true);
+        status.set_peer_group_paused("5678", "bar", true);
+        status.set_bandwidth(&nodes, &Bandwidth::default(), date);
+        assert_eq!(status.peers[0].paused_groups, vec!["bar".to_string()]);
+        status.set_peer_group_paused("5678", "bar", false);
+        assert!(status.peers[0].paused_groups.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_peer_path() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+        let nodes = vec![NodeData {
+            name: "foo".to_string(),
+            id: "5678".into(),
+            ..Default::default()
+        }];
+        let date = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        status.set_bandwidth(&nodes, &Bandwidth::default(), date);
+
+        let get_path = |kind: PathKind, addr: &str, latency_ms: u64| {
+            Some(PeerPath {
+                kind,
+                addr: Some(addr.to_string()),
+                latency_ms: Some(latency_ms),
+            })
+        };
+        let test_values = [
+            // (path, has changed, path changes)
+            (None, false, 0),
+            (get_path(PathKind::Direct, "10.0.0.2:4000", 3), false, 0),
+            (get_path(PathKind::Direct, "10.0.0.2:4000", 5), false, 0),
+            (None, false, 0),
+            (get_path(PathKind::Relay, "https://relay.foo", 80), true, 1),
+            (get_path(PathKind::Direct, "10.0.0.3:4000", 4), true, 2),
+        ];
+
+        for spec in test_values {
+            let has_changed = status.set_peer_path("5678", spec.0.clone());
+            assert_eq!(has_changed, spec.1, "{:?}", spec.0);
+            assert_eq!(status.peers[0].path_changes, spec.2, "{:?}", spec.0);
+        }
+        assert!(!status.set_peer_path("unknown", get_path(PathKind::Relay, "foo", 1)));
+
+        // the bandwidth refresh keeps the path
+        status.set_bandwidth(&nodes, &Bandwidth::default(), date);
+        assert_eq!(status.peers[0].path_changes, 2);
+        let formatted = status.to_string();
+        assert!(formatted.contains("direct 10.0.0.3:4000 4ms (2 path changes)"));
+
+        status.set_peer_path("5678", get_path(PathKind::Relay, "https://relay.foo", 80));
+        let formatted = status.to_string();
+        assert!(formatted.contains("relayed via https://relay.foo 80ms"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_bytes() -> Result<()> {
+        let test_values = [
+            // (bytes, expected)
+            (0, "0B"),
+            (1023, "1023B"),
+            (1024, "1.0KB"),
+            (1536, "1.5KB"),
+            (1024 * 1024 * 3, "3.0MB"),
+        ];
+
+        for spec in test_values {
+            assert_eq!(format_bytes(spec.0), spec.1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_pull() -> Result<()> {
+        let test_values = [
+            // (files, bytes, eta secs, expected)
+            (
+                3,
+                600,
+                None,
+                "- foo: pulling, 3 files (600B) left, time left unknown yet",
+            ),
+            (
+                1,
+                2048,
+                Some(45),
+                "- foo: pulling, 1 files (2.0KB) left, about 45s to go",
+            ),
+            (
+                2,
+                10,
+                Some(130),
+                "- foo: pulling, 2 files (10B) left, about 2m 10s to go",
+            ),
+            (
+                5,
+                10,
+                Some(7260),
+                "- foo: pulling, 5 files (10B) left, about 2h 1m to go",
+            ),
+        ];
+
+        for spec in test_values {
+            let mut status = Status::new("1234", &get_groups());
+            status.groups[0].pull = Some(SyncProgress {
+                files: spec.0,
+                bytes: spec.1,
+                eta_secs: spec.2,
+            });
+            assert!(status.to_string().contains(spec.3), "{}", spec.3);
+            assert!(!status.to_string().contains("- bar: pulling"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_load() -> Result<()> {
+        let mut status = Status::new("1234", &get_groups());
+        status.set_group_error("bar", "offline");
+
+        let path = std::env::temp_dir().join("fsy_test_status.toml");
+        status.save(&path)?;
+        let loaded = Status::load(&path)?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(loaded, status);
+        Ok(())
+    }
+}
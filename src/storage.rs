@@ -0,0 +1,210 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{control, instance};
+
+// the database of the blob store, a storage with it has blobs
+const STORE_DB_FILE_NAME: &str = "blobs.db";
+
+// StorageMigration: what moved from the old storage to the new one
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct StorageMigration {
+    pub from: String,
+    pub to: String,
+    pub moved: usize, // entries moved, the blob store and its partial downloads included
+    pub skipped: Vec<String>, // entries the new storage has already, left on the old one
+}
+
+impl fmt::Display for StorageMigration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "moved {} entries of the storage from {} to {}",
+            self.moved, self.from, self.to
+        )?;
+        for name in &self.skipped {
+            writeln!(f, "- skipped {name}, the new storage has it already")?;
+        }
+
+        Ok(())
+    }
+}
+
+// the state files the lanes of the groups share (pulled, generations,
+// approvals) are loaded, changed and saved by one of them at a time
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+// lock_state keeps the other lanes off the state files until dropped
+pub fn lock_state() -> MutexGuard<'static, ()> {
+    STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// write_atomic replaces the file with the content at once: written next to
+// it, synced and renamed over it. whoever reads it sees the old content or
+// the new one, never half of it
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+
+    let res = (|| -> Result<()> {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+// has_store checks if the storage has a blob store
+pub fn has_store(storage_path: &Path) -> bool {
+    storage_path.join(STORE_DB_FILE_NAME).exists()
+}
+
+// needs_migration checks if the data of the old storage should move to the
+// new one, the old one has a blob store and the new one none yet
+pub fn needs_migration(from: &Path, to: &Path) -> bool {
+    from != to && has_store(from) && !has_store(to)
+}
+
+// migrate_storage moves what the daemon kept on the old storage (the blob
+// store with its partial downloads, the state files...) into the new one,
+// so nothing is downloaded again. what the new one has already stays, and
+// so do the locks and the control socket of the old one
+pub fn migrate_storage(from: &Path, to: &Path) -> Result<StorageMigration> {
+    if from == to {
+        bail!("the storage is at {} already", to.display());
+    }
+    if !from.is_dir() {
+        bail!("no storage at {}", from.display());
+    }
+    if instance::is_storage_in_use(from) {
+        bail!("a daemon is running on {}, stop it first", from.display());
+    }
+    if has_store(to) {
+        bail!("{} has a blob store already", to.display());
+    }
+    fs::create_dir_all(to)?;
+
+    let mut migration = StorageMigration {
+        from: from.to_string_lossy().to_string(),
+        to: to.to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let src = entry.path();
+        let name = entry.file_name();
+        // NOTE: the new storage can be inside of the old one
+        if instance::is_lock_file(&src)
+            || name == control::CONTROL_SOCKET_FILE_NAME
+            || to.starts_with(&src)
+        {
+            continue;
+        }
+
+        let dst = to.join(&name);
+        if dst.exists() {
+            migration.skipped.push(name.to_string_lossy().to_string());
+            continue;
+        }
+
+        move_entry(&src, &dst)?;
+        migration.moved += 1;
+    }
+
+    Ok(migration)
+}
+
+// move_entry renames the file or folder. on another file system it is
+// copied next to the dst first, a failed copy leaves no half of it
+fn move_entry(src: &Path, dst: &Path) -> Result<()> {
+    match fs::rename(src, dst) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let staging_path = get_staging_path(dst);
+    if let Err(e) = copy_entry(src, &staging_path) {
+        let _ = fs::remove_dir_all(&staging_path);
+        let _ = fs::remove_file(&staging_path);
+        return Err(e);
+    }
+    fs::rename(&staging_path, dst)?;
+
+    match src.is_dir() {
+        true => fs::remove_dir_all(src)?,
+        false => fs::remove_file(src)?,
+    }
+    Ok(())
+}
+
+fn get_staging_path(dst: &Path) -> PathBuf {
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    dst.with_file_name(format!(".{name}.migrating"))
+}
+
+fn copy_entry(src: &Path, dst: &Path) -> Result<()> {
+    if !src.is_dir() {
+        fs::copy(src, dst)?;
+        return Ok(());
+    }
+
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        copy_entry(&entry.path(), &dst.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_migrate_storage() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_migrate_storage");
+        let _ = fs::remove_dir_all(&dir);
+        let from = dir.join("old");
+        let to = dir.join("new");
+        fs::create_dir_all(from.join("data"))?;
+        fs::write(from.join(STORE_DB_FILE_NAME), b"db")?;
+        fs::write(from.join("data/partial.data"), b"partial")?;
+        fs::write(from.join("status.toml"), b"old")?;
+        fs::write(from.join("instance-abc.lock"), b"")?;
+        fs::create_dir_all(&to)?;
+        fs::write(to.join("status.toml"), b"new")?;
+        assert!(needs_migration(&from, &to));
+
+        let migration = migrate_storage(&from, &to)?;
+        assert_eq!(migration.moved, 2);
+        assert_eq!(migration.skipped, vec!["status.toml".to_string()]);
+        assert_eq!(fs::read(to.join("data/partial.data"))?, b"partial");
+        assert_eq!(fs::read(to.join("status.toml"))?, b"new");
+        assert!(from.join("instance-abc.lock").exists());
+        assert!(!to.join("instance-abc.lock").exists());
+
+        // done once, the new one has the store now
+        assert!(!needs_migration(&from, &to));
+        assert!(migrate_storage(&from, &to).is_err());
+
+        // on another file system the folders are copied over
+        let copied = dir.join("copied");
+        copy_entry(&to.join("data"), &copied)?;
+        assert_eq!(fs::read(copied.join("partial.data"))?, b"partial");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt;
+
+use crate::config_check::{ConfigReport, Severity};
+use crate::target::{NodeData, Target, TargetGroup, TargetMode};
+
+// SyncthingImport: nodes and target groups mapped from a syncthing config
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SyncthingImport {
+    pub nodes: Vec<NodeData>,
+    pub target_groups: Vec<TargetGroup>,
+    #[serde(skip)]
+    pub report: ConfigReport,
+}
+
+// NOTE: the text output is the toml to be appended to the config
+impl fmt::Display for SyncthingImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let content = toml::to_string(self).map_err(|_e| fmt::Error)?;
+        write!(f, "{content}")
+    }
+}
+
+// import_syncthing maps the folders and devices of a syncthing
+// `config.xml` into target groups and nodes, reporting what can't be mapped
+pub fn import_syncthing(content: &str) -> Result<SyncthingImport> {
+    let doc = roxmltree::Document::parse(content)?;
+    let root = doc.root_element();
+    let mut imported = SyncthingImport::default();
+
+    // (device id, node name)
+    let mut devices: Vec<(&str, String)> = vec![];
+    for device in root.children().filter(|n| n.has_tag_name("device")) {
+        let Some(id) = device.attribute("id") else {
+            continue;
+        };
+
+        // the name is local to the config, fallback to the short id
+        let name = match device.attribute("name") {
+            Some(name) if !name.is_empty() => name.to_owned(),
+            _ => id.chars().take(7).collect(),
+        };
+
+        imported.report.add(
+            Severity::Warning,
+            format!("node \"{name}\" needs its id, run `fsy id` on that device"),
+        );
+        imported.nodes.push(NodeData {
+            name: name.clone(),
+            ..Default::default()
+        });
+        devices.push((id, name));
+    }
+
+    let folders: Vec<roxmltree::Node> = root
+        .children()
+        .filter(|n| n.has_tag_name("folder"))
+        .collect();
+    for folder in &folders {
+        // NOTE: the id is what is common between devices, the label is local
+        let Some(id) = folder.attribute("id") else {
+            continue;
+        };
+        let path = folder.attribute("path").unwrap_or_default();
+
+        let mode = match folder.attribute("type").unwrap_or("sendreceive") {
+            "sendreceive" => TargetMode::PushPull,
+            "sendonly" => TargetMode::Push,
+            "receiveonly" => TargetMode::Pull,
+            other => {
+                imported.report.add(
+                    Severity::Error,
+                    format!(
+                        "folder \"{id}\" is of type \"{other}\" which has no equivalent, skipped"
+                    ),
+                );
+                continue;
+            }
+        };
+
+        let targets: Vec<Target> = folder
+            .children()
+            .filter(|n| n.has_tag_name("device"))
+            .filter_map(|n| {
+                let device_id = n.attribute("id")?;
+                let (_, name) = devices.iter().find(|(id, _)| *id == device_id)?;
+                Some(Target {
+                    mode: mode.clone(),
+                    node_name: name.clone(),
+                    include: vec![],
+                })
+            })
+            .collect();
+
+        let has_versioning = folder.children().any(|n| {
+            n.has_tag_name("versioning") && n.attribute("type").is_some_and(|t| !t.is_empty())
+        });
+        if has_versioning {
+            imported.report.add(
+                Severity::Warning,
+                format!("folder \"{id}\" file versioning isn't supported, it is ignored"),
+            );
+        }
+
+        if folder.attribute("paused") == Some("true") {
+            imported.report.add(
+                Severity::Info,
+                format!("folder \"{id}\" is paused on syncthing but will sync on fsy"),
+            );
+        }
+
+        imported.target_groups.push(TargetGroup {
+            name: id.into(),
+            path: path.to_owned(),
+            targets,
+            ..Default::default()
+        });
+    }
+
+    // syncthing lists this device on every folder too, we can't tell which
+    // one it is so the user needs to remove it
+    for (device_id, name) in &devices {
+        let is_everywhere = !folders.is_empty()
+            && folders.iter().all(|folder| {
+                folder
+                    .children()
+                    .any(|n| n.has_tag_name("device") && n.attribute("id") == Some(device_id))
+            });
+        if is_everywhere {
+            imported.report.add(
+                Severity::Info,
+                format!("node \"{name}\" is on every folder, if it is this device remove it and its targets"),
+            );
+        }
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    const CONFIG_XML: &str = r#"<configuration version="37">
+    <folder id="docs-123" label="Docs" path="~/Docs" type="sendreceive">
+        <device id="AAAAAAA-1" introducedBy=""></device>
+        <device id="BBBBBBB-2" introducedBy=""></device>
+        <versioning type="simple"></versioning>
+    </folder>
+    <folder id="photos-456" label="Photos" path="/data/photos" type="receiveonly" paused="true">
+        <device id="AAAAAAA-1" introducedBy=""></device>
+        <device id="CCCCCCC-3" introducedBy=""></device>
+        <versioning></versioning>
+    </folder>
+    <folder id="secret-789" label="Secret" path="/data/secret" type="receiveencrypted">
+        <device id="AAAAAAA-1" introducedBy=""></device>
+    </folder>
+    <device id="AAAAAAA-1" name="laptop"></device>
+    <device id="BBBBBBB-2" name="desktop"></device>
+    <device id="CCCCCCC-3" name=""></device>
+</configuration>"#;
+
+    #[test]
+    fn test_import_syncthing() -> Result<()> {
+        let imported = import_syncthing(CONFIG_XML)?;
+
+        let node_names: Vec<&str> = imported.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(node_names, vec!["laptop", "desktop", "CCCCCCC"]);
+
+        assert_eq!(imported.target_groups.len(), 2);
+        let docs = &imported.target_groups[0];
+        assert_eq!(docs.name, "docs-123");
+        assert_eq!(docs.path, "~/Docs");
+        assert_eq!(docs.targets.len(), 2);
+        assert!(docs.targets.iter().all(|t| t.mode == TargetMode::PushPull));
+
+        let photos = &imported.target_groups[1];
+        assert_eq!(photos.name, "photos-456");
+        assert_eq!(photos.targets[1].node_name, "CCCCCCC");
+        assert!(photos.targets.iter().all(|t| t.mode == TargetMode::Pull));
+
+        let severities: Vec<Severity> = imported
+            .report
+            .findings
+            .iter()
+            .map(|f| f.severity.clone())
+            .collect();
+        assert_eq!(
+            severities,
+            vec![
+                Severity::Warning, // laptop id
+                Severity::Warning, // desktop id
+                Severity::Warning, // CCCCCCC id
+                Severity::Warning, // docs versioning
+                Severity::Info,    // photos paused
+                Severity::Error,   // secret encrypted
+                Severity::Info,    // laptop everywhere
+            ]
+        );
+
+        // the output needs to be valid to append to a config
+        let content = imported.to_string();
+        assert!(content.contains("[[target_groups]]"));
+        assert!(toml::from_str::<toml::Value>(&content).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_syncthing_invalid() -> Result<()> {
+        assert!(import_syncthing("<configuration>").is_err());
+        assert!(
+            import_syncthing("<configuration></configuration>")?
+                .nodes
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
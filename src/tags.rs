@@ -0,0 +1,145 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::target::TargetGroup;
+
+pub const DISABLED_TAGS_FILE_NAME: &str = "disabled_tags.toml";
+
+// DisabledTags: tags whose groups the daemon leaves out, so a set of groups
+// (the ones of work on a home machine) is turned off and on at once
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DisabledTags {
+    pub tags: Vec<String>,
+}
+
+impl DisabledTags {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        let parsed: DisabledTags = toml::from_str(&content)?;
+        Ok(parsed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let content = toml::to_string(self)?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn is_disabled(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    // set_enabled enables or disables the tag, false if it already was
+    pub fn set_enabled(&mut self, tag: &str, enabled: bool) -> bool {
+        if enabled != self.is_disabled(tag) {
+            return false;
+        }
+
+        match enabled {
+            true => self.tags.retain(|t| t != tag),
+            false => self.tags.push(tag.to_owned()),
+        }
+        true
+    }
+
+    // is_group_disabled checks if the group has any of the disabled tags
+    pub fn is_group_disabled(&self, group: &TargetGroup) -> bool {
+        group.tags.iter().any(|tag| self.is_disabled(tag))
+    }
+
+    // take_disabled removes the groups with a disabled tag, retrieving
+    // their names
+    pub fn take_disabled(&self, groups: &mut Vec<TargetGroup>) -> Vec<String> {
+        let disabled = groups
+            .iter()
+            .filter(|g| self.is_group_disabled(g))
+            .map(|g| g.name.to_string())
+            .collect();
+        groups.retain(|g| !self.is_group_disabled(g));
+        disabled
+    }
+}
+
+// TagToggle: the outcome of enabling or disabling a tag
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TagToggle {
+    pub tag: String,
+    pub enabled: bool,
+    pub changed: bool,
+    pub group_names: Vec<String>, // groups with the tag
+}
+
+impl fmt::Display for TagToggle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "enabled" } else { "disabled" };
+        if !self.changed {
+            return writeln!(f, "tag \"{}\" was {state} already", self.tag);
+        }
+
+        writeln!(
+            f,
+            "tag \"{}\" {state}, restart the daemon to apply it to {} groups",
+            self.tag,
+            self.group_names.len()
+        )?;
+        for group_name in &self.group_names {
+            writeln!(f, "- {group_name}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_group(name: &str, tags: &[&str]) -> TargetGroup {
+        TargetGroup {
+            name: name.into(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_take_disabled() -> Result<()> {
+        let mut disabled = DisabledTags::default();
+        assert!(disabled.set_enabled("work", false));
+        assert!(!disabled.set_enabled("work", false));
+
+        let mut groups = vec![
+            get_group("reports", &["work"]),
+            get_group("photos", &[]),
+            get_group("notes", &["home", "work"]),
+            get_group("music", &["home"]),
+        ];
+        assert_eq!(
+            disabled.take_disabled(&mut groups),
+            vec!["reports", "notes"]
+        );
+        let names: Vec<&str> = groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, vec!["photos", "music"]);
+
+        // enabled again, nothing is left out
+        assert!(disabled.set_enabled("work", true));
+        assert!(!disabled.set_enabled("work", true));
+        let mut groups = vec![get_group("reports", &["work"])];
+        assert!(disabled.take_disabled(&mut groups).is_empty());
+        assert_eq!(groups.len(), 1);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,30 @@
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::ids::{GroupName, PeerId};
+use crate::{conflict, glob, reserved, sanitize, transform};
+
+// placeholders of the paths of a group filled with the date of the sync
+const DATE_PLACEHOLDERS: [(&str, &str); 5] = [
+    ("{YYYY-MM-DD}", "%Y-%m-%d"),
+    ("{YYYY}", "%Y"),
+    ("{MM}", "%m"),
+    ("{DD}", "%d"),
+    ("{HH}", "%H"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct NodeData {
     pub name: String, // unique identifier of this node for the user
-    pub id: String,
+    pub id: PeerId,
+    #[serde(default)]
+    pub daily_quota_bytes: Option<u64>, // soft limit of bytes per day, pauses pushes
+    #[serde(default)]
+    pub weekly_quota_bytes: Option<u64>, // soft limit of bytes per week, pauses pushes
+    #[serde(default)]
+    pub addrs: Vec<String>, // `ip:port` the node is reached on without discovery
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -20,17 +41,203 @@ pub enum TargetMode {
 pub struct Target {
     pub mode: TargetMode,  // is it only push? only pull? both?
     pub node_name: String, // trustee name, the descritive
+    #[serde(default)]
+    pub include: Vec<String>, // patterns of the paths pushed to the node, all if empty
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum Durability {
+    #[default]
+    #[serde(rename = "none")]
+    None, // leave it to the os to write to disk
+    #[serde(rename = "fsync")]
+    Fsync, // fsync the pulled file and its folder before moving on
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum Approval {
+    #[default]
+    #[serde(rename = "auto")]
+    Auto, // changes notified are pulled right away
+    #[serde(rename = "manual")]
+    Manual, // changes notified wait on the storage until `fsy approve`
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct TargetGroup {
-    pub name: String, // name identifier to be passed as unique communicator between nodes
+    pub name: GroupName, // name identifier to be passed as unique communicator between nodes
+    #[serde(default)]
     pub path: String, // path for the file / folder
+    #[serde(default)]
+    pub paths: Vec<String>, // folders of a group spanning many, instead of path
     pub targets: Vec<Target>, // targets to whom push / pull
+    #[serde(default)]
+    pub durability: Durability, // how safe pulled files should be on power loss
+    #[serde(default)]
+    pub priority: i32, // higher priority groups are synced first
+    #[serde(default)]
+    pub max_file_size: Option<u64>, // bigger files pause the group until confirmed
+    #[serde(default)]
+    pub max_files_per_batch: Option<usize>, // more changed files pause the group until confirmed
+    #[serde(default)]
+    pub sync_xattrs: bool, // extended attributes (tags, labels) go along with the files
+    #[serde(default)]
+    pub windows_path_policy: sanitize::WindowsPathPolicy, // invalid windows names when pulling there
+    #[serde(default)]
+    pub mirror: bool, // pulled files not on the pusher are moved to the trash
+    #[serde(default)]
+    pub archive: bool, // replaced files are kept on dated folders, a backup
+    #[serde(default)]
+    pub conflict: conflict::ConflictPolicy, // when both sides changed a file
+    #[serde(default)]
+    pub conflict_name: Option<String>, // naming of the conflict copies
+    #[serde(default)]
+    pub conflict_location: conflict::ConflictLocation, // where the conflict copies go
+    #[serde(default)]
+    pub snapshot: bool, // changed files are sent as they were all at once
+    #[serde(default)]
+    pub description: Option<String>, // what the group is about, for the user
+    #[serde(default)]
+    pub tags: Vec<String>, // labels to organize the groups, `fsy status --tag`
+    #[serde(default)]
+    pub chown: Option<String>, // owner of the pulled files, "user:group"
+    #[serde(default)]
+    pub chmod: Option<String>, // octal mode of the pulled files, "640"
+    #[serde(default)]
+    pub umask: Option<String>, // octal umask of the pulled files when no chmod, "027"
+    #[serde(default)]
+    pub serve_http: bool, // the files are served read only over http
+    #[serde(default)]
+    pub http_bind: Option<String>, // address the files are served on, "0.0.0.0:8080"
+    #[serde(default)]
+    pub push_debounce_millisecs: Option<u64>, // overrides the local one for the group
+    #[serde(default)]
+    pub transforms: Vec<transform::Transform>, // what the files go through before being sent
+    #[serde(default)]
+    pub max_parallel_actions: Option<usize>, // actions of the group run at once, 1 if unset
+    #[serde(default)]
+    pub tombstone_generations: Option<u64>, // generations removed files are remembered for, 1000 if unset
+    #[serde(default)]
+    pub append_only: bool, // the files only grow (logs, journals), only what was appended is sent
+    #[serde(default)]
+    pub approval: Approval, // if the changes notified are pulled right away or wait for approval
+    #[serde(default)]
+    pub fs_snapshot_command: Option<String>, // snapshots the file system before a batch of pulls
+    #[serde(default)]
+    pub fs_rollback_command: Option<String>, // rolls the file system back to a snapshot, `fsy rollback`
+    #[serde(default)]
+    pub delete_grace_secs: Option<u64>, // removed files are only removed on the pullers if still gone after it
+    #[serde(default)]
+    pub max_versions: Option<usize>, // old versions of each file an archive keeps, all if unset
+}
+
+// GroupRoot: a folder of the group. on a group of many paths the files go
+// prefixed with the namespace of their root (its position on `paths`), on a
+// group of a single path they don't
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupRoot {
+    pub namespace: String,
+    pub path: String,
+}
+
+impl GroupRoot {
+    // get_group_relative_path retrieves the path in the group of a path
+    // relative to the root
+    pub fn get_group_relative_path(&self, relative_path: &str) -> String {
+        match (self.namespace.is_empty(), relative_path.is_empty()) {
+            (true, _) => relative_path.to_owned(),
+            (false, true) => self.namespace.clone(),
+            (false, false) => format!("{}/{relative_path}", self.namespace),
+        }
+    }
 }
 
 impl TargetGroup {
-    pub fn get_node_ids(&self, nodes: &[NodeData], modes: &[TargetMode]) -> Vec<String> {
+    // get_path retrieves the path of the group as of now, its placeholders
+    // filled
+    pub fn get_path(&self) -> String {
+        render_path(&self.path, &self.name, Local::now())
+    }
+
+    pub fn get_roots(&self) -> Vec<GroupRoot> {
+        if self.paths.is_empty() {
+            return vec![GroupRoot {
+                namespace: "".to_owned(),
+                path: self.get_path(),
+            }];
+        }
+
+        let now = Local::now();
+        self.paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| GroupRoot {
+                namespace: i.to_string(),
+                path: render_path(path, &self.name, now),
+            })
+            .collect()
+    }
+
+    // is_dated checks if where the group is changes with the date
+    pub fn is_dated(&self) -> bool {
+        is_dated_path(&self.path) || self.paths.iter().any(|p| is_dated_path(p))
+    }
+
+    pub fn get_root_with_path(&self, root_path: &str) -> Option<GroupRoot> {
+        self.get_roots().into_iter().find(|r| r.path == root_path)
+    }
+
+    // get_root_of_file retrieves the root a file of the group is under
+    pub fn get_root_of_file(&self, file_path: &Path) -> Option<GroupRoot> {
+        self.get_roots()
+            .into_iter()
+            .find(|r| file_path.starts_with(&r.path))
+    }
+
+    // resolve_relative_path retrieves the root of a path in the group along
+    // with the path relative to it, none if no root has its namespace or it
+    // is where fsy keeps its files
+    pub fn resolve_relative_path(&self, relative_path: &str) -> Option<(GroupRoot, String)> {
+        let mut roots = self.get_roots();
+        let (root, relative_path) = match self.paths.is_empty() {
+            true => (roots.remove(0), relative_path),
+            false => {
+                let (namespace, relative_path) =
+                    relative_path.split_once('/').unwrap_or((relative_path, ""));
+                let root = roots.into_iter().find(|r| r.namespace == namespace)?;
+                (root, relative_path)
+            }
+        };
+
+        if reserved::is_reserved_path(Path::new(relative_path)) {
+            return None;
+        }
+        Some((root, relative_path.to_owned()))
+    }
+
+    // get_file_path retrieves where a path in the group is on the disk
+    pub fn get_file_path(&self, relative_path: &str) -> Option<PathBuf> {
+        let (root, relative_path) = self.resolve_relative_path(relative_path)?;
+        Some(Path::new(&root.path).join(relative_path))
+    }
+
+    fn is_pull_only(&self) -> bool {
+        self.targets.iter().all(|t| t.mode == TargetMode::Pull)
+    }
+
+    // is_mirror checks if the group mirrors the pusher. a group that also
+    // pushes can't, its own new files would be taken as extraneous
+    pub fn is_mirror(&self) -> bool {
+        self.mirror && self.is_pull_only()
+    }
+
+    // is_archive checks if the group keeps the old versions of what it
+    // pulls. a group that also pushes would push the versions back
+    pub fn is_archive(&self) -> bool {
+        self.archive && self.is_pull_only()
+    }
+
+    pub fn get_node_ids(&self, nodes: &[NodeData], modes: &[TargetMode]) -> Vec<PeerId> {
         let target_names: Vec<String> = self
             .targets
             .iter()
@@ -54,6 +261,64 @@ impl TargetGroup {
             })
             .collect()
     }
+
+    // includes_path checks if a path of the group goes to the node, a push
+    // target with `include` only gets the paths matching one of them
+    pub fn includes_path(&self, nodes: &[NodeData], node_id: &str, relative_path: &str) -> bool {
+        let Some(node) = nodes.iter().find(|node| node.id == node_id) else {
+            return true;
+        };
+
+        let mut targets = self
+            .targets
+            .iter()
+            .filter(|t| t.node_name == node.name && t.mode != TargetMode::Pull)
+            .peekable();
+        targets.peek().is_none()
+            || targets.any(|t| {
+                t.include.is_empty() || t.include.iter().any(|p| glob::is_match(p, relative_path))
+            })
+    }
+}
+
+// get_group_priority retrieves the priority of the group with the name,
+// anything not related to a group has the default priority
+// render_path fills the placeholders of a path of a group: `{group}` with
+// its name and the dated ones with the date, so what is pulled lands on a
+// folder of the day (`~/backups/{group}/{YYYY-MM-DD}`)
+pub fn render_path(path: &str, group_name: &str, date: DateTime<Local>) -> String {
+    if !path.contains('{') {
+        return path.to_owned();
+    }
+
+    DATE_PLACEHOLDERS.iter().fold(
+        path.replace("{group}", group_name),
+        |path, (placeholder, format)| path.replace(placeholder, &date.format(format).to_string()),
+    )
+}
+
+pub fn is_dated_path(path: &str) -> bool {
+    DATE_PLACEHOLDERS.iter().any(|(p, _)| path.contains(p))
+}
+
+pub fn get_group_priority(groups: &[TargetGroup], name: Option<&str>) -> i32 {
+    name.and_then(|name| groups.iter().find(|g| g.name == name))
+        .map(|g| g.priority)
+        .unwrap_or_default()
+}
+
+// get_enabled_features lists the optional features the groups make use of,
+// peers get to know them so mismatches are easy to spot
+pub fn get_enabled_features(groups: &[TargetGroup]) -> Vec<String> {
+    let mut features = vec![];
+    if groups.iter().any(|g| g.sync_xattrs) {
+        features.push("xattrs".to_owned());
+    }
+    if groups.iter().any(|g| g.durability == Durability::Fsync) {
+        features.push("fsync".to_owned());
+    }
+
+    features
 }
 
 pub fn get_push_group_with_name(groups: &[TargetGroup], name: &str) -> Option<TargetGroup> {
@@ -72,6 +337,19 @@ pub fn get_push_group_with_name(groups: &[TargetGroup], name: &str) -> Option<Ta
         })
 }
 
+// get_push_group_names retrieves the names of the groups we push
+pub fn get_push_group_names(groups: &[TargetGroup]) -> Vec<GroupName> {
+    groups
+        .iter()
+        .filter(|item| {
+            item.targets
+                .iter()
+                .any(|t| t.mode == TargetMode::Push || t.mode == TargetMode::PushPull)
+        })
+        .map(|item| item.name.clone())
+        .collect()
+}
+
 pub fn get_push_group_paths(groups: &[TargetGroup]) -> Vec<String> {
     groups
         .iter()
@@ -84,8 +362,38 @@ pub fn get_push_group_paths(groups: &[TargetGroup]) -> Vec<String> {
                 return None;
             }
 
-            Some(item.path.clone())
+            Some(item.get_roots().into_iter().map(|r| r.path))
         })
+        .flatten()
+        .collect()
+}
+
+// get_push_group_debounces retrieves the paths of the push groups with their
+// own debounce
+pub fn get_push_group_debounces(groups: &[TargetGroup]) -> HashMap<String, u64> {
+    get_push_group_paths_with(groups, |group| group.push_debounce_millisecs)
+}
+
+// get_push_group_delete_graces retrieves the paths of the push groups with
+// how long their removed files wait to be removed on the pullers, in ms
+pub fn get_push_group_delete_graces(groups: &[TargetGroup]) -> HashMap<String, u64> {
+    get_push_group_paths_with(groups, |group| {
+        group
+            .delete_grace_secs
+            .map(|secs| secs.saturating_mul(1000))
+    })
+}
+
+fn get_push_group_paths_with(
+    groups: &[TargetGroup],
+    get_value: impl Fn(&TargetGroup) -> Option<u64>,
+) -> HashMap<String, u64> {
+    let push_paths = get_push_group_paths(groups);
+    groups
+        .iter()
+        .filter_map(|group| Some((group, get_value(group)?)))
+        .flat_map(|(group, value)| group.get_roots().into_iter().map(move |r| (r.path, value)))
+        .filter(|(path, _)| push_paths.contains(path))
         .collect()
 }
 
@@ -101,15 +409,14 @@ pub fn get_push_groups_with_path(groups: &[TargetGroup], file_path: &str) -> Vec
                 return None;
             }
 
-            if item.path != file_path {
-                return None;
-            }
+            item.get_root_with_path(file_path)?;
 
             Some(item.clone())
         })
         .collect()
 }
 
+#[allow(dead_code)]
 pub fn get_pull_group_paths(groups: &[TargetGroup]) -> Vec<String> {
     groups
         .iter()
@@ -122,8 +429,9 @@ pub fn get_pull_group_paths(groups: &[TargetGroup]) -> Vec<String> {
                 return None;
             }
 
-            Some(item.path.clone())
+            Some(item.get_roots().into_iter().map(|r| r.path))
         })
+        .flatten()
         .collect()
 }
 
@@ -143,6 +451,16 @@ pub fn get_pull_group_with_name(groups: &[TargetGroup], name: &str) -> Option<Ta
         })
 }
 
+// get_node_name retrieves the name of the node on the config, its id if it
+// isn't there
+pub fn get_node_name(nodes: &[NodeData], node_id: &str) -> String {
+    nodes
+        .iter()
+        .find(|node| node.id == node_id)
+        .map(|node| node.name.clone())
+        .unwrap_or(node_id.to_owned())
+}
+
 pub fn group_has_node_id(group: &TargetGroup, nodes: &[NodeData], node_id: &str) -> bool {
     nodes.iter().any(|node| {
         if node.id != node_id {
@@ -152,3 +470,130 @@ pub fn group_has_node_id(group: &TargetGroup, nodes: &[NodeData], node_id: &str)
         group.targets.iter().any(|target| target.node_name == node.name)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_resolve_relative_path() -> Result<()> {
+        let single = TargetGroup {
+            path: "/a".to_string(),
+            ..Default::default()
+        };
+        let many = TargetGroup {
+            paths: vec!["/a".to_string(), "/b".to_string()],
+            ..Default::default()
+        };
+
+        let test_values = [
+            // (group, relative_path, expected (root path, relative path))
+            (&single, "foo/bar.txt", Some(("/a", "foo/bar.txt"))),
+            (&single, "", Some(("/a", ""))),
+            (&many, "0/foo/bar.txt", Some(("/a", "foo/bar.txt"))),
+            (&many, "1/bar.txt", Some(("/b", "bar.txt"))),
+            (&many, "1", Some(("/b", ""))),
+            (&many, "2/bar.txt", None),
+            (&many, "bar.txt", None),
+            (&single, ".fsy/locks/a.txt", None),
+            (&many, "0/.fsy/locks/a.txt", None),
+        ];
+
+        for spec in test_values {
+            let resolved = spec.0.resolve_relative_path(spec.1);
+            let resolved = resolved
+                .as_ref()
+                .map(|(root, relative_path)| (root.path.as_str(), relative_path.as_str()));
+            assert_eq!(resolved, spec.2, "{}", spec.1);
+
+            // back and forth is the same path
+            if let Some((root, relative_path)) = spec.0.resolve_relative_path(spec.1) {
+                assert_eq!(root.get_group_relative_path(&relative_path), spec.1);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_path() -> Result<()> {
+        let date = Local.with_ymd_and_hms(2024, 3, 5, 7, 30, 0).unwrap();
+        let test_values = [
+            // (path, rendered, dated)
+            ("/backups/docs", "/backups/docs", false),
+            ("/backups/{group}", "/backups/docs", false),
+            (
+                "/backups/{group}/{YYYY-MM-DD}",
+                "/backups/docs/2024-03-05",
+                true,
+            ),
+            (
+                "/backups/{YYYY}/{MM}/{DD}/{HH}",
+                "/backups/2024/03/05/07",
+                true,
+            ),
+            ("/backups/{node}", "/backups/{node}", false),
+        ];
+        for spec in test_values {
+            assert_eq!(render_path(spec.0, "docs", date), spec.1, "{spec:?}");
+            assert_eq!(is_dated_path(spec.0), spec.2, "{spec:?}");
+        }
+
+        let group = TargetGroup {
+            name: "docs".into(),
+            paths: vec!["/a".to_string(), "/b/{group}".to_string()],
+            ..Default::default()
+        };
+        assert!(!group.is_dated());
+        assert_eq!(group.get_roots()[1].path, "/b/docs");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_includes_path() -> Result<()> {
+        let nodes: Vec<NodeData> = ["vps", "laptop", "nas"]
+            .iter()
+            .map(|name| NodeData {
+                name: name.to_string(),
+                id: format!("{name}_id").into(),
+                ..Default::default()
+            })
+            .collect();
+        let group = TargetGroup {
+            name: "photos".into(),
+            targets: vec![
+                Target {
+                    mode: TargetMode::Push,
+                    node_name: "vps".to_string(),
+                    include: vec!["2024/**".to_string(), "*.txt".to_string()],
+                },
+                Target {
+                    mode: TargetMode::PushPull,
+                    node_name: "laptop".to_string(),
+                    include: vec![],
+                },
+            ],
+            ..Default::default()
+        };
+
+        let test_values = [
+            // (node_id, relative_path, expected)
+            ("vps_id", "2024/trip/a.jpg", true),
+            ("vps_id", "notes.txt", true),
+            ("vps_id", "2023/a.jpg", false),
+            ("laptop_id", "2023/a.jpg", true),
+            ("nas_id", "2023/a.jpg", true),
+            ("unknown", "2023/a.jpg", true),
+        ];
+
+        for (node_id, relative_path, expected) in test_values {
+            let included = group.includes_path(&nodes, node_id, relative_path);
+            assert_eq!(included, expected, "{node_id} {relative_path}");
+        }
+
+        Ok(())
+    }
+}
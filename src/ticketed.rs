@@ -0,0 +1,109 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ids::{GroupName, PeerId, RelPath};
+use crate::manifest::FileEntry;
+
+// tickets kept, the oldest are forgotten first
+const MAX_TICKETS: usize = 10_000;
+
+// TicketedFile: a file a ticket was made of for a node, as it was then
+#[derive(Debug, Clone, PartialEq)]
+pub struct TicketedFile {
+    pub node_id: PeerId,
+    pub target_name: GroupName,
+    pub relative_path: RelPath, // in the group, as sent to the node
+    pub file_path: PathBuf,
+    pub entry: FileEntry,
+}
+
+impl TicketedFile {
+    // has_changed checks if the file isn't as it was when ticketed, the
+    // ticket has stale content then. a file gone is left to its remove
+    pub fn has_changed(&self) -> bool {
+        fs::metadata(&self.file_path).is_ok_and(|meta| FileEntry::from(&meta) != self.entry)
+    }
+}
+
+// Ticketed: the files tickets were made of by hash and node, so a download
+// of a file that changed since gets a new ticket
+#[derive(Debug, Clone, Default)]
+pub struct Ticketed {
+    files: HashMap<(String, String), TicketedFile>,
+    order: VecDeque<(String, String)>, // by when they were ticketed
+}
+
+impl Ticketed {
+    pub fn add(&mut self, hash: &str, file: TicketedFile) {
+        let key = (hash.to_owned(), file.node_id.to_string());
+        if !self.files.contains_key(&key) {
+            if self.order.len() >= MAX_TICKETS
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.files.remove(&oldest);
+            }
+            self.order.push_back(key.clone());
+        }
+
+        self.files.insert(key, file);
+    }
+
+    // get retrieves the file the ticket of the hash was made of for the node
+    pub fn get(&self, hash: &str, node_id: &str) -> Option<&TicketedFile> {
+        self.files.get(&(hash.to_owned(), node_id.to_owned()))
+    }
+
+    // take_stale retrieves the file of the ticket the node is downloading
+    // when it changed since, forgetting it
+    pub fn take_stale(&mut self, hash: &str, node_id: &str) -> Option<TicketedFile> {
+        let key = (hash.to_owned(), node_id.to_owned());
+        if !self.files.get(&key)?.has_changed() {
+            return None;
+        }
+
+        self.order.retain(|k| *k != key);
+        self.files.remove(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_take_stale() -> Result<()> {
+        let dir = std::env::temp_dir().join("fsy_test_ticketed");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let file_path = dir.join("a.txt");
+        fs::write(&file_path, "foo")?;
+
+        let mut ticketed = Ticketed::default();
+        let file = TicketedFile {
+            node_id: "node".into(),
+            target_name: "docs".into(),
+            relative_path: "a.txt".into(),
+            file_path: file_path.clone(),
+            entry: FileEntry::from(&fs::metadata(&file_path)?),
+        };
+        ticketed.add("abc", file.clone());
+
+        assert_eq!(ticketed.get("abc", "node"), Some(&file));
+        assert_eq!(ticketed.get("abc", "other"), None);
+
+        // as it was, or of another node
+        assert_eq!(ticketed.take_stale("abc", "node"), None);
+        fs::write(&file_path, "foo bar")?;
+        assert_eq!(ticketed.take_stale("abc", "other"), None);
+        assert_eq!(ticketed.take_stale("zed", "node"), None);
+
+        // changed since, only taken once
+        assert_eq!(ticketed.take_stale("abc", "node"), Some(file));
+        assert_eq!(ticketed.take_stale("abc", "node"), None);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
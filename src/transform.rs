@@ -0,0 +1,268 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::target::TargetGroup;
+
+// where the transformed copies of the files sent are kept, on the storage
+pub const TRANSFORMED_DIR_NAME: &str = "transformed";
+
+// jpeg markers, see `strip_exif`
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_APP1: u8 = 0xE1;
+const JPEG_SOS: u8 = 0xDA;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    #[serde(rename = "lf")]
+    Lf,
+    #[serde(rename = "crlf")]
+    Crlf,
+}
+
+// Transform: what a push group does to its files before they are sent, the
+// files on the disk are left as they are
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind")]
+pub enum Transform {
+    #[serde(rename = "strip-exif")]
+    StripExif, // jpegs go without their metadata (camera, location, ...)
+    #[serde(rename = "line-endings")]
+    LineEndings { to: LineEnding }, // text files go with the same line endings
+    #[serde(rename = "command")]
+    Command { command: String }, // the file goes through stdin to stdout of the command
+}
+
+// strip_exif retrieves the jpeg without its exif segments, anything else is
+// left as it is
+pub fn strip_exif(content: &[u8]) -> Vec<u8> {
+    if !content.starts_with(&JPEG_SOI) {
+        return content.to_vec();
+    }
+
+    let mut stripped = JPEG_SOI.to_vec();
+    let mut pos = JPEG_SOI.len();
+    while pos + 4 <= content.len() && content[pos] == 0xFF {
+        let marker = content[pos + 1];
+        // NOTE: the image data goes after the start of scan, as it is
+        if marker == JPEG_SOS {
+            break;
+        }
+
+        let len = u16::from_be_bytes([content[pos + 2], content[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > content.len() {
+            return content.to_vec();
+        }
+
+        let data = &content[pos + 4..end];
+        if marker != JPEG_APP1 || !data.starts_with(EXIF_HEADER) {
+            stripped.extend_from_slice(&content[pos..end]);
+        }
+        pos = end;
+    }
+
+    stripped.extend_from_slice(&content[pos..]);
+    stripped
+}
+
+// convert_line_endings retrieves the text with its line endings as asked,
+// binary content (with a nul) is left as it is
+pub fn convert_line_endings(content: &[u8], to: &LineEnding) -> Vec<u8> {
+    if content.contains(&0) {
+        return content.to_vec();
+    }
+
+    let mut converted = Vec::with_capacity(content.len());
+    for (i, byte) in content.iter().enumerate() {
+        match byte {
+            b'\r' if content.get(i + 1) == Some(&b'\n') => {}
+            b'\n' if *to == LineEnding::Crlf => converted.extend_from_slice(b"\r\n"),
+            _ => converted.push(*byte),
+        }
+    }
+
+    converted
+}
+
+// run_command passes the content through the command (on the shell), with
+// the path in the group on $FSY_PATH
+fn run_command(command: &str, relative_path: &str, content: &[u8]) -> Result<Vec<u8>> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    let mut child = cmd
+        .env("FSY_PATH", relative_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // NOTE: written on its own thread, a command writing as it reads would
+    //       block on a full stdout otherwise
+    let mut stdin = child.stdin.take();
+    let input = content.to_vec();
+    let writer = std::thread::spawn(move || stdin.as_mut().map(|s| s.write_all(&input)));
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        bail!(
+            "transform command \"{command}\" failed on {relative_path}: {}",
+            output.status
+        );
+    }
+    Ok(output.stdout)
+}
+
+// apply_transforms retrieves the content after going through all the
+// transforms, in order
+pub fn apply_transforms(
+    transforms: &[Transform],
+    relative_path: &str,
+    content: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let mut content = content;
+    for transform in transforms {
+        content = match transform {
+            Transform::StripExif => strip_exif(&content),
+            Transform::LineEndings { to } => convert_line_endings(&content, to),
+            Transform::Command { command } => run_command(command, relative_path, &content)?,
+        };
+    }
+
+    Ok(content)
+}
+
+// get_sent_path retrieves the file to send for a file of the group, itself
+// when the group has no transforms, a transformed copy on the storage
+// otherwise. the copy is what gets hashed and ticketed
+pub fn get_sent_path(
+    storage_path: &Path,
+    group: &TargetGroup,
+    file_path: &Path,
+) -> Result<PathBuf> {
+    if group.transforms.is_empty() || !file_path.is_file() {
+        return Ok(file_path.to_path_buf());
+    }
+
+    let Some(root) = group.get_root_of_file(file_path) else {
+        bail!("{} is not a file of {}", file_path.display(), group.name);
+    };
+    let relative_path = file_path.strip_prefix(&root.path)?.to_string_lossy();
+    let relative_path = root.get_group_relative_path(&relative_path);
+
+    let content = apply_transforms(&group.transforms, &relative_path, fs::read(file_path)?)?;
+    let sent_path = storage_path
+        .join(TRANSFORMED_DIR_NAME)
+        .join(group.name.as_str())
+        .join(match relative_path.is_empty() {
+            true => file_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            false => relative_path,
+        });
+    if let Some(parent) = sent_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&sent_path, content)?;
+
+    Ok(sent_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn get_jpeg(segments: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut content = JPEG_SOI.to_vec();
+        for (marker, data) in segments {
+            content.extend_from_slice(&[0xFF, *marker]);
+            content.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+            content.extend_from_slice(data);
+        }
+        content.extend_from_slice(&[0xFF, JPEG_SOS, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        content
+    }
+
+    #[test]
+    fn test_strip_exif() -> Result<()> {
+        let exif = [EXIF_HEADER, b"gps"].concat();
+        let jfif: &[u8] = b"JFIF\0";
+        let test_values = [
+            // (content, expected)
+            (
+                get_jpeg(&[(0xE0, jfif), (JPEG_APP1, &exif)]),
+                get_jpeg(&[(0xE0, jfif)]),
+            ),
+            (get_jpeg(&[(0xE0, jfif)]), get_jpeg(&[(0xE0, jfif)])),
+            (
+                get_jpeg(&[(JPEG_APP1, b"http://ns.adobe.com/xap/1.0/\0")]),
+                get_jpeg(&[(JPEG_APP1, b"http://ns.adobe.com/xap/1.0/\0")]),
+            ),
+            (b"not a jpeg".to_vec(), b"not a jpeg".to_vec()),
+            (
+                vec![0xFF, 0xD8, 0xFF, 0xE1, 0xFF],
+                vec![0xFF, 0xD8, 0xFF, 0xE1, 0xFF],
+            ),
+        ];
+
+        for spec in test_values {
+            assert_eq!(strip_exif(&spec.0), spec.1, "{:?}", spec.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_line_endings() -> Result<()> {
+        let test_values = [
+            // (content, to, expected)
+            ("a\r\nb\nc", LineEnding::Lf, "a\nb\nc"),
+            ("a\r\nb\nc", LineEnding::Crlf, "a\r\nb\r\nc"),
+            ("a\rb", LineEnding::Lf, "a\rb"),
+            ("a\0\r\n", LineEnding::Lf, "a\0\r\n"),
+            ("", LineEnding::Crlf, ""),
+        ];
+
+        for spec in test_values {
+            let converted = convert_line_endings(spec.0.as_bytes(), &spec.1);
+            assert_eq!(converted, spec.2.as_bytes(), "{:?}", spec);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_apply_transforms() -> Result<()> {
+        let transforms = [
+            Transform::LineEndings { to: LineEnding::Lf },
+            Transform::Command {
+                command: "tr a-z A-Z; printf \"$FSY_PATH\"".to_string(),
+            },
+        ];
+        let content = apply_transforms(&transforms, "b.txt", b"foo\r\n".to_vec())?;
+        assert_eq!(content, b"FOO\nb.txt");
+
+        let transforms = [Transform::Command {
+            command: "exit 1".to_string(),
+        }];
+        assert!(apply_transforms(&transforms, "b.txt", b"foo".to_vec()).is_err());
+
+        Ok(())
+    }
+}
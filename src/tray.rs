@@ -0,0 +1,372 @@
+// NOTE: only the icon uses the state, builds without it keep it tested
+#![cfg_attr(not(all(feature = "tray", target_os = "linux")), allow(dead_code))]
+
+use anyhow::Result;
+use std::fmt;
+
+use crate::config;
+use crate::health;
+use crate::history::Synced;
+use crate::status::Status;
+
+// errors of a group newer than this show on the tray
+const RECENT_ERROR_SECS: i64 = 10 * 60;
+
+// SyncState: what the tray shows of the running daemon
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncState {
+    Offline,        // the daemon isn't running
+    Paused(String), // why the sync waits
+    Error(String),  // the first problem found
+    Syncing,
+    Idle,
+}
+
+impl fmt::Display for SyncState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Offline => write!(f, "not running"),
+            Self::Paused(reason) => write!(f, "paused, {reason}"),
+            Self::Error(problem) => write!(f, "error, {problem}"),
+            Self::Syncing => write!(f, "syncing"),
+            Self::Idle => write!(f, "up to date"),
+        }
+    }
+}
+
+// get_sync_state retrieves what the tray shows of the status of the daemon,
+// the problems over what it is doing
+pub fn get_sync_state(status: &Status, now: i64) -> SyncState {
+    if let Some(reason) = &status.paused {
+        return SyncState::Paused(reason.clone());
+    }
+
+    let mut problems = health::get_problems(status, now, false);
+    for group in &status.groups {
+        if let Some(reason) = &group.paused {
+            problems.push(format!(
+                "{}: waiting for confirmation, {reason}",
+                group.name
+            ));
+        }
+        if let Some(reason) = &group.degraded {
+            problems.push(format!("{}: {reason}", group.name));
+        }
+        if let Some(error) = &group.last_error
+            && now - error.timestamp.timestamp() <= RECENT_ERROR_SECS
+        {
+            problems.push(format!("{}: {}", group.name, error.message));
+        }
+    }
+    if let Some(problem) = problems.into_iter().next() {
+        return SyncState::Error(problem);
+    }
+
+    let is_syncing = status.queue.depth > 0
+        || status
+            .groups
+            .iter()
+            .any(|g| g.scan.is_some() || g.pull.is_some());
+    match is_syncing {
+        true => SyncState::Syncing,
+        false => SyncState::Idle,
+    }
+}
+
+// get_synced_label retrieves the line of the menu of a path synced. the
+// underscores go doubled, a single one marks the access key of the item
+fn get_synced_label(synced: &Synced) -> String {
+    let timestamp = synced.timestamp.with_timezone(&chrono::Local);
+    let label = format!(
+        "{} {}: {} {} {}",
+        timestamp.format("%H:%M"),
+        synced.group_name,
+        synced.relative_path,
+        synced.direction.get_arrow(),
+        synced.node_name
+    );
+    label.replace('_', "__")
+}
+
+#[cfg(all(feature = "tray", target_os = "linux"))]
+mod icon {
+    use anyhow::{Result, bail};
+    use chrono::Utc;
+    use ksni::TrayMethods;
+    use ksni::menu::{CheckmarkItem, MenuItem, StandardItem};
+    use std::time::Duration;
+    use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+    use super::{SyncState, get_sync_state, get_synced_label};
+    use crate::{client, config, history, status};
+
+    // how often the tray asks the daemon how it is doing
+    const POLL_SECS: u64 = 2;
+
+    // paths synced listed on the menu
+    const RECENT_COUNT: usize = 5;
+
+    enum TrayAction {
+        Pause(bool),
+        Quit,
+    }
+
+    struct FsyTray {
+        state: SyncState,
+        recent: history::Recent,
+        actions_tx: UnboundedSender<TrayAction>, // the menu only hands over, the loop talks to the daemon
+    }
+
+    impl ksni::Tray for FsyTray {
+        const MENU_ON_ACTIVATE: bool = true;
+
+        fn id(&self) -> String {
+            "fsy".to_string()
+        }
+
+        fn title(&self) -> String {
+            format!("fsy: {}", self.state)
+        }
+
+        fn icon_name(&self) -> String {
+            get_icon_name(&self.state).to_string()
+        }
+
+        fn tool_tip(&self) -> ksni::ToolTip {
+            ksni::ToolTip {
+                title: "fsy".to_string(),
+                description: self.state.to_string(),
+                ..Default::default()
+            }
+        }
+
+        fn menu(&self) -> Vec<MenuItem<Self>> {
+            let mut items: Vec<MenuItem<Self>> = vec![
+                StandardItem {
+                    label: self.state.to_string().replace('_', "__"),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+            ];
+
+            if self.recent.synced.is_empty() {
+                items.push(
+                    StandardItem {
+                        label: "nothing synced yet".to_string(),
+                        enabled: false,
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+            }
+            for synced in &self.recent.synced {
+                items.push(
+                    StandardItem {
+                        label: get_synced_label(synced),
+                        enabled: false,
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+            }
+            items.push(MenuItem::Separator);
+
+            let is_paused = matches!(self.state, SyncState::Paused(_));
+            items.push(
+                CheckmarkItem {
+                    label: "Pause sync".to_string(),
+                    enabled: self.state != SyncState::Offline,
+                    checked: is_paused,
+                    activate: Box::new(move |tray: &mut Self| {
+                        let _ = tray.actions_tx.send(TrayAction::Pause(!is_paused));
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+            );
+            items.push(
+                StandardItem {
+                    label: "Quit".to_string(),
+                    activate: Box::new(|tray: &mut Self| {
+                        let _ = tray.actions_tx.send(TrayAction::Quit);
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+            );
+
+            items
+        }
+    }
+
+    // get_icon_name retrieves the icon of the state, of the freedesktop
+    // naming spec so every desktop theme has them
+    fn get_icon_name(state: &SyncState) -> &'static str {
+        match state {
+            SyncState::Offline => "network-offline",
+            SyncState::Paused(_) => "media-playback-pause",
+            SyncState::Error(_) => "dialog-error",
+            SyncState::Syncing => "view-refresh",
+            SyncState::Idle => "emblem-default",
+        }
+    }
+
+    // get_state asks the running daemon for its status, offline if it
+    // doesn't answer
+    async fn get_state(config: &config::Config) -> SyncState {
+        let Ok((200, status)) = client::request(config, "GET", "/status", "").await else {
+            return SyncState::Offline;
+        };
+
+        match serde_json::from_value::<status::Status>(status) {
+            Ok(status) => get_sync_state(&status, Utc::now().timestamp()),
+            Err(_) => SyncState::Offline,
+        }
+    }
+
+    async fn set_paused(config: &config::Config, paused: bool) -> Result<()> {
+        let body = serde_json::json!({ "paused": paused });
+        let (code, res) = client::request(config, "POST", "/pause", &body.to_string()).await?;
+        if code != 200 {
+            bail!("{}", res["error"].as_str().unwrap_or("unable to pause"));
+        }
+        Ok(())
+    }
+
+    // run shows the tray until quit from its menu, refreshing it with what
+    // the daemon does. the daemon going away leaves it as not running
+    pub async fn run(config: &config::Config) -> Result<()> {
+        let storage_path = config.get_storage_path();
+        let (actions_tx, mut actions_rx) = unbounded_channel();
+        let tray = FsyTray {
+            state: get_state(config).await,
+            recent: history::get_recent(&storage_path, None, RECENT_COUNT),
+            actions_tx,
+        };
+        let handle = match tray.spawn().await {
+            Ok(handle) => handle,
+            Err(e) => bail!("unable to show the tray icon, does the desktop have a tray? {e}"),
+        };
+
+        let mut interval = tokio::time::interval(Duration::from_secs(POLL_SECS));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                Some(action) = actions_rx.recv() => match action {
+                    TrayAction::Quit => break,
+                    TrayAction::Pause(paused) => {
+                        if let Err(e) = set_paused(config, paused).await {
+                            eprintln!("unable to pause the sync: {e}");
+                        }
+                    }
+                },
+            }
+
+            let state = get_state(config).await;
+            let recent = history::get_recent(&storage_path, None, RECENT_COUNT);
+            let updated = handle
+                .update(|tray| {
+                    tray.state = state;
+                    tray.recent = recent;
+                })
+                .await;
+            if updated.is_none() {
+                break;
+            }
+        }
+
+        handle.shutdown().await;
+        Ok(())
+    }
+}
+
+// run shows the tray icon of the daemon of the config, only a build with
+// the `tray` feature has it
+#[cfg(all(feature = "tray", target_os = "linux"))]
+pub async fn run(config: &config::Config) -> Result<()> {
+    icon::run(config).await
+}
+
+#[cfg(not(all(feature = "tray", target_os = "linux")))]
+pub async fn run(_config: &config::Config) -> Result<()> {
+    anyhow::bail!("fsy was built without the tray, build it with `--features tray` (linux only)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::Health;
+    use crate::history::Direction;
+    use crate::status::GroupError;
+    use crate::target::TargetGroup;
+    use anyhow::Result;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_get_sync_state() -> Result<()> {
+        let now = 1700000000;
+        let target_groups = vec![TargetGroup {
+            name: "docs".into(),
+            ..Default::default()
+        }];
+        let mut healthy = Status::new("1234", &target_groups);
+        healthy.health = Health {
+            checked_at: now,
+            ..Default::default()
+        };
+        healthy.health.network.bound = true;
+
+        let error = |seconds_ago: i64| GroupError {
+            message: "unable to pull".to_string(),
+            timestamp: DateTime::from_timestamp(now - seconds_ago, 0).unwrap(),
+        };
+        let test_values = [
+            // (paused, queue depth, last error, expected)
+            (None, 0, None, SyncState::Idle),
+            (None, 3, None, SyncState::Syncing),
+            (None, 0, Some(error(RECENT_ERROR_SECS + 1)), SyncState::Idle),
+            (
+                None,
+                3,
+                Some(error(60)),
+                SyncState::Error("docs: unable to pull".to_string()),
+            ),
+            (
+                Some("on a metered connection"),
+                3,
+                Some(error(60)),
+                SyncState::Paused("on a metered connection".to_string()),
+            ),
+        ];
+        for spec in test_values {
+            let mut status = healthy.clone();
+            status.paused = spec.0.map(|r| r.to_string());
+            status.queue.depth = spec.1;
+            status.groups[0].last_error = spec.2.clone();
+            assert_eq!(get_sync_state(&status, now), spec.3, "{spec:?}");
+        }
+
+        // a daemon that stopped checking on itself is wedged
+        let status = Status {
+            health: Health {
+                checked_at: now - 3600,
+                ..healthy.health.clone()
+            },
+            ..healthy.clone()
+        };
+        assert!(matches!(get_sync_state(&status, now), SyncState::Error(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_synced_label() -> Result<()> {
+        let synced = Synced::new(Direction::Pull, "1234", "home_nas", "docs", "a_b.txt");
+        let label = get_synced_label(&synced);
+        assert!(label.ends_with(" docs: a__b.txt <- home__nas"), "{label}");
+
+        Ok(())
+    }
+}
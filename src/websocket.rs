@@ -0,0 +1,164 @@
+use anyhow::{Result, bail};
+use data_encoding::BASE64;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// appended to the key of the client to accept it, as the rfc says
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// frames of the clients bigger than this close the connection, only the
+// control ones (close, ping) are expected of them
+const MAX_CLIENT_FRAME_BYTES: u64 = 64 * 1024;
+
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_CLOSE: u8 = 0x8;
+
+// get_accept_key retrieves what the server answers to the key of the client
+// for the upgrade to websocket
+pub fn get_accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    BASE64.encode(&hasher.finalize())
+}
+
+// get_upgrade_response retrieves the response switching the connection to
+// websocket
+pub fn get_upgrade_response(key: &str) -> String {
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        get_accept_key(key)
+    )
+}
+
+// is_local_origin checks if the page asking for the websocket comes from
+// the machine itself
+pub fn is_local_origin(origin: &str) -> bool {
+    let Some((_, host)) = origin.split_once("://") else {
+        return false;
+    };
+
+    let host = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(host, "127.0.0.1" | "localhost" | "::1")
+}
+
+// encode_frame retrieves the frame of the payload in a single piece, the
+// ones of the server go unmasked
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// read_frame reads the next frame of the client, retrieving its opcode and
+// its payload unmasked
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let opcode = head[0] & 0x0f;
+    let is_masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7f {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > MAX_CLIENT_FRAME_BYTES {
+        bail!("websocket frame of {len} bytes is too big");
+    }
+
+    let mut mask = [0u8; 4];
+    if is_masked {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if is_masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+
+    Ok((opcode, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_get_accept_key() -> Result<()> {
+        // the example of the rfc
+        assert_eq!(
+            get_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_local_origin() -> Result<()> {
+        let test_values = [
+            // (origin, local)
+            ("http://127.0.0.1:8080", true),
+            ("http://localhost", true),
+            ("http://[::1]:8080", true),
+            ("https://example.com", false),
+            ("http://localhost.example.com", false),
+            ("null", false),
+        ];
+        for spec in test_values {
+            assert_eq!(is_local_origin(spec.0), spec.1, "{spec:?}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_frames() -> Result<()> {
+        let test_values = [
+            // (payload length, header length)
+            (0, 2),
+            (125, 2),
+            (126, 4),
+            (0xffff, 4),
+            (0x10000, 10),
+        ];
+        for spec in test_values {
+            let payload = vec![b'a'; spec.0];
+            let frame = encode_frame(OPCODE_TEXT, &payload);
+            assert_eq!(frame.len(), spec.0 + spec.1, "{spec:?}");
+            assert_eq!(frame[0], 0x81, "{spec:?}");
+        }
+
+        // the frames of the clients come masked
+        let mask = [1u8, 2, 3, 4];
+        let mut frame = vec![0x88, 0x80 | 5];
+        frame.extend_from_slice(&mask);
+        frame.extend(b"bye!!".iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        let (opcode, payload) = read_frame(&mut frame.as_slice()).await?;
+        assert_eq!(opcode, OPCODE_CLOSE);
+        assert_eq!(payload, b"bye!!");
+
+        let frame = [0x81, 127, 0, 0, 0, 0, 1, 0, 0, 0];
+        assert!(read_frame(&mut frame.as_slice()).await.is_err());
+
+        Ok(())
+    }
+}
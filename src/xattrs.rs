@@ -0,0 +1,111 @@
+use anyhow::Result;
+use std::path::Path;
+
+#[cfg(unix)]
+use crate::logs::log_warning;
+
+// Xattr: an extended attribute of a file, (name, value)
+pub type Xattr = (String, Vec<u8>);
+
+// read_xattrs retrieves all the extended attributes of the path
+#[cfg(unix)]
+pub fn read_xattrs(path: &Path) -> Result<Vec<Xattr>> {
+    let mut attrs = vec![];
+    for name in xattr::list(path)? {
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        if let Some(value) = xattr::get(path, name)? {
+            attrs.push((name.to_owned(), value));
+        }
+    }
+
+    Ok(attrs)
+}
+
+#[cfg(not(unix))]
+pub fn read_xattrs(_path: &Path) -> Result<Vec<Xattr>> {
+    Ok(vec![])
+}
+
+// write_xattrs sets the extended attributes on the path. the file system
+// might not support them (or some, like security labels, need privileges)
+// in which case we let it be known but carry on, the content is what matters
+#[cfg(unix)]
+pub fn write_xattrs(path: &Path, attrs: &[Xattr]) {
+    for (name, value) in attrs {
+        if let Err(e) = xattr::set(path, name, value) {
+            log_warning!("- warning: unable to set xattr {name} on {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn write_xattrs(_path: &Path, _attrs: &[Xattr]) {}
+
+// encode_xattrs changes the attributes into a string safe to be part of a
+// message, `hex_name:hex_value` separated by `,`
+pub fn encode_xattrs(attrs: &[Xattr]) -> String {
+    attrs
+        .iter()
+        .map(|(name, value)| format!("{}:{}", hex::encode(name), hex::encode(value)))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// decode_xattrs is the reverse of encode_xattrs, invalid entries are ignored
+pub fn decode_xattrs(raw: &str) -> Vec<Xattr> {
+    raw.split(",")
+        .filter_map(|entry| {
+            let (name, value) = entry.split_once(":")?;
+            let name = String::from_utf8(hex::decode(name).ok()?).ok()?;
+            let value = hex::decode(value).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_encode_decode_xattrs() -> Result<()> {
+        let test_values: [Vec<Xattr>; 3] = [
+            vec![],
+            vec![("user.foo".to_string(), b"bar".to_vec())],
+            vec![
+                ("user.foo".to_string(), b"bar;zed,".to_vec()),
+                ("com.apple.metadata:_kMDItemUserTags".to_string(), vec![0, 1, 255]),
+            ],
+        ];
+
+        for spec in test_values {
+            let encoded = encode_xattrs(&spec);
+            assert!(!encoded.contains(";"));
+            assert_eq!(decode_xattrs(&encoded), spec);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_xattrs_invalid() -> Result<()> {
+        let test_values = [
+            // (raw, expected_len)
+            ("", 0),
+            ("foo", 0),
+            ("zz:00", 0),
+            ("6162:zz", 0),
+            ("6162:00,foo", 1),
+        ];
+
+        for spec in test_values {
+            assert_eq!(decode_xattrs(spec.0).len(), spec.1, "{}", spec.0);
+        }
+
+        Ok(())
+    }
+}